@@ -1,6 +1,13 @@
+use crate::state::snapshot;
+
 mod dmc;
+mod envelope;
+mod frame_counter;
+mod mixer;
 mod noise;
 mod pulse;
+mod scope;
+mod sweep;
 mod triangle;
 
 /// Length counter values table
@@ -45,28 +52,88 @@ const STATUS_REGISTER: u16 = 0x4015;
 const FRAME_COUNTER: u16 = 0x4017;
 
 use dmc::Dmc;
+use frame_counter::FrameCounter;
+use mixer::Mixer;
 use noise::Noise;
 use pulse::Pulse;
+use scope::Scope;
+use sweep::Channel;
 use triangle::Triangle;
 
+use crate::audio::APU_SAMPLE_RATE;
 use crate::filters::{Filter, HighPass, LowPass};
+use crate::region::Region;
+
+/// Represents audio generated by the cartridge, such as the extra channels of
+/// the VRC6, Namco 163, FDS and Sunsoft 5B, which is mixed with the APU
+/// output.
+///
+/// See: https://www.nesdev.org/wiki/Expansion_audio
+pub trait ExpansionAudio {
+    /// Advances the state of the expansion audio by one CPU cycle.
+    fn clock(&mut self);
+
+    /// Returns the current output of the expansion audio, where 1.0 is the
+    /// full scale output of the APU.
+    fn output(&self) -> f32;
+
+    /// Returns the volume of the expansion audio relative to the APU, as the
+    /// mix level differs between chips.
+    fn volume(&self) -> f32 {
+        1.0
+    }
+}
+
+/// The audio channels which can be muted or soloed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Expansion,
+}
+
+impl AudioChannel {
+    /// Returns the name of the channel.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioChannel::Pulse1 => "pulse1",
+            AudioChannel::Pulse2 => "pulse2",
+            AudioChannel::Triangle => "triangle",
+            AudioChannel::Noise => "noise",
+            AudioChannel::Dmc => "dmc",
+            AudioChannel::Expansion => "expansion",
+        }
+    }
+
+    /// All of the audio channels, in order.
+    pub const ALL: [AudioChannel; 6] = [
+        AudioChannel::Pulse1,
+        AudioChannel::Pulse2,
+        AudioChannel::Triangle,
+        AudioChannel::Noise,
+        AudioChannel::Dmc,
+        AudioChannel::Expansion,
+    ];
+}
 
-/// The mode in which the APU which loop over events.
-#[derive(PartialEq)]
-enum SequencerMode {
-    FourStep,
-    FiveStep,
+/// Returns the hardware output filters for the given APU clock rate, in Hz.
+///
+/// See: https://www.nesdev.org/wiki/APU_Mixer
+fn output_filters(clock_rate: f64) -> Vec<Box<dyn Filter>> {
+    vec![
+        Box::new(HighPass::new(90.0, clock_rate as f32)),
+        Box::new(HighPass::new(440.0, clock_rate as f32)),
+        Box::new(LowPass::new(14000.0, clock_rate as f32)),
+    ]
 }
 
 /// Represents the NES Audio Processing Unit (APU).
 pub struct Apu {
     cycles: u32,
-    frame_counter: u16,
-    disable_interrupt: bool,
-    pending_interrupt: Option<bool>,
-
-    sequencer: u8,
-    mode: SequencerMode,
+    frame_counter: FrameCounter,
 
     pulse1: Pulse,
     pulse2: Pulse,
@@ -74,51 +141,68 @@ pub struct Apu {
     noise: Noise,
     dmc: Dmc,
 
-    pulse_table: [f32; 31],
-    tnd_table: [f32; 203],
+    mixer: Mixer,
 
+    // The hardware output filters, applied to the mixed output at the APU
+    // clock rate.
     filters: Vec<Box<dyn Filter>>,
+    filters_enabled: bool,
+    sample: f32,
+    expansion: f32,
+
+    muted: [bool; AudioChannel::ALL.len()],
+    solo: Option<AudioChannel>,
+
+    scope: Scope,
 }
 
+snapshot!(
+    Apu,
+    cycles,
+    frame_counter,
+    pulse1,
+    pulse2,
+    triangle,
+    noise,
+    dmc,
+    sample,
+    expansion
+);
+
 impl Apu {
     /// Creates a new APU.
-    pub fn new(sample_rate: f32) -> Self {
-        let mut apu = Apu {
+    pub fn new() -> Self {
+        Apu {
             cycles: 0,
-            frame_counter: 0,
-            disable_interrupt: false,
-            pending_interrupt: None,
-
-            sequencer: 0,
-            mode: SequencerMode::FourStep,
+            frame_counter: FrameCounter::new(),
 
-            pulse1: Pulse::new(),
-            pulse2: Pulse::new(),
+            pulse1: Pulse::new(Channel::One),
+            pulse2: Pulse::new(Channel::Two),
             triangle: Triangle::new(),
             noise: Noise::new(),
             dmc: Dmc::new(),
 
-            pulse_table: [0.0; 31],
-            tnd_table: [0.0; 203],
+            mixer: Mixer::new(),
 
-            filters: vec![
-                Box::new(HighPass::new(90.0, sample_rate)),
-                Box::new(HighPass::new(440.0, sample_rate)),
-                Box::new(LowPass::new(14000.0, sample_rate)),
-            ],
-        };
+            filters: output_filters(APU_SAMPLE_RATE),
+            filters_enabled: true,
+            sample: 0.0,
+            expansion: 0.0,
 
-        // Precompute the pulse and tnd lookup tables.
-        //
-        // See: https://www.nesdev.org/wiki/APU_Mixer#Emulation
-        for i in 0..31 {
-            apu.pulse_table[i] = 95.52 / (8128.0 / i as f32 + 100.0);
-        }
-        for i in 0..203 {
-            apu.tnd_table[i] = 163.67 / (24329.0 / i as f32 + 100.0);
+            muted: [false; AudioChannel::ALL.len()],
+            solo: None,
+
+            scope: Scope::new(),
         }
+    }
 
-        apu
+    /// Sets the console region, which selects the PAL or NTSC channel and
+    /// frame counter timings, and the clock rate of the output filters.
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_counter.set_pal(region.pal_apu());
+        self.noise.set_pal(region.pal_apu());
+        self.dmc.set_pal(region.pal_apu());
+        self.filters = output_filters(region.cpu_clock_rate());
     }
 
     /// Advances the state of the APU by one CPU cycle.
@@ -129,48 +213,99 @@ impl Apu {
         self.dmc.clock();
 
         // Pulse and noise channels are clocked at half the rate of the CPU.
-        if self.cycles % 2 == 0 {
+        if self.cycles.is_multiple_of(2) {
             self.pulse1.clock_timer();
             self.pulse2.clock_timer();
             self.noise.clock_timer();
         }
 
-        // TODO: Don't understand any of this frame counter stuff!
-        self.frame_counter = self.frame_counter.wrapping_add(2);
-        if self.frame_counter >= 14915 {
-            self.frame_counter -= 14915;
+        let frame_clock = self.frame_counter.clock();
 
-            self.sequencer = self.sequencer.wrapping_add(1);
-            match self.mode {
-                SequencerMode::FourStep => self.sequencer %= 4,
-                SequencerMode::FiveStep => self.sequencer %= 5,
-            }
+        // Quarter frame clocks.
+        if frame_clock.quarter {
+            self.pulse1.clock_envelope();
+            self.pulse2.clock_envelope();
+            self.noise.clock_envelope();
+            self.triangle.clock_counter();
+        }
 
-            // Four step mode can request an interrupt on the last step
-            if !self.disable_interrupt
-                && self.mode == SequencerMode::FourStep
-                && self.sequencer == 0
-            {
-                self.pending_interrupt = Some(true);
-            }
+        // Half frame clocks.
+        if frame_clock.half {
+            self.pulse1.clock_length();
+            self.pulse2.clock_length();
+            self.pulse1.clock_sweep();
+            self.pulse2.clock_sweep();
+            self.triangle.clock_length();
+            self.noise.clock_length();
+        }
 
-            // Sweep and length clocks.
-            if (self.frame_counter & 0x5) == 1 {
-                self.pulse1.clock_length();
-                self.pulse2.clock_length();
-                self.pulse1.clock_sweep(pulse::Channel::One);
-                self.pulse2.clock_sweep(pulse::Channel::Two);
-                self.triangle.clock_length();
-                self.noise.clock_length();
-            }
+        self.update_sample();
+        self.update_scope();
+    }
 
-            if self.sequencer < 4 {
-                self.pulse1.clock_envelope();
-                self.pulse2.clock_envelope();
-                self.noise.clock_envelope();
-                self.triangle.clock_counter();
-            }
-        }
+    /// Records the output of each channel before mixing.
+    fn update_scope(&mut self) {
+        let outputs = [
+            self.pulse1.output() as f32 / 15.0,
+            self.pulse2.output() as f32 / 15.0,
+            self.triangle.output() as f32 / 15.0,
+            self.noise.output() as f32 / 15.0,
+            self.dmc.output() as f32 / 127.0,
+            self.expansion,
+        ];
+
+        self.scope.clock(|channel| outputs[channel as usize]);
+    }
+
+    /// Returns the output of each channel mixed in isolation, in the order of
+    /// AudioChannel::ALL, for recording stems.
+    ///
+    /// Mixing each channel alone keeps the levels of the stems relative to
+    /// each other the same as in the full mix.
+    pub fn stem_outputs(&self) -> [f32; AudioChannel::ALL.len()] {
+        [
+            self.mixer.mix(self.pulse1.output(), 0, 0, 0, 0),
+            self.mixer.mix(0, self.pulse2.output(), 0, 0, 0),
+            self.mixer.mix(0, 0, self.triangle.output(), 0, 0),
+            self.mixer.mix(0, 0, 0, self.noise.output(), 0),
+            self.mixer.mix(0, 0, 0, 0, self.dmc.output()),
+            self.expansion,
+        ]
+    }
+
+    /// Returns the recent output of a channel before mixing, from oldest to
+    /// newest, for visualizations.
+    #[cfg(any(feature = "sdl", feature = "winit", test))]
+    pub fn channel_history(&self, channel: AudioChannel) -> Vec<f32> {
+        self.scope.history(channel)
+    }
+
+    /// Mixes the channel outputs and passes the result through the output
+    /// filters.
+    ///
+    /// The filters are applied at the APU clock rate, before the output is
+    /// resampled, to avoid aliasing.
+    fn update_sample(&mut self) {
+        let sample = self.mixer.mix(
+            self.channel_output(AudioChannel::Pulse1, self.pulse1.output()),
+            self.channel_output(AudioChannel::Pulse2, self.pulse2.output()),
+            self.channel_output(AudioChannel::Triangle, self.triangle.output()),
+            self.channel_output(AudioChannel::Noise, self.noise.output()),
+            self.channel_output(AudioChannel::Dmc, self.dmc.output()),
+        );
+
+        let sample = match self.is_audible(AudioChannel::Expansion) {
+            true => sample + self.expansion,
+            false => sample,
+        };
+
+        self.sample = match self.filters_enabled {
+            true => self
+                .filters
+                .iter_mut()
+                .fold(sample, |sample, filter| filter.process(sample)),
+            false => sample,
+        };
     }
 
     /// Reads a byte from the APU.
@@ -181,6 +316,15 @@ impl Apu {
         }
     }
 
+    /// Returns the byte a read from the APU would return, without clearing
+    /// the frame interrupt flag.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            STATUS_REGISTER => self.peek_status(),
+            _ => 0,
+        }
+    }
+
     /// Writes a byte to the APU.
     pub fn write(&mut self, addr: u16, data: u8) {
         match addr {
@@ -221,57 +365,79 @@ impl Apu {
                 self.dmc.toggle(data & 0x10 != 0);
             }
 
-            FRAME_COUNTER => {
-                self.mode = match data & 0x80 == 0 {
-                    true => SequencerMode::FiveStep,
-                    false => SequencerMode::FourStep,
-                };
-
-                self.frame_counter = 0;
-                self.sequencer = 0;
-
-                self.disable_interrupt = data & 0x40 != 0;
-
-                // Clear the IRQ flag if set to disabled
-                if self.disable_interrupt {
-                    self.dmc.poll_interrupt();
-                    self.pending_interrupt = None;
-                }
-            }
+            FRAME_COUNTER => self
+                .frame_counter
+                .write(data, !self.cycles.is_multiple_of(2)),
 
             _ => (),
         }
     }
 
-    /// Returns an audio sample from the APU.
+    /// Returns the current audio sample from the APU.
     ///
     /// The NES APU mixer takes the channel outputs and converts them to an
     /// analog audio signal.
-    pub fn output(&mut self) -> f32 {
-        // The APU mixer formulas can be efficiently implemented using lookup
-        // tables.
-        //
-        // See: https://www.nesdev.org/wiki/APU_Mixer#Emulation
-        let pulse_output = self.pulse_table[(self.pulse1.output() + self.pulse2.output()) as usize];
+    pub fn output(&self) -> f32 {
+        self.sample
+    }
+
+    /// Sets whether the output filters are applied. Bypassing the filters
+    /// gives the raw output of the mixer.
+    pub fn set_filters_enabled(&mut self, enabled: bool) {
+        self.filters_enabled = enabled;
+    }
+
+    /// Sets the output of the cartridge's expansion audio, to be mixed with
+    /// the APU channels on the next clock.
+    pub fn set_expansion_output(&mut self, sample: f32) {
+        self.expansion = sample;
+    }
+
+    /// Returns the output of a channel, or 0 if the channel is not audible.
+    fn channel_output(&self, channel: AudioChannel, output: u8) -> u8 {
+        match self.is_audible(channel) {
+            true => output,
+            false => 0,
+        }
+    }
 
-        let tnd_output = self.tnd_table
-            [(3 * self.triangle.output() + 2 * self.noise.output() + self.dmc.output()) as usize];
+    /// Returns true if a channel is audible. While a channel is soloed, every
+    /// other channel is silenced.
+    pub fn is_audible(&self, channel: AudioChannel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel as usize],
+        }
+    }
 
-        let sample = pulse_output + tnd_output;
+    /// Mutes or unmutes a channel.
+    pub fn set_muted(&mut self, channel: AudioChannel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
 
-        self.filters
-            .iter_mut()
-            .fold(sample, |sample, filter| filter.process(sample))
+    /// Returns true if a channel is muted.
+    pub fn is_muted(&self, channel: AudioChannel) -> bool {
+        self.muted[channel as usize]
     }
 
-    /// Polls the IRQ flag
-    pub fn poll_interrupt(&mut self) -> bool {
-        // TODO: Hook this up to the system bus.
-        self.pending_interrupt.take().is_some() | self.dmc.poll_interrupt()
+    /// Solos a channel, or clears the solo if channel is None.
+    pub fn set_solo(&mut self, channel: Option<AudioChannel>) {
+        self.solo = channel;
+    }
+
+    /// Returns the soloed channel, if any.
+    pub fn solo(&self) -> Option<AudioChannel> {
+        self.solo
+    }
+
+    /// Returns true if the APU is asserting the IRQ line, from either the
+    /// frame counter or the DMC.
+    pub fn poll_interrupt(&self) -> bool {
+        self.frame_counter.interrupt() | self.dmc.interrupt()
     }
 
     /// Returns true if the DMC needs a new sample.
-    pub fn need_dmc_sample(&mut self) -> bool {
+    pub fn need_dmc_sample(&self) -> bool {
         self.dmc.need_sample()
     }
 
@@ -289,16 +455,23 @@ impl Apu {
     ///
     /// IF-D NT21
     ///
-    /// I: DMC Interrupt requested and clears it if set
-    /// F: Apu interrupt flag and clears it if set
+    /// I: DMC interrupt flag
+    /// F: Frame interrupt flag, cleared by the read
     /// D: 1 if DMC length counter > 0
     /// N: 1 if noise length counter > 0
     /// T: 1 if triangle length counter > 0
     /// 2: 1 if pulse 2 length counter > 0
     /// 1: 1 if pulse 1 length counter > 0
     fn status(&mut self) -> u8 {
-        (self.dmc.poll_interrupt() as u8) << 7
-            | (self.pending_interrupt.take().is_some() as u8) << 6
+        let status = self.peek_status();
+        self.frame_counter.clear_interrupt();
+        status
+    }
+
+    /// Returns the status register without clearing the frame interrupt flag.
+    fn peek_status(&self) -> u8 {
+        (self.dmc.interrupt() as u8) << 7
+            | (self.frame_counter.interrupt() as u8) << 6
             | ((self.dmc.length_counter() > 0) as u8) << 4
             | ((self.noise.length_counter() > 0) as u8) << 3
             | ((self.triangle.length_counter() > 0) as u8) << 2
@@ -306,3 +479,133 @@ impl Apu {
             | (self.pulse1.length_counter() > 0) as u8
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_length_counters() {
+        let mut apu = Apu::new();
+        apu.write(STATUS_REGISTER, 0x0F);
+        apu.write(PULSE1_TIMER_HIGH, 0x08);
+        apu.write(PULSE2_TIMER_HIGH, 0x08);
+        apu.write(TRIANGLE_TIMER_HIGH, 0x08);
+        apu.write(NOISE_TIMER_HIGH, 0x08);
+        assert_eq!(apu.read(STATUS_REGISTER), 0x0F);
+
+        // Disabling a channel clears its length counter.
+        apu.write(STATUS_REGISTER, 0x05);
+        assert_eq!(apu.read(STATUS_REGISTER), 0x05);
+    }
+
+    #[test]
+    fn test_status_dmc() {
+        let mut apu = Apu::new();
+        apu.write(DMC_SAMPLE_FREQUENCY, 0x80);
+        apu.write(STATUS_REGISTER, 0x10);
+        assert_eq!(apu.read(STATUS_REGISTER), 0x10);
+
+        apu.set_dmc_sample(0);
+        assert_eq!(apu.read(STATUS_REGISTER), 0x80);
+
+        // Writing to the status register clears the DMC interrupt.
+        apu.write(STATUS_REGISTER, 0x00);
+        assert_eq!(apu.read(STATUS_REGISTER), 0x00);
+    }
+
+    #[test]
+    fn test_status_clears_frame_interrupt() {
+        let mut apu = Apu::new();
+        for _ in 0..29830 {
+            apu.clock();
+        }
+
+        assert!(apu.poll_interrupt());
+        assert_eq!(apu.peek(STATUS_REGISTER), 0x40);
+        assert!(apu.poll_interrupt());
+        assert_eq!(apu.read(STATUS_REGISTER), 0x40);
+        assert!(!apu.poll_interrupt());
+        assert_eq!(apu.read(STATUS_REGISTER), 0x00);
+    }
+
+    #[test]
+    fn test_filter_bypass() {
+        let mut apu = Apu::new();
+        apu.write(DMC_SAMPLE_RAW, 0x7F);
+        apu.clock();
+
+        // The high-pass filters remove the DC offset of the DMC output.
+        let filtered = apu.output();
+        assert!(filtered < apu.mixer.mix(0, 0, 0, 0, 0x7F));
+
+        apu.set_filters_enabled(false);
+        apu.clock();
+        assert_eq!(apu.output(), apu.mixer.mix(0, 0, 0, 0, 0x7F));
+    }
+
+    #[test]
+    fn test_mute() {
+        let mut apu = Apu::new();
+        apu.set_filters_enabled(false);
+        apu.write(DMC_SAMPLE_RAW, 0x7F);
+
+        apu.set_muted(AudioChannel::Dmc, true);
+        assert!(apu.is_muted(AudioChannel::Dmc));
+        apu.clock();
+        assert_eq!(apu.output(), 0.0);
+
+        apu.set_muted(AudioChannel::Dmc, false);
+        apu.clock();
+        assert!(apu.output() > 0.0);
+    }
+
+    #[test]
+    fn test_solo() {
+        let mut apu = Apu::new();
+        apu.set_filters_enabled(false);
+        apu.write(DMC_SAMPLE_RAW, 0x7F);
+
+        apu.set_solo(Some(AudioChannel::Pulse1));
+        assert!(apu.is_audible(AudioChannel::Pulse1));
+        assert!(!apu.is_audible(AudioChannel::Dmc));
+        apu.clock();
+        assert_eq!(apu.output(), 0.0);
+
+        // Soloing takes precedence over muting.
+        apu.set_muted(AudioChannel::Dmc, true);
+        apu.set_solo(Some(AudioChannel::Dmc));
+        apu.clock();
+        assert!(apu.output() > 0.0);
+
+        apu.set_solo(None);
+        assert!(!apu.is_audible(AudioChannel::Dmc));
+    }
+
+    #[test]
+    fn test_expansion_output() {
+        let mut apu = Apu::new();
+        apu.set_filters_enabled(false);
+
+        apu.set_expansion_output(0.25);
+        apu.clock();
+        assert_eq!(apu.output(), 0.25);
+
+        apu.set_muted(AudioChannel::Expansion, true);
+        apu.clock();
+        assert_eq!(apu.output(), 0.0);
+    }
+
+    #[test]
+    fn test_channel_history() {
+        let mut apu = Apu::new();
+        apu.write(DMC_SAMPLE_RAW, 0x7F);
+        for _ in 0..1000 {
+            apu.clock();
+        }
+
+        let history = apu.channel_history(AudioChannel::Dmc);
+        assert_eq!(history.last(), Some(&1.0));
+        assert_eq!(apu.channel_history(AudioChannel::Pulse1).last(), Some(&0.0));
+    }
+}
@@ -1,60 +1,110 @@
+use crate::state::snapshot;
+
+/// Number of CPU cycles between each clock of the output unit, indexed by the
+/// rate index written to $4010.
 const RATE_TABLE: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
 ];
 
+/// Rate table of the PAL APU, which plays samples at close to the NTSC pitch
+/// despite the slower CPU clock.
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
 /// Represents the NES delta modulation channel (DMC) which can output 1-bit
 /// delta-encoded samples or can have its 7-bit counter directly loaded,
 /// allowing flexible manual sample playback.
+///
+/// See: https://www.nesdev.org/wiki/APU_DMC
 pub struct Dmc {
-    enabled: bool,
-
-    disable_interrupt: bool,
-    pending_interrupt: Option<bool>,
+    irq_enabled: bool,
+    interrupt: bool,
 
     loop_sample: bool,
+    rate_table: &'static [u16; 16],
     rate: u16,
-    rate_counter: u16,
-
-    pending_read: Option<bool>,
-    addr: u8,
-    last_addr: u16,
-    buf: u8,
-    phase: u8,
-
+    timer: u16,
+
+    // Memory reader.
+    //
+    // Fetches the sample bytes over the CPU bus (via DMA) into the sample
+    // buffer whenever the buffer is empty and there are bytes remaining.
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit.
+    //
+    // Shifts out the bits of the sample one at a time, adjusting the output
+    // level up or down by 2 for each bit.
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
     output_level: u8,
-    length_counter: u16,
-    pcm_length: u16,
 }
 
+snapshot!(
+    Dmc,
+    irq_enabled,
+    interrupt,
+    loop_sample,
+    rate in 1..,
+    timer,
+    sample_addr,
+    sample_length,
+    current_addr,
+    bytes_remaining,
+    sample_buffer,
+    shift,
+    bits_remaining in 1..=8,
+    silence,
+    output_level in 0..128
+);
+
 impl Dmc {
     /// Creates a new DMC.
     pub fn new() -> Self {
         Self {
-            enabled: false,
-            disable_interrupt: false,
-            pending_interrupt: None,
+            irq_enabled: false,
+            interrupt: false,
             loop_sample: false,
-            rate: 0,
-            rate_counter: 0,
-            pending_read: None,
-            addr: 0,
-            last_addr: 0xC000,
-            buf: 0,
-            phase: 0,
+            rate_table: &RATE_TABLE,
+            rate: RATE_TABLE[0],
+            timer: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
             output_level: 0,
-            length_counter: 0,
-            pcm_length: 0,
         }
     }
 
+    /// Selects the rate table of the PAL or NTSC APU.
+    pub fn set_pal(&mut self, pal: bool) {
+        self.rate_table = match pal {
+            true => &PAL_RATE_TABLE,
+            false => &RATE_TABLE,
+        };
+    }
+
     /// Toggles the channel on or off.
+    ///
+    /// Disabling the channel stops the sample after the current byte has
+    /// played, enabling it restarts the sample only if it has finished.
     pub fn toggle(&mut self, enabled: bool) {
-        self.enabled = enabled;
+        self.interrupt = false;
 
-        if !self.enabled {
-            self.length_counter = 0;
-        } else if self.length_counter == 0 {
-            self.length_counter = self.pcm_length * 16 + 1;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
         }
     }
 
@@ -67,9 +117,14 @@ impl Dmc {
     /// L: Loop flag
     /// R: Rate index (frequency)
     pub fn write_sample_frequency(&mut self, data: u8) {
-        self.rate = RATE_TABLE[(data & 0xF) as usize];
+        self.irq_enabled = data & 0x80 != 0;
         self.loop_sample = data & 0x40 != 0;
-        self.disable_interrupt = data & 0x80 != 0;
+        self.rate = self.rate_table[(data & 0xF) as usize];
+
+        // Clearing the IRQ enable flag also clears the interrupt flag.
+        if !self.irq_enabled {
+            self.interrupt = false;
+        }
     }
 
     /// Writes a raw PCM sample.
@@ -87,10 +142,9 @@ impl Dmc {
     /// Where data is equal to:
     ///
     /// AAAA AAAA
-    /// A: Start address
+    /// A: Start address, %11AAAAAA.AA000000 = $C000 + (A * 64)
     pub fn write_sample_start(&mut self, data: u8) {
-        self.addr = data;
-        self.last_addr = 0xC000 + (data as u16 * 64);
+        self.sample_addr = 0xC000 + (data as u16 * 64);
     }
 
     /// Writes the length of the sample.
@@ -98,90 +152,96 @@ impl Dmc {
     /// Where data is equal to:
     ///
     /// LLLL LLLL
-    /// L: Sample length (how many samples to play)
+    /// L: Sample length, %LLLL.LLLL0001 = (L * 16) + 1 bytes
     pub fn write_sample_length(&mut self, data: u8) {
-        self.pcm_length = data as u16;
-        self.length_counter = self.pcm_length * 16 + 1;
+        self.sample_length = data as u16 * 16 + 1;
     }
 
-    /// Clocks the DMC.
+    /// Clocks the DMC timer, which clocks the output unit when it expires.
     pub fn clock(&mut self) {
-        if self.rate_counter > 0 {
-            self.rate_counter -= 1;
+        if self.timer > 0 {
+            self.timer -= 1;
             return;
         }
 
-        self.clock_timer();
-        self.rate_counter = self.rate;
+        self.timer = self.rate - 1;
+        self.clock_output();
     }
 
-    /// Clocks the DMC timer.
-    fn clock_timer(&mut self) {
-        // Phase 0 means the PCM or DPCM sample has been played
-        if self.phase == 0 {
-            // If the length counter == 0 (all the samples have been played)
-            // and the loop flag is set, we load the start address and
-            // reset the length counter
-            if self.length_counter == 0 && self.loop_sample {
-                self.length_counter = self.pcm_length * 16 + 1;
-                self.last_addr = 0xC000 + (self.addr as u16 * 64);
-            }
-
-            if self.length_counter > 0 {
-                self.pending_read = Some(true);
-                self.phase = 8;
-                self.length_counter -= 1;
-            } else {
-                if !self.disable_interrupt {
-                    self.pending_interrupt = Some(true);
-                }
-
-                self.enabled = false;
+    /// Clocks the output unit.
+    fn clock_output(&mut self) {
+        if !self.silence {
+            // Adjust the output level, ignoring changes which would take it
+            // outside of the 0-127 range.
+            match self.shift & 0x1 != 0 {
+                true if self.output_level <= 125 => self.output_level += 2,
+                false if self.output_level >= 2 => self.output_level -= 2,
+                _ => {}
             }
         }
 
-        // Sample is still playing.
-        if self.phase != 0 {
-            self.phase -= 1;
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
 
-            // Adjust the volume.
-            let delta = (self.buf & (0x80 >> self.phase)) != 0;
-            let vol = match delta {
-                true => self.output_level.wrapping_add(2),
-                false => self.output_level.wrapping_sub(2),
-            };
+        // At the end of an output cycle, start a new one with the contents of
+        // the sample buffer. If the buffer is empty the channel is silenced.
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
 
-            if (0..=0x7F).contains(&vol) && self.enabled {
-                self.output_level = vol;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift = sample;
+                }
+                None => self.silence = true,
             }
         }
     }
 
+    /// Restarts the sample from the sample address.
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
     /// Returns the address of the next sample
     pub fn address(&self) -> u16 {
-        self.last_addr
+        self.current_addr
     }
 
-    /// Sets the audio sample of the channel
+    /// Sets the audio sample of the channel, as fetched by the memory reader.
     pub fn set_sample(&mut self, sample: u8) {
-        self.buf = sample;
-
-        self.last_addr = self.last_addr.wrapping_add(1) | 0x8000;
+        self.sample_buffer = Some(sample);
+
+        // The address wraps around to $8000 after $FFFF.
+        self.current_addr = match self.current_addr {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_sample {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
+            }
+        }
     }
 
-    /// Returns if the channel needs a sample or not
-    pub fn need_sample(&mut self) -> bool {
-        self.pending_read.take().is_some()
+    /// Returns true if the memory reader needs to fetch a sample.
+    pub fn need_sample(&self) -> bool {
+        self.sample_buffer.is_none() && self.bytes_remaining > 0
     }
 
-    /// Returns the length counter value
+    /// Returns the number of sample bytes remaining.
     pub fn length_counter(&self) -> u16 {
-        self.length_counter
+        self.bytes_remaining
     }
 
-    /// Returns true if the DMC chanel is waiting for an interrupt.
-    pub fn poll_interrupt(&mut self) -> bool {
-        self.pending_interrupt.take().is_some()
+    /// Returns true if the DMC interrupt flag is set.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
     }
 
     /// Returns the output volume of the channel
@@ -197,29 +257,27 @@ mod tests {
     #[test]
     fn test_new() {
         let dmc = Dmc::new();
-        assert!(!dmc.enabled);
-        assert!(!dmc.disable_interrupt);
-        assert_eq!(dmc.pending_interrupt, None);
+        assert!(!dmc.irq_enabled);
+        assert!(!dmc.interrupt);
         assert!(!dmc.loop_sample);
-        assert_eq!(dmc.rate, 0);
-        assert_eq!(dmc.rate_counter, 0);
-        assert_eq!(dmc.pending_read, None);
-        assert_eq!(dmc.addr, 0);
-        assert_eq!(dmc.last_addr, 0xC000);
-        assert_eq!(dmc.buf, 0);
-        assert_eq!(dmc.phase, 0);
+        assert_eq!(dmc.rate, RATE_TABLE[0]);
+        assert_eq!(dmc.timer, 0);
+        assert_eq!(dmc.sample_addr, 0xC000);
+        assert_eq!(dmc.current_addr, 0xC000);
+        assert_eq!(dmc.sample_buffer, None);
+        assert!(dmc.silence);
         assert_eq!(dmc.output_level, 0);
-        assert_eq!(dmc.length_counter, 0);
-        assert_eq!(dmc.pcm_length, 0);
+        assert_eq!(dmc.bytes_remaining, 0);
     }
 
     #[test]
     fn test_toggle() {
         let mut dmc = Dmc::new();
+        dmc.write_sample_length(0x1);
         dmc.toggle(true);
-        assert!(dmc.enabled);
+        assert_eq!(dmc.bytes_remaining, 17);
         dmc.toggle(false);
-        assert!(!dmc.enabled);
+        assert_eq!(dmc.bytes_remaining, 0);
     }
 
     #[test]
@@ -228,7 +286,15 @@ mod tests {
         dmc.write_sample_frequency(0xCF);
         assert_eq!(dmc.rate, RATE_TABLE[0xF]);
         assert!(dmc.loop_sample);
-        assert!(dmc.disable_interrupt);
+        assert!(dmc.irq_enabled);
+
+        dmc.interrupt = true;
+        dmc.write_sample_frequency(0x0F);
+        assert!(!dmc.interrupt);
+
+        dmc.set_pal(true);
+        dmc.write_sample_frequency(0x0F);
+        assert_eq!(dmc.rate, PAL_RATE_TABLE[0xF]);
     }
 
     #[test]
@@ -242,24 +308,91 @@ mod tests {
     fn test_write_sample_start() {
         let mut dmc = Dmc::new();
         dmc.write_sample_start(0x10);
-        assert_eq!(dmc.addr, 0x10);
-        assert_eq!(dmc.last_addr, 0xC000 + (0x10 * 64));
+        assert_eq!(dmc.sample_addr, 0xC000 + (0x10 * 64));
     }
 
     #[test]
     fn test_write_sample_length() {
         let mut dmc = Dmc::new();
         dmc.write_sample_length(0x10);
-        assert_eq!(dmc.pcm_length, 0x10);
-        assert_eq!(dmc.length_counter, dmc.pcm_length * 16 + 1);
+        assert_eq!(dmc.sample_length, 0x10 * 16 + 1);
     }
 
     #[test]
     fn test_clock() {
         let mut dmc = Dmc::new();
-        dmc.rate = 5;
-        dmc.rate_counter = 5;
+        dmc.timer = 5;
         dmc.clock();
-        assert_eq!(dmc.rate_counter, 4);
+        assert_eq!(dmc.timer, 4);
+    }
+
+    #[test]
+    fn test_memory_reader() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_frequency(0x80);
+        dmc.write_sample_start(0xFF);
+        dmc.write_sample_length(0x04);
+        dmc.toggle(true);
+
+        assert!(dmc.need_sample());
+        assert_eq!(dmc.address(), 0xFFC0);
+
+        for _ in 0..0x3F {
+            dmc.set_sample(0);
+            dmc.sample_buffer = None;
+        }
+
+        // The address wraps around to $8000.
+        dmc.set_sample(0);
+        assert_eq!(dmc.address(), 0x8000);
+        assert!(!dmc.need_sample());
+
+        // Last byte of the sample raises an interrupt.
+        dmc.sample_buffer = None;
+        dmc.set_sample(0);
+        assert_eq!(dmc.length_counter(), 0);
+        assert!(dmc.interrupt());
+    }
+
+    #[test]
+    fn test_interrupt_disabled() {
+        // With the IRQ disabled, as at power up, the end of the sample
+        // doesn't set the interrupt flag.
+        let mut dmc = Dmc::new();
+        dmc.toggle(true);
+        dmc.set_sample(0);
+
+        assert_eq!(dmc.length_counter(), 0);
+        assert!(!dmc.interrupt());
+    }
+
+    #[test]
+    fn test_loop_sample() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_frequency(0xC0);
+        dmc.toggle(true);
+        dmc.set_sample(0);
+
+        assert_eq!(dmc.address(), 0xC000);
+        assert_eq!(dmc.length_counter(), 1);
+        assert!(!dmc.interrupt());
+    }
+
+    #[test]
+    fn test_output_unit() {
+        let mut dmc = Dmc::new();
+        dmc.write_raw_sample(0x40);
+        dmc.sample_buffer = Some(0b0000_0011);
+        dmc.bits_remaining = 1;
+
+        // Finishing the output cycle loads the sample buffer.
+        dmc.clock_output();
+        assert!(!dmc.silence);
+        assert_eq!(dmc.output_level, 0x40);
+
+        dmc.clock_output();
+        dmc.clock_output();
+        dmc.clock_output();
+        assert_eq!(dmc.output_level, 0x42);
     }
 }
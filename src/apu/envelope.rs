@@ -0,0 +1,148 @@
+use crate::state::snapshot;
+
+/// Represents the envelope generator used by the pulse and noise channels.
+///
+/// The envelope generates either a constant volume or a saw envelope which
+/// decays from 15 to 0, optionally looping back to 15.
+///
+/// See: https://www.nesdev.org/wiki/APU_Envelope
+pub struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+
+    // Doubles as the divider period when the envelope is in use.
+    volume: u8,
+
+    divider: u8,
+    decay: u8,
+}
+
+snapshot!(
+    Envelope,
+    start,
+    loop_flag,
+    constant_volume,
+    volume in 0..16,
+    divider,
+    decay in 0..16
+);
+
+impl Envelope {
+    /// Creates a new envelope.
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            loop_flag: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    /// Writes the envelope settings.
+    ///
+    /// Where data is equal to:
+    ///
+    /// --LC VVVV
+    /// L: Envelope loop
+    /// C: Output constant volume
+    /// V: Volume value / envelope period
+    pub fn write(&mut self, data: u8) {
+        self.loop_flag = data & 0x20 != 0;
+        self.constant_volume = data & 0x10 != 0;
+        self.volume = data & 0xF;
+    }
+
+    /// Sets the start flag, restarting the envelope on the next clock.
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocks the envelope, on each quarter frame.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.volume;
+
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.loop_flag {
+            self.decay = 15;
+        }
+    }
+
+    /// Returns the volume of the envelope.
+    pub fn output(&self) -> u8 {
+        match self.constant_volume {
+            true => self.volume,
+            false => self.decay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_volume() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x1A);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 0xA);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x01);
+        envelope.restart();
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+
+        // The decay level is clocked every (period + 1) clocks.
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+
+        for _ in 0..28 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+
+        // Without the loop flag the envelope stays silent.
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 0);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x20);
+        envelope.restart();
+
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
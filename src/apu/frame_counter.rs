@@ -0,0 +1,305 @@
+use crate::state::{snapshot, snapshot_enum};
+
+/// The mode in which the frame counter sequences events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SequencerMode {
+    FourStep,
+    FiveStep,
+}
+
+snapshot_enum!(
+    SequencerMode,
+    [SequencerMode::FourStep, SequencerMode::FiveStep]
+);
+
+/// Signals generated by the frame counter on a given CPU cycle.
+#[derive(Default, Debug, PartialEq)]
+pub struct FrameClock {
+    /// Clocks the envelopes and the triangle linear counter.
+    pub quarter: bool,
+
+    /// Clocks the length counters and sweep units.
+    pub half: bool,
+}
+
+impl FrameClock {
+    const NONE: FrameClock = FrameClock {
+        quarter: false,
+        half: false,
+    };
+    const QUARTER: FrameClock = FrameClock {
+        quarter: true,
+        half: false,
+    };
+    const HALF: FrameClock = FrameClock {
+        quarter: true,
+        half: true,
+    };
+}
+
+/// CPU cycles at which the frame counter steps occur.
+struct StepTimings {
+    quarter: u32,
+    half: u32,
+    three_quarter: u32,
+
+    /// Last step of the 4-step sequence, which is surrounded by the frame
+    /// interrupt.
+    four_step: u32,
+    five_step: u32,
+}
+
+const NTSC_STEPS: StepTimings = StepTimings {
+    quarter: 7457,
+    half: 14913,
+    three_quarter: 22371,
+    four_step: 29829,
+    five_step: 37281,
+};
+
+const PAL_STEPS: StepTimings = StepTimings {
+    quarter: 8313,
+    half: 16627,
+    three_quarter: 24939,
+    four_step: 33253,
+    five_step: 41565,
+};
+
+/// Represents the APU frame counter (or frame sequencer).
+///
+/// The frame counter generates low frequency clocks for the channels and an
+/// optional 60 Hz interrupt. The step timings below are given in CPU cycles.
+///
+/// mode 0:    mode 1:      function
+/// =========  ===========  =============================
+/// . . . f    . . . . .    IRQ (if bit 6 is clear)
+/// . l . l    . l . . l    Length counter and sweep
+/// e e e e    e e e . e    Envelope and linear counter
+///
+/// See: https://www.nesdev.org/wiki/APU_Frame_Counter
+pub struct FrameCounter {
+    mode: SequencerMode,
+    steps: &'static StepTimings,
+    cycles: u32,
+
+    irq_inhibit: bool,
+    interrupt: bool,
+
+    /// Value written to $4017 and the number of CPU cycles until it takes
+    /// effect.
+    pending_write: Option<(u8, u8)>,
+}
+
+snapshot!(
+    FrameCounter,
+    mode,
+    cycles,
+    irq_inhibit,
+    interrupt,
+    pending_write
+);
+
+impl FrameCounter {
+    /// Creates a new frame counter.
+    pub fn new() -> Self {
+        FrameCounter {
+            mode: SequencerMode::FourStep,
+            steps: &NTSC_STEPS,
+            cycles: 0,
+            irq_inhibit: false,
+            interrupt: false,
+            pending_write: None,
+        }
+    }
+
+    /// Selects the step timings of the PAL or NTSC APU.
+    pub fn set_pal(&mut self, pal: bool) {
+        self.steps = match pal {
+            true => &PAL_STEPS,
+            false => &NTSC_STEPS,
+        };
+    }
+
+    /// Writes to the frame counter register.
+    ///
+    /// Where data is equal to:
+    ///
+    /// MI-- ----
+    /// M: Sequencer mode (0: 4-step, 1: 5-step)
+    /// I: Interrupt inhibit flag
+    ///
+    /// The interrupt inhibit flag takes effect immediately, but the sequencer
+    /// is only reset 3 or 4 CPU cycles after the write, depending on whether
+    /// the write happened on an odd or even CPU cycle.
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.interrupt = false;
+        }
+
+        let delay = match odd_cycle {
+            true => 4,
+            false => 3,
+        };
+        self.pending_write = Some((data, delay));
+    }
+
+    /// Advances the frame counter by one CPU cycle, returning the channel
+    /// clocks for this cycle.
+    pub fn clock(&mut self) -> FrameClock {
+        if let Some((data, delay)) = self.pending_write {
+            match delay {
+                0 => {
+                    self.pending_write = None;
+                    return self.reset(data);
+                }
+                _ => self.pending_write = Some((data, delay - 1)),
+            }
+        }
+
+        self.cycles += 1;
+
+        let steps = self.steps;
+        match (self.mode, self.cycles) {
+            (_, c) if c == steps.quarter => FrameClock::QUARTER,
+            (_, c) if c == steps.half => FrameClock::HALF,
+            (_, c) if c == steps.three_quarter => FrameClock::QUARTER,
+
+            (SequencerMode::FourStep, c) if c == steps.four_step - 1 => {
+                self.set_interrupt();
+                FrameClock::NONE
+            }
+            (SequencerMode::FourStep, c) if c == steps.four_step => {
+                self.set_interrupt();
+                FrameClock::HALF
+            }
+            (SequencerMode::FourStep, c) if c == steps.four_step + 1 => {
+                self.set_interrupt();
+                self.cycles = 0;
+                FrameClock::NONE
+            }
+
+            (SequencerMode::FiveStep, c) if c == steps.five_step => FrameClock::HALF,
+            (SequencerMode::FiveStep, c) if c == steps.five_step + 1 => {
+                self.cycles = 0;
+                FrameClock::NONE
+            }
+
+            _ => FrameClock::NONE,
+        }
+    }
+
+    /// Applies a delayed write to $4017, resetting the sequencer.
+    ///
+    /// Entering 5-step mode immediately clocks all the units.
+    fn reset(&mut self, data: u8) -> FrameClock {
+        self.mode = match data & 0x80 != 0 {
+            true => SequencerMode::FiveStep,
+            false => SequencerMode::FourStep,
+        };
+        self.cycles = 0;
+
+        match self.mode {
+            SequencerMode::FiveStep => FrameClock::HALF,
+            SequencerMode::FourStep => FrameClock::NONE,
+        }
+    }
+
+    /// Sets the frame interrupt flag, unless interrupts are inhibited.
+    fn set_interrupt(&mut self) {
+        if !self.irq_inhibit {
+            self.interrupt = true;
+        }
+    }
+
+    /// Returns true if the frame interrupt flag is set.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Clears the frame interrupt flag.
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks the frame counter for the given number of cycles, returning the
+    /// number of quarter and half frame clocks.
+    fn run(frame_counter: &mut FrameCounter, cycles: u32) -> (u32, u32) {
+        let mut quarters = 0;
+        let mut halves = 0;
+
+        for _ in 0..cycles {
+            let clock = frame_counter.clock();
+            quarters += clock.quarter as u32;
+            halves += clock.half as u32;
+        }
+
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step_mode() {
+        let mut frame_counter = FrameCounter::new();
+
+        assert_eq!(run(&mut frame_counter, 29827), (3, 1));
+        assert!(!frame_counter.interrupt());
+
+        assert_eq!(run(&mut frame_counter, 3), (1, 1));
+        assert!(frame_counter.interrupt());
+        assert_eq!(frame_counter.cycles, 0);
+    }
+
+    #[test]
+    fn test_pal_four_step_mode() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.set_pal(true);
+
+        assert_eq!(run(&mut frame_counter, 33251), (3, 1));
+        assert!(!frame_counter.interrupt());
+
+        assert_eq!(run(&mut frame_counter, 3), (1, 1));
+        assert!(frame_counter.interrupt());
+        assert_eq!(frame_counter.cycles, 0);
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write(0x80, false);
+
+        // Entering 5-step mode clocks the units after the write delay.
+        assert_eq!(run(&mut frame_counter, 4), (1, 1));
+        assert_eq!(frame_counter.mode, SequencerMode::FiveStep);
+
+        assert_eq!(run(&mut frame_counter, 37282), (4, 2));
+        assert!(!frame_counter.interrupt());
+        assert_eq!(frame_counter.cycles, 0);
+    }
+
+    #[test]
+    fn test_write_delay() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write(0x80, true);
+
+        assert_eq!(run(&mut frame_counter, 4), (0, 0));
+        assert_eq!(frame_counter.mode, SequencerMode::FourStep);
+        assert_eq!(run(&mut frame_counter, 1), (1, 1));
+        assert_eq!(frame_counter.mode, SequencerMode::FiveStep);
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.interrupt = true;
+        frame_counter.write(0x40, false);
+        assert!(!frame_counter.interrupt());
+
+        run(&mut frame_counter, 29830);
+        assert!(!frame_counter.interrupt());
+    }
+}
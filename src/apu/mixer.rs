@@ -0,0 +1,95 @@
+/// Represents the APU mixer, which combines the outputs of the five channels
+/// into a single analog signal.
+///
+/// The channels are not mixed linearly: the pulse channels and the triangle,
+/// noise and DMC channels (tnd) are mixed in two groups, each of which
+/// follows a nonlinear curve. The mixer formulas are efficiently implemented
+/// using lookup tables.
+///
+/// See: https://www.nesdev.org/wiki/APU_Mixer
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Mixer {
+    /// Creates a new mixer, precomputing the lookup tables.
+    pub fn new() -> Self {
+        let mut mixer = Mixer {
+            pulse_table: [0.0; 31],
+            tnd_table: [0.0; 203],
+        };
+
+        // See: https://www.nesdev.org/wiki/APU_Mixer#Lookup_Table
+        for (i, out) in mixer.pulse_table.iter_mut().enumerate() {
+            *out = 95.52 / (8128.0 / i as f32 + 100.0);
+        }
+        for (i, out) in mixer.tnd_table.iter_mut().enumerate() {
+            *out = 163.67 / (24329.0 / i as f32 + 100.0);
+        }
+
+        mixer
+    }
+
+    /// Mixes the channel outputs, returning a sample in the range 0.0 - 1.0.
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse_out = self.pulse_table[(pulse1 + pulse2) as usize];
+        let tnd_out = self.tnd_table[(3 * triangle + 2 * noise + dmc) as usize];
+
+        pulse_out + tnd_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The nonlinear mixer formulas, as given on nesdev.
+    fn formula(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse_out = match pulse1 + pulse2 {
+            0 => 0.0,
+            p => 95.88 / (8128.0 / p as f32 + 100.0),
+        };
+
+        let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+        let tnd_out = match tnd == 0.0 {
+            true => 0.0,
+            false => 159.79 / (1.0 / tnd + 100.0),
+        };
+
+        pulse_out + tnd_out
+    }
+
+    #[test]
+    fn test_silence() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_full_volume() {
+        let mixer = Mixer::new();
+        let sample = mixer.mix(15, 15, 15, 15, 127);
+        assert!(sample > 0.99 && sample <= 1.0);
+    }
+
+    #[test]
+    fn test_matches_formula() {
+        let mixer = Mixer::new();
+
+        for (p1, p2, t, n, d) in [(15, 0, 0, 0, 0), (8, 8, 0, 0, 0), (0, 0, 15, 15, 64)] {
+            let diff = (mixer.mix(p1, p2, t, n, d) - formula(p1, p2, t, n, d)).abs();
+            assert!(diff < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_nonlinear() {
+        let mixer = Mixer::new();
+
+        // Doubling the input does not double the output.
+        let single = mixer.mix(15, 0, 0, 0, 0);
+        let double = mixer.mix(15, 15, 0, 0, 0);
+        assert!(double < single * 2.0);
+    }
+}
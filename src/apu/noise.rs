@@ -1,9 +1,15 @@
+use super::envelope::Envelope;
 use super::LENGTH_TABLE;
+use crate::state::snapshot;
 
 const TIMER_PERIODS: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+const PAL_TIMER_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
 /// Represents the NES Noise channel which generates pseudo-random 1-bit noise
 /// at 16 different frequencies.
 pub struct Noise {
@@ -12,19 +18,28 @@ pub struct Noise {
 
     timer: u16,
     timer_period: u16,
+    timer_periods: &'static [u16; 16],
 
     length_halt: bool,
     length_counter: u8,
 
-    constant_volume: bool,
-    volume: u8,
-
-    envelope_timer: u8,
-    envelope_volume: u8,
+    envelope: Envelope,
 
     shift: u16,
 }
 
+snapshot!(
+    Noise,
+    enabled,
+    mode,
+    timer,
+    timer_period,
+    length_halt,
+    length_counter,
+    envelope,
+    shift
+);
+
 impl Noise {
     /// Creates a new Noise register.
     pub fn new() -> Self {
@@ -34,15 +49,21 @@ impl Noise {
             length_counter: 0,
             timer: 0,
             timer_period: 0,
+            timer_periods: &TIMER_PERIODS,
             length_halt: false,
-            constant_volume: false,
-            volume: 0,
-            envelope_timer: 0,
-            envelope_volume: 0,
+            envelope: Envelope::new(),
             shift: 0,
         }
     }
 
+    /// Selects the timer periods of the PAL or NTSC APU.
+    pub fn set_pal(&mut self, pal: bool) {
+        self.timer_periods = match pal {
+            true => &PAL_TIMER_PERIODS,
+            false => &TIMER_PERIODS,
+        };
+    }
+
     /// Toggles the channel on or off.
     pub fn toggle(&mut self, enabled: bool) {
         self.enabled = enabled;
@@ -62,8 +83,7 @@ impl Noise {
     /// V: Volume value / envelope period
     pub fn write_volume(&mut self, data: u8) {
         self.length_halt = data & 0x20 != 0;
-        self.constant_volume = data & 0x10 != 0;
-        self.volume = data & 0xF;
+        self.envelope.write(data);
     }
 
     /// Sets the timer low.
@@ -75,7 +95,7 @@ impl Noise {
     /// P: Timer period table index
     pub fn write_timer_low(&mut self, data: u8) {
         self.mode = data & 0x80 != 0;
-        self.timer_period = TIMER_PERIODS[(data & 0xF) as usize];
+        self.timer_period = self.timer_periods[(data & 0xF) as usize];
     }
 
     /// Sets the timer high.
@@ -85,9 +105,12 @@ impl Noise {
     /// LLLL L---
     /// L: Length counter table index
     pub fn write_timer_high(&mut self, data: u8) {
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
-        self.envelope_volume = 15;
-        self.envelope_timer = self.volume + 1;
+        // The length counter is only loaded if the channel is enabled.
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+
+        self.envelope.restart();
     }
 
     /// Clocks the timer / divider.
@@ -113,23 +136,8 @@ impl Noise {
     }
 
     /// Clocks the envelope.
-    ///
-    /// Depending on the nature of the envelope, it will either increment or
-    /// decrement the volume. This can be used to create a constant volume or
-    /// a increasing/decreasing volume.
     pub fn clock_envelope(&mut self) {
-        if self.envelope_timer > 0 {
-            self.envelope_timer -= 1;
-            return;
-        }
-
-        if self.envelope_volume > 0 {
-            self.envelope_volume -= 1;
-        } else if self.length_halt {
-            self.envelope_volume = 15;
-        }
-
-        self.envelope_timer = self.volume + 1;
+        self.envelope.clock();
     }
 
     /// Returns the length counter value.
@@ -144,17 +152,16 @@ impl Noise {
             return 0;
         }
 
-        // Check if we should output constant volume or the envelope volume
-        match self.constant_volume {
-            true => self.volume,
-            false => self.envelope_volume,
-        }
+        self.envelope.output()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::apu::{noise::TIMER_PERIODS, LENGTH_TABLE};
+    use crate::apu::{
+        noise::{PAL_TIMER_PERIODS, TIMER_PERIODS},
+        LENGTH_TABLE,
+    };
 
     use super::Noise;
 
@@ -167,10 +174,6 @@ mod tests {
         assert_eq!(noise.timer, 0);
         assert_eq!(noise.timer_period, 0);
         assert!(!noise.length_halt);
-        assert!(!noise.constant_volume);
-        assert_eq!(noise.volume, 0);
-        assert_eq!(noise.envelope_timer, 0);
-        assert_eq!(noise.envelope_volume, 0);
         assert_eq!(noise.shift, 0);
     }
 
@@ -189,8 +192,7 @@ mod tests {
         let mut noise = Noise::new();
         noise.write_volume(0x3F);
         assert!(noise.length_halt);
-        assert!(noise.constant_volume);
-        assert_eq!(noise.volume, 0xF);
+        assert_eq!(noise.envelope.output(), 0xF);
     }
 
     #[test]
@@ -199,15 +201,24 @@ mod tests {
         noise.write_timer_low(0x8F);
         assert!(noise.mode);
         assert_eq!(noise.timer_period, TIMER_PERIODS[0xF]);
+
+        noise.set_pal(true);
+        noise.write_timer_low(0x0F);
+        assert_eq!(noise.timer_period, PAL_TIMER_PERIODS[0xF]);
     }
 
     #[test]
     fn test_write_timer_high() {
         let mut noise = Noise::new();
         noise.write_timer_high(0xF8);
+        assert_eq!(noise.length_counter, 0);
+
+        noise.toggle(true);
+        noise.write_timer_high(0xF8);
         assert_eq!(noise.length_counter, LENGTH_TABLE[0x1F]);
-        assert_eq!(noise.envelope_volume, 15);
-        assert_eq!(noise.envelope_timer, noise.volume + 1);
+
+        noise.clock_envelope();
+        assert_eq!(noise.envelope.output(), 15);
     }
 
     #[test]
@@ -226,14 +237,6 @@ mod tests {
         assert_eq!(noise.length_counter, 4);
     }
 
-    #[test]
-    fn test_clock_envelope() {
-        let mut noise = Noise::new();
-        noise.envelope_timer = 5;
-        noise.clock_envelope();
-        assert_eq!(noise.envelope_timer, 4);
-    }
-
     #[test]
     fn test_length_counter() {
         let noise = Noise::new();
@@ -247,6 +250,6 @@ mod tests {
         noise.enabled = true;
         noise.length_counter = 5;
         noise.shift = 0;
-        assert_eq!(noise.output(), noise.envelope_volume);
+        assert_eq!(noise.output(), noise.envelope.output());
     }
 }
@@ -1,4 +1,7 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::sweep::{Channel, Sweep};
 use crate::apu::LENGTH_TABLE;
+use crate::state::snapshot;
 
 /// 0 - 0 1 0 0 0 0 0 0 (12.5%)
 /// 1 - 0 1 1 0 0 0 0 0 (25%)
@@ -7,12 +10,6 @@ use crate::apu::LENGTH_TABLE;
 /// Table of the different duty cycles
 const DUTY_TABLE: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
 
-/// Channel 1 or 2
-pub enum Channel {
-    One,
-    Two,
-}
-
 /// Represents the NES pulse (square) channel which generate a pulse wave with
 /// variable duty.
 pub struct Pulse {
@@ -26,54 +23,46 @@ pub struct Pulse {
     // in the duty cycle pattern.
     duty_phase: u8,
 
-    constant_volume: bool,
-    volume: u8,
-
     length_halt: bool,
     length_counter: u8,
 
-    sweep_enabled: bool,
-    sweep_period: u8,
-    sweep_negate: bool,
-    sweep_shift: u8,
-    sweep_timer: u8,
-
     timer: u16,
     timer_period: u16,
 
-    envelope_loop: bool,
-    envelope_period: u8,
-    envelope_timer: u8,
-    envelope_volume: u8,
+    envelope: Envelope,
+    sweep: Sweep,
 }
 
+snapshot!(
+    Pulse,
+    enabled,
+    duty_cycle in 0..4,
+    duty_phase in 0..8,
+    length_halt,
+    length_counter,
+    timer,
+    timer_period in 0..0x800,
+    envelope,
+    sweep
+);
+
 impl Pulse {
-    /// Creates a new Pulse struct.
-    pub fn new() -> Self {
+    /// Creates a new Pulse struct for the given channel.
+    pub fn new(channel: Channel) -> Self {
         Self {
             enabled: false,
 
             duty_cycle: 0,
             duty_phase: 0,
-            constant_volume: false,
-            volume: 0,
 
             length_halt: false,
             length_counter: 0,
 
-            sweep_enabled: false,
-            sweep_period: 0,
-            sweep_negate: false,
-            sweep_shift: 0,
-            sweep_timer: 0,
-
             timer: 0,
             timer_period: 0,
 
-            envelope_loop: false,
-            envelope_period: 0,
-            envelope_timer: 0,
-            envelope_volume: 0,
+            envelope: Envelope::new(),
+            sweep: Sweep::new(channel),
         }
     }
 
@@ -98,10 +87,7 @@ impl Pulse {
     pub fn write_volume(&mut self, data: u8) {
         self.duty_cycle = data >> 0x6;
         self.length_halt = (data & 0x20) != 0;
-        self.envelope_loop = self.length_halt;
-        self.constant_volume = (data & 0x10) != 0;
-        self.volume = data & 0xF;
-        self.envelope_period = self.volume;
+        self.envelope.write(data);
     }
 
     /// Sets the sweep unit used to manipulate the frequency of the pulse.
@@ -114,13 +100,7 @@ impl Pulse {
     /// N: Negate
     /// S: Shift
     pub fn write_sweep(&mut self, data: u8) {
-        self.sweep_enabled = (data & 0x80) != 0;
-        self.sweep_period = (data >> 0x4) & 7;
-        self.sweep_negate = (data & 0x8) != 0;
-        self.sweep_shift = data & 0x7;
-
-        // A write to this register reloads the sweep
-        self.sweep_timer = self.sweep_period + 1;
+        self.sweep.write(data);
     }
 
     /// Sets the timer low.
@@ -142,25 +122,32 @@ impl Pulse {
     /// T: Timer period high
     pub fn write_timer_high(&mut self, data: u8) {
         self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x7) << 8);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+
+        // The length counter is only loaded if the channel is enabled.
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
 
         // A write to this register reloads the length counter, restarts the
         // envelope, and resets the phase of the pulse generator.
         //
         // See: https://www.nesdev.org/wiki/APU#Pulse_($4000%E2%80%93$4007)
         self.duty_phase = 0;
-        self.envelope_volume = 15;
-        self.envelope_timer = self.envelope_period + 1;
+        self.envelope.restart();
     }
 
     /// Clocks the timer / divider.
+    ///
+    /// The timer counts down from the timer period, clocking the duty sequencer
+    /// when it reaches 0. This gives a sequencer step every (period + 1) APU
+    /// cycles.
     pub fn clock_timer(&mut self) {
         if self.timer > 0 {
             self.timer -= 1;
             return;
         }
 
-        self.timer = self.timer_period + 1;
+        self.timer = self.timer_period;
         self.duty_phase = (self.duty_phase + 1) % 8;
     }
 
@@ -172,37 +159,13 @@ impl Pulse {
     }
 
     /// Clocks the envelope.
-    ///
-    /// Depending on the nature of the envelope, it will either increment or
-    /// decrement the volume. This can be used to create a constant volume or
-    /// a increasing/decreasing volume.
     pub fn clock_envelope(&mut self) {
-        if self.envelope_timer > 0 {
-            self.envelope_timer -= 1;
-            return;
-        }
-
-        self.envelope_timer = self.envelope_period + 1;
-
-        if self.envelope_volume > 0 && !self.envelope_loop {
-            self.envelope_volume -= 1;
-        } else if self.envelope_volume < 15 && self.envelope_loop {
-            self.envelope_volume += 1;
-        }
+        self.envelope.clock();
     }
 
-    /// Clock the sweep unit which periodically adjusts the timer period.
-    pub fn clock_sweep(&mut self, chan: Channel) {
-        if self.sweep_timer > 0 {
-            self.sweep_timer -= 1;
-            return;
-        }
-
-        if self.sweep_enabled && self.timer_period > 7 && self.sweep_shift > 0 {
-            self.sweep(chan);
-        }
-
-        self.sweep_timer = self.sweep_period + 1;
+    /// Clocks the sweep unit which periodically adjusts the timer period.
+    pub fn clock_sweep(&mut self) {
+        self.timer_period = self.sweep.clock(self.timer_period);
     }
 
     /// Returns the output volume of the channel
@@ -212,71 +175,117 @@ impl Pulse {
         let duty = (dt & dp) != 0;
 
         if !self.enabled
-            || self.timer_period > 0x7FF
             || self.length_counter == 0
-            || self.timer_period < 8
+            || self.sweep.muting(self.timer_period)
             || !duty
         {
             return 0;
         }
 
-        match self.constant_volume {
-            true => self.volume,
-            false => self.envelope_volume,
-        }
+        self.envelope.output()
     }
 
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
         self.length_counter
     }
-
-    /// Adjusts the timer period based on the given channel.
-    fn sweep(&mut self, chan: Channel) {
-        let delta = self.timer_period >> self.sweep_shift;
-
-        self.timer_period = match self.sweep_negate {
-            true => match chan {
-                Channel::One => self.timer_period.wrapping_add(!delta),
-                Channel::Two => self.timer_period.wrapping_sub(delta),
-            },
-            false => self.timer_period.wrapping_add(delta),
-        };
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::{self, StateError};
 
     #[test]
     fn test_output() {
-        let mut pulse = Pulse::new();
+        let mut pulse = Pulse::new(Channel::One);
         pulse.enabled = true;
-        pulse.duty_cycle = 3;
+        pulse.write_volume(0xF5);
         pulse.duty_phase = 1;
-        pulse.length_halt = true;
-        pulse.timer_period = 0x7F0;
+        pulse.timer_period = 0x3F0;
         pulse.length_counter = 10;
-        pulse.constant_volume = true;
-        pulse.volume = 5;
         assert_eq!(pulse.output(), 5);
+
+        // The sweep unit mutes the channel when the target period overflows,
+        // even with a shift count of 0.
+        pulse.timer_period = 0x400;
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn test_write_timer_high() {
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.write_timer_high(0xFD);
+        assert_eq!(pulse.timer_period, 0x500);
+        assert_eq!(pulse.length_counter, 0);
+
+        pulse.toggle(true);
+        pulse.write_timer_high(0xFD);
+        assert_eq!(pulse.length_counter, LENGTH_TABLE[0x1F]);
+    }
+
+    #[test]
+    fn test_clock_timer() {
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.timer_period = 2;
+
+        // The sequencer is clocked every (period + 1) timer clocks.
+        for _ in 0..3 {
+            pulse.clock_timer();
+        }
+        assert_eq!(pulse.duty_phase, 1);
+
+        for _ in 0..3 {
+            pulse.clock_timer();
+        }
+        assert_eq!(pulse.duty_phase, 2);
     }
 
     #[test]
     fn test_length_counter() {
-        let mut pulse = Pulse::new();
+        let mut pulse = Pulse::new(Channel::One);
         pulse.length_counter = 10;
         assert_eq!(pulse.length_counter(), 10);
     }
 
     #[test]
     fn test_sweep() {
-        let mut pulse = Pulse::new();
+        let mut pulse = Pulse::new(Channel::One);
         pulse.timer_period = 100;
-        pulse.sweep_shift = 2;
-        pulse.sweep_negate = true;
-        pulse.sweep(Channel::One);
+        pulse.write_sweep(0x8A);
+        pulse.clock_sweep();
         assert_eq!(pulse.timer_period, 74);
     }
+
+    #[test]
+    fn test_envelope_restart() {
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.enabled = true;
+        pulse.length_counter = 10;
+        pulse.timer_period = 0x100;
+        pulse.write_volume(0xC2);
+
+        pulse.write_timer_high(0x01);
+        assert_eq!(pulse.output(), 0);
+
+        // The envelope restarts on the next quarter frame clock.
+        pulse.clock_envelope();
+        assert_eq!(pulse.output(), 15);
+    }
+
+    #[test]
+    fn test_corrupt_state() {
+        let mut corrupt = Pulse::new(Channel::One);
+        corrupt.duty_cycle = 4;
+        let state = state::save(&mut corrupt);
+
+        // A duty cycle past the end of the duty table isn't loaded.
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.write_volume(0x80);
+        assert_eq!(
+            state::load(&mut pulse, &state),
+            Err(StateError::InvalidValue)
+        );
+        assert_eq!(pulse.duty_cycle, 2);
+    }
 }
@@ -0,0 +1,84 @@
+use super::AudioChannel;
+
+/// Number of CPU cycles between each recorded sample, giving a sample rate of
+/// ~44.7kHz.
+const SAMPLE_INTERVAL: u32 = 40;
+
+/// Number of samples recorded per channel, ~46ms of audio.
+pub const SCOPE_SIZE: usize = 2048;
+
+/// Records the recent output of each audio channel before it is mixed, so
+/// that frontends can draw oscilloscope or piano roll visualizations.
+pub struct Scope {
+    buffers: [[f32; SCOPE_SIZE]; AudioChannel::ALL.len()],
+    position: usize,
+    cycles: u32,
+}
+
+impl Scope {
+    /// Creates a new scope, with silent history.
+    pub fn new() -> Self {
+        Scope {
+            buffers: [[0.0; SCOPE_SIZE]; AudioChannel::ALL.len()],
+            position: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Records the output of every channel, as returned by output, once every
+    /// sample interval. Outputs are normalised to the range 0.0 - 1.0.
+    pub fn clock(&mut self, output: impl Fn(AudioChannel) -> f32) {
+        self.cycles += 1;
+        if self.cycles < SAMPLE_INTERVAL {
+            return;
+        }
+        self.cycles = 0;
+
+        for channel in AudioChannel::ALL {
+            self.buffers[channel as usize][self.position] = output(channel);
+        }
+        self.position = (self.position + 1) % SCOPE_SIZE;
+    }
+
+    /// Returns the recorded history of a channel, from oldest to newest.
+    #[cfg(any(feature = "sdl", feature = "winit", test))]
+    pub fn history(&self, channel: AudioChannel) -> Vec<f32> {
+        let buffer = &self.buffers[channel as usize];
+
+        let mut history = Vec::with_capacity(SCOPE_SIZE);
+        history.extend_from_slice(&buffer[self.position..]);
+        history.extend_from_slice(&buffer[..self.position]);
+
+        history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut scope = Scope::new();
+
+        let mut level = 0.0;
+        for _ in 0..SCOPE_SIZE + 1 {
+            level += 1.0;
+            for _ in 0..SAMPLE_INTERVAL {
+                scope.clock(|channel| match channel {
+                    AudioChannel::Noise => level,
+                    _ => 0.0,
+                });
+            }
+        }
+
+        let history = scope.history(AudioChannel::Noise);
+        assert_eq!(history.len(), SCOPE_SIZE);
+        assert_eq!(history[0], 2.0);
+        assert_eq!(history[SCOPE_SIZE - 1], SCOPE_SIZE as f32 + 1.0);
+        assert!(scope
+            .history(AudioChannel::Pulse1)
+            .iter()
+            .all(|s| *s == 0.0));
+    }
+}
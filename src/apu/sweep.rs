@@ -0,0 +1,142 @@
+use crate::state::snapshot;
+
+/// Channel 1 or 2
+#[derive(Clone, Copy)]
+pub enum Channel {
+    One,
+    Two,
+}
+
+/// Represents the sweep unit of a pulse channel, which periodically adjusts
+/// the timer period of the channel up or down.
+///
+/// The sweep units of the two pulse channels differ in how they negate the
+/// change amount: pulse 1 uses one's complement and pulse 2 uses two's
+/// complement. Therefore pulse 1 sweeps down one step further than pulse 2.
+///
+/// See: https://www.nesdev.org/wiki/APU_Sweep
+pub struct Sweep {
+    channel: Channel,
+
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+
+    divider: u8,
+    reload: bool,
+}
+
+snapshot!(Sweep, enabled, period, negate, shift in 0..8, divider, reload);
+
+impl Sweep {
+    /// Creates a new sweep unit for the given channel.
+    pub fn new(channel: Channel) -> Self {
+        Sweep {
+            channel,
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+        }
+    }
+
+    /// Writes the sweep settings.
+    ///
+    /// Where data is equal to:
+    ///
+    /// EPPP NSSS
+    /// E: Enabled
+    /// P: Divider period
+    /// N: Negate
+    /// S: Shift count
+    pub fn write(&mut self, data: u8) {
+        self.enabled = data & 0x80 != 0;
+        self.period = (data >> 4) & 0x7;
+        self.negate = data & 0x8 != 0;
+        self.shift = data & 0x7;
+        self.reload = true;
+    }
+
+    /// Returns the timer period the sweep unit is targeting.
+    ///
+    /// This is continuously calculated, even if the sweep unit is disabled.
+    pub fn target_period(&self, timer_period: u16) -> u16 {
+        let delta = timer_period >> self.shift;
+
+        match (self.negate, self.channel) {
+            (false, _) => timer_period + delta,
+            (true, Channel::One) => timer_period.saturating_sub(delta + 1),
+            (true, Channel::Two) => timer_period.saturating_sub(delta),
+        }
+    }
+
+    /// Returns true if the sweep unit is muting the channel, either because
+    /// the current period is too low or the target period overflows.
+    pub fn muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target_period(timer_period) > 0x7FF
+    }
+
+    /// Clocks the sweep unit, on each half frame, returning the new timer
+    /// period of the channel.
+    pub fn clock(&mut self, timer_period: u16) -> u16 {
+        let mut period = timer_period;
+
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.muting(timer_period) {
+            period = self.target_period(timer_period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+
+        period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negate() {
+        let mut sweep1 = Sweep::new(Channel::One);
+        let mut sweep2 = Sweep::new(Channel::Two);
+        sweep1.write(0x8A);
+        sweep2.write(0x8A);
+
+        assert_eq!(sweep1.target_period(100), 74);
+        assert_eq!(sweep2.target_period(100), 75);
+    }
+
+    #[test]
+    fn test_muting() {
+        let mut sweep = Sweep::new(Channel::One);
+        assert!(sweep.muting(7));
+        assert!(!sweep.muting(0x3FF));
+
+        // The target period overflowing mutes the channel, even when the
+        // sweep unit is disabled.
+        assert!(sweep.muting(0x400));
+
+        sweep.write(0x01);
+        assert!(!sweep.muting(0x400));
+        assert!(sweep.muting(0x600));
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut sweep = Sweep::new(Channel::Two);
+        sweep.write(0x91);
+
+        // The period is adjusted every (divider period + 1) clocks.
+        assert_eq!(sweep.clock(0x100), 0x180);
+        assert_eq!(sweep.clock(0x180), 0x180);
+        assert_eq!(sweep.clock(0x180), 0x240);
+    }
+}
@@ -1,4 +1,5 @@
 use super::LENGTH_TABLE;
+use crate::state::snapshot;
 
 /// The sequencer sends the following looping 32-step sequence of values to the
 /// mixer.
@@ -24,6 +25,19 @@ pub struct Triangle {
     linear_counter: u8,
 }
 
+snapshot!(
+    Triangle,
+    enabled,
+    phase in 0..32,
+    timer_period,
+    timer,
+    counter_halt,
+    length_counter,
+    counter_reload,
+    counter_period,
+    linear_counter
+);
+
 impl Triangle {
     /// Creates a new Triangle register.
     pub fn new() -> Self {
@@ -88,7 +102,11 @@ impl Triangle {
     /// T: Timer period high
     pub fn write_timer_high(&mut self, data: u8) {
         self.timer_period = ((data & 0x7) as u16) << 8 | (self.timer_period & 0xFF);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+
+        // The length counter is only loaded if the channel is enabled.
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
         self.counter_reload = true;
     }
 
@@ -147,7 +165,7 @@ impl Triangle {
 
 #[cfg(test)]
 mod tests {
-    use crate::apu::triangle::OUTPUT_LEVELS;
+    use crate::apu::{triangle::OUTPUT_LEVELS, LENGTH_TABLE};
 
     use super::Triangle;
 
@@ -184,6 +202,19 @@ mod tests {
         assert_eq!(triangle.linear_counter, 0x0F);
     }
 
+    #[test]
+    fn test_write_timer_high() {
+        let mut triangle = Triangle::new();
+        triangle.write_timer_high(0xFF);
+        assert_eq!(triangle.timer_period, 0x700);
+        assert_eq!(triangle.length_counter, 0);
+
+        triangle.toggle(true);
+        triangle.write_timer_high(0xFF);
+        assert_eq!(triangle.length_counter, LENGTH_TABLE[0x1F]);
+        assert!(triangle.counter_reload);
+    }
+
     #[test]
     fn test_clock_length() {
         let mut triangle = Triangle::new();
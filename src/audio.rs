@@ -0,0 +1,405 @@
+mod resampler;
+mod ring_buffer;
+mod stems;
+mod wav;
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use resampler::Resampler;
+pub use ring_buffer::RingBuffer;
+use stems::StemRecorder;
+use wav::WavWriter;
+
+/// Rate at which the APU produces samples (the NTSC CPU clock rate) in Hz.
+pub const APU_SAMPLE_RATE: f64 = 1789773.0;
+
+/// Length of audio held in the output buffer, in seconds.
+const BUFFER_SECONDS: f64 = 0.5;
+
+/// Maximum adjustment made to the output sample rate by dynamic rate control.
+///
+/// A 0.5% deviation keeps the audio buffer in check without causing an
+/// audible change in pitch.
+#[cfg(any(feature = "sdl", feature = "winit", test))]
+pub const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Represents the audio output of the emulator.
+///
+/// The APU produces a sample every CPU cycle, which is resampled down to the
+/// sample rate requested by the frontend. The resampled audio is delivered to
+/// a callback, if one is set, or is held in a ring buffer for the frontend to
+/// read in either f32 or i16 format.
+///
+/// The resampled audio can also be recorded to a WAV file, and the individual
+/// channels to a set of WAV files (stems).
+pub struct AudioOutput<'a> {
+    clock_rate: f64,
+    sample_rate: u32,
+    resampler: Resampler,
+
+    // Speed of the emulation, which the output rate is divided by, and the
+    // adjustment made by dynamic rate control.
+    speed: f64,
+    rate_adjustment: f64,
+
+    buffer: RingBuffer,
+    callback: Option<Box<dyn FnMut(f32) + 'a>>,
+    recording: Option<WavWriter<BufWriter<File>>>,
+    stems: Option<StemRecorder>,
+
+    // The audio of a video recording, resampled at the nominal rate so it
+    // keeps in time with the frames.
+    video: Option<StemRecorder>,
+}
+
+impl<'a> AudioOutput<'a> {
+    /// Creates a new audio output with the given sample rate, in Hz.
+    pub fn new(sample_rate: f64) -> Self {
+        AudioOutput {
+            clock_rate: APU_SAMPLE_RATE,
+            sample_rate: sample_rate as u32,
+            resampler: Resampler::new(APU_SAMPLE_RATE, sample_rate),
+            speed: 1.0,
+            rate_adjustment: 1.0,
+            buffer: RingBuffer::new((sample_rate * BUFFER_SECONDS) as usize),
+            callback: None,
+            recording: None,
+            stems: None,
+            video: None,
+        }
+    }
+
+    /// Sets the rate at which the APU produces samples, in Hz, which is the
+    /// CPU clock rate of the console region.
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.resampler = Resampler::new(clock_rate, self.sample_rate as f64);
+        self.update_rate();
+    }
+
+    /// Sets the speed of the emulation, relative to the console, so the APU
+    /// samples of each second emulated are resampled to a second divided by
+    /// the speed. No audio is output at infinite speed.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.update_rate();
+    }
+
+    /// Sets a callback which receives each resampled sample, instead of it
+    /// being buffered.
+    // None of the built-in frontends take samples by callback.
+    #[allow(dead_code)]
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(f32) + 'a,
+    {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Adjusts the resampling rate based on the fill level of the frontend's
+    /// audio buffer, from 0.0 (empty) to 1.0 (full).
+    ///
+    /// The emulator runs at the video refresh rate, which never exactly
+    /// matches the audio clock. Dynamic rate control makes small adjustments
+    /// to the resampling rate to keep the buffer half full, so it neither
+    /// underruns nor accumulates latency over long sessions.
+    ///
+    /// See: https://docs.libretro.com/development/cores/dynamic-rate-control/
+    #[cfg(any(feature = "sdl", feature = "winit", test))]
+    pub fn update_fill_level(&mut self, fill: f64) {
+        let direction = 1.0 - 2.0 * fill.clamp(0.0, 1.0);
+        self.rate_adjustment = 1.0 + MAX_RATE_DELTA * direction;
+        self.update_rate();
+    }
+
+    /// Applies the speed and dynamic rate control to the resampling rate.
+    fn update_rate(&mut self) {
+        self.resampler
+            .set_rate_adjustment(self.rate_adjustment / self.speed);
+    }
+
+    /// Starts recording the audio output to a WAV file at the given path,
+    /// replacing any recording in progress.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.stop_recording()?;
+
+        let file = BufWriter::new(File::create(path)?);
+        self.recording = Some(WavWriter::new(file, self.sample_rate)?);
+
+        Ok(())
+    }
+
+    /// Stops recording the audio output, finalizing the WAV file.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        if let Some(recording) = self.recording.take() {
+            recording.finalize()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the audio output is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording each channel to its own WAV file at the given paths,
+    /// replacing any stem recording in progress.
+    pub fn start_stem_recording(&mut self, paths: &[PathBuf]) -> io::Result<()> {
+        self.stop_stem_recording()?;
+        self.stems = Some(StemRecorder::new(paths, self.clock_rate, self.sample_rate)?);
+
+        Ok(())
+    }
+
+    /// Stops recording the channel stems, finalizing the WAV files.
+    pub fn stop_stem_recording(&mut self) -> io::Result<()> {
+        if let Some(stems) = self.stems.take() {
+            stems.finalize()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the channel stems are being recorded.
+    pub fn is_recording_stems(&self) -> bool {
+        self.stems.is_some()
+    }
+
+    /// Starts recording the audio of a video to a WAV file at the given
+    /// path, at the nominal sample rate whatever the speed and dynamic rate
+    /// control, replacing any in progress.
+    pub fn start_video_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_video_recording()?;
+        let paths = [path.to_path_buf()];
+        self.video = Some(StemRecorder::new(
+            &paths,
+            self.clock_rate,
+            self.sample_rate,
+        )?);
+
+        Ok(())
+    }
+
+    /// Stops recording the audio of a video, finalizing the WAV file.
+    pub fn stop_video_recording(&mut self) -> io::Result<()> {
+        if let Some(video) = self.video.take() {
+            video.finalize()?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a sample from the APU for each channel being recorded as a
+    /// stem.
+    pub fn push_stems(&mut self, samples: &[f32]) {
+        if let Some(stems) = self.stems.as_mut() {
+            if let Err(err) = stems.push(samples) {
+                eprintln!("Stopped stem recording: {}", err);
+                self.stems = None;
+            }
+        }
+    }
+
+    /// Pushes a sample from the APU.
+    pub fn push(&mut self, sample: f32) {
+        if let Some(video) = self.video.as_mut() {
+            if let Err(err) = video.push(&[sample]) {
+                eprintln!("Stopped recording the audio of the video: {}", err);
+                self.video = None;
+            }
+        }
+
+        if self.speed.is_infinite() {
+            return;
+        }
+
+        let Some(sample) = self.resampler.push(sample) else {
+            return;
+        };
+
+        if let Some(recording) = self.recording.as_mut() {
+            if let Err(err) = recording.write_sample(sample) {
+                eprintln!("Stopped audio recording: {}", err);
+                self.recording = None;
+            }
+        }
+
+        match self.callback.as_mut() {
+            Some(callback) => callback(sample),
+            None => self.buffer.push(sample),
+        }
+    }
+
+    /// Returns the number of buffered samples.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns true if there are no buffered samples.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len() == 0
+    }
+
+    /// Reads buffered samples into out, returning the number of samples read.
+    pub fn read_f32(&mut self, out: &mut [f32]) -> usize {
+        self.read(out, |sample| sample)
+    }
+
+    /// Reads buffered samples into out as signed 16-bit integers, returning
+    /// the number of samples read.
+    // None of the built-in frontends play i16 samples.
+    #[allow(dead_code)]
+    pub fn read_i16(&mut self, out: &mut [i16]) -> usize {
+        self.read(out, |sample| {
+            (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+        })
+    }
+
+    /// Drains all of the buffered samples.
+    pub fn drain(&mut self) -> Vec<f32> {
+        let mut samples = vec![0.0; self.buffer.len()];
+        self.read_f32(&mut samples);
+        samples
+    }
+
+    /// Reads buffered samples into out, converting them to the output format.
+    fn read<T>(&mut self, out: &mut [T], convert: impl Fn(f32) -> T) -> usize {
+        let mut read = 0;
+
+        for slot in out.iter_mut() {
+            match self.buffer.pop() {
+                Some(sample) => *slot = convert(sample),
+                None => break,
+            }
+            read += 1;
+        }
+
+        read
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Number of APU samples in a frame, rounded up.
+    const FRAME_SAMPLES: usize = 29830;
+
+    #[test]
+    fn test_buffered_output() {
+        let mut audio = AudioOutput::new(44100.0);
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.5);
+        }
+
+        assert_eq!(audio.len(), 735);
+
+        let mut out = [0i16; 1024];
+        assert_eq!(audio.read_i16(&mut out), 735);
+        assert_eq!(out[0], i16::MAX / 2);
+        assert!(audio.is_empty());
+    }
+
+    #[test]
+    fn test_dynamic_rate_control() {
+        let mut audio = AudioOutput::new(44100.0);
+
+        // An empty buffer produces more samples, a full buffer fewer.
+        audio.update_fill_level(0.0);
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.0);
+        }
+        assert!(audio.len() > 735);
+
+        audio.drain();
+        audio.update_fill_level(1.0);
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.0);
+        }
+        assert!(audio.len() < 735);
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut audio = AudioOutput::new(44100.0);
+        audio.set_speed(2.0);
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.0);
+        }
+        assert_eq!(audio.len(), 367);
+
+        audio.drain();
+        audio.set_speed(f64::INFINITY);
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.0);
+        }
+        assert!(audio.is_empty());
+    }
+
+    #[test]
+    fn test_recording() {
+        let path = std::env::temp_dir().join("res_test_recording.wav");
+
+        let mut audio = AudioOutput::new(44100.0);
+        audio.start_recording(&path).unwrap();
+        assert!(audio.is_recording());
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.0);
+        }
+        audio.stop_recording().unwrap();
+        assert!(!audio.is_recording());
+
+        // Recorded samples are still delivered to the frontend.
+        assert_eq!(audio.len(), 735);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 735 * 2);
+    }
+
+    #[test]
+    fn test_stem_recording() {
+        let paths: Vec<PathBuf> = ["a", "b"]
+            .iter()
+            .map(|name| std::env::temp_dir().join(format!("res_test_stem_{}.wav", name)))
+            .collect();
+
+        let mut audio = AudioOutput::new(44100.0);
+        audio.start_stem_recording(&paths).unwrap();
+        assert!(audio.is_recording_stems());
+        for _ in 0..FRAME_SAMPLES {
+            audio.push_stems(&[0.0, 1.0]);
+        }
+        audio.stop_stem_recording().unwrap();
+        assert!(!audio.is_recording_stems());
+
+        for (path, level) in paths.iter().zip([0, i16::MAX]) {
+            let bytes = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(bytes.len(), 44 + 735 * 2);
+            assert_eq!(&bytes[44..46], &level.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_callback() {
+        let received = RefCell::new(Vec::new());
+
+        let mut audio = AudioOutput::new(48000.0);
+        audio.set_callback(|sample| received.borrow_mut().push(sample));
+        for _ in 0..FRAME_SAMPLES {
+            audio.push(0.25);
+        }
+
+        assert!(audio.is_empty());
+        assert_eq!(received.borrow().len(), 800);
+        assert!(received.borrow().iter().all(|s| *s == 0.25));
+    }
+}
@@ -0,0 +1,83 @@
+/// Resamples a signal from a high input rate down to a lower output rate.
+///
+/// Each output sample is the average of the input samples in its period (a
+/// box filter), which is cheap and avoids the worst of the aliasing caused
+/// by simply dropping samples.
+pub struct Resampler {
+    // Fraction of an output sample covered by each input sample, and the
+    // nominal value before any rate adjustment.
+    step: f64,
+    base_step: f64,
+    phase: f64,
+
+    sum: f32,
+    count: u32,
+}
+
+impl Resampler {
+    /// Creates a new resampler converting from the input rate to the output
+    /// rate, in Hz.
+    pub fn new(input_rate: f64, output_rate: f64) -> Self {
+        Resampler {
+            step: output_rate / input_rate,
+            base_step: output_rate / input_rate,
+            phase: 0.0,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Adjusts the output rate by the given ratio, relative to the nominal
+    /// output rate. A ratio above 1.0 produces more output samples.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.step = self.base_step * ratio;
+    }
+
+    /// Pushes an input sample, returning an output sample once enough input
+    /// samples have been accumulated.
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        self.sum += sample;
+        self.count += 1;
+        self.phase += self.step;
+
+        if self.phase < 1.0 {
+            return None;
+        }
+
+        self.phase -= 1.0;
+        let output = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        let mut resampler = Resampler::new(1789773.0, 44100.0);
+        let outputs = (0..1789773).filter_map(|_| resampler.push(0.0)).count();
+        assert!((44099..=44100).contains(&outputs));
+    }
+
+    #[test]
+    fn test_rate_adjustment() {
+        let mut resampler = Resampler::new(1789773.0, 44100.0);
+        resampler.set_rate_adjustment(1.01);
+        let outputs = (0..1789773).filter_map(|_| resampler.push(0.0)).count();
+        assert!((44540..=44541).contains(&outputs));
+    }
+
+    #[test]
+    fn test_average() {
+        let mut resampler = Resampler::new(4.0, 1.0);
+        assert_eq!(resampler.push(1.0), None);
+        assert_eq!(resampler.push(0.0), None);
+        assert_eq!(resampler.push(1.0), None);
+        assert_eq!(resampler.push(0.0), Some(0.5));
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+/// A fixed capacity FIFO buffer of audio samples.
+///
+/// When the buffer is full the oldest samples are dropped, so a frontend that
+/// stops consuming audio never causes unbounded memory growth.
+pub struct RingBuffer {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    /// Creates a new ring buffer holding up to capacity samples.
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Pushes a sample, dropping the oldest sample if the buffer is full.
+    pub fn push(&mut self, sample: f32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    /// Pops the oldest sample from the buffer.
+    pub fn pop(&mut self) -> Option<f32> {
+        self.samples.pop_front()
+    }
+
+    /// Returns the number of buffered samples.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.push(0.1);
+        buffer.push(0.2);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop(), Some(0.1));
+        assert_eq!(buffer.pop(), Some(0.2));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut buffer = RingBuffer::new(2);
+        buffer.push(0.1);
+        buffer.push(0.2);
+        buffer.push(0.3);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop(), Some(0.2));
+    }
+}
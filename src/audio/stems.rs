@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+use super::resampler::Resampler;
+use super::wav::WavWriter;
+
+/// Records a set of audio channels to their own WAV files (stems).
+///
+/// Each channel is resampled separately, at the nominal output rate, so the
+/// stems stay aligned with each other.
+pub struct StemRecorder {
+    stems: Vec<(Resampler, WavWriter<BufWriter<File>>)>,
+}
+
+impl StemRecorder {
+    /// Creates a new stem recorder, writing one WAV file per path, resampling
+    /// from the APU clock rate to the sample rate.
+    pub fn new(paths: &[PathBuf], clock_rate: f64, sample_rate: u32) -> io::Result<Self> {
+        let mut stems = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let file = BufWriter::new(File::create(path)?);
+            stems.push((
+                Resampler::new(clock_rate, sample_rate as f64),
+                WavWriter::new(file, sample_rate)?,
+            ));
+        }
+
+        Ok(StemRecorder { stems })
+    }
+
+    /// Pushes a sample from the APU for each channel, in the same order as
+    /// the paths given on creation.
+    pub fn push(&mut self, samples: &[f32]) -> io::Result<()> {
+        for ((resampler, wav), sample) in self.stems.iter_mut().zip(samples) {
+            if let Some(sample) = resampler.push(*sample) {
+                wav.write_sample(sample)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finalizes all of the WAV files.
+    pub fn finalize(self) -> io::Result<()> {
+        for (_, wav) in self.stems {
+            wav.finalize()?;
+        }
+
+        Ok(())
+    }
+}
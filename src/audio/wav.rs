@@ -0,0 +1,99 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Size of the RIFF/WAVE header in bytes.
+const HEADER_SIZE: u32 = 44;
+
+/// Writes mono 16-bit PCM audio in the WAV format.
+///
+/// The header is written up front with empty sizes, which are filled in once
+/// the writer is finalized.
+///
+/// See: http://soundfile.sapp.org/doc/WaveFormat/
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a new WAV writer with the given sample rate, writing the
+    /// header to out.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&bits_per_sample.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, data_size: 0 })
+    }
+
+    /// Writes a sample, converting it to 16-bit PCM.
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.out.write_all(&sample.to_le_bytes())?;
+        self.data_size += 2;
+
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and flushes the output, returning
+    /// the underlying writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_size.to_le_bytes())?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_sample(0.0).unwrap();
+        wav.write_sample(1.0).unwrap();
+        let bytes = wav.finalize().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &40u32.to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(&bytes[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(&bytes[40..44], &4u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_sample() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_sample(1.0).unwrap();
+        wav.write_sample(-2.0).unwrap();
+        let bytes = wav.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[44..46], &i16::MAX.to_le_bytes());
+        assert_eq!(&bytes[46..48], &(-i16::MAX).to_le_bytes());
+    }
+}
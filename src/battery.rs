@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::cartridge::Cartridge;
+
+/// How often battery RAM that has changed is written to the save file.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps the battery RAM of a cartridge in a save file, so the game is saved
+/// between runs.
+///
+/// The RAM is written whenever it has changed, every few seconds and when
+/// the backup is dropped (including when unwinding from a panic), so at most
+/// a few seconds of progress are lost if the emulator is killed. The save
+/// file is replaced in one step, so it's never left half written.
+pub struct BatteryBackup {
+    cart: Rc<RefCell<Cartridge>>,
+    path: PathBuf,
+
+    /// The RAM as last written to the save file.
+    saved: Vec<u8>,
+    last_flush: Instant,
+}
+
+impl BatteryBackup {
+    /// Returns the backup of the cartridge's battery RAM in the save file at
+    /// the given path, or none if the cartridge isn't battery-backed.
+    pub fn new<P: AsRef<Path>>(cart: Rc<RefCell<Cartridge>>, path: P) -> Option<Self> {
+        let saved = cart.borrow_mut().battery_ram()?.to_vec();
+
+        Some(BatteryBackup {
+            cart,
+            path: path.as_ref().to_path_buf(),
+            saved,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Returns the path of the save file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the save file into the battery RAM, returning false if there's
+    /// no save file yet.
+    pub fn load(&mut self) -> io::Result<bool> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        let mut cart = self.cart.borrow_mut();
+        let ram = cart.battery_ram().expect("cartridge has battery RAM");
+        if data.len() != ram.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {} bytes, found {}", ram.len(), data.len()),
+            ));
+        }
+
+        ram.copy_from_slice(&data);
+        self.saved = data;
+        Ok(true)
+    }
+
+    /// Writes the battery RAM to the save file if it has changed since it
+    /// was last written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+
+        // The cartridge may still be borrowed when unwinding from a panic.
+        let Ok(mut cart) = self.cart.try_borrow_mut() else {
+            return Ok(());
+        };
+        let ram = cart.battery_ram().expect("cartridge has battery RAM");
+        if ram == self.saved.as_slice() {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Writing a new file and renaming it over the old one means a crash
+        // part way through leaves the last save intact.
+        let temp_path = self.path.with_extension("sav.tmp");
+        std::fs::write(&temp_path, &*ram)?;
+        std::fs::rename(&temp_path, &self.path)?;
+
+        self.saved.copy_from_slice(ram);
+        Ok(())
+    }
+
+    /// Writes the battery RAM to the save file if it has changed and it's
+    /// been long enough since it was last written. Called once per frame.
+    pub fn update(&mut self) -> io::Result<()> {
+        match self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Drop for BatteryBackup {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            eprintln!("Failed to save {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an NROM cartridge, with battery-backed PRG RAM if given.
+    fn cartridge(battery: bool) -> Rc<RefCell<Cartridge>> {
+        let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 1, (battery as u8) << 1];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        Rc::new(RefCell::new(Cartridge::from_bytes(&raw).unwrap()))
+    }
+
+    #[test]
+    fn test_no_battery() {
+        assert!(BatteryBackup::new(cartridge(false), "Zelda.sav").is_none());
+    }
+
+    #[test]
+    fn test_flush_load() {
+        let path = std::env::temp_dir().join("res_test_battery.sav");
+        let _ = std::fs::remove_file(&path);
+
+        let cart = cartridge(true);
+        let mut backup = BatteryBackup::new(Rc::clone(&cart), &path).unwrap();
+        assert!(!backup.load().unwrap());
+
+        // Nothing is written until the RAM changes.
+        backup.flush().unwrap();
+        assert!(!path.exists());
+        cart.borrow_mut().cpu_write(0x6001, 0x42);
+        backup.update().unwrap();
+        assert!(!path.exists());
+        backup.flush().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[..2], [0x00, 0x42]);
+
+        // Dropping the backup writes any change.
+        cart.borrow_mut().cpu_write(0x7FFF, 0x24);
+        drop(backup);
+        assert_eq!(std::fs::read(&path).unwrap()[0x1FFF], 0x24);
+
+        let cart = cartridge(true);
+        let mut backup = BatteryBackup::new(Rc::clone(&cart), &path).unwrap();
+        assert!(backup.load().unwrap());
+        assert_eq!(cart.borrow_mut().cpu_read(0x6001), 0x42);
+        assert_eq!(cart.borrow_mut().cpu_read(0x7FFF), 0x24);
+
+        std::fs::write(&path, [0; 16]).unwrap();
+        assert!(backup.load().is_err());
+        drop(backup);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
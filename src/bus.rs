@@ -1,4 +1,8 @@
+mod clock;
+mod dma;
+pub mod hooks;
 mod ppu;
+pub mod register_log;
 mod system;
 
 pub use ppu::Memory;
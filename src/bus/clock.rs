@@ -0,0 +1,119 @@
+use crate::region::Region;
+use crate::state::snapshot;
+
+/// A component clocked by the master clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockEvent {
+    /// A PPU cycle (dot).
+    Ppu,
+
+    /// A CPU cycle, which also clocks the APU and cartridge.
+    Cpu,
+}
+
+/// Represents the master clock of the console, which the CPU and PPU clocks
+/// are divided from.
+///
+/// The clock steps from one component's cycle to the next in the order they
+/// happen, so the PPU runs 3 dots per CPU cycle on NTSC and Dendy consoles
+/// and 3.2 on PAL consoles, with the remainder carried over to the next CPU
+/// cycle.
+///
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart
+pub struct MasterClock {
+    region: Region,
+    cpu_cycles: u64,
+
+    /// Master clock cycles until the next CPU and PPU cycles.
+    cpu_countdown: u8,
+    ppu_countdown: u8,
+}
+
+snapshot!(MasterClock, cpu_cycles, cpu_countdown, ppu_countdown);
+
+impl MasterClock {
+    /// Returns a new master clock, with the NTSC dividers.
+    pub fn new() -> Self {
+        let region = Region::Ntsc;
+        MasterClock {
+            region,
+            cpu_cycles: 0,
+            cpu_countdown: region.cpu_divider(),
+            ppu_countdown: region.ppu_divider(),
+        }
+    }
+
+    /// Sets the console region, which determines the dividers.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.cpu_countdown = region.cpu_divider();
+        self.ppu_countdown = region.ppu_divider();
+    }
+
+    /// Returns the console region.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the number of CPU cycles since power on.
+    pub fn cpu_cycles(&self) -> u64 {
+        self.cpu_cycles
+    }
+
+    /// Advances the master clock to the next component cycle, returning the
+    /// component to clock. When the cycles line up, the PPU is clocked first.
+    pub fn next(&mut self) -> ClockEvent {
+        let elapsed = self.cpu_countdown.min(self.ppu_countdown);
+        self.cpu_countdown -= elapsed;
+        self.ppu_countdown -= elapsed;
+
+        if self.ppu_countdown == 0 {
+            self.ppu_countdown = self.region.ppu_divider();
+            return ClockEvent::Ppu;
+        }
+
+        self.cpu_countdown = self.region.cpu_divider();
+        self.cpu_cycles += 1;
+        ClockEvent::Cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the number of PPU cycles in each of the next CPU cycles.
+    fn ppu_cycles(clock: &mut MasterClock, cpu_cycles: usize) -> Vec<u8> {
+        let mut counts = vec![0; cpu_cycles];
+        for count in counts.iter_mut() {
+            while clock.next() == ClockEvent::Ppu {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_ntsc() {
+        let mut clock = MasterClock::new();
+        assert_eq!(ppu_cycles(&mut clock, 4), [3, 3, 3, 3]);
+        assert_eq!(clock.cpu_cycles(), 4);
+    }
+
+    #[test]
+    fn test_pal() {
+        let mut clock = MasterClock::new();
+        clock.set_region(Region::Pal);
+
+        // 16 dots every 5 CPU cycles.
+        assert_eq!(ppu_cycles(&mut clock, 5), [3, 3, 3, 3, 4]);
+        assert_eq!(ppu_cycles(&mut clock, 5), [3, 3, 3, 3, 4]);
+    }
+
+    #[test]
+    fn test_dendy() {
+        let mut clock = MasterClock::new();
+        clock.set_region(Region::Dendy);
+        assert_eq!(ppu_cycles(&mut clock, 3), [3, 3, 3]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::state::snapshot;
+
+/// Number of bytes copied to OAM by an OAM DMA.
+const OAM_DMA_BYTES: u16 = 256;
+
+/// Number of cycles the DMC DMA waits before it can read: the cycle which
+/// halts the CPU, then a dummy cycle.
+const DMC_DMA_WAIT: u8 = 2;
+
+/// Number of cycles the OAM DMA waits before it can read: the cycle which
+/// halts the CPU.
+const OAM_DMA_WAIT: u8 = 1;
+
+/// What the DMA unit does on a CPU cycle, while the CPU is halted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DmaCycle {
+    /// Nothing is transferred, such as while halting the CPU or aligning to a
+    /// get cycle.
+    Halt,
+
+    /// Reads the byte at the given address for OAM, which must be passed back
+    /// with set_oam_data.
+    OamRead(u16),
+
+    /// Writes the given byte to OAM ($2004).
+    OamWrite(u8),
+
+    /// Reads the next DMC sample.
+    DmcRead,
+}
+
+/// Represents an OAM DMA in progress.
+#[derive(Default)]
+struct OamTransfer {
+    page: u8,
+    index: u16,
+    wait: u8,
+
+    /// The byte read on the last get cycle, waiting for a put cycle.
+    data: Option<u8>,
+}
+
+snapshot!(OamTransfer, page, index in 0..OAM_DMA_BYTES, wait, data);
+
+/// Represents the DMA unit of the 2A03, which halts the CPU to copy a page of
+/// memory to OAM (written to $4014) or to fetch DMC samples.
+///
+/// The CPU cycles alternate between get cycles, on which the DMA unit can
+/// read, and put cycles, on which it can write. Each DMA first halts the CPU
+/// and then waits for a get cycle to read, so the number of cycles stalled
+/// depends on the alignment:
+///
+///   - OAM DMA takes 513 cycles, or 514 if it is halted on a get cycle.
+///   - DMC DMA takes 3 cycles, or 4 if it is halted on a put cycle.
+///
+/// When both overlap, the DMC read takes priority on a get cycle and the OAM
+/// DMA realigns afterwards, typically adding 2 cycles to the OAM DMA.
+///
+/// See: https://www.nesdev.org/wiki/DMA
+pub struct Dma {
+    oam: Option<OamTransfer>,
+
+    /// Number of cycles before a pending DMC DMA can read, None if no sample
+    /// is needed.
+    dmc_wait: Option<u8>,
+}
+
+snapshot!(Dma, oam, dmc_wait);
+
+impl Dma {
+    /// Returns a new, idle DMA unit.
+    pub fn new() -> Self {
+        Dma {
+            oam: None,
+            dmc_wait: None,
+        }
+    }
+
+    /// Starts an OAM DMA copying the given page ($XX00-$XXFF) to OAM.
+    pub fn start_oam(&mut self, page: u8) {
+        self.oam = Some(OamTransfer {
+            page,
+            index: 0,
+            wait: OAM_DMA_WAIT,
+            data: None,
+        });
+    }
+
+    /// Requests a DMC DMA to fetch the next sample, unless one is pending.
+    pub fn request_dmc(&mut self) {
+        self.dmc_wait.get_or_insert(DMC_DMA_WAIT);
+    }
+
+    /// Returns true if a DMA is halting the CPU.
+    pub fn active(&self) -> bool {
+        self.oam.is_some() || self.dmc_wait.is_some()
+    }
+
+    /// Passes the byte read for an OamRead cycle back to the OAM DMA.
+    pub fn set_oam_data(&mut self, data: u8) {
+        if let Some(oam) = &mut self.oam {
+            oam.data = Some(data);
+        }
+    }
+
+    /// Returns what the DMA unit does on the next CPU cycle, given whether it
+    /// is a get cycle.
+    pub fn step(&mut self, get: bool) -> DmaCycle {
+        if get && self.dmc_wait == Some(0) {
+            self.dmc_wait = None;
+            return DmaCycle::DmcRead;
+        }
+
+        // The DMC DMA halts the CPU alongside any OAM DMA, so its wait
+        // overlaps the OAM transfer.
+        if let Some(wait) = &mut self.dmc_wait {
+            *wait = wait.saturating_sub(1);
+        }
+
+        let Some(oam) = &mut self.oam else {
+            return DmaCycle::Halt;
+        };
+
+        if oam.wait > 0 {
+            oam.wait -= 1;
+            return DmaCycle::Halt;
+        }
+
+        match (oam.data.take(), get) {
+            (None, true) => DmaCycle::OamRead((oam.page as u16) << 8 | oam.index),
+            (Some(data), false) => {
+                oam.index += 1;
+                if oam.index == OAM_DMA_BYTES {
+                    self.oam = None;
+                }
+
+                DmaCycle::OamWrite(data)
+            }
+
+            // Waits for the next get cycle to read, or put cycle to write.
+            (data, _) => {
+                oam.data = data;
+                DmaCycle::Halt
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the DMA until it stops halting the CPU, starting on a get cycle
+    /// if get is true. Calls request on each cycle, to start further DMAs.
+    /// Returns the number of cycles taken and the cycles which transferred
+    /// data.
+    fn run_dma<F>(dma: &mut Dma, mut get: bool, mut request: F) -> (u32, Vec<DmaCycle>)
+    where
+        F: FnMut(&mut Dma, u32),
+    {
+        let mut cycles = 0;
+        let mut transfers = vec![];
+
+        while dma.active() {
+            request(dma, cycles);
+
+            let cycle = dma.step(get);
+            if let DmaCycle::OamRead(addr) = cycle {
+                dma.set_oam_data(addr as u8);
+            }
+            if cycle != DmaCycle::Halt {
+                transfers.push(cycle);
+            }
+
+            cycles += 1;
+            get = !get;
+        }
+
+        (cycles, transfers)
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        for (get, cycles) in [(false, 513), (true, 514)] {
+            let mut dma = Dma::new();
+            dma.start_oam(0x02);
+
+            let (taken, transfers) = run_dma(&mut dma, get, |_, _| {});
+            assert_eq!(taken, cycles);
+            assert_eq!(transfers.len(), 512);
+            assert_eq!(transfers[0], DmaCycle::OamRead(0x0200));
+            assert_eq!(transfers[1], DmaCycle::OamWrite(0x00));
+            assert_eq!(transfers[511], DmaCycle::OamWrite(0xFF));
+        }
+    }
+
+    #[test]
+    fn test_dmc_dma() {
+        for (get, cycles) in [(true, 3), (false, 4)] {
+            let mut dma = Dma::new();
+            dma.request_dmc();
+
+            let (taken, transfers) = run_dma(&mut dma, get, |_, _| {});
+            assert_eq!(taken, cycles);
+            assert_eq!(transfers, [DmaCycle::DmcRead]);
+        }
+    }
+
+    #[test]
+    fn test_dmc_dma_during_oam_dma() {
+        let mut dma = Dma::new();
+        dma.start_oam(0x02);
+
+        let (taken, transfers) = run_dma(&mut dma, false, |dma, cycle| {
+            if cycle == 100 {
+                dma.request_dmc();
+            }
+        });
+        assert_eq!(taken, 513 + 2);
+        assert_eq!(transfers.len(), 513);
+
+        // The DMC read takes a get cycle between two OAM transfers.
+        let dmc = transfers
+            .iter()
+            .position(|&cycle| cycle == DmaCycle::DmcRead)
+            .unwrap();
+        assert!(matches!(transfers[dmc - 1], DmaCycle::OamWrite(_)));
+        assert!(matches!(transfers[dmc + 1], DmaCycle::OamRead(_)));
+    }
+}
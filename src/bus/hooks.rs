@@ -0,0 +1,174 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// The kind of memory access a hook observes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "R"),
+            Access::Write => write!(f, "W"),
+        }
+    }
+}
+
+/// Represents an error parsing a memory watch.
+#[derive(Debug, PartialEq)]
+pub enum WatchError {
+    /// The watch isn't in the form AAAA, AAAA-BBBB, AAAA:r or AAAA-BBBB:r.
+    InvalidFormat,
+
+    /// The range ends before it starts.
+    EmptyRange,
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::InvalidFormat => {
+                write!(
+                    f,
+                    "watch must be in the form AAAA or AAAA-BBBB, with :r for reads"
+                )
+            }
+            WatchError::EmptyRange => write!(f, "watch range ends before it starts"),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+/// Represents a range of addresses whose writes, or reads, are printed as
+/// they're made, for debugging.
+///
+/// Watches are written as a hex address or range, AAAA or AAAA-BBBB, with a
+/// :r suffix to watch reads rather than writes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryWatch {
+    pub access: Access,
+    pub range: RangeInclusive<u16>,
+}
+
+impl FromStr for MemoryWatch {
+    type Err = WatchError;
+
+    fn from_str(watch: &str) -> Result<Self, Self::Err> {
+        let (range, access) = match watch.trim().split_once(':') {
+            None => (watch.trim(), Access::Write),
+            Some((range, "r" | "R")) => (range, Access::Read),
+            Some(_) => return Err(WatchError::InvalidFormat),
+        };
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+
+        let addr = |addr: &str| match addr.len() {
+            4 => u16::from_str_radix(addr, 16).map_err(|_| WatchError::InvalidFormat),
+            _ => Err(WatchError::InvalidFormat),
+        };
+        let (start, end) = (addr(start)?, addr(end)?);
+        if end < start {
+            return Err(WatchError::EmptyRange);
+        }
+
+        Ok(MemoryWatch {
+            access,
+            range: start..=end,
+        })
+    }
+}
+
+/// Callback given the address and data of each access.
+type HookFn<'a> = Box<dyn FnMut(u16, u8) + 'a>;
+
+/// Represents a callback observing accesses to a range of addresses.
+struct Hook<'a> {
+    access: Access,
+    range: RangeInclusive<u16>,
+    callback: HookFn<'a>,
+}
+
+/// Observers of the memory accesses made on the CPU bus, for tools such as
+/// achievement trackers, auto-splitters and scripts.
+///
+/// Hooks are called after each access and can't change its outcome.
+pub struct MemoryHooks<'a> {
+    hooks: Vec<Hook<'a>>,
+}
+
+impl<'a> MemoryHooks<'a> {
+    /// Returns an empty set of hooks.
+    pub fn new() -> Self {
+        MemoryHooks { hooks: vec![] }
+    }
+
+    /// Adds a hook, called with the address and data of each access of the
+    /// given kind to an address in range.
+    pub fn add<F>(&mut self, access: Access, range: RangeInclusive<u16>, callback: F)
+    where
+        F: FnMut(u16, u8) + 'a,
+    {
+        self.hooks.push(Hook {
+            access,
+            range,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Calls the hooks observing the given access.
+    pub fn notify(&mut self, access: Access, addr: u16, data: u8) {
+        for hook in &mut self.hooks {
+            if hook.access == access && hook.range.contains(&addr) {
+                (hook.callback)(addr, data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let accesses = RefCell::new(vec![]);
+
+        let mut hooks = MemoryHooks::new();
+        hooks.add(Access::Write, 0x0000..=0x00FF, |addr, data| {
+            accesses.borrow_mut().push((addr, data))
+        });
+
+        hooks.notify(Access::Write, 0x0010, 0x42);
+        hooks.notify(Access::Read, 0x0010, 0x42);
+        hooks.notify(Access::Write, 0x0100, 0x42);
+        drop(hooks);
+
+        assert_eq!(accesses.into_inner(), [(0x0010, 0x42)]);
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        let watch = |access, range| Ok(MemoryWatch { access, range });
+        assert_eq!("0075".parse(), watch(Access::Write, 0x0075..=0x0075));
+        assert_eq!("2000-2007".parse(), watch(Access::Write, 0x2000..=0x2007));
+        assert_eq!("4016:r".parse(), watch(Access::Read, 0x4016..=0x4016));
+        assert_eq!("0300-03ff:R".parse(), watch(Access::Read, 0x0300..=0x03FF));
+
+        for invalid in ["75", "0075:w", "00750", "GGGG", "0075-", ""] {
+            assert_eq!(
+                invalid.parse::<MemoryWatch>(),
+                Err(WatchError::InvalidFormat)
+            );
+        }
+        assert_eq!(
+            "2007-2000".parse::<MemoryWatch>(),
+            Err(WatchError::EmptyRange)
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::cartridge::{Cartridge, Mirroring};
+use rand::rngs::StdRng;
+
+use crate::cartridge::Cartridge;
+use crate::ram_init::RamInit;
+use crate::state::{snapshot, Snapshot};
 
 const ROM: u16 = 0x0000;
 const ROM_END: u16 = 0x1FFF;
@@ -21,9 +25,24 @@ pub struct PPUBus {
     pub vram: [u8; 2048],
 }
 
-pub trait Memory {
+snapshot!(PPUBus, palette_table, vram);
+
+/// The bus of the PPU, which has its own state.
+pub trait Memory: Snapshot {
     fn write_data(&mut self, addr: u16, value: u8);
     fn read_data(&mut self, addr: u16) -> u8;
+
+    /// Returns the data at the given address without any of the side effects
+    /// of a read. By default reads are assumed to have no side effects.
+    fn peek_data(&mut self, addr: u16) -> u8 {
+        self.read_data(addr)
+    }
+
+    /// Called on a (filtered) rising edge of PPU address line A12.
+    fn notify_a12_rising_edge(&mut self) {}
+
+    /// Fills any RAM on the bus with its power on pattern.
+    fn init_ram(&mut self, _init: RamInit, _rng: &mut StdRng) {}
 }
 
 impl PPUBus {
@@ -34,36 +53,15 @@ impl PPUBus {
             vram: [0; 2048],
         }
     }
+}
 
-    /// Horizontal:
-    ///   [ A ] [ a ]
-    ///   [ B ] [ b ]
-    ///
-    /// Vertical:
-    ///   [ A ] [ B ]
-    ///   [ a ] [ b ]
-    fn mirror_vram_addr(&self, addr: u16) -> u16 {
-        // Mirror down 0x3000-0x3EFF to 0x2000 - 0x2EFF
-        let mirrored_vram = addr & 0x2FFF;
-
-        // To VRAM vector.
-        let vram_index = mirrored_vram - 0x2000;
-        let name_table = vram_index / 0x400;
-
-        match self.cart.borrow().mirroring() {
-            Mirroring::Vertical => match name_table {
-                2 | 3 => vram_index - (0x400 * 2),
-                _ => vram_index,
-            },
-            Mirroring::Horizontal => match name_table {
-                1 | 2 => vram_index - 0x400,
-                3 => vram_index - (0x400 * 2),
-                _ => vram_index,
-            },
-            Mirroring::SingleScreenLo => vram_index & 0x3FF,
-            Mirroring::SingleScreenHi => (vram_index & 0x3FF) + 0x400,
-            Mirroring::FourScreen => vram_index,
-        }
+/// Returns the index in the palette table of a palette address. The palette
+/// is mirrored every 32 bytes, and addresses $3F10/$3F14/$3F18/$3F1C are
+/// mirrors of $3F00/$3F04/$3F08/$3F0C.
+fn palette_index(addr: u16) -> usize {
+    match addr & 0x1F {
+        0x10 | 0x14 | 0x18 | 0x1C => (addr & 0x0F) as usize,
+        index => index as usize,
     }
 }
 
@@ -71,19 +69,13 @@ impl Memory for PPUBus {
     /// Writes data to appropriate location based on the address register.
     fn write_data(&mut self, addr: u16, data: u8) {
         match addr {
-            ROM..=ROM_END => self.cart.borrow_mut().write_chr(addr, data),
+            ROM..=ROM_END => self.cart.borrow_mut().ppu_write(addr, data),
             VRAM..=VRAM_END => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = data;
-            }
-            // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of
-            // $3F00/$3F04/$3F08/$3F0C
-            0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3F00) as usize] = data;
-            }
-            PALETTE..=PALETTE_END => {
-                self.palette_table[(addr - 0x3F00) as usize] = data;
+                self.cart
+                    .borrow_mut()
+                    .nametable_write(addr, data, &mut self.vram);
             }
+            PALETTE..=PALETTE_END => self.palette_table[palette_index(addr)] = data,
             _ => unreachable!("unexpected access to mirrored space {}", addr),
         }
     }
@@ -91,10 +83,29 @@ impl Memory for PPUBus {
     /// Retuns data from appropriate source based on the address register.
     fn read_data(&mut self, addr: u16) -> u8 {
         match addr {
-            ROM..=ROM_END => self.cart.borrow().read_chr(addr),
-            VRAM..=VRAM_END => self.vram[self.mirror_vram_addr(addr) as usize],
-            PALETTE..=PALETTE_END => self.palette_table[(addr - 0x3F00) as usize],
+            ROM..=ROM_END => self.cart.borrow_mut().ppu_read(addr),
+            VRAM..=VRAM_END => self.cart.borrow_mut().nametable_read(addr, &self.vram),
+            PALETTE..=PALETTE_END => self.palette_table[palette_index(addr)],
             _ => unreachable!("unexpected access to mirrored space {}", addr),
         }
     }
+
+    /// Returns data without the side effects of reading the pattern tables,
+    /// such as MMC2 latch switches.
+    fn peek_data(&mut self, addr: u16) -> u8 {
+        match addr {
+            ROM..=ROM_END => self.cart.borrow_mut().ppu_peek(addr),
+            _ => self.read_data(addr),
+        }
+    }
+
+    /// Forwards A12 rising edges to the cartridge mapper.
+    fn notify_a12_rising_edge(&mut self) {
+        self.cart.borrow_mut().notify_a12_rising_edge();
+    }
+
+    /// Fills the VRAM with its power on pattern.
+    fn init_ram(&mut self, init: RamInit, rng: &mut StdRng) {
+        init.fill(&mut self.vram, rng);
+    }
 }
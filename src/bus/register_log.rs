@@ -0,0 +1,130 @@
+use std::fmt;
+
+use super::hooks::Access;
+
+/// Represents an access of a PPU, APU or controller register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterAccess {
+    pub access: Access,
+    pub addr: u16,
+    pub data: u8,
+
+    /// CPU cycle of the access, counted from power on.
+    pub cpu_cycle: u64,
+
+    /// Position of the PPU at the time of the access, where the pre render
+    /// scanline is -1.
+    pub scanline: i32,
+    pub dot: usize,
+}
+
+impl fmt::Display for RegisterAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ${:04X} = ${:02X} PPU:{:>3},{:>3} CYC:{}",
+            self.access, self.addr, self.data, self.scanline, self.dot, self.cpu_cycle
+        )
+    }
+}
+
+/// A log of the register accesses made in each frame, for event viewers and
+/// debugging raster effects.
+///
+/// Frames are split at the start of the vertical blank, so each frame holds
+/// the writes made by the NMI handler followed by those made mid-frame.
+pub struct RegisterLog {
+    enabled: bool,
+    current: Vec<RegisterAccess>,
+    last: Vec<RegisterAccess>,
+}
+
+impl RegisterLog {
+    /// Returns a new, disabled register log.
+    pub fn new() -> Self {
+        RegisterLog {
+            enabled: false,
+            current: vec![],
+            last: vec![],
+        }
+    }
+
+    /// Sets whether accesses are logged, clearing the log.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.current.clear();
+        self.last.clear();
+    }
+
+    /// Returns true if accesses are logged.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Logs an access made in the current frame.
+    pub fn record(&mut self, access: RegisterAccess) {
+        if self.enabled {
+            self.current.push(access);
+        }
+    }
+
+    /// Ends the current frame, which becomes the last frame.
+    pub fn end_frame(&mut self) {
+        if self.enabled {
+            self.last = std::mem::take(&mut self.current);
+        }
+    }
+
+    /// Returns the accesses made so far in the current frame.
+    pub fn current_frame(&self) -> &[RegisterAccess] {
+        &self.current
+    }
+
+    /// Returns the accesses made in the last completed frame.
+    pub fn last_frame(&self) -> &[RegisterAccess] {
+        &self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a write of the given data to PPUSCROLL.
+    fn scroll_write(data: u8) -> RegisterAccess {
+        RegisterAccess {
+            access: Access::Write,
+            addr: 0x2005,
+            data,
+            cpu_cycle: 0,
+            scanline: 0,
+            dot: 0,
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut log = RegisterLog::new();
+        log.record(scroll_write(1));
+        assert!(log.current_frame().is_empty());
+
+        log.set_enabled(true);
+        log.record(scroll_write(2));
+        log.end_frame();
+        log.record(scroll_write(3));
+
+        assert_eq!(log.last_frame(), [scroll_write(2)]);
+        assert_eq!(log.current_frame(), [scroll_write(3)]);
+    }
+
+    #[test]
+    fn test_format() {
+        let access = RegisterAccess {
+            scanline: -1,
+            dot: 340,
+            cpu_cycle: 29781,
+            ..scroll_write(0x1F)
+        };
+        assert_eq!(access.to_string(), "W $2005 = $1F PPU: -1,340 CYC:29781");
+    }
+}
@@ -1,13 +1,31 @@
 use std::cell::RefCell;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
-use crate::apu::Apu;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::apu::{Apu, AudioChannel};
+use crate::audio::AudioOutput;
 use crate::cartridge::Cartridge;
-use crate::cpu::Memory;
+use crate::cheat::{Cheats, RamCheat};
+use crate::cpu::{Bus, Memory};
+use crate::input::{InputProvider, PortDevice};
 use crate::joypad::Joypad;
+use crate::keyboard::FamilyKeyboard;
+use crate::movie::InputMovie;
 use crate::ppu::NesPpu;
+use crate::ppu::Palette;
 use crate::ppu::Ppu;
-
+use crate::ram_init::RamInit;
+use crate::region::Region;
+use crate::state::{self, ChunkId, Chunked, Snapshot, StateBuffer};
+use crate::zapper::Zapper;
+
+use super::clock::{ClockEvent, MasterClock};
+use super::dma::{Dma, DmaCycle};
+use super::hooks::{Access, MemoryHooks};
+use super::register_log::{RegisterAccess, RegisterLog};
 use super::PPUBus;
 
 /// | Address range | Size  | Device                                                                  |
@@ -35,8 +53,11 @@ const APU_STATUS: u16 = 0x4015;
 const APU_CHAN_ENABLE: u16 = 0x4015;
 const APU_FRAME_COUNTER: u16 = 0x4017;
 
-/// Delay betwen samples produced by the APU.
-const APU_SAMPLE_DELAY: f32 = 1.0 / 1789773.0;
+/// Bits driven by reads of the controller ports, the rest are open bus.
+const CONTROLLER_BITS: u8 = 0x1F;
+
+/// Bit of $4016 set while the microphone on Famicom controller 2 hears sound.
+const MICROPHONE_BIT: u8 = 0x04;
 
 /// SystemBus abstracts a single location for data read/write, interrupts,
 /// memory mapping and PPU/CPU clock cycles.
@@ -45,11 +66,35 @@ pub struct SystemBus<'a> {
     cart: Rc<RefCell<Cartridge>>,
     ppu: NesPpu<'a>,
     pub joypad1: Joypad,
+    pub joypad2: Joypad,
+
+    // A Zapper connected to the second controller port in place of joypad 2.
+    zapper: Option<Zapper>,
+
+    // A Family BASIC keyboard connected to the expansion port.
+    keyboard: Option<FamilyKeyboard>,
+
+    // Whether the microphone on Famicom controller 2 hears sound.
+    microphone: bool,
+
+    // The controller input of each frame, being recorded or played back.
+    movie: InputMovie,
+
+    clock: MasterClock,
+    ppu_enabled: bool,
+    dma: Dma,
+
+    // The last value driven on the CPU data bus, which is read back from
+    // addresses that nothing drives.
+    open_bus: u8,
 
     apu: Apu,
-    apu_interval: f32,
-    apu_sample_time: f32,
-    apu_samples: Vec<f32>,
+    audio: AudioOutput<'a>,
+    audio_enabled: bool,
+
+    cheats: Cheats,
+    hooks: MemoryHooks<'a>,
+    register_log: RegisterLog,
 }
 
 impl<'a> SystemBus<'a> {
@@ -66,53 +111,330 @@ impl<'a> SystemBus<'a> {
             cart,
             ppu,
             joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            zapper: None,
+            keyboard: None,
+            microphone: false,
+
+            movie: InputMovie::new(),
+
+            clock: MasterClock::new(),
+            ppu_enabled: true,
+            dma: Dma::new(),
+
+            open_bus: 0,
 
-            apu: Apu::new(audio_sample_rate),
-            apu_interval: 0.0,
-            apu_sample_time: 1.0 / audio_sample_rate,
-            apu_samples: Vec::new(),
+            apu: Apu::new(),
+            audio: AudioOutput::new(audio_sample_rate as f64),
+            audio_enabled: true,
+
+            cheats: Cheats::new(),
+            hooks: MemoryHooks::new(),
+            register_log: RegisterLog::new(),
         }
     }
 
-    /// Updates the APU DMC chanel with a new sample if it needs one.
-    fn update_dmc_sample(&mut self) {
+    /// Runs the PPU, APU and cartridge for one CPU cycle.
+    fn clock(&mut self) {
+        // PPU runs three (or 3.2) times faster than CPU. The master clock
+        // steps through each dot up to the end of the CPU cycle.
+        let frame_count = self.ppu.read_frame_count();
+        while self.clock.next() == ClockEvent::Ppu {
+            if self.ppu_enabled {
+                self.ppu.clock();
+            }
+        }
+
+        // Cheats are forced as each frame ends, before the NMI handler runs.
+        if self.ppu.read_frame_count() != frame_count {
+            self.apply_frame_cheats();
+            self.register_log.end_frame();
+
+            self.movie
+                .record([self.joypad1.frame_status(), self.joypad2.frame_status()]);
+            let frame_rate = self.clock.region().frame_rate();
+            self.joypad1.end_frame(frame_rate);
+            self.joypad2.end_frame(frame_rate);
+            self.play_movie_frame();
+        }
+
+        // The cartridge and APU run at the same speed as the CPU, mixing
+        // in any audio from the cartridge.
+        let expansion = {
+            let mut cart = self.cart.borrow_mut();
+            cart.clock();
+            cart.clock_expansion_audio()
+        };
+        self.apu.set_expansion_output(expansion);
+        self.apu.clock();
+
+        // The DMC memory reader fetches samples via DMA.
         if self.apu.need_dmc_sample() {
-            let addr = self.apu.dmc_sample_address();
-            let sample = self.mem_read_byte(addr);
+            self.dma.request_dmc();
+        }
+
+        if self.audio_enabled {
+            self.audio.push(self.apu.output());
+            if self.audio.is_recording_stems() {
+                self.audio.push_stems(&self.apu.stem_outputs());
+            }
+        }
+    }
 
-            self.apu.set_dmc_sample(sample);
+    /// Runs any pending DMA, which stalls the CPU while the PPU and APU carry
+    /// on running. Even CPU cycles are get cycles and odd ones put cycles.
+    fn run_dma(&mut self) {
+        while self.dma.active() {
+            match self.dma.step(self.clock.cpu_cycles().is_multiple_of(2)) {
+                DmaCycle::Halt => {}
+                DmaCycle::OamRead(addr) => {
+                    let data = self.mem_read_byte(addr);
+                    self.dma.set_oam_data(data);
+                }
+                DmaCycle::OamWrite(data) => self.ppu.write_oam_data(data),
+                DmaCycle::DmcRead => {
+                    let sample = self.mem_read_byte(self.apu.dmc_sample_address());
+                    self.apu.set_dmc_sample(sample);
+                }
+            }
 
-            self.tick(4);
+            self.clock();
         }
     }
 
-    /// For every CPU tick, run the PPU and APU appropriately.
-    pub fn tick(&mut self, cycles: u8) {
-        for _ in 0..cycles {
-            // PPU runs three times faster than CPU.
-            for _ in 0..3 {
-                self.ppu.clock();
+    /// Writes the values of the RAM cheats which are forced each frame.
+    fn apply_frame_cheats(&mut self) {
+        for cheat in self.cheats.frame_writes() {
+            match cheat.addr {
+                RAM..=RAM_MIRRORS_END => self.ram[cheat.addr as usize] = cheat.value,
+                _ => self.cart.borrow_mut().cpu_write(cheat.addr, cheat.value),
             }
+        }
+    }
 
-            // The APU runs at the same speed as the CPU.
-            self.apu.clock();
-            self.update_dmc_sample();
+    /// Returns the number of CPU cycles since power on.
+    pub fn cpu_cycles(&self) -> u64 {
+        self.clock.cpu_cycles()
+    }
 
-            // Ensure the APU stays in sync.
-            self.apu_interval += APU_SAMPLE_DELAY;
+    /// Sets whether the PPU is clocked. The PPU can be suppressed when it is
+    /// not used, such as when playing an NSF.
+    pub fn set_ppu_enabled(&mut self, enabled: bool) {
+        self.ppu_enabled = enabled;
+    }
+
+    /// Sets the console region, which determines the PPU and APU timings.
+    pub fn set_region(&mut self, region: Region) {
+        self.clock.set_region(region);
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.audio.set_clock_rate(region.cpu_clock_rate());
+    }
+
+    /// Fills the CPU RAM, VRAM and OAM with a power on pattern. Random
+    /// patterns are generated from the given seed.
+    pub fn set_ram_init(&mut self, init: RamInit, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        init.fill(&mut self.ram, &mut rng);
+        self.ppu.init_ram(init, &mut rng);
+    }
+
+    /// Sets the number of times per second turbo buttons are pressed.
+    pub fn set_turbo_rate(&mut self, rate: f64) {
+        self.joypad1.set_turbo_rate(rate);
+        self.joypad2.set_turbo_rate(rate);
+    }
 
-            if self.apu_interval >= self.apu_sample_time {
-                self.apu_interval -= self.apu_sample_time;
+    /// Polls the provider for the state of the input devices in the next
+    /// frame, connecting and updating them. A Zapper on the first port is
+    /// treated as an unconnected controller.
+    pub fn poll_input<P: InputProvider + ?Sized>(&mut self, provider: &mut P) {
+        let state = provider.poll();
+        match state.ports[0] {
+            PortDevice::Joypad { buttons, turbo } => self.joypad1.set_status(buttons, turbo),
+            PortDevice::Zapper(_) => self.joypad1.set_status(0, 0),
+        }
 
-                let sample = self.apu.output();
-                self.apu_samples.push(sample);
+        match state.ports[1] {
+            PortDevice::Joypad { buttons, turbo } => {
+                self.joypad2.set_status(buttons, turbo);
+                self.zapper = None;
+            }
+            PortDevice::Zapper(state) => {
+                let zapper = self.zapper.get_or_insert_with(Zapper::new);
+                zapper.set_trigger(state.trigger);
+                zapper.aim(state.aim);
             }
         }
+
+        self.microphone = state.microphone;
     }
 
-    /// Returns the NMI status of the PPU.
-    pub fn nmi_status(&mut self) -> bool {
-        self.ppu.poll_nmi()
+    /// Returns the state of the Zapper, if one is connected, which depends
+    /// on the frame the PPU is drawing.
+    fn read_zapper(&self) -> Option<u8> {
+        let zapper = self.zapper.as_ref()?;
+        let (scanline, dot) = self.ppu.position();
+        Some(zapper.read(self.ppu.frame(), scanline, dot))
+    }
+
+    /// Sets whether a Family BASIC keyboard is connected to the expansion
+    /// port.
+    pub fn set_keyboard_connected(&mut self, connected: bool) {
+        self.keyboard = connected.then(FamilyKeyboard::new);
+    }
+
+    /// Returns the connected Family BASIC keyboard, if any.
+    pub fn keyboard(&mut self) -> Option<&mut FamilyKeyboard> {
+        self.keyboard.as_mut()
+    }
+
+    /// Returns the bits of $4017 driven by the expansion port.
+    fn read_expansion(&self) -> u8 {
+        self.keyboard.as_ref().map_or(0, FamilyKeyboard::read)
+    }
+
+    /// Returns the bit of $4016 driven by the microphone on Famicom
+    /// controller 2, which games such as The Legend of Zelda check to defeat
+    /// Pols Voice.
+    fn read_microphone(&self) -> u8 {
+        match self.microphone {
+            true => MICROPHONE_BIT,
+            false => 0,
+        }
+    }
+
+    /// Starts recording the controller input of each frame into a new movie.
+    pub fn record_movie(&mut self) {
+        self.movie.start_recording();
+    }
+
+    /// Starts playing back the controller input of the given movie in place
+    /// of the player's, from the current frame.
+    pub fn play_movie(&mut self, movie: InputMovie) {
+        self.movie = movie;
+        self.movie.start_playback();
+        self.play_movie_frame();
+    }
+
+    /// Returns the movie being recorded or played back.
+    pub fn movie(&self) -> &InputMovie {
+        &self.movie
+    }
+
+    /// Sets the controller input of the current frame from the movie, if
+    /// playing back.
+    fn play_movie_frame(&mut self) {
+        if let Some([status1, status2]) = self.movie.next_frame() {
+            self.joypad1.set_frame_status(status1);
+            self.joypad2.set_frame_status(status2);
+        }
+    }
+
+    /// Adds a RAM cheat, which forces a byte of RAM to a value.
+    pub fn add_cheat(&mut self, cheat: RamCheat) {
+        self.cheats.add_ram(cheat);
+    }
+
+    /// Adds a hook, called with the address and data of every CPU read or
+    /// write (including DMA) of an address in range. Peeks aren't observed.
+    pub fn add_memory_hook<F>(&mut self, access: Access, range: RangeInclusive<u16>, callback: F)
+    where
+        F: FnMut(u16, u8) + 'a,
+    {
+        self.hooks.add(access, range, callback);
+    }
+
+    /// Sets whether accesses of the PPU, APU and controller registers are
+    /// logged.
+    pub fn set_register_log(&mut self, enabled: bool) {
+        self.register_log.set_enabled(enabled);
+    }
+
+    /// Returns the log of register accesses.
+    pub fn register_log(&self) -> &RegisterLog {
+        &self.register_log
+    }
+
+    /// Logs an access if it is of a PPU, APU or controller register.
+    fn log_register_access(&mut self, access: Access, addr: u16, data: u8) {
+        if !self.register_log.enabled() || !(PPU_REGISTERS..=APU_FRAME_COUNTER).contains(&addr) {
+            return;
+        }
+
+        let (scanline, dot) = self.ppu.position();
+        self.register_log.record(RegisterAccess {
+            access,
+            addr,
+            data,
+            cpu_cycle: self.clock.cpu_cycles(),
+            scanline,
+            dot,
+        });
+    }
+
+    /// Sets whether the PPU limits the number of sprites per scanline to 8.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.set_sprite_limit(enabled);
+    }
+
+    /// Sets the colours the PPU draws for each palette index.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.ppu.set_palette(palette);
+    }
+
+    /// Sets whether frames are passed to the render callback.
+    pub fn set_render_enabled(&mut self, enabled: bool) {
+        self.ppu.set_render_enabled(enabled);
+    }
+
+    /// Sets whether the APU output is passed to the audio output, including
+    /// any recordings. Audio of frames which are emulated again, such as when
+    /// running ahead, is left out so it isn't heard twice.
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        self.audio_enabled = enabled;
+    }
+
+    /// Sets whether the APU output filters are applied.
+    pub fn set_audio_filters(&mut self, enabled: bool) {
+        self.apu.set_filters_enabled(enabled);
+    }
+
+    /// Toggles whether an audio channel is muted.
+    pub fn toggle_channel_mute(&mut self, channel: AudioChannel) {
+        let muted = self.apu.is_muted(channel);
+        self.apu.set_muted(channel, !muted);
+    }
+
+    /// Toggles whether an audio channel is soloed.
+    pub fn toggle_channel_solo(&mut self, channel: AudioChannel) {
+        match self.apu.solo() == Some(channel) {
+            true => self.apu.set_solo(None),
+            false => self.apu.set_solo(Some(channel)),
+        }
+    }
+
+    /// Returns the recent output of an audio channel before mixing, from
+    /// oldest to newest, for visualizations.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn channel_history(&self, channel: AudioChannel) -> Vec<f32> {
+        self.apu.channel_history(channel)
+    }
+
+    /// Returns the CRC32 of the ROM in the cartridge.
+    pub fn rom_crc32(&self) -> u32 {
+        self.cart.borrow().crc32()
+    }
+
+    /// Returns the RGB pixels of the frame the PPU is drawing, where the
+    /// lines yet to be drawn hold the previous frame.
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
+    }
+
+    /// Returns the CPU's internal RAM.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
     }
 
     /// Returns the number of rendered frames from the PPU.
@@ -120,111 +442,206 @@ impl<'a> SystemBus<'a> {
         self.ppu.read_frame_count()
     }
 
-    /// Returns the audio samples generated by the APU.
-    pub fn audio_samples(&mut self) -> Vec<f32> {
-        std::mem::take(self.apu_samples.as_mut())
+    /// Returns the audio output, resampled to the requested sample rate.
+    pub fn audio(&mut self) -> &mut AudioOutput<'a> {
+        &mut self.audio
     }
-}
 
-impl Memory for SystemBus<'_> {
-    fn mem_read_byte(&mut self, addr: u16) -> u8 {
+    /// Returns the byte driven on the data bus by a read of the given
+    /// address, or the open bus value if nothing drives it.
+    fn read(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.ram[mirror_down_addr as usize]
             }
-            PPU_REGISTERS | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => 0,
+            PPU_REGISTERS | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.ppu.read_open_bus(),
             0x2002 => self.ppu.read_status(),
             0x2004 => self.ppu.read_oam_data(),
             0x2007 => self.ppu.read_data(),
 
-            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS => self.apu.read(addr),
-
-            0x4016 => self.joypad1.read(),
+            // Bit 5 of the status register isn't driven.
+            APU_STATUS => self.apu.read(addr) | (self.open_bus & 0x20),
 
+            // The controller ports only drive the low 5 bits.
+            0x4016 => {
+                self.joypad1.read() | self.read_microphone() | (self.open_bus & !CONTROLLER_BITS)
+            }
             0x4017 => {
-                // ignore joypad 2
-                0
+                let data = self.read_zapper().unwrap_or_else(|| self.joypad2.read());
+                data | self.read_expansion() | (self.open_bus & !CONTROLLER_BITS)
             }
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_read_byte(mirror_down_addr)
+                self.read(mirror_down_addr)
+            }
+            // The expansion area ($4020-$5FFF), PRG RAM ($6000-$7FFF) and PRG
+            // ROM ($8000-$FFFF) are all mapped by the cartridge, which may
+            // leave parts of them undriven.
+            PRG..=PRG_END if self.cart.borrow().drives_cpu_bus(addr) => {
+                self.cart.borrow_mut().cpu_read(addr)
             }
-            PRG..=PRG_END => self.cart.borrow().read_prg(addr),
 
-            _ => 0,
+            // The write only APU registers, OAM DMA, the disabled APU test
+            // registers and unmapped cartridge space.
+            _ => self.open_bus,
         }
     }
 
-    fn mem_write_byte(&mut self, addr: u16, data: u8) {
-        self.ppu.refresh_open_bus(data);
-
+    /// Writes a byte to the device mapped at the given address.
+    fn write(&mut self, addr: u16, data: u8) {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b11111111111;
-                self.ram[mirror_down_addr as usize] = data;
-            }
-            PPU_REGISTERS => {
-                self.ppu.write_ctrl(data);
+                self.ram[mirror_down_addr as usize] =
+                    self.cheats.filter_write(mirror_down_addr, data);
             }
-
-            0x2001 => {
-                self.ppu.write_mask(data);
+            PPU_REGISTERS..=0x2007 => {
+                // Any write to a PPU register fills the open bus latch.
+                self.ppu.refresh_open_bus(data);
+
+                match addr {
+                    PPU_REGISTERS => self.ppu.write_ctrl(data),
+                    0x2001 => self.ppu.write_mask(data),
+                    0x2003 => self.ppu.write_oam_addr(data),
+                    0x2004 => self.ppu.write_oam_data(data),
+                    0x2005 => self.ppu.write_scroll(data),
+                    0x2006 => self.ppu.write_addr(data),
+                    0x2007 => self.ppu.write_data(data),
+
+                    // The status register is read-only, the write only
+                    // updates the open bus latch.
+                    _ => {}
+                }
             }
-            0x2002 => panic!("attempt to write to PPU status register"),
 
-            0x2003 => {
-                self.ppu.write_oam_addr(data);
-            }
-            0x2004 => {
-                self.ppu.write_oam_data(data);
-            }
-            0x2005 => {
-                self.ppu.write_scroll(data);
-            }
-            0x2006 => {
-                self.ppu.write_addr(data);
-            }
-            0x2007 => {
-                self.ppu.write_data(data);
-            }
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_write_byte(mirror_down_addr, data);
+                self.write(mirror_down_addr, data);
             }
 
             APU_REGISTERS..=APU_REGISTERS_END | APU_CHAN_ENABLE | APU_FRAME_COUNTER => {
                 self.apu.write(addr, data)
             }
 
-            0x4014 => {
-                let mut buffer: [u8; 256] = [0; 256];
-                let hi: u16 = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.mem_read_byte(hi + i);
+            // The copy runs once the instruction has finished.
+            0x4014 => self.dma.start_oam(data),
+            // Both controllers share the strobe, while writes to $4017 go to
+            // the APU frame counter.
+            0x4016 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+                if let Some(keyboard) = self.keyboard.as_mut() {
+                    keyboard.write(data);
                 }
+            }
 
-                self.ppu.write_oam_dma(&buffer);
-
-                // DMC can need a new sample during DMA.
-                //
-                // See: https://www.nesdev.org/wiki/APU_DMC
-                self.update_dmc_sample();
+            // Writes anywhere in cartridge space reach the mapper, which may
+            // have registers in the expansion area or over PRG RAM and ROM.
+            PRG..=PRG_END => {
+                let data = self.cheats.filter_write(addr, data);
+                self.cart.borrow_mut().cpu_write(addr, data);
             }
+
+            // The disabled APU test registers.
+            _ => {}
+        }
+    }
+}
+
+/// Saves the state of the console's hardware. The devices connected, the
+/// input and the emulator's settings aren't saved.
+impl Chunked for SystemBus<'_> {
+    fn chunks(&mut self, visit: &mut dyn FnMut(ChunkId, u16, &mut dyn Snapshot)) {
+        visit(*b"RAM ", 1, &mut (&mut self.ram, &mut self.open_bus));
+        visit(*b"CART", 1, &mut *self.cart.borrow_mut());
+        visit(*b"PPU ", 1, &mut self.ppu);
+        visit(*b"APU ", 1, &mut self.apu);
+        visit(*b"CTRL", 1, &mut (&mut self.joypad1, &mut self.joypad2));
+        visit(*b"KEYB", 1, &mut self.keyboard);
+        visit(*b"CLK ", 1, &mut (&mut self.clock, &mut self.dma));
+    }
+}
+
+impl Snapshot for SystemBus<'_> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        state::snapshot_chunks(self, state);
+    }
+}
+
+impl Memory for SystemBus<'_> {
+    fn mem_read_byte(&mut self, addr: u16) -> u8 {
+        let data = self.read(addr);
+
+        // The status register is read inside the CPU, so the value isn't
+        // driven onto the external data bus.
+        if addr != APU_STATUS {
+            self.open_bus = data;
+        }
+
+        self.hooks.notify(Access::Read, addr, data);
+        self.log_register_access(Access::Read, addr, data);
+        data
+    }
+
+    fn mem_write_byte(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        self.hooks.notify(Access::Write, addr, data);
+        self.log_register_access(Access::Write, addr, data);
+
+        self.write(addr, data);
+    }
+}
+
+impl Bus for SystemBus<'_> {
+    /// Returns the byte at the given address without side effects, as a read
+    /// of it would return.
+    fn mem_peek_byte(&mut self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.ram[(addr & 0b00000111_11111111) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.ppu.peek(addr),
+            APU_STATUS => self.apu.peek(addr) | (self.open_bus & 0x20),
             0x4016 => {
-                self.joypad1.write(data);
+                self.joypad1.peek() | self.read_microphone() | (self.open_bus & !CONTROLLER_BITS)
             }
+            0x4017 => {
+                let data = self.read_zapper().unwrap_or_else(|| self.joypad2.peek());
+                data | self.read_expansion() | (self.open_bus & !CONTROLLER_BITS)
+            }
+            PRG..=PRG_END if self.cart.borrow().drives_cpu_bus(addr) => {
+                self.cart.borrow_mut().cpu_peek(addr)
+            }
+            _ => self.open_bus,
+        }
+    }
 
-            PRG..=PRG_END => self.cart.borrow_mut().write_prg(addr, data),
-
-            _ => unreachable!("unreachable write at: {}", addr),
+    /// For every CPU tick, run the PPU and APU appropriately, then stall the
+    /// CPU for any DMA started in the meantime.
+    fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.clock();
         }
+
+        self.run_dma();
+    }
+
+    /// Returns the NMI status of the PPU.
+    fn nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    /// Returns true if the IRQ line is being asserted, by either the APU or
+    /// the cartridge.
+    fn irq_status(&self) -> bool {
+        self.apu.poll_interrupt() || self.cart.borrow().irq_pending()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::cartridge::tests::test_cartridge;
+    use crate::input::{InputState, ZapperState};
+    use crate::joypad::{JOYPAD_BUTTON_A, JOYPAD_BUTTON_B};
 
     use super::*;
 
@@ -236,4 +653,320 @@ mod tests {
         bus.mem_write_byte(0x01, 0x55);
         assert_eq!(bus.mem_read_byte(0x01), 0x55);
     }
+
+    #[test]
+    fn test_open_bus() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+
+        // Unmapped addresses return the last value on the bus.
+        bus.mem_write_byte(0x4018, 0xA5);
+        assert_eq!(bus.mem_read_byte(0x4018), 0xA5);
+        assert_eq!(bus.mem_read_byte(0x4000), 0xA5);
+
+        // The controller port only drives the low bits.
+        bus.mem_write_byte(0x00, 0x40);
+        assert_eq!(bus.mem_read_byte(0x00), 0x40);
+        assert_eq!(bus.mem_read_byte(0x4016) & 0xE0, 0x40);
+        assert_eq!(bus.mem_read_byte(0x4017), 0x40);
+
+        // Reading the APU status doesn't update the bus.
+        bus.mem_read_byte(0x4015);
+        assert_eq!(bus.mem_read_byte(0x401F), 0x40);
+    }
+
+    #[test]
+    fn test_controllers() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.joypad1.set_button_pressed_status(JOYPAD_BUTTON_A, true);
+        bus.joypad2.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+
+        // The strobe at $4016 latches both controllers.
+        bus.mem_write_byte(0x4016, 1);
+        bus.mem_write_byte(0x4016, 0);
+        assert_eq!(bus.mem_read_byte(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read_byte(0x4017) & 1, 0);
+        assert_eq!(bus.mem_read_byte(0x4017) & 1, 1);
+
+        // Writing the frame counter at $4017 doesn't strobe them.
+        bus.mem_write_byte(0x4017, 1);
+        assert_eq!(bus.mem_read_byte(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn test_zapper() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let mut input = InputState::new(true);
+        *input.zapper().unwrap() = ZapperState {
+            trigger: true,
+            aim: Some((128, 120)),
+        };
+        bus.poll_input(&mut input);
+
+        // The black screen isn't sensed as light.
+        assert_eq!(bus.mem_read_byte(0x4017) & 0x18, 0x18);
+
+        // Controller 2 is reconnected in its place.
+        bus.poll_input(&mut InputState::new(false));
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.mem_read_byte(0x4017) & 0x18, 0);
+    }
+
+    #[test]
+    fn test_poll_input() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+
+        // A synthetic provider presses A on controller 1 in every frame.
+        let mut provider = || {
+            let mut state = InputState::new(false);
+            state.set_button_pressed(1, JOYPAD_BUTTON_A, true);
+            state
+        };
+        bus.poll_input(&mut provider);
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.mem_read_byte(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read_byte(0x4017) & 1, 0);
+    }
+
+    #[test]
+    fn test_microphone() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        assert_eq!(bus.mem_read_byte(0x4016) & MICROPHONE_BIT, 0);
+
+        let mut input = InputState::new(false);
+        input.microphone = true;
+        bus.poll_input(&mut input);
+        assert_eq!(bus.mem_read_byte(0x4016) & MICROPHONE_BIT, MICROPHONE_BIT);
+        assert_eq!(bus.mem_read_byte(0x4017) & MICROPHONE_BIT, 0);
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_keyboard_connected(true);
+        bus.keyboard().unwrap().set_key_pressed(0, 0, true);
+        bus.joypad2.set_button_pressed_status(JOYPAD_BUTTON_A, true);
+
+        // The keyboard shares $4017 with controller 2.
+        bus.mem_write_byte(0x4016, 0b101);
+        bus.mem_write_byte(0x4016, 0b100);
+        assert_eq!(bus.mem_read_byte(0x4017) & 0x1F, 0b00001111);
+    }
+
+    #[test]
+    fn test_movie() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.record_movie();
+
+        // Each frame records the buttons the game sampled, not those pressed
+        // after.
+        for buttons in [JOYPAD_BUTTON_A, JOYPAD_BUTTON_B] {
+            bus.joypad1.set_button_pressed_status(buttons, true);
+            bus.mem_write_byte(0x4016, 1);
+            bus.mem_write_byte(0x4016, 0);
+            bus.joypad1.set_button_pressed_status(buttons, false);
+            bus.joypad2.set_button_pressed_status(buttons, true);
+
+            let frame_count = bus.ppu_frame_count();
+            while bus.ppu_frame_count() == frame_count {
+                bus.tick(1);
+            }
+            bus.joypad2.set_button_pressed_status(buttons, false);
+        }
+
+        let movie = InputMovie::from_bytes(&bus.movie().to_bytes()).unwrap();
+        assert_eq!(movie.len(), 2);
+
+        // Playback replaces the player's buttons, until the movie ends.
+        bus.play_movie(movie);
+        for buttons in [JOYPAD_BUTTON_A, JOYPAD_BUTTON_B, 0] {
+            bus.mem_write_byte(0x4016, 1);
+            bus.mem_write_byte(0x4016, 0);
+            assert_eq!(bus.joypad1.frame_status(), buttons);
+            assert_eq!(bus.joypad2.frame_status(), 0);
+
+            let frame_count = bus.ppu_frame_count();
+            while bus.ppu_frame_count() == frame_count {
+                bus.tick(1);
+            }
+        }
+        assert!(!bus.movie().is_playing());
+    }
+
+    #[test]
+    fn test_cartridge_space() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x6000, 0x42);
+        assert_eq!(bus.mem_read_byte(0x6000), 0x42);
+
+        // The Namco 163 has its IRQ counter in the expansion area.
+        let mut raw = b"NES\x1a\x01\x01\x30\x10".to_vec();
+        raw.resize(16 + 16384 + 8192, 0);
+        let cart = Cartridge::from_bytes(&raw).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x5000, 0x34);
+        bus.mem_write_byte(0x5800, 0x12);
+        assert_eq!(bus.mem_read_byte(0x5000), 0x34);
+        assert_eq!(bus.mem_read_byte(0x5800), 0x12);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+
+        for i in 0..256 {
+            bus.mem_write_byte(0x0200 + i, 0x66);
+        }
+        bus.mem_write_byte(0x0200, 0x77);
+        bus.mem_write_byte(0x02FF, 0x88);
+
+        bus.mem_write_byte(0x2003, 0x10);
+        bus.mem_write_byte(0x4014, 0x02);
+        bus.tick(1);
+
+        // The copy stalls the CPU after the write for 513 or 514 cycles.
+        assert!((514..=515).contains(&bus.cpu_cycles()));
+
+        bus.mem_write_byte(0x2003, 0x0F);
+        assert_eq!(bus.mem_read_byte(0x2004), 0x88);
+
+        bus.mem_write_byte(0x2003, 0x10);
+        assert_eq!(bus.mem_read_byte(0x2004), 0x77);
+
+        bus.mem_write_byte(0x2003, 0x11);
+        assert_eq!(bus.mem_read_byte(0x2004), 0x66);
+    }
+
+    #[test]
+    fn test_ram_init() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_ram_init(RamInit::Ff, 0);
+        assert_eq!(bus.mem_read_byte(0x0000), 0xFF);
+        assert_eq!(bus.mem_read_byte(0x07FF), 0xFF);
+
+        // The same seed fills RAM with the same random bytes.
+        let mut rams = vec![];
+        for _ in 0..2 {
+            let cart = test_cartridge(vec![], None).unwrap();
+            let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+            bus.set_ram_init(RamInit::Random, 42);
+            rams.push(bus.ram);
+        }
+        assert_eq!(rams[0], rams[1]);
+    }
+
+    #[test]
+    fn test_memory_hooks() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let reads = Rc::new(RefCell::new(0));
+
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let hook_writes = Rc::clone(&writes);
+        bus.add_memory_hook(Access::Write, 0x0000..=0x07FF, move |addr, data| {
+            hook_writes.borrow_mut().push((addr, data))
+        });
+        let hook_reads = Rc::clone(&reads);
+        bus.add_memory_hook(Access::Read, 0x0075..=0x0075, move |_, _| {
+            *hook_reads.borrow_mut() += 1
+        });
+
+        bus.mem_write_byte(0x0075, 0x09);
+        bus.mem_write_byte(0x0800, 0x01);
+        bus.mem_read_byte(0x0075);
+        bus.mem_peek_byte(0x0075);
+
+        assert_eq!(*writes.borrow(), [(0x0075, 0x09)]);
+        assert_eq!(*reads.borrow(), 1);
+    }
+
+    #[test]
+    fn test_register_log() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_register_log(true);
+
+        bus.mem_write_byte(0x2005, 0x10);
+        bus.mem_write_byte(0x0000, 0x10);
+        bus.tick(10);
+        bus.mem_read_byte(0x4016);
+
+        let log = bus.register_log().current_frame();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].access, Access::Write);
+        assert_eq!((log[0].addr, log[0].data), (0x2005, 0x10));
+        assert_eq!((log[1].addr, log[1].cpu_cycle), (0x4016, 10));
+        assert_eq!(log[1].dot, log[0].dot + 30);
+
+        // Mirrors of the PPU registers are logged once.
+        bus.mem_write_byte(0x3FFD, 0x20);
+        assert_eq!(bus.register_log().current_frame().len(), 3);
+
+        let frame_count = bus.ppu_frame_count();
+        while bus.ppu_frame_count() == frame_count {
+            bus.tick(1);
+        }
+        assert_eq!(bus.register_log().last_frame().len(), 3);
+        assert!(bus.register_log().current_frame().is_empty());
+    }
+
+    #[test]
+    fn test_cheats() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.add_cheat("0075:09".parse().unwrap());
+        bus.add_cheat("6000:63:w".parse().unwrap());
+
+        bus.mem_write_byte(0x6000, 0x01);
+        assert_eq!(bus.mem_read_byte(0x6000), 0x63);
+
+        // The frame cheat is only forced once the frame ends.
+        bus.mem_write_byte(0x0875, 0x01);
+        assert_eq!(bus.mem_read_byte(0x0075), 0x01);
+
+        let frame_count = bus.ppu_frame_count();
+        while bus.ppu_frame_count() == frame_count {
+            bus.tick(1);
+        }
+        assert_eq!(bus.mem_read_byte(0x0075), 0x09);
+    }
+
+    #[test]
+    fn test_region_frame_cycles() {
+        for (region, cycles) in [
+            (Region::Ntsc, 29780..=29781),
+            (Region::Pal, 33247..=33248),
+            (Region::Dendy, 35464..=35464),
+        ] {
+            let cart = test_cartridge(vec![], None).unwrap();
+            let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+            bus.set_region(region);
+
+            let mut frame_start = 0;
+            for _ in 0..2 {
+                let frame_count = bus.ppu_frame_count();
+                frame_start = bus.cpu_cycles();
+                while bus.ppu_frame_count() == frame_count {
+                    bus.tick(1);
+                }
+            }
+
+            let frame_cycles = bus.cpu_cycles() - frame_start;
+            assert!(
+                cycles.contains(&frame_cycles),
+                "{:?}: {}",
+                region,
+                frame_cycles
+            );
+        }
+    }
 }
@@ -1,80 +1,277 @@
+use std::io::Read;
+
+use crate::state::{snapshot, snapshot_enum};
 use crate::{
-    mapper::{Mapper, Nrom, Uxrom, MMC1},
-    rom::Rom,
+    mapper::{
+        Axrom, Bnrom, Camerica, Cnrom, Fme7, Mapper, Namco163, Nrom, NsfMapper, Uxrom, Vrc4, Vrc6,
+        Vrc7, MMC1, MMC2, MMC3, MMC4,
+    },
+    nsf::Nsf,
+    rom::{self, Rom, RomError, CHR_PAGE_SIZE, PRG_PAGE_SIZE},
 };
 
 /// Represents the screen mirroring mode.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum Mirroring {
     Vertical,
+    #[default]
     Horizontal,
     SingleScreenLo,
     SingleScreenHi,
     FourScreen,
 }
 
+snapshot_enum!(
+    Mirroring,
+    [
+        Mirroring::Vertical,
+        Mirroring::Horizontal,
+        Mirroring::SingleScreenLo,
+        Mirroring::SingleScreenHi,
+        Mirroring::FourScreen
+    ]
+);
+
 /// Represents a NES cartridge.
 pub struct Cartridge {
     mapper: Box<dyn Mapper>,
+
+    /// CRC32 of the ROM, which identifies the game in save states.
+    crc32: u32,
+
+    /// Whether the PRG RAM is kept by a battery, to save the game.
+    battery: bool,
 }
 
+snapshot!(Cartridge, mapper);
+
 impl Cartridge {
-    /// Creates a new Cartridge from the given raw ROM data.
-    pub fn new(raw: &[u8]) -> Result<Cartridge, String> {
-        let rom = match Rom::new(raw) {
-            Ok(rom) => rom,
-            Err(e) => return Err(e),
-        };
+    /// Creates a new Cartridge from the given ROM.
+    pub fn new(mut rom: Rom) -> Result<Cartridge, RomError> {
+        let trainer = rom.trainer.take();
+        let crc32 = rom.crc32();
+        let battery = rom.header.battery();
 
         let mapper = rom.header.mapper();
-        let cart = Cartridge {
+
+        // Mappers divide the ROMs into whole banks, fixing some of them to the
+        // last, so NES 2.0 headers giving fewer bytes than the banks mapped at
+        // once are rejected rather than read out of bounds.
+        let min_prg_size = match mapper {
+            // The MMC2 fixes the last three 8 KB banks, after a switched one.
+            9 => 2 * PRG_PAGE_SIZE,
+            _ => PRG_PAGE_SIZE,
+        };
+        if rom.prg.len() < min_prg_size {
+            return Err(RomError::PrgTooSmall {
+                minimum: min_prg_size,
+                actual: rom.prg.len(),
+            });
+        }
+        if rom.chr.len() < CHR_PAGE_SIZE {
+            return Err(RomError::ChrTooSmall {
+                minimum: CHR_PAGE_SIZE,
+                actual: rom.chr.len(),
+            });
+        }
+
+        let mut cart = Cartridge {
             mapper: match mapper {
                 0 => Box::new(Nrom::new(rom)),
                 1 => Box::new(MMC1::new(rom)),
-                2 => Box::new(Uxrom::new(rom)),
-                _ => return Err(format!("Mapper {} is not supported", mapper)),
+                // iNES headers don't indicate whether the board has bus
+                // conflicts, so assume the common case for each mapper: most
+                // CNROM games were released on boards with them, and UxROM
+                // and AxROM games on boards without.
+                2 => {
+                    let bus_conflicts = rom.header.bus_conflicts(false);
+                    Box::new(Uxrom::new(rom, bus_conflicts))
+                }
+                3 => {
+                    let bus_conflicts = rom.header.bus_conflicts(true);
+                    Box::new(Cnrom::new(rom, mapper, bus_conflicts))
+                }
+                185 => Box::new(Cnrom::new(rom, mapper, false)),
+                4 | 118 | 119 | 206 => Box::new(MMC3::new(rom, mapper)),
+                7 => {
+                    let bus_conflicts = rom.header.bus_conflicts(false);
+                    Box::new(Axrom::new(rom, bus_conflicts))
+                }
+                9 => Box::new(MMC2::new(rom)),
+                10 => Box::new(MMC4::new(rom)),
+                19 => Box::new(Namco163::new(rom)),
+                21 | 22 | 23 | 25 => Box::new(Vrc4::new(rom, mapper)),
+                24 | 26 => Box::new(Vrc6::new(rom, mapper)),
+                // Without a submapper, the NINA-001 is told apart from BNROM
+                // by its CHR ROM, which is larger than 8 KB.
+                34 => {
+                    let nina001 = match rom.header.submapper() {
+                        1 => true,
+                        2 => false,
+                        _ => rom.header.chr_size() > CHR_PAGE_SIZE,
+                    };
+                    Box::new(Bnrom::new(rom, nina001))
+                }
+                69 => Box::new(Fme7::new(rom)),
+                71 => Box::new(Camerica::new(rom)),
+                85 => Box::new(Vrc7::new(rom)),
+                _ => return Err(RomError::UnsupportedMapper(mapper)),
             },
+            crc32,
+            battery,
         };
 
+        if let Some(trainer) = trainer {
+            cart.load_trainer(&trainer);
+        }
+
         Ok(cart)
     }
 
-    /// Returns a byte from PRG ROM at the given address.
-    pub fn read_prg(&self, addr: u16) -> u8 {
-        self.mapper.read_prg(addr)
+    /// Creates a new Cartridge from a ROM file in iNES or NES 2.0 format.
+    // The emulator reads ROMs itself, to unzip and patch them first.
+    #[allow(dead_code)]
+    pub fn from_bytes(raw: &[u8]) -> Result<Cartridge, RomError> {
+        Cartridge::new(Rom::new(raw)?)
+    }
+
+    /// Creates a new Cartridge from a ROM file in iNES or NES 2.0 format,
+    /// read to the end of the given reader.
+    #[allow(dead_code)]
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Cartridge, RomError> {
+        let mut raw = vec![];
+        reader
+            .read_to_end(&mut raw)
+            .map_err(|err| RomError::Io(err.kind()))?;
+
+        Cartridge::from_bytes(&raw)
+    }
+
+    /// Writes a trainer into the PRG RAM at $7000-$71FF, which only takes
+    /// effect on boards whose PRG RAM is writable at power on.
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        for (addr, &data) in (0x7000..).zip(trainer) {
+            self.cpu_write(addr, data);
+        }
+    }
+
+    /// Creates a new Cartridge which maps the data of the given NSF.
+    pub fn from_nsf(nsf: &Nsf) -> Cartridge {
+        Cartridge {
+            mapper: Box::new(NsfMapper::new(&nsf.data, nsf.load_addr, nsf.banks)),
+            crc32: rom::crc32(&nsf.data),
+            battery: false,
+        }
     }
 
-    /// Writes a byte to PRG ROM at the given address.
-    pub fn write_prg(&mut self, addr: u16, data: u8) {
-        self.mapper.write_prg(addr, data)
+    /// Returns the CRC32 of the ROM, or of the data of an NSF.
+    pub fn crc32(&self) -> u32 {
+        self.crc32
     }
 
-    /// Returns a byte from CHR ROM at the given address.
-    pub fn read_chr(&self, addr: u16) -> u8 {
-        self.mapper.read_chr(addr)
+    /// Returns the PRG RAM kept by the battery, if the cartridge is
+    /// battery-backed.
+    pub fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        match self.battery {
+            true => self.mapper.prg_ram(),
+            false => None,
+        }
     }
 
-    /// Writes a byte to CHR ROM at the given address.
-    pub fn write_chr(&mut self, addr: u16, data: u8) {
-        self.mapper.write_chr(addr, data)
+    /// Returns a byte from the CPU address space at the given address.
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_read(addr)
     }
 
-    /// Returns the Mirroring mode.
-    pub fn mirroring(&self) -> Mirroring {
-        self.mapper.mirroring()
+    /// Returns a byte from the CPU address space at the given address,
+    /// without any of the side effects of a read.
+    pub fn cpu_peek(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_peek(addr)
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.cpu_write(addr, data)
+    }
+
+    /// Returns true if the cartridge drives the data bus when the CPU reads
+    /// the given address.
+    pub fn drives_cpu_bus(&self, addr: u16) -> bool {
+        self.mapper.drives_cpu_bus(addr)
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr)
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address,
+    /// without any of the side effects of a read.
+    pub fn ppu_peek(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_peek(addr)
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_write(addr, data)
+    }
+
+    /// Returns a byte from the nametables at the given address.
+    pub fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        self.mapper.nametable_read(addr, vram)
+    }
+
+    /// Writes a byte to the nametables at the given address.
+    pub fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        self.mapper.nametable_write(addr, data, vram)
+    }
+
+    /// Returns true if the mapper is asserting the CPU IRQ line.
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+
+    /// Notifies the mapper of a rising edge on PPU address line A12.
+    pub fn notify_a12_rising_edge(&mut self) {
+        self.mapper.notify_a12_rising_edge()
+    }
+
+    /// Clocks the mapper by one CPU cycle.
+    pub fn clock(&mut self) {
+        self.mapper.clock()
+    }
+
+    /// Clocks the expansion audio of the cartridge by one CPU cycle,
+    /// returning its output scaled by its volume. Returns 0.0 if the
+    /// cartridge has no expansion audio.
+    pub fn clock_expansion_audio(&mut self) -> f32 {
+        match self.mapper.expansion_audio() {
+            Some(audio) => {
+                audio.clock();
+                audio.output() * audio.volume()
+            }
+            None => 0.0,
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::apu::ExpansionAudio;
     use crate::rom::tests::test_rom;
+    use crate::state::{Snapshot, StateBuffer};
 
     /// Creates a new Cartridge from the given PRG ROM data.
-    pub fn test_cartridge(prg: Vec<u8>, mirroring: Option<Mirroring>) -> Result<Cartridge, String> {
+    pub fn test_cartridge(
+        prg: Vec<u8>,
+        mirroring: Option<Mirroring>,
+    ) -> Result<Cartridge, RomError> {
         let rom = test_rom(1, prg, 1, vec![], None, None, mirroring).unwrap();
 
         Ok(Cartridge {
+            crc32: rom.crc32(),
+            battery: false,
             mapper: Box::new(Nrom::new(rom)),
         })
     }
@@ -82,35 +279,188 @@ pub mod tests {
     #[test]
     fn test_new_cartridge() {
         let prg = vec![0; 16384];
-        let cartridge = test_cartridge(prg.clone(), None).unwrap();
-        assert_eq!(cartridge.read_prg(0), prg[0]);
+        let mut cartridge = test_cartridge(prg.clone(), None).unwrap();
+        assert_eq!(cartridge.cpu_read(0), prg[0]);
     }
 
     #[test]
     fn test_read_prg() {
         let prg = vec![0; 16384];
-        let cartridge = test_cartridge(prg.clone(), None).unwrap();
-        assert_eq!(cartridge.read_prg(0), prg[0]);
+        let mut cartridge = test_cartridge(prg.clone(), None).unwrap();
+        assert_eq!(cartridge.cpu_read(0), prg[0]);
+    }
+
+    /// Returns a ROM file with a 16 KB PRG ROM, where the first byte holds
+    /// $42, and an 8 KB CHR ROM for the given mapper.
+    fn test_rom_file(mapper: u8) -> Vec<u8> {
+        let mut raw = b"NES\x1a\x01\x01".to_vec();
+        raw.extend([mapper << 4, mapper & 0xF0]);
+        raw.resize(16, 0);
+        raw.push(0x42);
+        raw.resize(16 + 16384 + 8192, 0);
+        raw
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut cartridge = Cartridge::from_bytes(&test_rom_file(0)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0x42);
+
+        assert_eq!(
+            Cartridge::from_bytes(&test_rom_file(0xFF)).err(),
+            Some(RomError::UnsupportedMapper(0xFF))
+        );
+    }
+
+    #[test]
+    fn test_small_roms() {
+        // NES 2.0 headers give sizes as 2^E*(M*2+1) bytes, so can be smaller
+        // than a bank.
+        let nes2 = |mapper: u8, prg_size: u8, chr_size: u8| {
+            let mut raw = test_rom_file(mapper);
+            raw[4] = prg_size;
+            raw[5] = chr_size;
+            raw[7] |= 0x08;
+            raw[9] = match (prg_size, chr_size) {
+                (1, _) => 0xF0,
+                (_, 1) => 0x0F,
+                _ => 0xFF,
+            };
+            raw
+        };
+
+        // 8 KB of PRG ROM, as 2^13 bytes.
+        assert_eq!(
+            Cartridge::from_bytes(&nes2(4, 13 << 2, 1)).err(),
+            Some(RomError::PrgTooSmall {
+                minimum: 0x4000,
+                actual: 0x2000
+            })
+        );
+
+        // 1 KB of CHR ROM, as 2^10 bytes.
+        assert_eq!(
+            Cartridge::from_bytes(&nes2(0, 1, 10 << 2)).err(),
+            Some(RomError::ChrTooSmall {
+                minimum: 0x2000,
+                actual: 0x400
+            })
+        );
+
+        // The MMC2 needs at least 32 KB.
+        assert_eq!(
+            Cartridge::from_bytes(&test_rom_file(9)).err(),
+            Some(RomError::PrgTooSmall {
+                minimum: 0x8000,
+                actual: 0x4000
+            })
+        );
+    }
+
+    /// A reader which always fails.
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let raw = test_rom_file(0);
+        let mut cartridge = Cartridge::from_reader(raw.as_slice()).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0x42);
+
+        assert_eq!(
+            Cartridge::from_reader(FailingReader).err(),
+            Some(RomError::Io(std::io::ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_load_trainer() {
+        let mut cartridge = test_cartridge(vec![0; 16384], None).unwrap();
+        cartridge.load_trainer(&[0x42; 512]);
+
+        assert_eq!(cartridge.cpu_read(0x6FFF), 0);
+        assert_eq!(cartridge.cpu_read(0x7000), 0x42);
+        assert_eq!(cartridge.cpu_read(0x71FF), 0x42);
+        assert_eq!(cartridge.cpu_read(0x7200), 0);
     }
 
     #[test]
     fn test_write_prg() {
         let prg = vec![0; 16384];
         let mut cartridge = test_cartridge(prg.clone(), None).unwrap();
-        cartridge.write_prg(0x6000, 1);
-        assert_eq!(cartridge.read_prg(0x6000), 1);
+        cartridge.cpu_write(0x6000, 1);
+        assert_eq!(cartridge.cpu_read(0x6000), 1);
     }
 
     #[test]
     fn test_read_chr() {
-        let cartridge = test_cartridge(vec![0; 16384], None).unwrap();
-        assert_eq!(cartridge.read_chr(0), 0);
+        let mut cartridge = test_cartridge(vec![0; 16384], None).unwrap();
+        assert_eq!(cartridge.ppu_read(0), 0);
+    }
+
+    /// A mapper with expansion audio which outputs a constant level.
+    struct ExpansionMapper;
+
+    impl Snapshot for ExpansionMapper {
+        fn snapshot(&mut self, _state: &mut StateBuffer) {}
+    }
+
+    impl Mapper for ExpansionMapper {
+        fn cpu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn ppu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+
+        fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+            Some(self)
+        }
+    }
+
+    impl ExpansionAudio for ExpansionMapper {
+        fn clock(&mut self) {}
+
+        fn output(&self) -> f32 {
+            0.5
+        }
+
+        fn volume(&self) -> f32 {
+            0.5
+        }
+    }
+
+    #[test]
+    fn test_clock_expansion_audio() {
+        let mut cartridge = test_cartridge(vec![0; 16384], None).unwrap();
+        assert_eq!(cartridge.clock_expansion_audio(), 0.0);
+
+        let mut cartridge = Cartridge {
+            mapper: Box::new(ExpansionMapper),
+            crc32: 0,
+            battery: false,
+        };
+        assert_eq!(cartridge.clock_expansion_audio(), 0.25);
     }
 
     #[test]
     fn test_mirroring() {
         let prg = vec![0; 16384];
         let cartridge = test_cartridge(prg.clone(), None).unwrap();
-        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        assert_eq!(cartridge.mapper.mirroring(), Mirroring::Horizontal);
     }
 }
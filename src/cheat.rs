@@ -0,0 +1,170 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Represents an error parsing a cheat code.
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    /// The code isn't in the form AAAA:VV or AAAA:VV:w.
+    InvalidFormat,
+
+    /// The address isn't in the internal RAM ($0000-$1FFF) or PRG RAM
+    /// ($6000-$7FFF).
+    NotRam(u16),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheatError::InvalidFormat => {
+                write!(f, "cheat must be in the form AAAA:VV or AAAA:VV:w")
+            }
+            CheatError::NotRam(addr) => write!(f, "${:04X} is not a RAM address", addr),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+/// When a RAM cheat forces its value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatMode {
+    /// The value is written at the start of every vertical blank, before the
+    /// game's NMI handler runs.
+    EachFrame,
+
+    /// The value replaces any value the game writes to the address.
+    OnWrite,
+}
+
+/// Represents a RAM cheat (or Pro Action Replay code), which forces a byte of
+/// RAM to a value, such as the number of lives.
+///
+/// Cheats are written as a hex address and value, AAAA:VV, with a :w suffix
+/// to force the value on write rather than each frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RamCheat {
+    pub addr: u16,
+    pub value: u8,
+    pub mode: CheatMode,
+}
+
+impl FromStr for RamCheat {
+    type Err = CheatError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = code.trim().split(':').collect();
+        let (addr, value, mode) = match parts[..] {
+            [addr, value] => (addr, value, CheatMode::EachFrame),
+            [addr, value, "w" | "W"] => (addr, value, CheatMode::OnWrite),
+            _ => return Err(CheatError::InvalidFormat),
+        };
+
+        if addr.len() != 4 || value.len() != 2 {
+            return Err(CheatError::InvalidFormat);
+        }
+        let addr = u16::from_str_radix(addr, 16).map_err(|_| CheatError::InvalidFormat)?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| CheatError::InvalidFormat)?;
+
+        match addr {
+            // Internal RAM is mirrored up to $1FFF.
+            0x0000..=0x1FFF => Ok(RamCheat {
+                addr: addr & 0x07FF,
+                value,
+                mode,
+            }),
+            0x6000..=0x7FFF => Ok(RamCheat { addr, value, mode }),
+            _ => Err(CheatError::NotRam(addr)),
+        }
+    }
+}
+
+/// The cheats applied to the running game.
+pub struct Cheats {
+    ram: Vec<RamCheat>,
+}
+
+impl Cheats {
+    /// Returns an empty set of cheats.
+    pub fn new() -> Self {
+        Cheats { ram: vec![] }
+    }
+
+    /// Adds a RAM cheat.
+    pub fn add_ram(&mut self, cheat: RamCheat) {
+        self.ram.push(cheat);
+    }
+
+    /// Returns the RAM cheats which are forced each frame.
+    pub fn frame_writes(&self) -> impl Iterator<Item = &RamCheat> {
+        self.ram
+            .iter()
+            .filter(|cheat| cheat.mode == CheatMode::EachFrame)
+    }
+
+    /// Returns the value to write to the given address, which is replaced if
+    /// a cheat forces the address on write. Internal RAM addresses must be
+    /// mirrored down to $0000-$07FF.
+    pub fn filter_write(&self, addr: u16, data: u8) -> u8 {
+        self.ram
+            .iter()
+            .rev()
+            .find(|cheat| cheat.mode == CheatMode::OnWrite && cheat.addr == addr)
+            .map_or(data, |cheat| cheat.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "0075:09".parse(),
+            Ok(RamCheat {
+                addr: 0x0075,
+                value: 0x09,
+                mode: CheatMode::EachFrame,
+            })
+        );
+        assert_eq!(
+            "7ffe:ff:w".parse(),
+            Ok(RamCheat {
+                addr: 0x7FFE,
+                value: 0xFF,
+                mode: CheatMode::OnWrite,
+            })
+        );
+
+        // Mirrors of internal RAM are mirrored down.
+        assert_eq!("0875:09".parse::<RamCheat>().unwrap().addr, 0x0075);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for code in ["", "0075", "75:09", "0075:9", "0075:09:x", "00G5:09"] {
+            assert_eq!(
+                code.parse::<RamCheat>(),
+                Err(CheatError::InvalidFormat),
+                "{}",
+                code
+            );
+        }
+
+        assert_eq!(
+            "8000:09".parse::<RamCheat>(),
+            Err(CheatError::NotRam(0x8000))
+        );
+    }
+
+    #[test]
+    fn test_filter_write() {
+        let mut cheats = Cheats::new();
+        cheats.add_ram("0075:09".parse().unwrap());
+        cheats.add_ram("0076:63:w".parse().unwrap());
+
+        assert_eq!(cheats.filter_write(0x0075, 0x01), 0x01);
+        assert_eq!(cheats.filter_write(0x0076, 0x01), 0x63);
+        assert_eq!(cheats.frame_writes().count(), 1);
+    }
+}
@@ -0,0 +1,1235 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::apu::AudioChannel;
+use crate::joypad::{
+    JOYPAD_BUTTON_A, JOYPAD_BUTTON_B, JOYPAD_DOWN, JOYPAD_LEFT, JOYPAD_RIGHT, JOYPAD_SELECT,
+    JOYPAD_START, JOYPAD_UP,
+};
+use crate::ram_init::RamInit;
+use crate::region::Region;
+use crate::run_ahead::MAX_RUN_AHEAD;
+use crate::video::{Filter, PixelAspect};
+
+/// The most lines or columns that may be cropped from an edge of the frames.
+const MAX_OVERSCAN: u32 = 64;
+
+/// Default pixel scaling factor of the window.
+const DEFAULT_SCALE: f32 = 3.0;
+
+/// The default settings other than the keys, written at the start of a new
+/// config.
+const DEFAULT_SETTINGS: &str = r#"# Config of the emulator. Settings left out keep their defaults, and
+# command line arguments override them.
+
+[video]
+# Pixel scaling factor of the window, from 1 to 16.
+scale = 3.0
+# Fill the screen with the window.
+fullscreen = false
+# path/to/.pal file of the colours to draw, in place of the built-in palette.
+# palette = "/path/to/palette.pal"
+# Filter the frames are drawn through: "none", "scale2x", "scale3x" or "crt".
+filter = "none"
+# Shape of the pixels: "square", or "8:7" to draw them as wide as an NTSC TV.
+aspect = "square"
+# Keep the shape of the frames when the window is resized, with black bars
+# filling the rest, or stretch them over the whole window.
+letterbox = true
+# Scale the frames by whole multiples of their height when letterboxed.
+integer_scaling = false
+# Number of seconds of frames kept to save as a GIF with the hotkey, from 0 to
+# 10. 0 keeps none.
+gif_seconds = 5.0
+# Show messages, such as a state being saved, over the frames for a few
+# seconds.
+messages = true
+
+[video.crt]
+# Intensity of each effect of the CRT filter, from 0 to 1.
+# Dark gaps between the scanlines.
+scanlines = 0.5
+# Shadow mask tinting each column of pixels red, green or blue.
+mask = 0.3
+# Curve of the screen.
+curvature = 0.5
+# Darkening of the edges of the screen.
+vignette = 0.3
+
+[video.overscan]
+# Lines and columns cropped from each edge of the frames, from 0 to 64. Edges
+# left out are cropped by the default of the console region: 8 lines at the
+# top and bottom on NTSC, and none on PAL.
+# top = 8
+# bottom = 8
+# left = 0
+# right = 0
+
+[audio]
+# Pass the audio through the filters of the console's audio output.
+filters = true
+# Part of the name of the output device, or the default device if unset.
+# device = "USB"
+# Length of audio queued ahead of the output device, in milliseconds, from 10
+# to 500. Less reacts to the controls sooner, but may crackle on slow machines.
+latency = 50
+# Number of samples the output device plays at a time, from 64 to 8192.
+buffer_size = 1024
+
+[emulation]
+# Limit the number of sprites per scanline to 8 like the console. Games
+# flicker sprites to work around the limit.
+sprite_limit = true
+# Pattern the RAM holds at power on: "zero", "ff", "striped" or "random".
+ram_init = "zero"
+# Number of frames to run ahead of the frame shown, from 0 to 4, to hide the
+# input lag of games. Each costs as much CPU time as emulating the console.
+run_ahead = 0
+# Speed of the fast forward hotkey, from 1 to 16 times the console's, or 0 to
+# run as fast as possible, without sound.
+fast_forward = 4.0
+# Speed of the slow motion hotkey, from 0.1 to 1 times the console's.
+slow_motion = 0.5
+
+[gamepad]
+# How far an analog stick is pushed before it presses the D-pad, from 0 to 1.
+deadzone = 0.5
+# Part of the name of the gamepads used by each player, or any if unset.
+# player1 = "8BitDo"
+# player2 = "Xbox"
+
+[paths]
+# Directory games' battery saves and save states are kept in, by default the
+# platform's data directory.
+# data_dir = "/path/to/saves"
+# path/to/database of NES 2.0 headers, used to correct bad headers. It's a
+# text file with a line per ROM, holding the CRC32 of the ROM after its header,
+# the header in hex and, optionally, the name of the game.
+# rom_db = "/path/to/headers.txt"
+
+# Keys are given by their SDL names, such as "A", "Space" or "Keypad 8". A
+# key set to "" is unbound.
+"#;
+
+/// Default keys of player 1, in the order of the ControllerKeys fields.
+const PLAYER1_KEYS: [&str; 10] = [
+    "Up", "Down", "Left", "Right", "Space", "Return", "A", "S", "Q", "W",
+];
+
+/// Default keys of player 2, on the keypad.
+const PLAYER2_KEYS: [&str; 10] = [
+    "Keypad 8",
+    "Keypad 2",
+    "Keypad 4",
+    "Keypad 6",
+    "Keypad -",
+    "Keypad Enter",
+    "Keypad 0",
+    "Keypad .",
+    "Keypad 1",
+    "Keypad 3",
+];
+
+/// Default hotkeys, in the order of the Hotkeys fields.
+const HOTKEYS: [&str; 32] = [
+    "Escape",
+    "F9",
+    "F10",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "Scroll Lock",
+    "M",
+    "F7",
+    "F8",
+    "0",
+    "1",
+    "2",
+    "3",
+    "4",
+    "5",
+    "6",
+    "7",
+    "8",
+    "9",
+    "F12",
+    "F11",
+    "Tab",
+    "Backspace",
+    "P",
+    "K",
+    "O",
+    "G",
+    "I",
+];
+
+/// Represents an error loading the config.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file couldn't be read.
+    Io(std::io::Error),
+
+    /// The config isn't valid TOML, or has unknown or mistyped settings.
+    Parse(toml::de::Error),
+
+    /// A key name that isn't recognised, with the setting it is given for.
+    UnknownKey { setting: String, key: String },
+
+    /// A key bound to two actions, with their settings.
+    DuplicateKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+
+    /// A setting with a value out of range, with the range.
+    OutOfRange {
+        setting: &'static str,
+        range: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "failed to read the config, {}", err),
+            ConfigError::Parse(err) => write!(f, "{}", err),
+            ConfigError::UnknownKey { setting, key } => {
+                write!(f, "{} is set to \"{}\", which is not a key", setting, key)
+            }
+            ConfigError::DuplicateKey { key, first, second } => write!(
+                f,
+                "key \"{}\" is bound to both {} and {}",
+                key, first, second
+            ),
+            ConfigError::OutOfRange { setting, range } => {
+                write!(f, "{} must be in the range {}", setting, range)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The emulator config, loaded from a TOML file. Settings left out keep
+/// their defaults, and command line arguments override them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub keys: KeyBindings,
+    pub gamepad: GamepadConfig,
+    pub emulation: EmulationConfig,
+    pub paths: PathsConfig,
+}
+
+impl Config {
+    /// Returns the config loaded from the TOML file at the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&text)
+    }
+
+    /// Returns the config parsed from the given TOML.
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(ConfigError::Parse)?;
+        if !(1.0..=16.0).contains(&config.video.scale) {
+            return Err(ConfigError::OutOfRange {
+                setting: "video.scale",
+                range: "1 to 16",
+            });
+        }
+        if !(0.0..=10.0).contains(&config.video.gif_seconds) {
+            return Err(ConfigError::OutOfRange {
+                setting: "video.gif_seconds",
+                range: "0 to 10",
+            });
+        }
+        config.video.overscan.check([
+            "video.overscan.top",
+            "video.overscan.bottom",
+            "video.overscan.left",
+            "video.overscan.right",
+        ])?;
+        let crt = &config.video.crt;
+        for (setting, intensity) in [
+            ("video.crt.scanlines", crt.scanlines),
+            ("video.crt.mask", crt.mask),
+            ("video.crt.curvature", crt.curvature),
+            ("video.crt.vignette", crt.vignette),
+        ] {
+            if !(0.0..=1.0).contains(&intensity) {
+                return Err(ConfigError::OutOfRange {
+                    setting,
+                    range: "0 to 1",
+                });
+            }
+        }
+        if !(10..=500).contains(&config.audio.latency) {
+            return Err(ConfigError::OutOfRange {
+                setting: "audio.latency",
+                range: "10 to 500",
+            });
+        }
+        if !(64..=8192).contains(&config.audio.buffer_size) {
+            return Err(ConfigError::OutOfRange {
+                setting: "audio.buffer_size",
+                range: "64 to 8192",
+            });
+        }
+        if !(0.0..1.0).contains(&config.gamepad.deadzone) {
+            return Err(ConfigError::OutOfRange {
+                setting: "gamepad.deadzone",
+                range: "0 to 1",
+            });
+        }
+        if config.emulation.run_ahead > MAX_RUN_AHEAD {
+            return Err(ConfigError::OutOfRange {
+                setting: "emulation.run_ahead",
+                range: "0 to 4",
+            });
+        }
+        let fast_forward = config.emulation.fast_forward;
+        if fast_forward != 0.0 && !(1.0..=16.0).contains(&fast_forward) {
+            return Err(ConfigError::OutOfRange {
+                setting: "emulation.fast_forward",
+                range: "1 to 16, or 0",
+            });
+        }
+        if !(0.1..=1.0).contains(&config.emulation.slow_motion) {
+            return Err(ConfigError::OutOfRange {
+                setting: "emulation.slow_motion",
+                range: "0.1 to 1",
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the TOML of the default config, with every setting given and
+    /// described.
+    pub fn default_toml() -> String {
+        let mut text = DEFAULT_SETTINGS.to_string();
+        let mut section = String::new();
+        for (setting, _, key) in KeyBindings::default().bindings() {
+            let (name_section, name) = setting.rsplit_once('.').unwrap();
+            if name_section != section {
+                section = name_section.to_string();
+                text.push_str(&format!("\n[{}]\n", section));
+            }
+
+            let key = toml::Value::String(key.to_string());
+            text.push_str(&format!("{} = {}\n", name, key));
+        }
+
+        text
+    }
+
+    /// Writes the default config to a new file at the given path, creating
+    /// its directory. Fails if the file already exists.
+    pub fn write_default<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        file.write_all(Self::default_toml().as_bytes())
+    }
+}
+
+/// The video settings.
+///
+/// [video]
+/// scale = 4.0
+/// fullscreen = true
+/// palette = "/path/to/palette.pal"
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    /// Pixel scaling factor of the window, from 1 to 16.
+    pub scale: f32,
+
+    /// Fill the screen with the window.
+    pub fullscreen: bool,
+
+    /// path/to/.pal file of the colours to draw, if not the built-in
+    /// palette.
+    pub palette: Option<String>,
+
+    /// Filter the frames are drawn through.
+    pub filter: Filter,
+
+    /// Shape of the pixels.
+    pub aspect: PixelAspect,
+
+    /// Keep the shape of the frames in a resized window, between black
+    /// bars, instead of stretching them over it.
+    pub letterbox: bool,
+
+    /// Scale the frames by whole multiples of their height when
+    /// letterboxed.
+    pub integer_scaling: bool,
+
+    /// Number of seconds of frames kept to save as a GIF with the hotkey,
+    /// from 0 to 10, or 0 to keep none.
+    pub gif_seconds: f64,
+
+    /// Show messages, such as a state being saved, over the frames for a
+    /// few seconds.
+    pub messages: bool,
+
+    pub crt: CrtConfig,
+
+    pub overscan: OverscanConfig,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            palette: None,
+            filter: Filter::None,
+            aspect: PixelAspect::Square,
+            letterbox: true,
+            integer_scaling: false,
+            gif_seconds: 5.0,
+            messages: true,
+            crt: CrtConfig::default(),
+            overscan: OverscanConfig::default(),
+        }
+    }
+}
+
+/// The settings of the CRT filter, with the intensity of each effect from 0
+/// to 1.
+///
+/// [video.crt]
+/// scanlines = 0.75
+/// curvature = 0.0
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrtConfig {
+    /// Dark gaps between the scanlines.
+    pub scanlines: f32,
+
+    /// Shadow mask tinting each column of pixels red, green or blue.
+    pub mask: f32,
+
+    /// Curve of the screen.
+    pub curvature: f32,
+
+    /// Darkening of the edges of the screen.
+    pub vignette: f32,
+}
+
+impl Default for CrtConfig {
+    fn default() -> Self {
+        CrtConfig {
+            scanlines: 0.5,
+            mask: 0.3,
+            curvature: 0.5,
+            vignette: 0.3,
+        }
+    }
+}
+
+/// The audio settings.
+///
+/// [audio]
+/// filters = false
+/// device = "USB"
+/// latency = 100
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Pass the audio through the filters of the console's audio output.
+    pub filters: bool,
+
+    /// Part of the name of the output device, or the default device if
+    /// unset.
+    pub device: Option<String>,
+
+    /// Length of audio queued ahead of the output device, in milliseconds.
+    pub latency: u32,
+
+    /// Number of samples the output device plays at a time.
+    pub buffer_size: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            filters: true,
+            device: None,
+            latency: 50,
+            buffer_size: 1024,
+        }
+    }
+}
+
+/// Default distance an analog stick is pushed before it presses the D-pad,
+/// from 0 to 1.
+pub const DEFAULT_DEADZONE: f32 = 0.5;
+
+/// The gamepad settings.
+///
+/// [gamepad]
+/// deadzone = 0.5
+/// player1 = "8BitDo"
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadConfig {
+    /// How far an analog stick is pushed before it presses the D-pad, from
+    /// 0 to 1.
+    pub deadzone: f32,
+
+    /// Part of the name of the gamepads used by player 1, or any gamepad if
+    /// unset.
+    pub player1: Option<String>,
+
+    /// Part of the name of the gamepads used by player 2, or any gamepad if
+    /// unset.
+    pub player2: Option<String>,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        GamepadConfig {
+            deadzone: DEFAULT_DEADZONE,
+            player1: None,
+            player2: None,
+        }
+    }
+}
+
+/// The lines and columns cropped from each edge of the frames before they're
+/// scaled. Edges left out are cropped by the default of the console region:
+/// 8 lines at the top and bottom on NTSC, and none on PAL.
+///
+/// [video.overscan]
+/// top = 16
+/// left = 8
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverscanConfig {
+    pub top: Option<u32>,
+    pub bottom: Option<u32>,
+    pub left: Option<u32>,
+    pub right: Option<u32>,
+}
+
+impl OverscanConfig {
+    /// Returns an error if an edge is cropped by more than the most allowed,
+    /// naming its setting, given for the top, bottom, left and right edges.
+    fn check(&self, settings: [&'static str; 4]) -> Result<(), ConfigError> {
+        let edges = [self.top, self.bottom, self.left, self.right];
+        for (setting, edge) in settings.into_iter().zip(edges) {
+            if edge.is_some_and(|edge| edge > MAX_OVERSCAN) {
+                return Err(ConfigError::OutOfRange {
+                    setting,
+                    range: "0 to 64",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns these edges, with those left out taken from the given
+    /// overscan.
+    pub fn or(&self, other: &OverscanConfig) -> OverscanConfig {
+        OverscanConfig {
+            top: self.top.or(other.top),
+            bottom: self.bottom.or(other.bottom),
+            left: self.left.or(other.left),
+            right: self.right.or(other.right),
+        }
+    }
+}
+
+/// The emulation settings.
+///
+/// [emulation]
+/// sprite_limit = false
+/// ram_init = "random"
+/// run_ahead = 1
+/// fast_forward = 0.0
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmulationConfig {
+    /// Limit the number of sprites per scanline to 8 like the console.
+    pub sprite_limit: bool,
+
+    /// Pattern the CPU RAM, VRAM and OAM hold at power on.
+    pub ram_init: RamInit,
+
+    /// Number of frames the console runs ahead of the frame shown, to hide
+    /// the input lag of games, from 0 to 4. Each frame ahead costs as much
+    /// CPU time as emulating the console again.
+    pub run_ahead: u8,
+
+    /// Speed of the fast forward hotkey, relative to the console, from 1 to
+    /// 16, or 0 to run as fast as possible.
+    pub fast_forward: f64,
+
+    /// Speed of the slow motion hotkey, relative to the console, from 0.1
+    /// to 1.
+    pub slow_motion: f64,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        EmulationConfig {
+            sprite_limit: true,
+            ram_init: RamInit::Zero,
+            run_ahead: 0,
+            fast_forward: 4.0,
+            slow_motion: 0.5,
+        }
+    }
+}
+
+/// The paths of files and directories the emulator uses.
+///
+/// [paths]
+/// data_dir = "/path/to/saves"
+/// rom_db = "/path/to/headers.txt"
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Directory games' battery saves and save states are kept in, if not
+    /// the platform's data directory.
+    pub data_dir: Option<String>,
+
+    /// path/to/database of NES 2.0 headers, used to correct bad headers, in
+    /// the text format read by RomDatabase.
+    pub rom_db: Option<String>,
+}
+
+/// The config of a game, overriding the emulator config and the command line
+/// for it, loaded from the config.toml in the game's directory of the data
+/// directory, which is named after the CRC32 of its ROM. Settings left out
+/// are left alone.
+///
+/// region = "pal"
+/// zapper = true
+/// sprite_limit = false
+///
+/// [overscan]
+/// top = 16
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// Region of the console, in place of the one given by the ROM header.
+    pub region: Option<Region>,
+
+    /// Connect a Zapper to the second controller port, or a controller.
+    pub zapper: Option<bool>,
+
+    /// path/to/.pal file of the colours to draw.
+    pub palette: Option<String>,
+
+    /// Limit the number of sprites per scanline to 8 like the console.
+    pub sprite_limit: Option<bool>,
+
+    /// Pattern the CPU RAM, VRAM and OAM hold at power on.
+    pub ram_init: Option<RamInit>,
+
+    /// Lines and columns cropped from each edge of the frames.
+    pub overscan: OverscanConfig,
+}
+
+impl GameConfig {
+    /// Returns the game config loaded from the TOML file at the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&text)
+    }
+
+    /// Returns the game config parsed from the given TOML.
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: GameConfig = toml::from_str(text).map_err(ConfigError::Parse)?;
+        config.overscan.check([
+            "overscan.top",
+            "overscan.bottom",
+            "overscan.left",
+            "overscan.right",
+        ])?;
+
+        Ok(config)
+    }
+}
+
+/// An action bound to a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyAction {
+    /// Presses a controller button, given the player and button.
+    Button(u8, u8),
+
+    /// Holds a controller button with turbo, given the player and button.
+    Turbo(u8, u8),
+
+    /// Mutes an audio channel, or solos it with Shift held.
+    Channel(AudioChannel),
+
+    /// Starts or stops recording audio.
+    RecordAudio,
+
+    /// Starts or stops recording audio channel stems.
+    RecordStems,
+
+    /// Starts or stops sending the keys to the Family BASIC keyboard.
+    CaptureKeyboard,
+
+    /// Makes a sound into the microphone on Famicom controller 2 while held.
+    Microphone,
+
+    /// Saves a save state to the selected slot.
+    SaveState,
+
+    /// Loads the save state in the selected slot.
+    LoadState,
+
+    /// Selects the save state slot, from 0 to 9.
+    SelectSlot(u8),
+
+    /// Selects the next video filter.
+    CycleFilter,
+
+    /// Switches the window between fullscreen and windowed.
+    ToggleFullscreen,
+
+    /// Runs at the fast forward speed while held.
+    FastForward,
+
+    /// Starts or stops running at the slow motion speed.
+    SlowMotion,
+
+    /// Pauses or resumes the emulation.
+    Pause,
+
+    /// Advances a frame while paused, or pauses.
+    FrameAdvance,
+
+    /// Starts or stops recording video.
+    RecordVideo,
+
+    /// Saves the last few seconds of frames as an animated GIF.
+    SaveGif,
+
+    /// Shows or hides the performance overlay.
+    TogglePerfOverlay,
+
+    Quit,
+}
+
+/// The keys bound to the controllers and hotkeys, given by name, such as
+/// "A", "Space" or "Keypad 8". A key set to "" is unbound.
+///
+/// [keys.player1]
+/// up = "Up"
+/// turbo_a = "Q"
+///
+/// [keys.hotkeys]
+/// record_audio = "F9"
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    player1: ControllerKeys,
+    player2: ControllerKeys,
+    hotkeys: Hotkeys,
+}
+
+/// The keys bound to the buttons of a controller, or the defaults if unset.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ControllerKeys {
+    up: Option<String>,
+    down: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+    select: Option<String>,
+    start: Option<String>,
+    a: Option<String>,
+    b: Option<String>,
+    turbo_a: Option<String>,
+    turbo_b: Option<String>,
+}
+
+/// The keys bound to emulator actions, or the defaults if unset.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Hotkeys {
+    quit: Option<String>,
+    record_audio: Option<String>,
+    record_stems: Option<String>,
+    mute_pulse1: Option<String>,
+    mute_pulse2: Option<String>,
+    mute_triangle: Option<String>,
+    mute_noise: Option<String>,
+    mute_dmc: Option<String>,
+    mute_expansion: Option<String>,
+    capture_keyboard: Option<String>,
+    microphone: Option<String>,
+    save_state: Option<String>,
+    load_state: Option<String>,
+    select_slot0: Option<String>,
+    select_slot1: Option<String>,
+    select_slot2: Option<String>,
+    select_slot3: Option<String>,
+    select_slot4: Option<String>,
+    select_slot5: Option<String>,
+    select_slot6: Option<String>,
+    select_slot7: Option<String>,
+    select_slot8: Option<String>,
+    select_slot9: Option<String>,
+    cycle_filter: Option<String>,
+    toggle_fullscreen: Option<String>,
+    fast_forward: Option<String>,
+    slow_motion: Option<String>,
+    pause: Option<String>,
+    frame_advance: Option<String>,
+    record_video: Option<String>,
+    save_gif: Option<String>,
+    perf_overlay: Option<String>,
+}
+
+impl KeyBindings {
+    /// Returns the setting, action and key name of each binding.
+    fn bindings(&self) -> Vec<(String, KeyAction, &str)> {
+        let mut bindings = vec![];
+        for (player, keys, defaults) in [
+            (1, &self.player1, PLAYER1_KEYS),
+            (2, &self.player2, PLAYER2_KEYS),
+        ] {
+            let settings = [
+                ("up", KeyAction::Button(player, JOYPAD_UP), &keys.up),
+                ("down", KeyAction::Button(player, JOYPAD_DOWN), &keys.down),
+                ("left", KeyAction::Button(player, JOYPAD_LEFT), &keys.left),
+                (
+                    "right",
+                    KeyAction::Button(player, JOYPAD_RIGHT),
+                    &keys.right,
+                ),
+                (
+                    "select",
+                    KeyAction::Button(player, JOYPAD_SELECT),
+                    &keys.select,
+                ),
+                (
+                    "start",
+                    KeyAction::Button(player, JOYPAD_START),
+                    &keys.start,
+                ),
+                ("a", KeyAction::Button(player, JOYPAD_BUTTON_A), &keys.a),
+                ("b", KeyAction::Button(player, JOYPAD_BUTTON_B), &keys.b),
+                (
+                    "turbo_a",
+                    KeyAction::Turbo(player, JOYPAD_BUTTON_A),
+                    &keys.turbo_a,
+                ),
+                (
+                    "turbo_b",
+                    KeyAction::Turbo(player, JOYPAD_BUTTON_B),
+                    &keys.turbo_b,
+                ),
+            ];
+
+            for ((name, action, key), default) in settings.into_iter().zip(defaults) {
+                let setting = format!("keys.player{}.{}", player, name);
+                bindings.push((setting, action, key.as_deref().unwrap_or(default)));
+            }
+        }
+
+        let hotkeys = &self.hotkeys;
+        let settings = [
+            ("quit", KeyAction::Quit, &hotkeys.quit),
+            (
+                "record_audio",
+                KeyAction::RecordAudio,
+                &hotkeys.record_audio,
+            ),
+            (
+                "record_stems",
+                KeyAction::RecordStems,
+                &hotkeys.record_stems,
+            ),
+            (
+                "mute_pulse1",
+                KeyAction::Channel(AudioChannel::Pulse1),
+                &hotkeys.mute_pulse1,
+            ),
+            (
+                "mute_pulse2",
+                KeyAction::Channel(AudioChannel::Pulse2),
+                &hotkeys.mute_pulse2,
+            ),
+            (
+                "mute_triangle",
+                KeyAction::Channel(AudioChannel::Triangle),
+                &hotkeys.mute_triangle,
+            ),
+            (
+                "mute_noise",
+                KeyAction::Channel(AudioChannel::Noise),
+                &hotkeys.mute_noise,
+            ),
+            (
+                "mute_dmc",
+                KeyAction::Channel(AudioChannel::Dmc),
+                &hotkeys.mute_dmc,
+            ),
+            (
+                "mute_expansion",
+                KeyAction::Channel(AudioChannel::Expansion),
+                &hotkeys.mute_expansion,
+            ),
+            (
+                "capture_keyboard",
+                KeyAction::CaptureKeyboard,
+                &hotkeys.capture_keyboard,
+            ),
+            ("microphone", KeyAction::Microphone, &hotkeys.microphone),
+            ("save_state", KeyAction::SaveState, &hotkeys.save_state),
+            ("load_state", KeyAction::LoadState, &hotkeys.load_state),
+        ];
+        let slots = [
+            &hotkeys.select_slot0,
+            &hotkeys.select_slot1,
+            &hotkeys.select_slot2,
+            &hotkeys.select_slot3,
+            &hotkeys.select_slot4,
+            &hotkeys.select_slot5,
+            &hotkeys.select_slot6,
+            &hotkeys.select_slot7,
+            &hotkeys.select_slot8,
+            &hotkeys.select_slot9,
+        ];
+        let settings = settings
+            .into_iter()
+            .map(|(name, action, key)| (name.to_string(), action, key))
+            .chain(slots.into_iter().enumerate().map(|(slot, key)| {
+                let name = format!("select_slot{}", slot);
+                (name, KeyAction::SelectSlot(slot as u8), key)
+            }))
+            .chain([
+                (
+                    "cycle_filter".to_string(),
+                    KeyAction::CycleFilter,
+                    &hotkeys.cycle_filter,
+                ),
+                (
+                    "toggle_fullscreen".to_string(),
+                    KeyAction::ToggleFullscreen,
+                    &hotkeys.toggle_fullscreen,
+                ),
+                (
+                    "fast_forward".to_string(),
+                    KeyAction::FastForward,
+                    &hotkeys.fast_forward,
+                ),
+                (
+                    "slow_motion".to_string(),
+                    KeyAction::SlowMotion,
+                    &hotkeys.slow_motion,
+                ),
+                ("pause".to_string(), KeyAction::Pause, &hotkeys.pause),
+                (
+                    "frame_advance".to_string(),
+                    KeyAction::FrameAdvance,
+                    &hotkeys.frame_advance,
+                ),
+                (
+                    "record_video".to_string(),
+                    KeyAction::RecordVideo,
+                    &hotkeys.record_video,
+                ),
+                (
+                    "save_gif".to_string(),
+                    KeyAction::SaveGif,
+                    &hotkeys.save_gif,
+                ),
+                (
+                    "perf_overlay".to_string(),
+                    KeyAction::TogglePerfOverlay,
+                    &hotkeys.perf_overlay,
+                ),
+            ]);
+        for ((name, action, key), default) in settings.zip(HOTKEYS) {
+            let setting = format!("keys.hotkeys.{}", name);
+            bindings.push((setting, action, key.as_deref().unwrap_or(default)));
+        }
+
+        bindings
+    }
+
+    /// Returns the action bound to each key, given a function returning the
+    /// key with a name, if there is one. Fails if a key name isn't
+    /// recognised or a key is bound twice.
+    pub fn key_map<K, F>(&self, parse_key: F) -> Result<HashMap<K, KeyAction>, ConfigError>
+    where
+        K: Eq + Hash,
+        F: Fn(&str) -> Option<K>,
+    {
+        let mut bound: HashMap<K, (KeyAction, String)> = HashMap::new();
+        for (setting, action, name) in self.bindings() {
+            if name.is_empty() {
+                continue;
+            }
+
+            let Some(key) = parse_key(name) else {
+                return Err(ConfigError::UnknownKey {
+                    setting,
+                    key: name.to_string(),
+                });
+            };
+            if let Some((_, first)) = bound.get(&key) {
+                return Err(ConfigError::DuplicateKey {
+                    key: name.to_string(),
+                    first: first.clone(),
+                    second: setting,
+                });
+            }
+
+            bound.insert(key, (action, setting));
+        }
+
+        Ok(bound
+            .into_iter()
+            .map(|(key, (action, _))| (key, action))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the action bound to each key in the given config, taking key
+    /// names as they are.
+    fn test_key_map(text: &str) -> Result<HashMap<String, KeyAction>, ConfigError> {
+        Config::from_toml(text)?
+            .keys
+            .key_map(|name| Some(name.to_string()))
+    }
+
+    #[test]
+    fn test_default_keys() {
+        let key_map = test_key_map("").unwrap();
+        assert_eq!(key_map.len(), 52);
+        assert_eq!(key_map["A"], KeyAction::Button(1, JOYPAD_BUTTON_A));
+        assert_eq!(key_map["Keypad 3"], KeyAction::Turbo(2, JOYPAD_BUTTON_B));
+        assert_eq!(key_map["F9"], KeyAction::RecordAudio);
+        assert_eq!(key_map["F6"], KeyAction::Channel(AudioChannel::Expansion));
+        assert_eq!(key_map["F7"], KeyAction::SaveState);
+        assert_eq!(key_map["3"], KeyAction::SelectSlot(3));
+        assert_eq!(key_map["F12"], KeyAction::CycleFilter);
+        assert_eq!(key_map["F11"], KeyAction::ToggleFullscreen);
+        assert_eq!(key_map["Tab"], KeyAction::FastForward);
+        assert_eq!(key_map["K"], KeyAction::FrameAdvance);
+        assert_eq!(key_map["I"], KeyAction::TogglePerfOverlay);
+    }
+
+    #[test]
+    fn test_rebind_keys() {
+        let key_map =
+            test_key_map("[keys.player1]\na = \"Z\"\nb = \"X\"\n\n[keys.hotkeys]\nquit = \"\"\n")
+                .unwrap();
+        assert_eq!(key_map.len(), 51);
+        assert_eq!(key_map["Z"], KeyAction::Button(1, JOYPAD_BUTTON_A));
+        assert_eq!(key_map["X"], KeyAction::Button(1, JOYPAD_BUTTON_B));
+        assert_eq!(key_map["Up"], KeyAction::Button(1, JOYPAD_UP));
+        assert!(!key_map.contains_key("A"));
+        assert!(!key_map.contains_key("Escape"));
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(matches!(
+            test_key_map("[keys.player1]\njump = \"A\"\n"),
+            Err(ConfigError::Parse(_))
+        ));
+
+        let err = Config::from_toml("[keys.player2]\nup = \"Foo\"\n")
+            .unwrap()
+            .keys
+            .key_map(|name| (name != "Foo").then(|| name.to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "keys.player2.up is set to \"Foo\", which is not a key"
+        );
+
+        let err = test_key_map("[keys.player2]\nstart = \"Return\"\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "key \"Return\" is bound to both keys.player1.start and keys.player2.start"
+        );
+    }
+
+    #[test]
+    fn test_gamepad() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.gamepad.deadzone, DEFAULT_DEADZONE);
+
+        let config = Config::from_toml("[gamepad]\ndeadzone = 0.25\nplayer2 = \"Xbox\"\n").unwrap();
+        assert_eq!(config.gamepad.deadzone, 0.25);
+        assert_eq!(config.gamepad.player1, None);
+        assert_eq!(config.gamepad.player2.as_deref(), Some("Xbox"));
+
+        let err = Config::from_toml("[gamepad]\ndeadzone = 1.5\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "gamepad.deadzone must be in the range 0 to 1"
+        );
+    }
+
+    #[test]
+    fn test_audio() {
+        let config = Config::from_toml("[audio]\nlatency = 100\ndevice = \"USB\"\n").unwrap();
+        assert_eq!(config.audio.latency, 100);
+        assert_eq!(config.audio.device.as_deref(), Some("USB"));
+
+        let err = Config::from_toml("[audio]\nlatency = 5\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "audio.latency must be in the range 10 to 500"
+        );
+        let err = Config::from_toml("[audio]\nbuffer_size = 16\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "audio.buffer_size must be in the range 64 to 8192"
+        );
+    }
+
+    #[test]
+    fn test_video() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.video.scale, DEFAULT_SCALE);
+        assert!(!config.video.fullscreen);
+
+        let config = Config::from_toml("[video]\nscale = 2.0\nfullscreen = true\n").unwrap();
+        assert_eq!(config.video.scale, 2.0);
+        assert!(config.video.fullscreen);
+
+        let err = Config::from_toml("[video]\nscale = 0.0\n").unwrap_err();
+        assert_eq!(err.to_string(), "video.scale must be in the range 1 to 16");
+
+        let config =
+            Config::from_toml("[video]\nfilter = \"crt\"\n[video.crt]\nmask = 0.0\n").unwrap();
+        assert_eq!(config.video.filter, Filter::Crt);
+        assert_eq!(config.video.crt.mask, 0.0);
+        assert_eq!(config.video.crt.scanlines, 0.5);
+
+        let config =
+            Config::from_toml("[video]\naspect = \"8:7\"\ninteger_scaling = true\n").unwrap();
+        assert_eq!(config.video.aspect, PixelAspect::Ntsc);
+        assert!(config.video.integer_scaling);
+        assert!(config.video.letterbox);
+
+        let config = Config::from_toml("[video.overscan]\nleft = 8\n").unwrap();
+        assert_eq!(config.video.overscan.left, Some(8));
+        assert_eq!(config.video.overscan.top, None);
+
+        let err = Config::from_toml("[video.overscan]\ntop = 100\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "video.overscan.top must be in the range 0 to 64"
+        );
+
+        let err = Config::from_toml("[video.crt]\ncurvature = 2.0\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "video.crt.curvature must be in the range 0 to 1"
+        );
+    }
+
+    #[test]
+    fn test_emulation() {
+        assert_eq!(Config::default().emulation.run_ahead, 0);
+        let config =
+            Config::from_toml("[emulation]\nrun_ahead = 2\nram_init = \"striped\"\n").unwrap();
+        assert_eq!(config.emulation.run_ahead, 2);
+        assert_eq!(config.emulation.ram_init, RamInit::Striped);
+        assert!(config.emulation.sprite_limit);
+
+        let config = Config::from_toml("[emulation]\nfast_forward = 0.0\n").unwrap();
+        assert_eq!(config.emulation.fast_forward, 0.0);
+        assert_eq!(config.emulation.slow_motion, 0.5);
+
+        let err = Config::from_toml("[emulation]\nfast_forward = 0.5\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "emulation.fast_forward must be in the range 1 to 16, or 0"
+        );
+
+        let err = Config::from_toml("[emulation]\nrun_ahead = 5\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "emulation.run_ahead must be in the range 0 to 4"
+        );
+    }
+
+    #[test]
+    fn test_game_config() {
+        let config = GameConfig::from_toml("").unwrap();
+        assert_eq!(config.region, None);
+        assert_eq!(config.zapper, None);
+
+        let config = GameConfig::from_toml(
+            "region = \"pal\"\nzapper = true\nram_init = \"ff\"\n[overscan]\ntop = 16\n",
+        )
+        .unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert_eq!(config.zapper, Some(true));
+        assert_eq!(config.ram_init, Some(RamInit::Ff));
+        assert_eq!(config.sprite_limit, None);
+
+        let global = OverscanConfig {
+            top: Some(8),
+            left: Some(4),
+            ..OverscanConfig::default()
+        };
+        let overscan = config.overscan.or(&global);
+        assert_eq!(overscan.top, Some(16));
+        assert_eq!(overscan.left, Some(4));
+        assert_eq!(overscan.bottom, None);
+
+        let err = GameConfig::from_toml("[overscan]\nleft = 65\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "overscan.left must be in the range 0 to 64"
+        );
+        assert!(GameConfig::from_toml("[video]\nscale = 2.0\n").is_err());
+    }
+
+    #[test]
+    fn test_default_toml() {
+        let config = Config::from_toml(&Config::default_toml()).unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.video.scale, defaults.video.scale);
+        assert_eq!(config.video.crt.mask, defaults.video.crt.mask);
+        assert_eq!(config.audio.filters, defaults.audio.filters);
+        assert_eq!(config.audio.device, None);
+        assert_eq!(config.audio.latency, defaults.audio.latency);
+        assert_eq!(config.audio.buffer_size, defaults.audio.buffer_size);
+        assert_eq!(config.emulation.ram_init, defaults.emulation.ram_init);
+        assert_eq!(config.gamepad.deadzone, defaults.gamepad.deadzone);
+        assert_eq!(config.paths.data_dir, None);
+
+        let key_map = |config: &Config| config.keys.key_map(|name| Some(name.to_string()));
+        assert_eq!(key_map(&config).unwrap(), key_map(&defaults).unwrap());
+        assert!(Config::default_toml().contains("\n[keys.hotkeys]\nquit = \"Escape\"\n"));
+    }
+}
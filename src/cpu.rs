@@ -1,7 +1,11 @@
 use core::panic;
+use std::io::{self, Write};
 
 use crate::bus::SystemBus;
 use crate::instructions::OPCODES;
+use crate::state::{self, ChunkId, Chunked, Snapshot, StateBuffer, StateError};
+use crate::thumbnail::Thumbnail;
+use crate::trace::trace;
 
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
@@ -61,8 +65,6 @@ pub enum AddressingMode {
     Implied,
 }
 
-// TODO(dr): Define trait for remainder of system bus operations.
-
 pub trait Memory {
     /// Returns the byte at the given address in memory.
     fn mem_read_byte(&mut self, addr: u16) -> u8;
@@ -77,14 +79,23 @@ pub trait Memory {
 
         u16::from_le_bytes([lo, hi])
     }
+}
 
-    /// Writes two bytes to memory, split from the data word, as addr and addr + 1.
-    fn mem_write_word(&mut self, addr: u16, data: u16) {
-        let bytes = data.to_le_bytes();
+/// The bus which connects the CPU to the rest of the system, such as the NES
+/// or a flat 64 KB RAM.
+pub trait Bus: Memory {
+    /// Returns the byte at the given address without any of the side effects
+    /// of a read, for debuggers and the tracer.
+    fn mem_peek_byte(&mut self, addr: u16) -> u8;
 
-        self.mem_write_byte(addr, bytes[0]);
-        self.mem_write_byte(addr + 1, bytes[1]);
-    }
+    /// Advances the rest of the system by the given number of CPU cycles.
+    fn tick(&mut self, cycles: u8);
+
+    /// Returns true if a non-maskable interrupt has been raised, clearing it.
+    fn nmi_status(&mut self) -> bool;
+
+    /// Returns true if the IRQ line is being asserted.
+    fn irq_status(&self) -> bool;
 }
 
 const CARRY: u8 = 0b00000001;
@@ -104,8 +115,12 @@ const STATUS_DEFAULT: u8 = 0b00100100;
 // Reset vector points to the beginning of the PRG ROM.
 const RESET_VECTOR: u16 = 0xFFFC;
 
-/// Represents the NES CPU.
-pub struct Cpu<'a> {
+// Return address used when calling a subroutine from outside the CPU. It lies
+// in the disabled APU test registers, so never holds code.
+const SUBROUTINE_RETURN: u16 = 0x4018;
+
+/// Represents the NES CPU, connected to the system by the given bus.
+pub struct Cpu<B: Bus> {
     /// Accumulator, a special register for storing results of arithmetic and
     /// logical operations.
     pub a: u8,
@@ -143,35 +158,37 @@ pub struct Cpu<'a> {
 
     /// Handles data read/write, interrupts, memory mapping and PPU/CPU clock
     /// cycles.
-    pub bus: SystemBus<'a>,
+    pub bus: B,
+
+    /// Number of cycles spent on memory accesses by the current instruction
+    /// or interrupt, which have already been ticked on the bus.
+    access_cycles: u8,
 }
 
-impl Memory for Cpu<'_> {
+/// Each memory access takes a CPU cycle, which is ticked on the bus before
+/// the access resolves. This way the PPU, APU and cartridge have caught up
+/// with the CPU when a register is read or written, as on the real console.
+///
+/// See: https://www.nesdev.org/wiki/CPU_pin_out_and_signal_description
+impl<B: Bus> Memory for Cpu<B> {
     /// Returns the byte at the given address in memory.
     fn mem_read_byte(&mut self, addr: u16) -> u8 {
+        self.access_cycle();
         self.bus.mem_read_byte(addr)
     }
 
     /// Writes the data at the given address in memory.
     fn mem_write_byte(&mut self, addr: u16, data: u8) {
+        self.access_cycle();
         self.bus.mem_write_byte(addr, data)
     }
-
-    /// Returns a word from memory, merged from the two bytes at addr and addr + 1.
-    fn mem_read_word(&mut self, addr: u16) -> u16 {
-        self.bus.mem_read_word(addr)
-    }
-
-    /// Writes two bytes to memory, split from the data word, as addr and addr + 1.
-    fn mem_write_word(&mut self, addr: u16, data: u16) {
-        self.bus.mem_write_word(addr, data)
-    }
 }
 
 mod interrupt {
     #[derive(PartialEq, Eq)]
     pub enum InterruptType {
         Nmi,
+        Irq,
     }
 
     #[derive(PartialEq, Eq)]
@@ -187,11 +204,90 @@ mod interrupt {
         status_mask: 0b00100000,
         cpu_cycles: 7,
     };
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::Irq,
+        vector_addr: 0xFFFE,
+        status_mask: 0b00100000,
+        cpu_cycles: 7,
+    };
+}
+
+/// The registers of a CPU, which are saved in their own chunk.
+struct Registers<'a, B: Bus>(&'a mut Cpu<B>);
+
+impl<B: Bus> Snapshot for Registers<'_, B> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        let cpu = &mut self.0;
+        cpu.a.snapshot(state);
+        cpu.x.snapshot(state);
+        cpu.y.snapshot(state);
+        cpu.status.snapshot(state);
+        cpu.pc.snapshot(state);
+        cpu.sp.snapshot(state);
+        cpu.access_cycles.snapshot(state);
+    }
+}
+
+impl<B: Bus + Chunked> Chunked for Cpu<B> {
+    fn chunks(&mut self, visit: &mut dyn FnMut(ChunkId, u16, &mut dyn Snapshot)) {
+        visit(*b"CPU ", 1, &mut Registers(self));
+        self.bus.chunks(visit);
+    }
 }
 
-impl<'a> Cpu<'a> {
+impl<B: Bus + Chunked> Snapshot for Cpu<B> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        state::snapshot_chunks(self, state);
+    }
+}
+
+impl Cpu<SystemBus<'_>> {
+    /// Returns a save state file of the console, holding the state of the
+    /// CPU and everything on its bus, and a thumbnail of the frame.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let crc32 = self.bus.rom_crc32();
+        let mut file = state::save_file(self, crc32);
+        Thumbnail::from_frame(self.bus.frame()).append_to_state(&mut file);
+        file
+    }
+
+    /// Loads a save state file of the console, which must have been saved
+    /// with the same game. If the state can't be loaded the console carries
+    /// on as it was.
+    pub fn load_state(&mut self, file: &[u8]) -> Result<(), StateError> {
+        let crc32 = self.bus.rom_crc32();
+        state::load_file(self, crc32, file)
+    }
+
+    /// Clocks the CPU until the PPU has rendered a frame, returning true if
+    /// the CPU should be shut down.
+    pub fn run_frame(&mut self) -> bool {
+        let frame_count = self.bus.ppu_frame_count();
+        let mut halted = false;
+        while !halted && self.bus.ppu_frame_count() == frame_count {
+            halted = self.clock();
+        }
+
+        halted
+    }
+
+    /// Clocks the CPU until the PPU has rendered a frame, like run_frame,
+    /// writing the trace of each instruction to the log before it's run.
+    pub fn trace_frame<W: Write>(&mut self, log: &mut W) -> io::Result<bool> {
+        let frame_count = self.bus.ppu_frame_count();
+        let mut halted = false;
+        while !halted && self.bus.ppu_frame_count() == frame_count {
+            writeln!(log, "{}", trace(self))?;
+            halted = self.clock();
+        }
+
+        Ok(halted)
+    }
+}
+
+impl<B: Bus> Cpu<B> {
     /// Returns an instantiated CPU.
-    pub fn new(bus: SystemBus) -> Cpu {
+    pub fn new(bus: B) -> Self {
         Cpu {
             a: 0,
             x: 0,
@@ -200,6 +296,7 @@ impl<'a> Cpu<'a> {
             pc: 0,
             sp: STACK_RESET,
             bus,
+            access_cycles: 0,
         }
     }
 
@@ -214,7 +311,26 @@ impl<'a> Cpu<'a> {
         self.sp = STACK_RESET;
         self.status = STATUS_DEFAULT;
 
-        self.pc = self.mem_read_word(RESET_VECTOR);
+        self.pc = self.bus.mem_read_word(RESET_VECTOR);
+    }
+
+    /// Calls the subroutine at the given address, clocking the CPU until it
+    /// returns. Returns true if the CPU should be shut down.
+    ///
+    /// This is used to drive code which is not started by the reset vector,
+    /// such as the init and play routines of an NSF.
+    pub fn call_subroutine(&mut self, addr: u16) -> bool {
+        // Mimic JSR, which pushes the return address minus one.
+        self.stack_push_word(SUBROUTINE_RETURN - 1);
+        self.pc = addr;
+
+        while self.pc != SUBROUTINE_RETURN {
+            if self.clock() {
+                return true;
+            }
+        }
+
+        false
     }
 
     /// Pops a byte off the stack and increments the stack pointer.
@@ -262,57 +378,70 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    /// Sets the pressed state of the given button.
-    pub fn set_button_pressed_status(&mut self, button: u8, pressed: bool) {
-        self.bus.joypad1.set_button_pressed_status(button, pressed);
+    /// Returns the address of the operand for a given non-immediate addressing
+    /// mode, without any of the side effects of reading it or ticking the bus,
+    /// for the tracer.
+    pub fn peek_operand_address(&mut self, mode: &AddressingMode, operand: u16) -> (u16, bool) {
+        self.operand_address(mode, operand, |cpu, addr| cpu.bus.mem_peek_byte(addr))
     }
 
     /// Returns the address of the operand for a given non-immediate addressing
-    /// mode.
-    pub fn get_operand_mode_address(&mut self, mode: &AddressingMode, operand: u16) -> (u16, bool) {
+    /// mode, reading the operand and any pointers with the given function.
+    fn operand_address<F>(
+        &mut self,
+        mode: &AddressingMode,
+        operand: u16,
+        mut read: F,
+    ) -> (u16, bool)
+    where
+        F: FnMut(&mut Self, u16) -> u8,
+    {
         match mode {
             AddressingMode::Immediate => (operand, false),
 
-            AddressingMode::ZeroPage => (self.mem_read_byte(operand) as u16, false),
+            AddressingMode::ZeroPage => (read(self, operand) as u16, false),
 
-            AddressingMode::Absolute => (self.mem_read_word(operand), false),
+            AddressingMode::Absolute => (
+                u16::from_le_bytes([read(self, operand), read(self, operand + 1)]),
+                false,
+            ),
 
             AddressingMode::ZeroPageX => {
-                let pos = self.mem_read_byte(operand);
+                let pos = read(self, operand);
                 let addr = pos.wrapping_add(self.x) as u16;
                 (addr, false)
             }
             AddressingMode::ZeroPageY => {
-                let pos = self.mem_read_byte(operand);
+                let pos = read(self, operand);
                 let addr = pos.wrapping_add(self.y) as u16;
                 (addr, false)
             }
 
             AddressingMode::AbsoluteX => {
-                let base = self.mem_read_word(operand);
+                let base = u16::from_le_bytes([read(self, operand), read(self, operand + 1)]);
                 let addr = base.wrapping_add(self.x as u16);
                 (addr, page_cross(base, addr))
             }
             AddressingMode::AbsoluteY => {
-                let base = self.mem_read_word(operand);
+                let base = u16::from_le_bytes([read(self, operand), read(self, operand + 1)]);
                 let addr = base.wrapping_add(self.y as u16);
                 (addr, page_cross(base, addr))
             }
 
             AddressingMode::IndirectX => {
-                let base = self.mem_read_byte(operand);
+                let base = read(self, operand);
 
                 let ptr: u8 = base.wrapping_add(self.x);
-                let lo = self.mem_read_byte(ptr as u16);
-                let hi = self.mem_read_byte(ptr.wrapping_add(1) as u16);
+                let lo = read(self, ptr as u16);
+                let hi = read(self, ptr.wrapping_add(1) as u16);
 
                 (u16::from_le_bytes([lo, hi]), false)
             }
             AddressingMode::IndirectY => {
-                let base = self.mem_read_byte(operand);
+                let base = read(self, operand);
 
-                let lo = self.mem_read_byte(base as u16);
-                let hi = self.mem_read_byte(base.wrapping_add(1) as u16);
+                let lo = read(self, base as u16);
+                let hi = read(self, base.wrapping_add(1) as u16);
 
                 let deref_base = u16::from_le_bytes([lo, hi]);
                 let deref = deref_base.wrapping_add(self.y as u16);
@@ -330,6 +459,8 @@ impl<'a> Cpu<'a> {
     pub fn clock(&mut self) -> bool {
         if self.bus.nmi_status() {
             self.interrupt(interrupt::NMI);
+        } else if self.bus.irq_status() && self.status & INTERRUPT_DISABLE == 0 {
+            self.interrupt(interrupt::IRQ);
         }
 
         // Get the opcode at the program counter.
@@ -633,9 +764,10 @@ impl<'a> Cpu<'a> {
             0x9B => self.tas(&opcode.mode),
         }
 
-        // Inform the bus the number of CPU cycles for this operation in
-        // order for the other components to process as appropriate.
-        self.bus.tick(opcode.cycles);
+        // Inform the bus of the remaining CPU cycles for this operation, those
+        // which didn't access memory, in order for the other components to
+        // process as appropriate.
+        self.finish_cycles(opcode.cycles);
 
         // Program counter needs to be incremented by the number of bytes
         // used in the opcode, if not done so elsewhere.
@@ -652,7 +784,7 @@ impl<'a> Cpu<'a> {
     fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
         match mode {
             AddressingMode::Immediate => (self.pc, false),
-            _ => self.get_operand_mode_address(mode, self.pc),
+            _ => self.operand_address(mode, self.pc, |cpu, addr| cpu.mem_read_byte(addr)),
         }
     }
 
@@ -663,14 +795,11 @@ impl<'a> Cpu<'a> {
     /// is set, this enables multiple byte addition to be performed.
     fn adc(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
         self.add_to_accumulator(param);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// AND - Logical AND.
@@ -679,14 +808,11 @@ impl<'a> Cpu<'a> {
     /// the contents of a byte of memory.
     fn and(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
         self.set_accumulator(self.a & param);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// ASL: Arithmetic Shift Left
@@ -935,14 +1061,11 @@ impl<'a> Cpu<'a> {
     /// using the contents of a byte of memory.
     fn eor(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
         self.set_accumulator(self.a ^ param);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// INC: Increment Memory
@@ -999,13 +1122,10 @@ impl<'a> Cpu<'a> {
     /// negative flags as appropriate.
     fn lda(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
         let data = self.mem_read_byte(addr);
 
         self.set_accumulator(data);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// LDX: Load X Register
@@ -1014,15 +1134,12 @@ impl<'a> Cpu<'a> {
     /// flags as appropriate.
     fn ldx(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
         self.x = param;
 
         self.update_zero_and_negative_flags(self.x);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// LDY: Load Y Register
@@ -1031,15 +1148,12 @@ impl<'a> Cpu<'a> {
     /// flags as appropriate.
     fn ldy(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
         self.y = param;
 
         self.update_zero_and_negative_flags(self.y);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// LSR: Logical Shift Right
@@ -1090,14 +1204,11 @@ impl<'a> Cpu<'a> {
     /// using the contents of a byte of memory.
     fn ora(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
         self.set_accumulator(self.a | param);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// PHA: Push Accumulator
@@ -1276,14 +1387,11 @@ impl<'a> Cpu<'a> {
     /// performed.
     fn sbc(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
         self.add_to_accumulator(param.wrapping_neg().wrapping_sub(1));
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// SEC: Set Carry Flag.
@@ -1462,11 +1570,8 @@ impl<'a> Cpu<'a> {
     /// no register nor flags
     fn ign(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
         self.mem_read_byte(addr);
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// ISB.
@@ -1683,6 +1788,7 @@ impl<'a> Cpu<'a> {
     /// appropriate status flags.
     fn compare(&mut self, mode: &AddressingMode, data: u8) {
         let (addr, page_cross) = self.get_operand_address(mode);
+        self.page_cross_cycle(page_cross);
 
         let param = self.mem_read_byte(addr);
 
@@ -1693,10 +1799,6 @@ impl<'a> Cpu<'a> {
         }
 
         self.update_zero_and_negative_flags(data.wrapping_sub(param));
-
-        if page_cross {
-            self.bus.tick(1);
-        }
     }
 
     /// Sets the Z (zero) and N (negative) flags on the CPU status based on the
@@ -1740,8 +1842,6 @@ impl<'a> Cpu<'a> {
     fn jmp_indirect(&mut self) {
         let addr = self.mem_read_word(self.pc);
 
-        let mut jump_addr = self.mem_read_word(addr);
-
         // Example:
         //
         // Assume a memory layout of:
@@ -1756,14 +1856,10 @@ impl<'a> Cpu<'a> {
         //
         // However, 6502 will fetch the high byte from $3000, resulting in a
         // jump to $4080 instead!
-        if addr & 0x00FF == 0x00FF {
-            let lo = self.mem_read_byte(addr);
-            let hi = self.mem_read_byte(addr & 0xFF00);
-
-            jump_addr = u16::from_le_bytes([lo, hi]);
-        }
+        let lo = self.mem_read_byte(addr);
+        let hi = self.mem_read_byte((addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF));
 
-        self.pc = jump_addr;
+        self.pc = u16::from_le_bytes([lo, hi]);
     }
 
     /// Handles the CPU interrupt.
@@ -1780,8 +1876,31 @@ impl<'a> Cpu<'a> {
         // Set interrupt disable flag.
         self.status |= INTERRUPT_DISABLE;
 
-        self.bus.tick(interrupt.cpu_cycles);
         self.pc = self.mem_read_word(interrupt.vector_addr);
+        self.finish_cycles(interrupt.cpu_cycles);
+    }
+
+    /// Ticks the bus for a memory access made by the current instruction or
+    /// interrupt.
+    fn access_cycle(&mut self) {
+        self.bus.tick(1);
+        self.access_cycles += 1;
+    }
+
+    /// Ticks the bus for the extra cycle taken when indexing crosses a page,
+    /// which comes before the operand is read.
+    fn page_cross_cycle(&mut self, page_cross: bool) {
+        if page_cross {
+            self.bus.tick(1);
+        }
+    }
+
+    /// Ticks the bus for the remaining cycles of an instruction or interrupt,
+    /// which takes the given number of cycles on top of any extra cycles for
+    /// branching and page crossing.
+    fn finish_cycles(&mut self, cycles: u8) {
+        self.bus.tick(cycles.saturating_sub(self.access_cycles));
+        self.access_cycles = 0;
     }
 }
 
@@ -1805,7 +1924,7 @@ mod tests {
     use std::io::{BufRead, BufReader};
     use std::rc::Rc;
 
-    fn test_cpu(cart: Cartridge) -> Cpu<'static> {
+    fn test_cpu(cart: Cartridge) -> Cpu<SystemBus<'static>> {
         let mut cpu = Cpu::new(SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {}));
 
         // Force the program counter to the start of PRG ROM.
@@ -1817,7 +1936,7 @@ mod tests {
     }
 
     // Runs the CPU for the given number of cycles.
-    fn run_test_cpu(cpu: &mut Cpu, cycles: u8) {
+    fn run_test_cpu<B: Bus>(cpu: &mut Cpu<B>, cycles: u8) {
         for _ in 0..cycles {
             let halted = cpu.clock();
             if halted {
@@ -1826,6 +1945,96 @@ mod tests {
         }
     }
 
+    /// A bus with 64 KB of RAM filling the whole address space, which counts
+    /// the cycles it is ticked.
+    struct FlatRam {
+        ram: Vec<u8>,
+        cycles: u64,
+
+        /// The cycle and address of each read.
+        reads: Vec<(u64, u16)>,
+    }
+
+    impl FlatRam {
+        /// Returns a flat RAM with the given program loaded at $0200.
+        fn new(program: &[u8]) -> Self {
+            let mut ram = vec![0; 0x10000];
+            ram[0x0200..0x0200 + program.len()].copy_from_slice(program);
+            FlatRam {
+                ram,
+                cycles: 0,
+                reads: vec![],
+            }
+        }
+    }
+
+    impl Memory for FlatRam {
+        fn mem_read_byte(&mut self, addr: u16) -> u8 {
+            self.reads.push((self.cycles, addr));
+            self.ram[addr as usize]
+        }
+
+        fn mem_write_byte(&mut self, addr: u16, data: u8) {
+            self.ram[addr as usize] = data;
+        }
+    }
+
+    impl Bus for FlatRam {
+        fn mem_peek_byte(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.cycles += cycles as u64;
+        }
+
+        fn nmi_status(&mut self) -> bool {
+            false
+        }
+
+        fn irq_status(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_flat_ram_bus() {
+        // LDA #$42; STA $1234; INC $1234
+        let mut cpu = Cpu::new(FlatRam::new(&[
+            0xA9, 0x42, 0x8D, 0x34, 0x12, 0xEE, 0x34, 0x12,
+        ]));
+        cpu.pc = 0x0200;
+        run_test_cpu(&mut cpu, 3);
+
+        // The NES would mirror $1234 to $0234 in its 2 KB of RAM.
+        assert_eq!(cpu.bus.ram[0x1234], 0x43);
+        assert_eq!(cpu.bus.ram[0x0234], 0);
+        assert_eq!(cpu.bus.cycles, 2 + 4 + 6);
+    }
+
+    #[test]
+    fn test_access_cycles() {
+        // LDX #$01; LDA $12FF,X
+        let mut cpu = Cpu::new(FlatRam::new(&[0xA2, 0x01, 0xBD, 0xFF, 0x12]));
+        cpu.pc = 0x0200;
+        run_test_cpu(&mut cpu, 2);
+
+        // Each read is made once the bus has been ticked for its cycle, and
+        // the read across a page comes after the extra cycle.
+        assert_eq!(
+            cpu.bus.reads,
+            [
+                (1, 0x0200),
+                (2, 0x0201),
+                (3, 0x0202),
+                (4, 0x0203),
+                (5, 0x0204),
+                (7, 0x1300),
+            ]
+        );
+        assert_eq!(cpu.bus.cycles, 2 + 5);
+    }
+
     #[test]
     fn test_0xa9_lda_immediate_load_data() {
         let cart = test_cartridge(vec![0xA9, 0x05], None).unwrap();
@@ -1860,6 +2069,24 @@ mod tests {
         assert_eq!(cpu.a, 0x55);
     }
 
+    #[test]
+    fn test_lda_controller_open_bus() {
+        // LDA $4016 leaves the high byte of the address on the data bus, which
+        // fills the bits the controller doesn't drive.
+        let cart = test_cartridge(vec![0xAD, 0x16, 0x40, 0x00], None).unwrap();
+
+        let mut cpu = test_cpu(cart);
+        cpu.bus
+            .joypad1
+            .set_button_pressed_status(crate::joypad::JOYPAD_BUTTON_A, true);
+        cpu.bus.mem_write_byte(0x4016, 1);
+        cpu.bus.mem_write_byte(0x4016, 0);
+
+        run_test_cpu(&mut cpu, 1);
+
+        assert_eq!(cpu.a, 0x41);
+    }
+
     #[test]
     fn test_sta() {
         let cart = test_cartridge(vec![0xA9, 0x05, 0x85, 0x20, 0x00], None).unwrap();
@@ -1917,11 +2144,40 @@ mod tests {
         assert_eq!(cpu.x, 0xc1)
     }
 
+    #[test]
+    fn test_save_state() {
+        let cart = Cartridge::from_reader(File::open("nestest.nes").unwrap()).unwrap();
+        let mut cpu = Cpu::new(SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {}));
+        cpu.reset();
+        cpu.pc = 0xC000;
+        for _ in 0..1000 {
+            cpu.clock();
+        }
+
+        // Running on from a loaded state ends up in the same state as
+        // running on from where it was saved.
+        let saved = cpu.save_state();
+        for _ in 0..1000 {
+            cpu.clock();
+        }
+        let expected = cpu.save_state();
+        assert_ne!(saved, expected);
+
+        cpu.load_state(&saved).unwrap();
+        assert_eq!(cpu.save_state(), saved);
+        for _ in 0..1000 {
+            cpu.clock();
+        }
+        assert_eq!(cpu.save_state(), expected);
+
+        assert_eq!(cpu.load_state(&saved[..100]), Err(StateError::Truncated));
+        assert_eq!(cpu.save_state(), expected);
+    }
+
     #[test]
     fn test_compare_nestest_rom() {
         // Run test ROM to collect the trace output.
-        let bytes: Vec<u8> = std::fs::read("nestest.nes").unwrap();
-        let cart = Cartridge::new(&bytes).unwrap();
+        let cart = Cartridge::from_reader(File::open("nestest.nes").unwrap()).unwrap();
 
         let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
         let mut cpu = Cpu::new(bus);
@@ -0,0 +1,1170 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::apu::AudioChannel;
+use crate::battery::BatteryBackup;
+use crate::bus::hooks::Access;
+use crate::bus::SystemBus;
+use crate::cartridge::Cartridge;
+use crate::config::{Config, GameConfig, OverscanConfig};
+use crate::cpu::Cpu;
+use crate::frontend::Frontend;
+#[cfg(feature = "gamepad")]
+use crate::gamepad::Gamepads;
+use crate::input::InputState;
+use crate::movie::InputMovie;
+use crate::nsf::{Nsf, NsfPlayer, NSF_TAG};
+use crate::ppu::{self, Frame};
+use crate::ram_init::RamInit;
+use crate::region::Region;
+use crate::rom::{self, Rom, RomDatabase};
+use crate::run_ahead::RunAhead;
+use crate::save_slots::SaveSlots;
+use crate::storage::{self, GameStorage};
+use crate::video::{GifClip, Message, Overscan, Y4mWriter};
+use crate::Args;
+
+/// A game, or NSF, running in a frontend, with the parts of the emulator
+/// every frontend shares: the console, the gamepads, save states, battery
+/// saves, recordings and run-ahead. Frontends show the frames, play the
+/// audio and turn their input into the controllers' input and hotkeys.
+pub struct Emulator<'a> {
+    pub cpu: Cpu<SystemBus<'a>>,
+    player: Option<NsfPlayer>,
+    region: Region,
+    overscan: OverscanConfig,
+
+    /// Name of the game, or NSF, from the ROM database or file name.
+    name: String,
+
+    /// The input of the controllers, updated by the frontend and gamepads
+    /// and polled once per frame.
+    pub input: InputState,
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<Gamepads>,
+
+    slots: Option<SaveSlots>,
+    battery: Option<BatteryBackup>,
+    run_ahead: RunAhead,
+
+    /// Speed of the emulation relative to the console, and the frames owed
+    /// at that speed, run once they add up to a whole frame.
+    speed: f64,
+    frames_due: f64,
+
+    // Speed run at with neither hotkey on, the speeds of the fast forward and
+    // slow motion hotkeys, and whether each is on.
+    normal_speed: f64,
+    fast_forward_speed: f64,
+    slow_motion_speed: f64,
+    fast_forward: bool,
+    slow_motion: bool,
+
+    /// Whether the emulation is paused, and the number of frames to advance
+    /// while paused.
+    paused: bool,
+    frames_to_advance: u32,
+
+    /// Number of frames run since the emulator started.
+    frames_run: u64,
+
+    /// Storage the recordings made with the hotkeys are saved in, which NSFs
+    /// have for their recordings alone.
+    recordings: GameStorage,
+    record_input: Option<String>,
+
+    /// Video every frame rendered is recorded to, if any.
+    video: Rc<RefCell<Option<Y4mWriter<BufWriter<File>>>>>,
+
+    /// The last few seconds of frames, saved as a GIF with the hotkey, and
+    /// the GIF being saved in the background, if any.
+    clip: Rc<RefCell<Option<GifClip>>>,
+    gif_save: Option<JoinHandle<()>>,
+
+    /// Log the instructions run by games are traced to, if any.
+    trace: Option<BufWriter<File>>,
+
+    /// Log the register accesses of each frame are written to, if any.
+    register_log: Option<BufWriter<File>>,
+
+    /// Where messages are shown on screen, if the frontend shows them.
+    messages: Option<Sender<Message>>,
+
+    /// Called by the frontend before exiting, if set, to restore what it
+    /// changed, such as the mode of the terminal.
+    #[cfg(feature = "terminal")]
+    on_exit: Option<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a> Emulator<'a> {
+    /// Loads the ROM or NSF given by the arguments, with the audio output at
+    /// the given sample rate and each frame passed to the render function.
+    /// Exits if it can't be loaded.
+    pub fn new<F>(args: &Args, config: &Config, sample_rate: u32, render: F) -> Self
+    where
+        F: FnMut(&[u8]) + 'a,
+    {
+        Emulator::load(args, config, sample_rate, render).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
+    }
+
+    /// Loads the ROM or NSF given by the arguments, with the audio output at
+    /// the given sample rate and each frame passed to the render function.
+    /// Returns an error saying what failed if it can't be loaded.
+    pub fn load<F>(
+        args: &Args,
+        config: &Config,
+        sample_rate: u32,
+        render: F,
+    ) -> Result<Self, String>
+    where
+        F: FnMut(&[u8]) + 'a,
+    {
+        // Runs without a window leave the saves and gamepads alone, so they
+        // can be reproduced.
+        let headless = args.frontend == Frontend::Headless;
+
+        // Frames are recorded to the video, if any, as they're rendered.
+        let video = Rc::new(RefCell::new(None::<Y4mWriter<BufWriter<File>>>));
+        let recording = Rc::clone(&video);
+        let clip = Rc::new(RefCell::new(None::<GifClip>));
+        let clipping = Rc::clone(&clip);
+        let mut render = render;
+        let render = move |frame: &[u8]| {
+            let mut video = recording.borrow_mut();
+            if let Some(Err(err)) = video.as_mut().map(|video| video.write_frame(frame)) {
+                eprintln!("Stopped video recording: {}", err);
+                *video = None;
+            }
+
+            if let Some(clip) = clipping.borrow_mut().as_mut() {
+                clip.push(frame);
+            }
+
+            render(frame);
+        };
+
+        // Games' saves are kept in the data directory.
+        let data_dir = match args.data_dir.as_ref().map(PathBuf::from) {
+            Some(dir) => dir,
+            None => storage::data_dir().ok_or_else(|| {
+                "Failed to find the data directory, give one with --data-dir".to_string()
+            })?,
+        };
+
+        // Load ROM, or an NSF to play.
+        let bytes = match rom::read_file(&args.rom) {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(format!("Failed to load {}: {}", args.rom, err)),
+        };
+        let (cart, mut player, region, storage, name) = match bytes.starts_with(&NSF_TAG) {
+            true => {
+                let nsf = match Nsf::new(&bytes) {
+                    Ok(nsf) => nsf,
+                    Err(err) => return Err(format!("Failed to load {}: {}", args.rom, err)),
+                };
+                println!(
+                    "Playing \"{}\" by {} ({})",
+                    nsf.name, nsf.artist, nsf.copyright
+                );
+
+                // Unknown names are given as "<?>".
+                let name = match nsf.name.as_str() {
+                    "" | "<?>" => rom::game_name(&args.rom),
+                    name => name.to_string(),
+                };
+
+                let region = nsf.region;
+                (
+                    Cartridge::from_nsf(&nsf),
+                    Some(NsfPlayer::new(nsf)),
+                    region,
+                    GameStorage::new(&data_dir, &args.rom, rom::crc32(&bytes)),
+                    name,
+                )
+            }
+            false => {
+                let bytes = match args.patch_path() {
+                    Some(path) => {
+                        let patched = std::fs::read(&path)
+                            .map_err(|err| err.to_string())
+                            .and_then(|patch| {
+                                rom::apply_patch(&bytes, &patch).map_err(|err| err.to_string())
+                            });
+
+                        match patched {
+                            Ok(patched) => {
+                                println!("Applied patch {}", path.display());
+                                patched
+                            }
+                            Err(err) => {
+                                return Err(format!(
+                                    "Failed to apply patch {}: {}",
+                                    path.display(),
+                                    err
+                                ))
+                            }
+                        }
+                    }
+                    None => bytes,
+                };
+
+                let database =
+                    match &args.rom_db {
+                        Some(path) => Some(RomDatabase::load(path).map_err(|err| {
+                            format!("Failed to load ROM database {}: {}", path, err)
+                        })?),
+                        None => None,
+                    };
+
+                let name = database
+                    .as_ref()
+                    .and_then(|database| database.name(&bytes))
+                    .map_or_else(|| rom::game_name(&args.rom), str::to_string);
+
+                let bytes = match database.and_then(|database| database.fix_header(&bytes)) {
+                    Some(fixed) => {
+                        println!("Corrected the header of {} from the ROM database", args.rom);
+                        fixed
+                    }
+                    None => bytes,
+                };
+
+                let cart = Rom::new(&bytes).and_then(|rom| {
+                    println!("Loaded {} ({})", args.rom, rom.header);
+                    let region = Region::from_timing(rom.header.timing());
+                    let storage = GameStorage::new(&data_dir, &args.rom, rom.crc32());
+                    Cartridge::new(rom).map(|cart| (cart, region, storage))
+                });
+
+                match cart {
+                    Ok((cart, region, storage)) => (cart, None, region, storage, name),
+                    Err(err) => return Err(format!("Failed to load {}: {}", args.rom, err)),
+                }
+            }
+        };
+
+        // Recordings are kept in the game's storage, but saves only for
+        // games, not NSFs.
+        let recordings = storage.clone();
+
+        // Battery RAM and save states are kept in the game's storage, with
+        // its config overriding the settings for it.
+        let storage = Some(storage).filter(|_| player.is_none() && !headless);
+        let config_path = storage.as_ref().map(GameStorage::config_path);
+        let game_config = match config_path.filter(|path| path.exists()) {
+            Some(path) => match GameConfig::load(&path) {
+                Ok(game_config) => {
+                    println!("Loaded the game's config from {}", path.display());
+                    game_config
+                }
+                Err(err) => {
+                    return Err(format!("Failed to load config {}: {}", path.display(), err))
+                }
+            },
+            None => GameConfig::default(),
+        };
+        let args = &args.with_game_config(&game_config);
+        let overscan = game_config.overscan.or(&config.video.overscan);
+        let cart = Rc::new(RefCell::new(cart));
+        let mut battery = storage
+            .as_ref()
+            .and_then(|storage| BatteryBackup::new(Rc::clone(&cart), storage.battery_path()));
+        let slots = storage.map(SaveSlots::new);
+        if let Some(battery) = battery.as_mut() {
+            match battery.load() {
+                Ok(true) => println!("Loaded the saved game from {}", battery.path().display()),
+                Ok(false) => {}
+                Err(err) => {
+                    return Err(format!(
+                        "Failed to load {}: {}",
+                        battery.path().display(),
+                        err
+                    ))
+                }
+            }
+        }
+
+        let mut bus = SystemBus::new(cart, sample_rate as f32, render);
+
+        let region = args.region.unwrap_or(region);
+        bus.set_region(region);
+        if let Some(player) = player.as_mut() {
+            player.set_region(region);
+        }
+        let ram_seed = args.ram_seed.unwrap_or_else(rand::random);
+        bus.set_ram_init(args.ram_init, ram_seed);
+        bus.set_sprite_limit(!args.no_sprite_limit);
+        bus.set_audio_filters(!args.no_audio_filters);
+        bus.set_turbo_rate(args.turbo_rate);
+        bus.set_keyboard_connected(args.family_keyboard);
+        for cheat in &args.cheats {
+            bus.add_cheat(*cheat);
+        }
+        for watch in &args.watches {
+            let access = watch.access;
+            bus.add_memory_hook(
+                access,
+                watch.range.clone(),
+                move |addr, data| match access {
+                    Access::Read => println!("Read ${:02X} from ${:04X}", data, addr),
+                    Access::Write => println!("Wrote ${:02X} to ${:04X}", data, addr),
+                },
+            );
+        }
+
+        if let Some(path) = &args.record_input {
+            bus.record_movie();
+            println!("Recording input to {}", path);
+            if args.ram_init == RamInit::Random {
+                println!("Play back with --ram-seed {}", ram_seed);
+            }
+        }
+        if let Some(path) = &args.play_input {
+            let movie = std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|raw| InputMovie::from_bytes(&raw).map_err(|err| err.to_string()));
+
+            match movie {
+                Ok(movie) => bus.play_movie(movie),
+                Err(err) => return Err(format!("Failed to load input movie {}: {}", path, err)),
+            }
+        }
+        if let Some(path) = &args.input_script {
+            let movie = std::fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|script| InputMovie::from_script(&script).map_err(|err| err.to_string()));
+
+            match movie {
+                Ok(movie) => bus.play_movie(movie),
+                Err(err) => return Err(format!("Failed to load input script {}: {}", path, err)),
+            }
+        }
+
+        if let Some(path) = &args.palette {
+            let palette = std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|raw| ppu::from_pal_file(&raw).map_err(|err| err.to_string()));
+
+            match palette {
+                Ok(palette) => bus.set_palette(palette),
+                Err(err) => return Err(format!("Failed to load palette {}: {}", path, err)),
+            }
+        }
+
+        // The PPU is not used when playing an NSF.
+        bus.set_ppu_enabled(player.is_none());
+
+        let mut cpu = Cpu::new(bus);
+        match player.as_mut() {
+            Some(player) => {
+                let song = player.song();
+                player.init(&mut cpu, song);
+            }
+            None => cpu.reset(),
+        }
+
+        // Gamepads are optional, so the keyboard is used if they're unavailable.
+        #[cfg(feature = "gamepad")]
+        let gamepads = match headless {
+            true => None,
+            false => match Gamepads::new(&config.gamepad) {
+                Ok(gamepads) => Some(gamepads),
+                Err(err) => {
+                    eprintln!("Gamepads are unavailable: {}", err);
+                    None
+                }
+            },
+        };
+
+        let trace = match &args.trace {
+            Some(path) => match File::create(path) {
+                Ok(file) => {
+                    println!("Tracing instructions to {}", path);
+                    Some(BufWriter::new(file))
+                }
+                Err(err) => return Err(format!("Failed to create trace {}: {}", path, err)),
+            },
+            None => None,
+        };
+
+        // NSFs have no frames to log the registers of.
+        let register_log = match &args.log_registers {
+            Some(_) if player.is_some() => {
+                println!("Registers aren't logged when playing an NSF");
+                None
+            }
+            Some(path) => match File::create(path) {
+                Ok(file) => {
+                    println!("Logging register accesses to {}", path);
+                    cpu.bus.set_register_log(true);
+                    Some(BufWriter::new(file))
+                }
+                Err(err) => return Err(format!("Failed to create register log {}: {}", path, err)),
+            },
+            None => None,
+        };
+
+        // Running ahead would record, play back, trace, watch or log extra
+        // frames, and there's no input lag to hide when headless.
+        let run_ahead_off = args.record_input.is_some()
+            || args.play_input.is_some()
+            || args.input_script.is_some()
+            || trace.is_some()
+            || !args.watches.is_empty()
+            || register_log.is_some();
+        let run_ahead_frames = match run_ahead_off {
+            _ if headless => 0,
+            true if config.emulation.run_ahead > 0 => {
+                println!("Run-ahead is disabled while recording, playing back or debugging");
+                0
+            }
+            _ => config.emulation.run_ahead,
+        };
+        let run_ahead = RunAhead::new(run_ahead_frames);
+        if run_ahead.frames() > 0 && player.is_none() {
+            println!(
+                "Running {} frames ahead, which needs {} times the CPU time",
+                run_ahead.frames(),
+                run_ahead.frames() + 1
+            );
+        }
+
+        let gif_seconds = config.video.gif_seconds;
+        if gif_seconds > 0.0 && !headless {
+            let frame_rate = region.frame_rate();
+            *clip.borrow_mut() = Some(GifClip::new(
+                gif_seconds,
+                Frame::WIDTH,
+                Frame::HEIGHT,
+                frame_rate,
+            ));
+        }
+
+        // Fast forward at 0x runs as fast as possible.
+        let fast_forward_speed = match config.emulation.fast_forward {
+            speed if speed > 0.0 => speed,
+            _ => f64::INFINITY,
+        };
+
+        let mut emulator = Emulator {
+            cpu,
+            player,
+            region,
+            overscan,
+            name,
+            input: InputState::new(args.zapper),
+            #[cfg(feature = "gamepad")]
+            gamepads,
+            slots,
+            battery,
+            run_ahead,
+            speed: 1.0,
+            frames_due: 0.0,
+            normal_speed: 1.0,
+            fast_forward_speed,
+            slow_motion_speed: config.emulation.slow_motion,
+            fast_forward: false,
+            slow_motion: false,
+            paused: false,
+            frames_to_advance: 0,
+            frames_run: 0,
+            recordings,
+            record_input: args.record_input.clone(),
+            video,
+            clip,
+            gif_save: None,
+            trace,
+            register_log,
+            messages: None,
+            #[cfg(feature = "terminal")]
+            on_exit: None,
+        };
+        emulator.set_speed_percent(args.speed);
+
+        if let Some(path) = &args.record_video {
+            if let Err(err) = emulator.start_video_recording(Path::new(path)) {
+                return Err(format!("Failed to record video {}: {}", path, err));
+            }
+        }
+
+        Ok(emulator)
+    }
+
+    /// Shows the messages printed about what the hotkeys do on screen too,
+    /// by sending them to the given sender.
+    pub fn show_messages(&mut self, sender: Sender<Message>) {
+        self.messages = Some(sender);
+    }
+
+    /// Prints the given message, and shows it on screen if the frontend
+    /// shows messages.
+    pub fn notify(&self, message: String) {
+        println!("{}", message);
+        self.show(message);
+    }
+
+    /// Prints the given message as an error, and shows it on screen if the
+    /// frontend shows messages.
+    pub fn notify_error(&self, message: String) {
+        eprintln!("{}", message);
+        self.show(message);
+    }
+
+    /// Shows the given message on screen, without printing it, if the
+    /// frontend shows messages.
+    fn show<M: Into<Message>>(&self, message: M) {
+        if let Some(messages) = &self.messages {
+            // The frontend may have stopped showing messages.
+            let _ = messages.send(message.into());
+        }
+    }
+
+    /// Sets the function called before exiting.
+    #[cfg(feature = "terminal")]
+    pub fn set_on_exit<F>(&mut self, on_exit: F)
+    where
+        F: FnOnce() + 'a,
+    {
+        self.on_exit = Some(Box::new(on_exit));
+    }
+
+    /// Returns the name of the game, or NSF, playing.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the overscan cropped from the frames of the game.
+    pub fn overscan(&self) -> Overscan {
+        Overscan::new(&self.overscan, self.region)
+    }
+
+    /// Returns the region of the console emulated.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns true if an NSF is playing, rather than a game.
+    pub fn is_nsf(&self) -> bool {
+        self.player.is_some()
+    }
+
+    /// Emulates a frame, or a call of the NSF play routine, with the input of
+    /// the controllers, and returns how long it lasts on the console. Exits
+    /// if the CPU shuts down.
+    pub fn run_frame(&mut self) -> Duration {
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepads) = self.gamepads.as_mut() {
+            gamepads.update(&mut self.input);
+        }
+        self.cpu.bus.poll_input(&mut self.input);
+        let playing = self.cpu.bus.movie().is_playing();
+
+        let frame_time = self.frame_time();
+        let halted = match self.player.as_mut() {
+            Some(player) => player.play(&mut self.cpu),
+            None => self.run_game_frame(),
+        };
+
+        if halted {
+            self.exit();
+        }
+        self.write_register_log(false);
+        self.frames_run += 1;
+
+        // The controllers take over once the input movie has played.
+        if playing && !self.cpu.bus.movie().is_playing() {
+            self.notify("Finished playing back the input movie".to_string());
+        }
+
+        if let Some(battery) = self.battery.as_mut() {
+            if let Err(err) = battery.update() {
+                eprintln!("Failed to save {}: {}", battery.path().display(), err);
+            }
+        }
+
+        frame_time
+    }
+
+    /// Runs the frames due in a frame of real time at the speed set, and
+    /// returns how long a frame lasts on the console, for the frontend to
+    /// wait. At infinite speed, frames are run until that time has passed.
+    /// While paused, only the frames advanced are run, without audio.
+    pub fn run_frames(&mut self) -> Duration {
+        let frame_time = self.frame_time();
+        if self.paused {
+            if self.frames_to_advance > 0 {
+                self.frames_to_advance -= 1;
+                self.run_frame();
+                self.cpu.bus.audio().drain();
+            }
+            return frame_time;
+        }
+
+        if self.speed.is_infinite() {
+            let start = Instant::now();
+            while start.elapsed() < frame_time {
+                self.run_frame();
+            }
+            return frame_time;
+        }
+
+        self.frames_due += self.speed;
+        while self.frames_due >= 1.0 {
+            self.frames_due -= 1.0;
+            self.run_frame();
+        }
+
+        frame_time
+    }
+
+    /// Returns the speed of the emulation relative to the console.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the speed of the emulation relative to the console, 1.0 being
+    /// real time, with the pitch of the audio following it. At infinite
+    /// speed, frames are run as fast as possible without audio.
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0, "speed must be positive");
+        self.speed = speed;
+        self.cpu.bus.audio().set_speed(speed);
+    }
+
+    /// Sets the speed of the emulation as a percentage of the console's, 100
+    /// being real time, for tools driving the emulator rather than hotkeys.
+    /// The fast forward and slow motion hotkeys still override it while on.
+    pub fn set_speed_percent(&mut self, percent: NonZeroU32) {
+        self.normal_speed = percent.get() as f64 / 100.0;
+        self.update_speed();
+    }
+
+    /// Returns the number of frames run since the emulator started.
+    pub fn frames_run(&self) -> u64 {
+        self.frames_run
+    }
+
+    /// Returns true if the emulation is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Returns true if the fast forward hotkey is held.
+    pub fn is_fast_forwarding(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Pauses or resumes the emulation.
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.frames_to_advance = 0;
+        match self.paused {
+            true => self.notify("Paused".to_string()),
+            false => self.notify("Resumed".to_string()),
+        }
+    }
+
+    /// Advances a frame while paused, or pauses if running.
+    pub fn advance_frame(&mut self) {
+        match self.paused {
+            true => self.frames_to_advance += 1,
+            false => self.toggle_pause(),
+        }
+    }
+
+    /// Runs at the fast forward speed while held.
+    pub fn set_fast_forward(&mut self, held: bool) {
+        self.fast_forward = held;
+        self.update_speed();
+    }
+
+    /// Starts or stops running at the slow motion speed.
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+        match self.slow_motion {
+            true => self.notify(format!("Slow motion at {}x", self.slow_motion_speed)),
+            false => self.notify("Slow motion off".to_string()),
+        }
+        self.update_speed();
+    }
+
+    /// Sets the speed of the fast forward or slow motion on, fast forward
+    /// taking priority, or the normal speed if neither is.
+    fn update_speed(&mut self) {
+        let speed = match (self.fast_forward, self.slow_motion) {
+            (true, _) => self.fast_forward_speed,
+            (false, true) => self.slow_motion_speed,
+            (false, false) => self.normal_speed,
+        };
+        self.set_speed(speed);
+    }
+
+    /// Returns the number of frames the console runs per second, or of
+    /// calls to the NSF play routine.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn frame_rate(&self) -> f64 {
+        1.0 / self.frame_time().as_secs_f64()
+    }
+
+    /// Returns how long a frame lasts on the console, or the period the NSF
+    /// play routine is called at.
+    pub fn frame_time(&self) -> Duration {
+        match &self.player {
+            Some(player) => player.play_period(),
+            None => Duration::from_secs_f64(1.0 / self.region.frame_rate()),
+        }
+    }
+
+    /// Emulates a frame of the game, running ahead or tracing it, and
+    /// returns true if the CPU shuts down. Tracing stops if the trace can't
+    /// be written.
+    fn run_game_frame(&mut self) -> bool {
+        let Some(trace) = self.trace.as_mut() else {
+            return self.run_ahead.run_frame(&mut self.cpu);
+        };
+
+        match self.cpu.trace_frame(trace) {
+            Ok(halted) => halted,
+            Err(err) => {
+                eprintln!("Failed to write the trace: {}", err);
+                self.trace = None;
+                false
+            }
+        }
+    }
+
+    /// Writes the register accesses of the frame just run, or of the frame
+    /// being run if current, to the register log, if any. Logging stops if
+    /// the log can't be written.
+    fn write_register_log(&mut self, current: bool) {
+        let Some(log) = self.register_log.as_mut() else {
+            return;
+        };
+
+        let accesses = match current {
+            true => self.cpu.bus.register_log().current_frame(),
+            false => self.cpu.bus.register_log().last_frame(),
+        };
+        let frame = self.frames_run;
+        let written = accesses
+            .iter()
+            .try_for_each(|access| writeln!(log, "{:>6} {}", frame, access));
+        if let Err(err) = written {
+            eprintln!("Failed to write the register log: {}", err);
+            self.register_log = None;
+        }
+    }
+
+    /// Saves a save state to the selected slot.
+    pub fn save_state(&mut self) {
+        let Some(slots) = &self.slots else {
+            return;
+        };
+
+        let slot = slots.selected();
+        match slots.save(slot, &self.cpu.save_state()) {
+            Ok(()) => self.notify(format!("Saved state to slot {}", slot)),
+            Err(err) => {
+                self.notify_error(format!("Failed to save state to slot {}: {}", slot, err))
+            }
+        }
+    }
+
+    /// Loads the save state in the selected slot.
+    pub fn load_state(&mut self) {
+        let Some(slots) = &self.slots else {
+            return;
+        };
+
+        let slot = slots.selected();
+        let loaded = slots
+            .load(slot)
+            .map_err(|err| err.to_string())
+            .and_then(|state| self.cpu.load_state(&state).map_err(|err| err.to_string()));
+
+        match loaded {
+            Ok(()) => self.notify(format!("Loaded state from slot {}", slot)),
+            Err(err) => {
+                self.notify_error(format!("Failed to load state from slot {}: {}", slot, err))
+            }
+        }
+    }
+
+    /// Selects the save state slot used by the hotkeys, showing when the
+    /// state in it was saved with its thumbnail.
+    pub fn select_slot(&mut self, slot: u8) {
+        let Some(slots) = self.slots.as_mut() else {
+            return;
+        };
+
+        slots.select(slot);
+        let info = slots.list().into_iter().find(|info| info.slot == slot);
+        let text = match &info {
+            Some(info) => format!("Selected slot {}, saved {}", slot, info.saved_ago()),
+            None => format!("Selected slot {}, empty", slot),
+        };
+        println!("{}", text);
+        self.show(Message {
+            text,
+            thumbnail: info.and_then(|info| info.thumbnail),
+        });
+    }
+
+    /// Starts or stops recording audio to a WAV file in the game's storage.
+    pub fn toggle_audio_recording(&mut self) {
+        let path = self.recordings.recording_dir().join("audio.wav");
+        let audio = self.cpu.bus.audio();
+        match audio.is_recording() {
+            true => match audio.stop_recording() {
+                Ok(()) => self.show("Saved audio recording".to_string()),
+                Err(err) => self.notify_error(format!("Failed to save audio recording: {}", err)),
+            },
+            false => match self
+                .recordings
+                .create()
+                .and_then(|()| audio.start_recording(&path))
+            {
+                Ok(()) => self.show("Recording audio".to_string()),
+                Err(err) => self.notify_error(format!("Failed to record audio: {}", err)),
+            },
+        }
+    }
+
+    /// Starts or stops recording each audio channel to its own WAV file in
+    /// the game's storage.
+    pub fn toggle_stem_recording(&mut self) {
+        let dir = self.recordings.recording_dir();
+        let paths: Vec<_> = AudioChannel::ALL
+            .iter()
+            .map(|channel| dir.join(format!("audio.{}.wav", channel.name())))
+            .collect();
+        let audio = self.cpu.bus.audio();
+        match audio.is_recording_stems() {
+            true => match audio.stop_stem_recording() {
+                Ok(()) => self.show("Saved audio stems".to_string()),
+                Err(err) => self.notify_error(format!("Failed to save audio stems: {}", err)),
+            },
+            false => match self
+                .recordings
+                .create()
+                .and_then(|()| audio.start_stem_recording(&paths))
+            {
+                Ok(()) => self.show("Recording audio stems".to_string()),
+                Err(err) => self.notify_error(format!("Failed to record audio stems: {}", err)),
+            },
+        }
+    }
+
+    /// Starts or stops recording video, with its audio, in the game's
+    /// storage.
+    pub fn toggle_video_recording(&mut self) {
+        let path = self.recordings.recording_dir().join("video.y4m");
+        let recording = self.video.borrow().is_some();
+        let result = match recording {
+            true => self.stop_video_recording(),
+            false => self
+                .recordings
+                .create()
+                .and_then(|()| self.start_video_recording(&path)),
+        };
+
+        // Paths are printed, but too long to show.
+        match (recording, result) {
+            (false, Ok(())) => {
+                println!("Recording video to {}", path.display());
+                self.show("Recording video".to_string());
+            }
+            (true, Ok(())) => {
+                println!("Saved video to {}", path.display());
+                self.show("Saved video".to_string());
+            }
+            (_, Err(err)) => {
+                eprintln!("Failed to record video {}: {}", path.display(), err);
+                self.show("Failed to record video".to_string());
+            }
+        }
+    }
+
+    /// Starts recording every frame rendered to a YUV4MPEG2 video at the
+    /// given path, and the audio to a WAV file next to it, at the frame rate
+    /// of the console whatever the speed. Replaces any recording in
+    /// progress.
+    pub fn start_video_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_video_recording()?;
+
+        let file = BufWriter::new(File::create(path)?);
+        let frame_rate = self.region.frame_rate();
+        let writer = Y4mWriter::new(file, Frame::WIDTH, Frame::HEIGHT, frame_rate)?;
+        *self.video.borrow_mut() = Some(writer);
+        self.cpu
+            .bus
+            .audio()
+            .start_video_recording(&path.with_extension("wav"))
+    }
+
+    /// Stops recording video, finishing the video and its audio.
+    pub fn stop_video_recording(&mut self) -> io::Result<()> {
+        if let Some(video) = self.video.borrow_mut().take() {
+            video.finish()?;
+        }
+
+        self.cpu.bus.audio().stop_video_recording()
+    }
+
+    /// Saves the last few seconds of frames as an animated GIF in the game's
+    /// storage, named by the time. The GIF is written in the background.
+    pub fn save_gif(&mut self) {
+        let clip = match self.clip.borrow().as_ref() {
+            Some(clip) if !clip.is_empty() => clip.snapshot(),
+            _ => return,
+        };
+
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self
+            .recordings
+            .recording_dir()
+            .join(format!("{}.gif", time));
+        if let Err(err) = self.recordings.create() {
+            eprintln!("Failed to save GIF {}: {}", path.display(), err);
+            self.show("Failed to save GIF".to_string());
+            return;
+        }
+
+        self.finish_gif_save();
+        let messages = self.messages.clone();
+        self.gif_save = Some(thread::spawn(move || {
+            let result = File::create(&path).and_then(|file| clip.write(BufWriter::new(file)));
+            let message = match result {
+                Ok(()) => {
+                    println!("Saved GIF to {}", path.display());
+                    "Saved GIF"
+                }
+                Err(err) => {
+                    eprintln!("Failed to save GIF {}: {}", path.display(), err);
+                    "Failed to save GIF"
+                }
+            };
+            if let Some(messages) = messages {
+                let _ = messages.send(message.to_string().into());
+            }
+        }));
+    }
+
+    /// Waits for the GIF being saved, if any.
+    fn finish_gif_save(&mut self) {
+        if let Some(gif_save) = self.gif_save.take() {
+            gif_save.join().unwrap();
+        }
+    }
+
+    /// Skips to the next or previous song of the NSF playing, if any.
+    pub fn skip_song(&mut self, next: bool) {
+        if let Some(player) = self.player.as_mut() {
+            player.skip(&mut self.cpu, next);
+            let message = format!("Song {}/{}", player.song(), player.nsf().total_songs);
+            self.notify(message);
+        }
+    }
+
+    /// Swaps the game, or NSF, at the given path, which may be zipped, in
+    /// for this one, returning the emulator playing it from power on with
+    /// the given render callback. This one is closed, as if exiting, once
+    /// the new one has loaded. The arguments given for the first game alone
+    /// are left out. Returns an error saying what failed, closing nothing,
+    /// if it can't be loaded.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn swap_game<F>(
+        &mut self,
+        path: &str,
+        args: &Args,
+        config: &Config,
+        sample_rate: u32,
+        render: F,
+    ) -> Result<Emulator<'a>, String>
+    where
+        F: FnMut(&[u8]) + 'a,
+    {
+        // The battery RAM is saved before loading, in case the game is
+        // swapped for itself.
+        self.save_battery();
+        let mut emulator = Emulator::load(&args.for_game(path), config, sample_rate, render)?;
+        self.close();
+
+        #[cfg(feature = "gamepad")]
+        if self.gamepads.is_some() {
+            emulator.gamepads = self.gamepads.take();
+        }
+        emulator.messages = self.messages.take();
+        emulator.normal_speed = self.normal_speed;
+        emulator.update_speed();
+        #[cfg(feature = "terminal")]
+        {
+            emulator.on_exit = self.on_exit.take();
+        }
+        Ok(emulator)
+    }
+
+    /// Calls the function set to be called before exiting, if any, stops
+    /// any recordings, saving the controller input recorded since power on,
+    /// saves the battery RAM, and exits.
+    pub fn exit(&mut self) -> ! {
+        #[cfg(feature = "terminal")]
+        if let Some(on_exit) = self.on_exit.take() {
+            on_exit();
+        }
+        self.close();
+        std::process::exit(0)
+    }
+
+    /// Stops any recordings, saving the controller input recorded since
+    /// power on, and saves the battery RAM.
+    fn close(&mut self) {
+        if let Err(err) = self.cpu.bus.audio().stop_recording() {
+            eprintln!("Failed to save the audio recording: {}", err);
+        }
+        if let Err(err) = self.cpu.bus.audio().stop_stem_recording() {
+            eprintln!("Failed to save the audio stems: {}", err);
+        }
+        if let Err(err) = self.stop_video_recording() {
+            eprintln!("Failed to save the video: {}", err);
+        }
+        self.finish_gif_save();
+
+        if let Some(path) = &self.record_input {
+            let movie = self.cpu.bus.movie();
+            match std::fs::write(path, movie.to_bytes()) {
+                Ok(()) => println!("Saved {} frames of input to {}", movie.len(), path),
+                Err(err) => eprintln!("Failed to save input movie {}: {}", path, err),
+            }
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            if let Err(err) = trace.flush() {
+                eprintln!("Failed to write the trace: {}", err);
+            }
+        }
+
+        // The accesses of the frame cut short are logged too.
+        self.write_register_log(true);
+        if let Some(log) = self.register_log.as_mut() {
+            if let Err(err) = log.flush() {
+                eprintln!("Failed to write the register log: {}", err);
+            }
+        }
+
+        // Destructors aren't run on exit, so the battery RAM is saved here.
+        self.save_battery();
+    }
+
+    /// Saves the battery RAM, if the game has any.
+    fn save_battery(&mut self) {
+        if let Some(battery) = self.battery.as_mut() {
+            if let Err(err) = battery.flush() {
+                eprintln!("Failed to save {}: {}", battery.path().display(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_set_speed_percent() {
+        let args = Args::parse_from([
+            "res",
+            "--rom",
+            "nestest.nes",
+            "--frontend",
+            "headless",
+            "--frames",
+            "2",
+        ]);
+        let mut emulator = Emulator::new(&args, &Config::default(), 44100, |_| {});
+
+        // The first frame after power on is cut short.
+        emulator.run_frame();
+        emulator.cpu.bus.audio().drain();
+        emulator.run_frame();
+        let samples = emulator.cpu.bus.audio().drain().len();
+
+        // Half speed stretches each frame of audio over twice the time.
+        emulator.set_speed_percent(NonZeroU32::new(50).unwrap());
+        assert_eq!(emulator.speed(), 0.5);
+        emulator.run_frame();
+        let slowed = emulator.cpu.bus.audio().drain().len();
+        assert!((slowed as f64 / samples as f64 - 2.0).abs() < 0.01);
+
+        // The hotkeys return to it when released.
+        emulator.set_fast_forward(true);
+        assert_eq!(emulator.speed(), 4.0);
+        emulator.set_fast_forward(false);
+        assert_eq!(emulator.speed(), 0.5);
+    }
+
+    #[test]
+    fn test_load_error() {
+        let patch = std::env::temp_dir().join("res_test_load_error.ips");
+        std::fs::write(&patch, b"NOT A PATCH").unwrap();
+        let args = Args::parse_from([
+            "res",
+            "--rom",
+            "nestest.nes",
+            "--frontend",
+            "headless",
+            "--frames",
+            "1",
+            "--patch",
+            patch.to_str().unwrap(),
+        ]);
+
+        // A game that can't be loaded is an error rather than an exit.
+        let err = Emulator::load(&args, &Config::default(), 44100, |_| {})
+            .err()
+            .unwrap();
+        std::fs::remove_file(&patch).unwrap();
+        assert!(err.starts_with("Failed to apply patch"), "{}", err);
+    }
+
+    #[test]
+    fn test_recording_path() {
+        let data_dir = std::env::temp_dir().join("res_test_recording_path");
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let args = Args::parse_from([
+            "res",
+            "--rom",
+            "nestest.nes",
+            "--frontend",
+            "headless",
+            "--frames",
+            "1",
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+        ]);
+        let mut emulator = Emulator::new(&args, &Config::default(), 44100, |_| {});
+
+        // Recordings are saved in the game's storage rather than by the ROM.
+        emulator.toggle_audio_recording();
+        emulator.toggle_audio_recording();
+        let path = emulator.recordings.recording_dir().join("audio.wav");
+        assert!(path.starts_with(&data_dir));
+        assert!(path.is_file());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+#[cfg(feature = "winit")]
+mod cpal_audio;
+pub mod headless;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+#[cfg(any(feature = "sdl", feature = "winit", feature = "terminal"))]
+mod title;
+#[cfg(feature = "winit")]
+mod viewport_renderer;
+#[cfg(feature = "winit")]
+pub mod winit_pixels;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use crate::config::AudioConfig;
+use crate::config::{ConfigError, KeyAction, KeyBindings};
+use crate::emulator::Emulator;
+use crate::joypad::{JOYPAD_LEFT, JOYPAD_RIGHT};
+use crate::keyboard::{KEYBOARD_ROWS, KEYBOARD_ROW_KEYS};
+
+/// The frontends the emulator can run in. Each is only available when the
+/// emulator is built with its feature.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Frontend {
+    /// SDL2, built with the "sdl" feature (the default).
+    Sdl,
+
+    /// winit and pixels, which need no C libraries, built with the "winit"
+    /// feature.
+    Winit,
+
+    /// The terminal, drawn in coloured half blocks without sound, which can
+    /// run over SSH, built with the "terminal" feature.
+    Terminal,
+
+    /// No window or audio, running a number of frames as fast as possible
+    /// and printing hashes of the frames and RAM.
+    Headless,
+}
+
+impl Default for Frontend {
+    /// Returns SDL2 if it's built, or winit otherwise.
+    fn default() -> Self {
+        match cfg!(feature = "sdl") {
+            true => Frontend::Sdl,
+            false => Frontend::Winit,
+        }
+    }
+}
+
+/// Returns a render callback copying each frame rendered into the given
+/// buffer, for the frontend to draw.
+fn copy_frames(frame: &Rc<RefCell<Vec<u8>>>) -> impl FnMut(&[u8]) {
+    let frame = Rc::clone(frame);
+    move |pixels| {
+        let mut frame = frame.borrow_mut();
+        frame.clear();
+        frame.extend_from_slice(pixels);
+    }
+}
+
+/// Returns the number of samples at the given rate the audio queue of a
+/// frontend holds for the latency in the config. Dynamic rate control keeps
+/// the queue half full, so it holds twice the latency.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn audio_queue_capacity(config: &AudioConfig, sample_rate: u32) -> usize {
+    (config.latency as u64 * sample_rate as u64 * 2 / 1000) as usize
+}
+
+/// Prints the latency of the audio in the config, and of the output
+/// device's buffer of the given number of samples at the given rate if
+/// known.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn report_audio_latency(config: &AudioConfig, device_buffer: Option<(u32, u32)>) {
+    let latency = Duration::from_millis(config.latency as u64);
+    match device_buffer {
+        Some((samples, sample_rate)) => {
+            let buffer = Duration::from_secs_f64(samples as f64 / sample_rate as f64);
+            println!("Audio latency: {} ms", (latency + buffer).as_millis());
+        }
+        None => println!(
+            "Audio latency: {} ms, and the output device's buffer",
+            latency.as_millis()
+        ),
+    }
+}
+
+/// The host keys mapped to each key of the Family BASIC keyboard, by SDL name,
+/// in the order of the key matrix. Keys missing from the host keyboard are
+/// mapped to nearby ones, with STOP on End, KANA on Right Ctrl and GRPH on
+/// Left Alt.
+const FAMILY_KEYBOARD_KEYS: [[&str; KEYBOARD_ROW_KEYS]; KEYBOARD_ROWS] = [
+    [
+        "]",
+        "[",
+        "Return",
+        "F8",
+        "End",
+        "\\",
+        "Right Shift",
+        "Right Ctrl",
+    ],
+    [";", "'", "`", "F7", "=", "-", "/", "Right Alt"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    [
+        "Left Ctrl",
+        "Q",
+        "Escape",
+        "F1",
+        "2",
+        "1",
+        "Left Alt",
+        "Left Shift",
+    ],
+    [
+        "Left", "Right", "Up", "Home", "Insert", "Delete", "Space", "Down",
+    ],
+];
+
+/// Turns the keys of a frontend, of type K, into the input of the
+/// controllers and the hotkeys. Keys are named as in SDL, which each
+/// frontend maps to its own keys.
+pub struct Controls<K> {
+    key_map: HashMap<K, KeyAction>,
+
+    // The keys go to the controllers until the Family BASIC keyboard is
+    // captured.
+    family_keyboard_map: HashMap<K, (usize, usize)>,
+    keyboard_captured: bool,
+
+    microphone_held: bool,
+}
+
+impl<K: Copy + Eq + Hash> Controls<K> {
+    /// Returns the controls with the given key bindings, given a function
+    /// returning the key with an SDL name, if there is one.
+    pub fn new<F>(bindings: &KeyBindings, key_from_name: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<K>,
+    {
+        let family_keyboard_map = FAMILY_KEYBOARD_KEYS
+            .iter()
+            .enumerate()
+            .flat_map(|(row, keys)| {
+                keys.iter()
+                    .enumerate()
+                    .map(move |(key, name)| (row, key, name))
+            })
+            .filter_map(|(row, key, name)| Some((key_from_name(name)?, (row, key))))
+            .collect();
+
+        Ok(Controls {
+            key_map: bindings.key_map(&key_from_name)?,
+            family_keyboard_map,
+            keyboard_captured: false,
+            microphone_held: false,
+        })
+    }
+
+    /// Returns true while the microphone key is held.
+    pub fn microphone_held(&self) -> bool {
+        self.microphone_held
+    }
+
+    /// Handles a key being pressed, with Shift held or not. Hotkeys ignore
+    /// the repeats of a key held down. Returns the hotkeys which act on the
+    /// frontend, such as cycling the video filter or toggling fullscreen,
+    /// for it to handle.
+    pub fn key_down(
+        &mut self,
+        emulator: &mut Emulator,
+        key: K,
+        shift: bool,
+        repeat: bool,
+    ) -> Option<KeyAction> {
+        match self.key_map.get(&key) {
+            Some(KeyAction::CaptureKeyboard) if !repeat => {
+                if let Some(keyboard) = emulator.cpu.bus.keyboard() {
+                    keyboard.release_keys();
+                    self.keyboard_captured = !self.keyboard_captured;
+                    match self.keyboard_captured {
+                        true => emulator
+                            .notify("Keys are sent to the Family BASIC keyboard".to_string()),
+                        false => emulator.notify("Keys are sent to the controllers".to_string()),
+                    }
+                }
+            }
+            _ if self.keyboard_captured => {
+                let key = self.family_keyboard_map.get(&key);
+                if let (Some(keyboard), Some(&(row, key))) = (emulator.cpu.bus.keyboard(), key) {
+                    keyboard.set_key_pressed(row, key, true);
+                }
+            }
+            Some(KeyAction::Quit) => emulator.exit(),
+            Some(KeyAction::Microphone) => self.microphone_held = true,
+            Some(KeyAction::SaveState) if !repeat => emulator.save_state(),
+            Some(KeyAction::FastForward) => emulator.set_fast_forward(true),
+            Some(KeyAction::RecordVideo) if !repeat => emulator.toggle_video_recording(),
+            Some(KeyAction::SaveGif) if !repeat => emulator.save_gif(),
+            Some(KeyAction::Pause) if !repeat => emulator.toggle_pause(),
+            Some(KeyAction::FrameAdvance) => emulator.advance_frame(),
+            Some(KeyAction::SlowMotion) if !repeat => emulator.toggle_slow_motion(),
+            Some(KeyAction::LoadState) if !repeat => emulator.load_state(),
+            Some(&KeyAction::SelectSlot(slot)) => emulator.select_slot(slot),
+            Some(&KeyAction::Channel(channel)) if !repeat => match shift {
+                true => emulator.cpu.bus.toggle_channel_solo(channel),
+                false => emulator.cpu.bus.toggle_channel_mute(channel),
+            },
+            Some(KeyAction::RecordAudio) if !repeat => emulator.toggle_audio_recording(),
+            Some(KeyAction::RecordStems) if !repeat => emulator.toggle_stem_recording(),
+            Some(&KeyAction::Button(_, button @ (JOYPAD_LEFT | JOYPAD_RIGHT)))
+                if emulator.is_nsf() =>
+            {
+                emulator.skip_song(button == JOYPAD_RIGHT)
+            }
+            Some(&KeyAction::Button(player, button)) => {
+                emulator.input.set_button_pressed(player, button, true)
+            }
+            Some(&KeyAction::Turbo(player, button)) => {
+                emulator.input.set_turbo_pressed(player, button, true)
+            }
+            Some(
+                &action @ (KeyAction::CycleFilter
+                | KeyAction::ToggleFullscreen
+                | KeyAction::TogglePerfOverlay),
+            ) if !repeat => return Some(action),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Handles a key being released.
+    pub fn key_up(&mut self, emulator: &mut Emulator, key: K) {
+        // Keys are released everywhere, in case they were pressed before the
+        // keyboard was captured or released.
+        let family_key = self.family_keyboard_map.get(&key);
+        if let (Some(keyboard), Some(&(row, key))) = (emulator.cpu.bus.keyboard(), family_key) {
+            keyboard.set_key_pressed(row, key, false);
+        }
+
+        match self.key_map.get(&key) {
+            Some(KeyAction::Microphone) => self.microphone_held = false,
+            Some(KeyAction::FastForward) => emulator.set_fast_forward(false),
+            Some(&KeyAction::Button(player, button)) => {
+                emulator.input.set_button_pressed(player, button, false)
+            }
+            Some(&KeyAction::Turbo(player, button)) => {
+                emulator.input.set_turbo_pressed(player, button, false)
+            }
+            _ => {}
+        }
+    }
+}
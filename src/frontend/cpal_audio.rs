@@ -0,0 +1,261 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig, StreamError,
+    SupportedBufferSize,
+};
+
+use super::{audio_queue_capacity, report_audio_latency};
+use crate::audio::RingBuffer;
+use crate::config::AudioConfig;
+
+/// How long to wait between attempts to open the output again after the
+/// device is lost.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much the last sample fades each sample the buffer runs dry for.
+const UNDERRUN_FADE: f32 = 0.995;
+
+/// Plays the audio of the emulator on an output device through cpal. The
+/// samples are queued by the emulator and played from the device's thread.
+/// If the device is lost, such as by being unplugged, the output is opened
+/// again, on the default device if the one selected is gone.
+pub struct CpalAudio {
+    config: AudioConfig,
+    sample_rate: u32,
+
+    // The samples queued, of which the buffer holds up to its capacity
+    // before the oldest are dropped. Dynamic rate control aims to keep it
+    // half full.
+    buffer: Arc<Mutex<RingBuffer>>,
+    capacity: usize,
+
+    // The output stops when the stream is dropped. The device's thread sets
+    // failed when it loses the device.
+    stream: Option<Stream>,
+    failed: Arc<AtomicBool>,
+    last_attempt: Instant,
+}
+
+impl CpalAudio {
+    /// Opens the output device in the config, or the default device, to
+    /// play samples at the given rate in Hz with the latency in the config.
+    pub fn open(config: &AudioConfig, sample_rate: u32) -> Result<Self, String> {
+        let capacity = audio_queue_capacity(config, sample_rate);
+        let mut audio = CpalAudio {
+            config: config.clone(),
+            sample_rate,
+            buffer: Arc::new(Mutex::new(RingBuffer::new(capacity))),
+            capacity,
+            stream: None,
+            failed: Arc::new(AtomicBool::new(false)),
+            last_attempt: Instant::now(),
+        };
+        audio.stream = Some(audio.open_stream(config.device.as_deref())?);
+        Ok(audio)
+    }
+
+    /// Queues samples to be played.
+    pub fn queue(&mut self, samples: &[f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for &sample in samples {
+            buffer.push(sample);
+        }
+    }
+
+    /// Returns how full the buffer is, from 0 to 1.
+    pub fn fill_level(&self) -> f64 {
+        self.buffer.lock().unwrap().len() as f64 / self.capacity as f64
+    }
+
+    /// Opens the output again if the device was lost, on the device
+    /// selected or the default device if it's gone, trying at most once per
+    /// retry interval.
+    pub fn recover(&mut self) {
+        if !self.failed.load(Ordering::Relaxed) || self.last_attempt.elapsed() < RETRY_INTERVAL {
+            return;
+        }
+
+        let lost = self.stream.take().is_some();
+        let device_name = self.config.device.clone();
+        let stream = match self.open_stream(device_name.as_deref()) {
+            Err(_) if device_name.is_some() => self.open_stream(None),
+            stream => stream,
+        };
+        match stream {
+            Ok(stream) => {
+                println!("Audio output resumed");
+                self.stream = Some(stream);
+            }
+            Err(err) => {
+                // Only the first failure is reported, as it's tried again.
+                if lost {
+                    eprintln!("Failed to open the audio device: {}", err);
+                }
+                self.failed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Opens and starts a stream on the first output device with the given
+    /// text in its name, or the default device.
+    fn open_stream(&mut self, device_name: Option<&str>) -> Result<Stream, String> {
+        self.last_attempt = Instant::now();
+        self.failed.store(false, Ordering::Relaxed);
+
+        let device = find_device(device_name)?;
+        let supported = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+        let mut config = supported.config();
+
+        // The device plays the buffer size in the config if it can.
+        let device_buffer = match *supported.buffer_size() {
+            SupportedBufferSize::Range { min, max } => {
+                let samples = self.config.buffer_size.clamp(min, max);
+                config.buffer_size = BufferSize::Fixed(samples);
+                Some((samples, config.sample_rate.0))
+            }
+            SupportedBufferSize::Unknown => None,
+        };
+        report_audio_latency(&self.config, device_buffer);
+
+        match supported.sample_format() {
+            SampleFormat::F32 => self.build_stream::<f32>(&device, &config),
+            SampleFormat::I16 => self.build_stream::<i16>(&device, &config),
+            SampleFormat::U16 => self.build_stream::<u16>(&device, &config),
+            format => Err(format!("unsupported sample format {}", format)),
+        }
+    }
+
+    /// Starts a stream of samples in the given format on the device, the
+    /// same sample played on every channel.
+    fn build_stream<T>(&self, device: &Device, config: &StreamConfig) -> Result<Stream, String>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let buffer = Arc::clone(&self.buffer);
+        let mut converter = RateConverter::new(self.sample_rate, config.sample_rate.0);
+        let channels = config.channels as usize;
+        let play = move |data: &mut [T], _: &_| {
+            let mut buffer = buffer.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                frame.fill(T::from_sample(converter.next(&mut buffer)));
+            }
+        };
+
+        let failed = Arc::clone(&self.failed);
+        let error = move |err| {
+            eprintln!("Audio device error: {}", err);
+            if let StreamError::DeviceNotAvailable = err {
+                failed.store(true, Ordering::Relaxed);
+            }
+        };
+
+        let stream = device
+            .build_output_stream(config, play, error, None)
+            .map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+        Ok(stream)
+    }
+}
+
+/// Returns the first output device with the given text in its name, or the
+/// default device.
+fn find_device(device_name: Option<&str>) -> Result<Device, String> {
+    let host = cpal::default_host();
+    let Some(device_name) = device_name else {
+        return host
+            .default_output_device()
+            .ok_or_else(|| "no audio output device".to_string());
+    };
+
+    let devices: Vec<_> = host
+        .output_devices()
+        .map_err(|err| err.to_string())?
+        .collect();
+    let names: Vec<_> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    match names.iter().position(|name| name.contains(device_name)) {
+        Some(index) => Ok(devices.into_iter().nth(index).unwrap()),
+        None => Err(format!(
+            "no audio output device named {}, the devices are: {}",
+            device_name,
+            names.join(", ")
+        )),
+    }
+}
+
+/// Converts the samples queued at the emulator's rate to the device's, by
+/// linear interpolation.
+struct RateConverter {
+    step: f64,
+    position: f64,
+    previous: f32,
+    next: f32,
+}
+
+impl RateConverter {
+    /// Returns a converter from the given rate to the given rate, in Hz.
+    fn new(from: u32, to: u32) -> Self {
+        RateConverter {
+            step: from as f64 / to as f64,
+            position: 0.0,
+            previous: 0.0,
+            next: 0.0,
+        }
+    }
+
+    /// Returns the next sample at the device's rate, taking samples from
+    /// the buffer as they're due. If the buffer runs dry the last sample
+    /// fades to silence, rather than jumping to it, which would click.
+    fn next(&mut self, buffer: &mut RingBuffer) -> f32 {
+        self.position += self.step;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.previous = self.next;
+            self.next = buffer.pop().unwrap_or(self.next * UNDERRUN_FADE);
+        }
+
+        self.previous + (self.next - self.previous) * self.position as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the samples played at the given rate from the given samples
+    /// at 44100 Hz.
+    fn convert(to: u32, samples: &[f32], count: usize) -> Vec<f32> {
+        let mut buffer = RingBuffer::new(4096);
+        samples.iter().for_each(|&sample| buffer.push(sample));
+        let mut converter = RateConverter::new(44100, to);
+        (0..count).map(|_| converter.next(&mut buffer)).collect()
+    }
+
+    #[test]
+    fn test_rate_conversion() {
+        // The same rate plays the samples as they are, a sample behind.
+        let same = convert(44100, &[0.5, 0.25, -0.5], 4);
+        assert_eq!(same, [0.0, 0.5, 0.25, -0.5]);
+
+        // Twice the rate plays a sample between each.
+        let doubled = convert(88200, &[0.5, 0.25], 6);
+        assert_eq!(doubled, [0.0, 0.0, 0.25, 0.5, 0.375, 0.25]);
+    }
+
+    #[test]
+    fn test_underrun() {
+        let played = convert(44100, &[0.5], 100);
+        assert_eq!(played[1], 0.5);
+        assert!(played[2] < 0.5);
+        assert!(played[99] < played[2]);
+        assert!(played[99] > 0.0);
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::emulator::Emulator;
+use crate::rom::crc32;
+use crate::Args;
+
+/// Sample rate of the audio output, in Hz. The audio isn't played.
+const SAMPLE_RATE: u32 = 44100;
+
+/// Runs the emulator without a window or audio for the number of frames
+/// given by the arguments, as fast as it can, then exits. The CRC32s of the
+/// frame and the CPU RAM are printed after the last frame, and every
+/// --hash-interval frames, so runs can be compared.
+pub fn run(args: &Args, config: &Config) -> ! {
+    // The frames are read from the PPU when hashed, rather than rendered.
+    let mut emulator = Emulator::new(args, config, SAMPLE_RATE, |_| {});
+    // The audio is only made for a video being recorded.
+    emulator
+        .cpu
+        .bus
+        .set_audio_enabled(args.record_video.is_some());
+
+    let frames = args.frames.unwrap_or_default();
+    let start = Instant::now();
+    for frame in 1..=frames {
+        emulator.run_frame();
+
+        let hashed = match args.hash_interval {
+            Some(interval) => frame % interval == 0,
+            None => false,
+        };
+        if hashed || frame == frames {
+            println!(
+                "Frame {}: frame {:08x}, RAM {:08x}",
+                frame,
+                crc32(emulator.cpu.bus.frame()),
+                crc32(emulator.cpu.bus.ram())
+            );
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "Ran {} frames in {:.2}s ({:.0} frames per second)",
+        frames,
+        elapsed,
+        frames as f64 / elapsed
+    );
+
+    emulator.exit()
+}
@@ -0,0 +1,301 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use sdl2::audio::AudioSpecDesired;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+
+use super::title::WindowTitle;
+use super::{audio_queue_capacity, copy_frames, report_audio_latency, Controls};
+use crate::config::{Config, KeyAction};
+use crate::emulator::Emulator;
+use crate::microphone::Microphone;
+use crate::perf::PerfOverlay;
+use crate::timer::{vsync_matches, Timer};
+use crate::video::{Osd, VideoFilter, Viewport};
+use crate::Args;
+
+/// Runs the emulator in an SDL2 window until it's closed.
+pub fn run(args: &Args, config: &Config) -> ! {
+    // Initialise SDL.
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+
+    // Initialise sound.
+    let sample_rate = 44100;
+    let spec = AudioSpecDesired {
+        freq: Some(sample_rate),
+        channels: Some(1),
+        samples: Some(config.audio.buffer_size as u16),
+    };
+    // Sound is played on the first output device with the name in the
+    // config, or the default device.
+    let device = config.audio.device.as_deref().and_then(|name| {
+        let count = audio_subsystem.num_audio_playback_devices().unwrap_or(0);
+        let device = (0..count)
+            .filter_map(|index| audio_subsystem.audio_playback_device_name(index).ok())
+            .find(|device| device.contains(name));
+        if device.is_none() {
+            eprintln!("No audio output device named {}, using the default", name);
+        }
+        device
+    });
+    let queue = audio_subsystem
+        .open_queue::<f32, _>(device.as_deref(), &spec)
+        .unwrap();
+    queue.resume();
+
+    // The queue is kept half full, at the latency in the config.
+    let spec = queue.spec();
+    report_audio_latency(&config.audio, Some((spec.samples as u32, spec.freq as u32)));
+    let queue_capacity = audio_queue_capacity(&config.audio, sample_rate as u32);
+
+    // Samples stores the audio samples generated by the APU.
+    let mut samples = vec![0.0; 1024];
+    let volume = 1.0;
+
+    // The last frame rendered, drawn after each frame is run.
+    let frame = Rc::new(RefCell::new(vec![]));
+    let mut emulator = Emulator::new(args, config, sample_rate as u32, copy_frames(&frame));
+
+    // Frames are cropped, then post-processed before they're drawn, which
+    // may upscale them. The window fits the cropped frames.
+    let overscan = emulator.overscan();
+    let mut filter = VideoFilter::new(&config.video, overscan, args.window_w, args.window_h);
+    let mut frame_size = filter.frame_size();
+    let (window_w, window_h) = args.scaled_window_size(frame_size);
+    let window_w = (window_w as f32 * config.video.aspect.ratio()) as u32;
+
+    let mut window = video_subsystem.window(&WindowTitle::first(&emulator), window_w, window_h);
+    window.position_centered().resizable().allow_highdpi();
+    if args.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().unwrap();
+
+    // Frames are paced by vsync if the display refreshes at the frame rate
+    // of the console, and by the timer otherwise. A game swapped in at
+    // another frame rate is paced by the timer as well.
+    let refresh_rate = window
+        .display_index()
+        .and_then(|index| video_subsystem.current_display_mode(index))
+        .map(|mode| mode.refresh_rate as f64)
+        .ok();
+    let vsync_paces = |emulator: &Emulator| {
+        refresh_rate.is_some_and(|refresh_rate| vsync_matches(emulator.frame_rate(), refresh_rate))
+    };
+    let vsync = vsync_paces(&emulator);
+    let mut paced_by_vsync = vsync;
+
+    // Initialise graphics.
+    let mut canvas = window.into_canvas();
+    if vsync {
+        canvas = canvas.present_vsync();
+    }
+    let mut canvas = canvas.build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_draw_color(Color::BLACK);
+
+    // The texture is made again when the filter changes the frame size.
+    let creator = canvas.texture_creator();
+    let mut texture: Option<Texture> = None;
+    let mut texture_size = (0, 0);
+
+    // Frames are drawn in the viewport of the window's current size. The
+    // mouse is aimed in window coordinates, which differ from the pixels
+    // drawn on high-DPI screens, so the viewport is kept in both.
+    let mut mouse_viewport = Viewport::default();
+    let mut fullscreen = args.fullscreen;
+    let mut perf = PerfOverlay::new();
+    let mut title = WindowTitle::new(&emulator);
+
+    // Messages are drawn over the frames, as well as printed, if shown.
+    let mut osd = Osd::new();
+    if config.video.messages {
+        emulator.show_messages(osd.sender());
+    }
+
+    let mut controls = match Controls::new(&config.keys, Keycode::from_name) {
+        Ok(controls) => controls,
+        Err(err) => {
+            eprintln!("Invalid key bindings: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // The microphone hears sound while its key is held, or from the
+    // recording device if given.
+    let microphone = match args.mic_input {
+        true => match Microphone::open(&audio_subsystem) {
+            Ok(microphone) => Some(microphone),
+            Err(err) => {
+                eprintln!("Failed to open the microphone: {}", err);
+                std::process::exit(1);
+            }
+        },
+        false => None,
+    };
+
+    let mut timer = Timer::new();
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => emulator.exit(),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat,
+                    ..
+                } => {
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match controls.key_down(&mut emulator, keycode, shift, repeat) {
+                        Some(KeyAction::CycleFilter) => {
+                            emulator.notify(format!("Video filter: {}", filter.cycle()));
+                        }
+                        Some(KeyAction::ToggleFullscreen) => {
+                            let fullscreen_type = match fullscreen {
+                                true => FullscreenType::Off,
+                                false => FullscreenType::Desktop,
+                            };
+                            match canvas.window_mut().set_fullscreen(fullscreen_type) {
+                                Ok(()) => fullscreen = !fullscreen,
+                                Err(err) => eprintln!("Failed to toggle fullscreen: {}", err),
+                            }
+                        }
+                        Some(KeyAction::TogglePerfOverlay) => perf.toggle(&emulator),
+                        _ => {}
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => controls.key_up(&mut emulator, keycode),
+                Event::MouseMotion { x, y, .. } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.aim = mouse_viewport
+                            .frame_position(x, y, frame_size)
+                            .map(|position| filter.overscan().uncrop(position));
+                    }
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.trigger = true;
+                    }
+                }
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.trigger = false;
+                    }
+                }
+                Event::DropFile { filename, .. } => {
+                    let render = copy_frames(&frame);
+                    match emulator.swap_game(&filename, args, config, sample_rate as u32, render) {
+                        Ok(swapped) => {
+                            emulator = swapped;
+                            filter.set_overscan(emulator.overscan());
+                            frame_size = filter.frame_size();
+                            paced_by_vsync = vsync && vsync_paces(&emulator);
+                            title = WindowTitle::new(&emulator);
+                            set_title(&mut canvas, &WindowTitle::first(&emulator));
+                        }
+                        Err(err) => emulator.notify_error(err),
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::Leave,
+                    ..
+                } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.aim = None;
+                    }
+                }
+                _ => { /* do nothing */ }
+            }
+        }
+
+        emulator.input.microphone =
+            controls.microphone_held() || microphone.as_ref().is_some_and(Microphone::hears_sound);
+
+        // Adjust the audio rate based on how much audio is still queued.
+        let queued = queue.size() / std::mem::size_of::<f32>() as u32;
+        let audio_fill = queued as f64 / queue_capacity as f64;
+        emulator.cpu.bus.audio().update_fill_level(audio_fill);
+
+        // Clock the CPU until a frame has been rendered, or the NSF play
+        // routine has been called.
+        let frame_time = emulator.run_frames();
+        perf.update(&emulator, Some(audio_fill));
+        if let Some(text) = title.update(&emulator) {
+            set_title(&mut canvas, &text);
+        }
+        osd.update();
+
+        // Draw the last frame in the viewport of the window's current size.
+        let pixels = frame.borrow();
+        if !pixels.is_empty() {
+            let size @ (w, h) = filter.size();
+            if texture_size != size {
+                let created = creator
+                    .create_texture_target(PixelFormatEnum::RGB24, w, h)
+                    .unwrap();
+                texture = Some(created);
+                texture_size = size;
+            }
+            let scale = filter.scale() as usize;
+            let filtered = filter.process(&pixels);
+            let texture = texture.as_mut().unwrap();
+            texture
+                .update(
+                    None,
+                    perf.draw(osd.draw(filtered, w as usize, scale), w as usize, scale),
+                    w as usize * 3,
+                )
+                .unwrap();
+
+            let viewport = Viewport::new(&config.video, canvas.output_size().unwrap(), frame_size);
+            let window_size = canvas.window().size();
+            mouse_viewport = Viewport::new(&config.video, window_size, frame_size);
+
+            let dst = Rect::new(viewport.x, viewport.y, viewport.w, viewport.h);
+            canvas.clear();
+            canvas.copy(texture, None, dst).unwrap();
+        }
+
+        // Presenting waits for the display to refresh with vsync. Without,
+        // the timer waits until the frame is over on the console.
+        canvas.present();
+        if !paced_by_vsync {
+            timer.wait(frame_time);
+        }
+
+        samples.append(&mut emulator.cpu.bus.audio().drain());
+
+        // Adjust the volume.
+        samples.iter_mut().for_each(|s| *s *= volume);
+
+        // Add the samples to the SDL audio queue.
+        queue.queue_audio(&samples).unwrap();
+
+        // Clear the samples buffer before the next frame.
+        samples.clear();
+    }
+}
+
+/// Sets the title of the window the canvas draws on.
+fn set_title(canvas: &mut WindowCanvas, title: &str) {
+    if let Err(err) = canvas.window_mut().set_title(title) {
+        eprintln!("Failed to set the window title: {}", err);
+    }
+}
@@ -0,0 +1,501 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers,
+    KeyboardEnhancementFlags, ModifierKeyCode, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, SetTitle,
+};
+use crossterm::{execute, queue};
+
+use super::title::WindowTitle;
+use super::{copy_frames, Controls};
+use crate::config::Config;
+use crate::emulator::Emulator;
+use crate::ppu::Frame;
+use crate::timer::Timer;
+use crate::Args;
+
+/// Sample rate of the audio, in Hz. The audio isn't played, but can be
+/// recorded.
+const SAMPLE_RATE: u32 = 44100;
+
+/// How long keys are held after each press in terminals which don't report
+/// key releases. A key held down is held as long as the terminal repeats it.
+const HOLD_TIME: Duration = Duration::from_millis(150);
+
+/// How long each message is shown on the status line.
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+/// How often the whole screen is drawn again, rather than the cells which
+/// changed, clearing anything printed over it.
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A key of the terminal, and whether it's on the keypad, which only
+/// terminals reporting key releases tell.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Key {
+    code: KeyCode,
+    keypad: bool,
+}
+
+impl Key {
+    /// Returns the key of the given event. Letters are the same key with or
+    /// without Shift.
+    fn from_event(event: &KeyEvent) -> Self {
+        let code = match event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            KeyCode::BackTab => KeyCode::Tab,
+            code => code,
+        };
+        Key {
+            code,
+            keypad: event.state.contains(KeyEventState::KEYPAD),
+        }
+    }
+}
+
+/// Returns the terminal key with an SDL key name, ignoring case, if there is
+/// one.
+fn key_from_name(name: &str) -> Option<Key> {
+    let (keypad, name) = match name.strip_prefix("Keypad ") {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+
+    let code = match name.to_ascii_lowercase().as_str() {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        "return" | "enter" => KeyCode::Enter,
+        "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "scroll lock" => KeyCode::ScrollLock,
+        "left shift" => KeyCode::Modifier(ModifierKeyCode::LeftShift),
+        "right shift" => KeyCode::Modifier(ModifierKeyCode::RightShift),
+        "left ctrl" => KeyCode::Modifier(ModifierKeyCode::LeftControl),
+        "right ctrl" => KeyCode::Modifier(ModifierKeyCode::RightControl),
+        "left alt" => KeyCode::Modifier(ModifierKeyCode::LeftAlt),
+        "right alt" => KeyCode::Modifier(ModifierKeyCode::RightAlt),
+        name if name.len() > 1 && name.starts_with('f') => {
+            KeyCode::F(name[1..].parse().ok().filter(|n| (1..=12).contains(n))?)
+        }
+        name => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(Key { code, keypad })
+}
+
+/// A cell of the terminal, drawn as an upper half block: the colour of the
+/// pixel above, in the foreground, and of the pixel below, in the
+/// background.
+type Cell = ([u8; 3], [u8; 3]);
+
+/// Runs the emulator in the terminal, drawing the frames in coloured half
+/// blocks, until it's quit. There's no sound.
+pub fn run(args: &Args, config: &Config) -> ! {
+    let frame = Rc::new(RefCell::new(vec![]));
+    let mut emulator = Emulator::new(args, config, SAMPLE_RATE, copy_frames(&frame));
+
+    // A game's config may connect the Zapper.
+    if args.mic_input || emulator.input.zapper().is_some() {
+        eprintln!("--mic-input and the Zapper can't be used in the terminal");
+        std::process::exit(1);
+    }
+
+    let mut controls = match Controls::new(&config.keys, key_from_name) {
+        Ok(controls) => controls,
+        Err(err) => {
+            eprintln!("Invalid key bindings: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Only some terminals report keys being released. In others keys are
+    // held for a moment after each press.
+    if let Err(err) = terminal::enable_raw_mode() {
+        eprintln!("Failed to set up the terminal: {}", err);
+        std::process::exit(1);
+    }
+    let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let mut out = io::stdout();
+    let _ = execute!(out, EnterAlternateScreen, Hide);
+    if reports_releases {
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+            | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+            | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES;
+        let _ = execute!(out, PushKeyboardEnhancementFlags(flags));
+    }
+    emulator.set_on_exit(move || restore(reports_releases));
+    let panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore(reports_releases);
+        panic_hook(info);
+    }));
+
+    // Messages are shown on the status line under the frames.
+    let (sender, messages) = mpsc::channel();
+    if config.video.messages {
+        emulator.show_messages(sender);
+    }
+    let mut message: Option<(String, Instant)> = None;
+
+    let overscan = emulator.overscan();
+    let frame_size = overscan.size(Frame::WIDTH as u32, Frame::HEIGHT as u32);
+    let frame_size = (frame_size.0 as usize, frame_size.1 as usize);
+    let aspect = config.video.aspect.ratio();
+    let mut cropped = vec![];
+
+    let mut title = WindowTitle::new(&emulator);
+    let _ = execute!(out, SetTitle(WindowTitle::first(&emulator)));
+
+    let mut held: HashMap<Key, Instant> = HashMap::new();
+    let mut drawn: Vec<Cell> = vec![];
+    let mut redrawn = Instant::now();
+    let mut timer = Timer::new();
+    loop {
+        let mut redraw = false;
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let key_event = match event::read() {
+                Ok(Event::Key(key_event)) => key_event,
+                Ok(Event::Resize(..)) => {
+                    redraw = true;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            // Raw mode turns Ctrl+C into a key, so it's handled here.
+            let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl && key_event.code == KeyCode::Char('c') {
+                emulator.exit();
+            }
+
+            let key = Key::from_event(&key_event);
+            let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+            match key_event.kind {
+                KeyEventKind::Press | KeyEventKind::Repeat => {
+                    let repeat = key_event.kind == KeyEventKind::Repeat
+                        || (!reports_releases && held.contains_key(&key));
+                    // The video filters, fullscreen and overlay don't apply.
+                    let _ = controls.key_down(&mut emulator, key, shift, repeat);
+                    if !reports_releases {
+                        held.insert(key, Instant::now());
+                    }
+                }
+                KeyEventKind::Release => controls.key_up(&mut emulator, key),
+            }
+        }
+
+        let now = Instant::now();
+        let released: Vec<_> = held
+            .iter()
+            .filter(|(_, &pressed)| now - pressed >= HOLD_TIME)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in released {
+            held.remove(&key);
+            controls.key_up(&mut emulator, key);
+        }
+
+        emulator.input.microphone = controls.microphone_held();
+        let frame_time = emulator.run_frames();
+        emulator.cpu.bus.audio().drain();
+        if let Some(text) = title.update(&emulator) {
+            let _ = execute!(out, SetTitle(text));
+        }
+
+        // Messages are printed as well, over the screen, so it's drawn
+        // again.
+        if let Some(last) = messages.try_iter().last() {
+            message = Some((last.text, now));
+            redraw = true;
+        }
+        if message
+            .as_ref()
+            .is_some_and(|(_, shown)| now - *shown >= MESSAGE_TIME)
+        {
+            message = None;
+            redraw = true;
+        }
+        if now - redrawn >= REDRAW_INTERVAL {
+            redraw = true;
+        }
+
+        let pixels = frame.borrow();
+        if !pixels.is_empty() {
+            let (cols, rows) = terminal::size().unwrap_or((80, 24));
+            let (cols, rows) = (cols as usize, rows as usize);
+
+            // The frames fill the screen above the status line, two pixels
+            // to a cell.
+            overscan.crop(&pixels, Frame::WIDTH, &mut cropped);
+            let shown_w = (frame_size.0 as f32 * aspect) as usize;
+            let size = fit((shown_w, frame_size.1), (cols, rows.saturating_sub(1) * 2));
+            let cells = cells(&shrink(&cropped, frame_size, size), size);
+            let origin = (
+                (cols - size.0) / 2,
+                (rows.saturating_sub(1) - size.1.div_ceil(2)) / 2,
+            );
+
+            if redraw || cells.len() != drawn.len() {
+                drawn.clear();
+                redrawn = now;
+            }
+
+            let mut buffer = vec![];
+            let result = draw(
+                &mut buffer,
+                &cells,
+                &drawn,
+                size.0,
+                origin,
+                redraw.then(|| status_line(&message, cols, rows)),
+            )
+            .and_then(|()| out.write_all(&buffer))
+            .and_then(|()| out.flush());
+            if result.is_err() {
+                emulator.exit();
+            }
+            drawn = cells;
+        }
+        drop(pixels);
+
+        timer.wait(frame_time);
+    }
+}
+
+/// Restores the terminal to how it was before running, ignoring errors, as
+/// it's about to be left.
+fn restore(reports_releases: bool) {
+    let mut out = io::stdout();
+    if reports_releases {
+        let _ = execute!(out, PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(out, ResetColor, Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Returns the status line on the given row, the last, showing the given
+/// message if any, cut to the given width.
+fn status_line(message: &Option<(String, Instant)>, cols: usize, rows: usize) -> (u16, String) {
+    let text = message.as_ref().map_or("", |(text, _)| text.as_str());
+    (
+        rows.saturating_sub(1) as u16,
+        text.chars().take(cols).collect(),
+    )
+}
+
+/// Returns the size to draw frames of the given size, in pixels, in a screen
+/// of the given size: the largest that fits with their shape kept, but no
+/// larger than the frames.
+fn fit(frame: (usize, usize), screen: (usize, usize)) -> (usize, usize) {
+    let scale = (screen.0 as f32 / frame.0 as f32)
+        .min(screen.1 as f32 / frame.1 as f32)
+        .min(1.0);
+    (
+        (frame.0 as f32 * scale) as usize,
+        (frame.1 as f32 * scale) as usize,
+    )
+}
+
+/// Returns the RGB pixels of the given frame of the given size scaled to
+/// the given size, no larger, each the average of the pixels it covers.
+fn shrink(
+    frame: &[u8],
+    (frame_w, frame_h): (usize, usize),
+    (w, h): (usize, usize),
+) -> Vec<[u8; 3]> {
+    let span = |i: usize, from: usize, to: usize| {
+        let start = i * from / to;
+        start..((i + 1) * from / to).max(start + 1)
+    };
+
+    let mut pixels = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for source_y in span(y, frame_h, h) {
+                for source_x in span(x, frame_w, w) {
+                    let i = (source_y * frame_w + source_x) * 3;
+                    for (sum, &level) in sum.iter_mut().zip(&frame[i..i + 3]) {
+                        *sum += level as u32;
+                    }
+                    count += 1;
+                }
+            }
+            pixels.push(sum.map(|sum| (sum / count) as u8));
+        }
+    }
+    pixels
+}
+
+/// Returns the cells drawing the given pixels of the given size, a pixel
+/// above another in each. An odd last row has black below it.
+fn cells(pixels: &[[u8; 3]], (w, h): (usize, usize)) -> Vec<Cell> {
+    let row = |y: usize| pixels.get(y * w..(y + 1) * w);
+    (0..h.div_ceil(2))
+        .flat_map(|row_pair| {
+            let above = row(row_pair * 2).unwrap_or_default();
+            let below = row(row_pair * 2 + 1).unwrap_or_default();
+            (0..w).map(move |x| (above[x], below.get(x).copied().unwrap_or_default()))
+        })
+        .collect()
+}
+
+/// Writes the cells, a row of the given width after another, to the screen
+/// from the given position, leaving out those the same as the cells drawn
+/// last, and the status line on its row if given. Colours and the cursor
+/// are only set when they change.
+fn draw<W: Write>(
+    out: &mut W,
+    cells: &[Cell],
+    drawn: &[Cell],
+    width: usize,
+    (x, y): (usize, usize),
+    status: Option<(u16, String)>,
+) -> io::Result<()> {
+    if drawn.is_empty() {
+        queue!(out, ResetColor, Clear(ClearType::All))?;
+    }
+
+    let mut cursor = None;
+    let (mut foreground, mut background) = (None, None);
+    for (i, &cell @ (above, below)) in cells.iter().enumerate() {
+        if drawn.get(i) == Some(&cell) {
+            continue;
+        }
+
+        let position = ((x + i % width) as u16, (y + i / width) as u16);
+        if cursor != Some(position) {
+            queue!(out, MoveTo(position.0, position.1))?;
+        }
+        if foreground != Some(above) {
+            queue!(out, SetForegroundColor(rgb(above)))?;
+            foreground = Some(above);
+        }
+        if background != Some(below) {
+            queue!(out, SetBackgroundColor(rgb(below)))?;
+            background = Some(below);
+        }
+        queue!(out, Print('▀'))?;
+        cursor = Some((position.0 + 1, position.1));
+    }
+
+    if let Some((row, text)) = status {
+        queue!(
+            out,
+            ResetColor,
+            MoveTo(0, row),
+            Clear(ClearType::CurrentLine),
+            Print(text)
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the terminal colour of an RGB pixel.
+fn rgb([r, g, b]: [u8; 3]) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_from_name() {
+        let key = |code, keypad| Some(Key { code, keypad });
+        assert_eq!(key_from_name("A"), key(KeyCode::Char('a'), false));
+        assert_eq!(key_from_name("Return"), key(KeyCode::Enter, false));
+        assert_eq!(key_from_name("F12"), key(KeyCode::F(12), false));
+        assert_eq!(key_from_name("Keypad 8"), key(KeyCode::Char('8'), true));
+        assert_eq!(key_from_name("Keypad Enter"), key(KeyCode::Enter, true));
+        assert_eq!(key_from_name("F13"), None);
+        assert_eq!(key_from_name("Nope"), None);
+
+        let shifted = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(Some(Key::from_event(&shifted)), key_from_name("a"));
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit((256, 224), (80, 46)), (52, 46));
+        assert_eq!(fit((256, 224), (400, 200)), (228, 200));
+        assert_eq!(fit((256, 224), (300, 300)), (256, 224));
+    }
+
+    #[test]
+    fn test_shrink() {
+        // A 4x2 frame of black and white columns, halved.
+        let frame = [[0; 3], [255; 3], [0; 3], [0; 3]].repeat(2).concat();
+        assert_eq!(shrink(&frame, (4, 2), (2, 1)), [[127; 3], [0; 3]]);
+        assert_eq!(shrink(&frame, (4, 2), (4, 2)).len(), 8);
+    }
+
+    #[test]
+    fn test_cells() {
+        let pixels = [[1; 3], [2; 3], [3; 3], [4; 3], [5; 3], [6; 3]];
+        let cells = cells(&pixels, (2, 3));
+        assert_eq!(
+            cells,
+            [
+                ([1; 3], [3; 3]),
+                ([2; 3], [4; 3]),
+                ([5; 3], [0; 3]),
+                ([6; 3], [0; 3])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_draw() {
+        let red = ([255, 0, 0], [255, 0, 0]);
+        let cells = [red, red, red, ([0; 3], [0; 3])];
+        let mut out = vec![];
+        draw(&mut out, &cells, &[], 2, (1, 0), None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // The screen is cleared, then each row drawn, the colours set once.
+        let expected = "\x1b[0m\x1b[2J\x1b[1;2H\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m▀▀\
+                        \x1b[2;2H▀\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀";
+        assert_eq!(out, expected);
+
+        // Only the cells which changed are drawn again.
+        let mut changed = cells;
+        changed[1] = ([0; 3], [0; 3]);
+        let mut out = vec![];
+        draw(&mut out, &changed, &cells, 2, (1, 0), None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "\x1b[1;3H\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀");
+
+        let mut out = vec![];
+        draw(&mut out, &cells, &cells, 2, (1, 0), None).unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use crate::emulator::Emulator;
+
+/// Name of the emulator, at the end of the window title.
+const APP_NAME: &str = "RES";
+
+/// How often the frame rate in the title is worked out again, so it can be
+/// read.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The title of the emulator window, naming the game running and the region
+/// of the console, with the frames emulated per second and the speed, so
+/// it can be told at a glance whether the game is running at full speed.
+pub struct WindowTitle {
+    since: Instant,
+    frames_run: u64,
+}
+
+impl WindowTitle {
+    /// Returns a new title of a window showing the given emulator.
+    pub fn new(emulator: &Emulator) -> Self {
+        WindowTitle {
+            since: Instant::now(),
+            frames_run: emulator.frames_run(),
+        }
+    }
+
+    /// Returns the title first shown, before the frame rate can be worked
+    /// out.
+    pub fn first(emulator: &Emulator) -> String {
+        title_text(emulator.name(), emulator.region().name(), None, None)
+    }
+
+    /// Returns the title again once it's due to change.
+    pub fn update(&mut self, emulator: &Emulator) -> Option<String> {
+        let now = Instant::now();
+        let elapsed = now - self.since;
+        if elapsed < UPDATE_INTERVAL {
+            return None;
+        }
+
+        let frames_run = emulator.frames_run().saturating_sub(self.frames_run);
+        let fps = frames_run as f64 / elapsed.as_secs_f64();
+        self.since = now;
+        self.frames_run = emulator.frames_run();
+
+        let name = emulator.name();
+        let region = emulator.region().name();
+        Some(title_text(name, region, Some(fps), speed_text(emulator)))
+    }
+}
+
+/// Returns the speed of the emulation as shown in the title, or None at full
+/// speed.
+fn speed_text(emulator: &Emulator) -> Option<String> {
+    match emulator.speed() {
+        _ if emulator.is_paused() => Some("Paused".to_string()),
+        _ if emulator.is_fast_forwarding() => Some("Fast forward".to_string()),
+        speed if speed.is_infinite() => Some("Max speed".to_string()),
+        speed if speed != 1.0 => Some(format!("{:.0}% speed", speed * 100.0)),
+        _ => None,
+    }
+}
+
+/// Returns the title naming the given game and region, with the frame rate
+/// and speed if known.
+fn title_text(name: &str, region: &str, fps: Option<f64>, speed: Option<String>) -> String {
+    let mut title = format!("{} ({})", name, region);
+    if let Some(fps) = fps {
+        title += &format!(" - {:.1} FPS", fps);
+    }
+    if let Some(speed) = speed {
+        title += &format!(" - {}", speed);
+    }
+    format!("{} - {}", title, APP_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_text() {
+        let title = title_text("Super Mario Bros.", "NTSC", None, None);
+        assert_eq!(title, "Super Mario Bros. (NTSC) - RES");
+
+        let title = title_text("Zelda", "PAL", Some(49.96), None);
+        assert_eq!(title, "Zelda (PAL) - 50.0 FPS - RES");
+
+        let speed = Some("Paused".to_string());
+        let title = title_text("Zelda", "PAL", Some(0.0), speed);
+        assert_eq!(title, "Zelda (PAL) - 0.0 FPS - Paused - RES");
+    }
+}
@@ -0,0 +1,186 @@
+use pixels::{wgpu, Pixels};
+
+use crate::video::Viewport;
+
+/// Draws one triangle covering the viewport, sampling the frame across it.
+const SHADER: &str = r"
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let position = vec2<f32>(f32(index & 1u), f32(index >> 1u)) * 4.0 - 1.0;
+
+    var out: VertexOutput;
+    out.tex_coord = fma(position, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0) var frame: texture_2d<f32>;
+@group(0) @binding(1) var frame_sampler: sampler;
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(frame, frame_sampler, tex_coord);
+}
+";
+
+/// Draws the frame in the pixels texture in a viewport of the window, with
+/// the rest of it black. pixels' own renderer only draws whole multiples of
+/// the frame in square pixels, whatever the config.
+pub struct ViewportRenderer {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ViewportRenderer {
+    pub fn new(pixels: &Pixels) -> Self {
+        let device = pixels.device();
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("viewport_shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        // Pixels are drawn sharp, as they are by the SDL frontend.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("viewport_sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("viewport_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("viewport_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("viewport_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: pixels.render_texture_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let bind_group = bind_frame(pixels, &layout, &sampler);
+        ViewportRenderer {
+            layout,
+            sampler,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Binds the frame again once the pixels buffer is resized, which
+    /// replaces its texture.
+    pub fn resize(&mut self, pixels: &Pixels) {
+        self.bind_group = bind_frame(pixels, &self.layout, &self.sampler);
+    }
+
+    /// Clears the render target and draws the frame in the given viewport.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        viewport: Viewport,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("viewport_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        // A minimised window has nowhere to draw.
+        if viewport.w == 0 || viewport.h == 0 {
+            return;
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.w as f32,
+            viewport.h as f32,
+            0.0,
+            1.0,
+        );
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Returns the bind group of the pixels texture frames are copied to.
+fn bind_frame(
+    pixels: &Pixels,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let view = pixels
+        .texture()
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    pixels
+        .device()
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("viewport_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+}
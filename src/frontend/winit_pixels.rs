@@ -0,0 +1,395 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use pixels::{PixelsBuilder, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::{Fullscreen, WindowBuilder};
+
+use super::cpal_audio::CpalAudio;
+use super::title::WindowTitle;
+use super::viewport_renderer::ViewportRenderer;
+use super::{copy_frames, Controls};
+use crate::config::{Config, KeyAction};
+use crate::emulator::Emulator;
+use crate::perf::PerfOverlay;
+use crate::timer::{vsync_matches, Timer};
+use crate::video::{Osd, VideoFilter, Viewport};
+use crate::Args;
+
+/// Sample rate of the audio output, in Hz.
+const SAMPLE_RATE: u32 = 44100;
+
+/// The winit keys with each SDL key name.
+const KEY_NAMES: [(&str, VirtualKeyCode); 95] = [
+    ("A", VirtualKeyCode::A),
+    ("B", VirtualKeyCode::B),
+    ("C", VirtualKeyCode::C),
+    ("D", VirtualKeyCode::D),
+    ("E", VirtualKeyCode::E),
+    ("F", VirtualKeyCode::F),
+    ("G", VirtualKeyCode::G),
+    ("H", VirtualKeyCode::H),
+    ("I", VirtualKeyCode::I),
+    ("J", VirtualKeyCode::J),
+    ("K", VirtualKeyCode::K),
+    ("L", VirtualKeyCode::L),
+    ("M", VirtualKeyCode::M),
+    ("N", VirtualKeyCode::N),
+    ("O", VirtualKeyCode::O),
+    ("P", VirtualKeyCode::P),
+    ("Q", VirtualKeyCode::Q),
+    ("R", VirtualKeyCode::R),
+    ("S", VirtualKeyCode::S),
+    ("T", VirtualKeyCode::T),
+    ("U", VirtualKeyCode::U),
+    ("V", VirtualKeyCode::V),
+    ("W", VirtualKeyCode::W),
+    ("X", VirtualKeyCode::X),
+    ("Y", VirtualKeyCode::Y),
+    ("Z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0),
+    ("1", VirtualKeyCode::Key1),
+    ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3),
+    ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6),
+    ("7", VirtualKeyCode::Key7),
+    ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("F1", VirtualKeyCode::F1),
+    ("F2", VirtualKeyCode::F2),
+    ("F3", VirtualKeyCode::F3),
+    ("F4", VirtualKeyCode::F4),
+    ("F5", VirtualKeyCode::F5),
+    ("F6", VirtualKeyCode::F6),
+    ("F7", VirtualKeyCode::F7),
+    ("F8", VirtualKeyCode::F8),
+    ("F9", VirtualKeyCode::F9),
+    ("F10", VirtualKeyCode::F10),
+    ("F11", VirtualKeyCode::F11),
+    ("F12", VirtualKeyCode::F12),
+    ("Up", VirtualKeyCode::Up),
+    ("Down", VirtualKeyCode::Down),
+    ("Left", VirtualKeyCode::Left),
+    ("Right", VirtualKeyCode::Right),
+    ("Space", VirtualKeyCode::Space),
+    ("Return", VirtualKeyCode::Return),
+    ("Escape", VirtualKeyCode::Escape),
+    ("Tab", VirtualKeyCode::Tab),
+    ("Backspace", VirtualKeyCode::Back),
+    ("Insert", VirtualKeyCode::Insert),
+    ("Delete", VirtualKeyCode::Delete),
+    ("Home", VirtualKeyCode::Home),
+    ("End", VirtualKeyCode::End),
+    ("PageUp", VirtualKeyCode::PageUp),
+    ("PageDown", VirtualKeyCode::PageDown),
+    ("Scroll Lock", VirtualKeyCode::Scroll),
+    ("Left Shift", VirtualKeyCode::LShift),
+    ("Right Shift", VirtualKeyCode::RShift),
+    ("Left Ctrl", VirtualKeyCode::LControl),
+    ("Right Ctrl", VirtualKeyCode::RControl),
+    ("Left Alt", VirtualKeyCode::LAlt),
+    ("Right Alt", VirtualKeyCode::RAlt),
+    ("-", VirtualKeyCode::Minus),
+    ("=", VirtualKeyCode::Equals),
+    ("[", VirtualKeyCode::LBracket),
+    ("]", VirtualKeyCode::RBracket),
+    ("\\", VirtualKeyCode::Backslash),
+    (";", VirtualKeyCode::Semicolon),
+    ("'", VirtualKeyCode::Apostrophe),
+    ("`", VirtualKeyCode::Grave),
+    (",", VirtualKeyCode::Comma),
+    (".", VirtualKeyCode::Period),
+    ("/", VirtualKeyCode::Slash),
+    ("Keypad 0", VirtualKeyCode::Numpad0),
+    ("Keypad 1", VirtualKeyCode::Numpad1),
+    ("Keypad 2", VirtualKeyCode::Numpad2),
+    ("Keypad 3", VirtualKeyCode::Numpad3),
+    ("Keypad 4", VirtualKeyCode::Numpad4),
+    ("Keypad 5", VirtualKeyCode::Numpad5),
+    ("Keypad 6", VirtualKeyCode::Numpad6),
+    ("Keypad 7", VirtualKeyCode::Numpad7),
+    ("Keypad 8", VirtualKeyCode::Numpad8),
+    ("Keypad 9", VirtualKeyCode::Numpad9),
+    ("Keypad -", VirtualKeyCode::NumpadSubtract),
+    ("Keypad +", VirtualKeyCode::NumpadAdd),
+    ("Keypad .", VirtualKeyCode::NumpadDecimal),
+    ("Keypad Enter", VirtualKeyCode::NumpadEnter),
+];
+
+/// Returns the winit key with an SDL key name, ignoring case, if there is
+/// one.
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+/// Runs the emulator in a winit window, drawn with pixels, with the audio
+/// played through cpal, until it's closed.
+pub fn run(args: Args, config: Config) -> ! {
+    if args.mic_input {
+        eprintln!("The microphone can only be used with the SDL frontend");
+        std::process::exit(1);
+    }
+
+    // The last frame rendered, drawn when the window is redrawn.
+    let frame = Rc::new(RefCell::new(vec![]));
+    let mut emulator = Emulator::new(&args, &config, SAMPLE_RATE, copy_frames(&frame));
+
+    // Frames are cropped, then post-processed before they're drawn, which
+    // may upscale them. The buffer is resized when the filter changes their
+    // size. The window fits the cropped frames.
+    let overscan = emulator.overscan();
+    let mut filter = VideoFilter::new(&config.video, overscan, args.window_w, args.window_h);
+    let mut buffer_size = filter.size();
+    let (window_w, window_h) = args.scaled_window_size(filter.frame_size());
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title(WindowTitle::first(&emulator))
+        .with_inner_size(LogicalSize::new(window_w, window_h))
+        .with_fullscreen(args.fullscreen.then_some(Fullscreen::Borderless(None)))
+        .build(&event_loop)
+        .unwrap();
+
+    // Frames are paced by vsync if the display refreshes at the frame rate
+    // of the console, and by the timer otherwise. A game swapped in at
+    // another frame rate is paced by the timer as well.
+    let refresh_rate = window
+        .current_monitor()
+        .and_then(|monitor| monitor.refresh_rate_millihertz())
+        .map(|refresh_rate| refresh_rate as f64 / 1000.0);
+    let vsync_paces = move |emulator: &Emulator| {
+        refresh_rate.is_some_and(|refresh_rate| vsync_matches(emulator.frame_rate(), refresh_rate))
+    };
+    let vsync = vsync_paces(&emulator);
+    let mut paced_by_vsync = vsync;
+
+    let size = window.inner_size();
+    let surface = SurfaceTexture::new(size.width, size.height, &window);
+    let mut pixels = PixelsBuilder::new(buffer_size.0, buffer_size.1, surface)
+        .enable_vsync(vsync)
+        .build()
+        .unwrap();
+
+    // Frames are drawn in the viewport of the window's current size.
+    let mut renderer = ViewportRenderer::new(&pixels);
+
+    let mut controls = match Controls::new(&config.keys, key_from_name) {
+        Ok(controls) => controls,
+        Err(err) => {
+            eprintln!("Invalid key bindings: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // winit doesn't tell key repeats apart, so the keys held are tracked.
+    let mut held = HashSet::new();
+    let mut modifiers = ModifiersState::empty();
+
+    // The emulator runs without sound if there's no audio device.
+    let mut audio = match CpalAudio::open(&config.audio, SAMPLE_RATE) {
+        Ok(audio) => Some(audio),
+        Err(err) => {
+            eprintln!("Failed to open the audio device: {}", err);
+            None
+        }
+    };
+
+    let mut perf = PerfOverlay::new();
+    let mut title = WindowTitle::new(&emulator);
+
+    // Messages are drawn over the frames, as well as printed, if shown.
+    let mut osd = Osd::new();
+    if config.video.messages {
+        emulator.show_messages(osd.sender());
+    }
+    let mut timer = Timer::new();
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => emulator.exit(),
+                WindowEvent::ModifiersChanged(state) => modifiers = state,
+                WindowEvent::Resized(size)
+                | WindowEvent::ScaleFactorChanged {
+                    new_inner_size: &mut size,
+                    ..
+                } => {
+                    if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                        eprintln!("Failed to resize the window: {}", err);
+                        emulator.exit();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state,
+                            ..
+                        },
+                    ..
+                } => match state {
+                    ElementState::Pressed => {
+                        let repeat = !held.insert(key);
+                        match controls.key_down(&mut emulator, key, modifiers.shift(), repeat) {
+                            Some(KeyAction::CycleFilter) => {
+                                emulator.notify(format!("Video filter: {}", filter.cycle()));
+                            }
+                            Some(KeyAction::ToggleFullscreen) => {
+                                let fullscreen = match window.fullscreen() {
+                                    Some(_) => None,
+                                    None => Some(Fullscreen::Borderless(None)),
+                                };
+                                window.set_fullscreen(fullscreen);
+                            }
+                            Some(KeyAction::TogglePerfOverlay) => perf.toggle(&emulator),
+                            _ => {}
+                        }
+                    }
+                    ElementState::Released => {
+                        held.remove(&key);
+                        controls.key_up(&mut emulator, key);
+                    }
+                },
+                WindowEvent::CursorMoved { position, .. } => {
+                    let size = window.inner_size();
+                    let frame_size = filter.frame_size();
+                    let viewport = Viewport::new(&config.video, size.into(), frame_size);
+                    let overscan = filter.overscan();
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.aim = viewport
+                            .frame_position(position.x as i32, position.y as i32, frame_size)
+                            .map(|position| overscan.uncrop(position));
+                    }
+                }
+                WindowEvent::DroppedFile(path) => {
+                    let path = path.to_string_lossy();
+                    let render = copy_frames(&frame);
+                    match emulator.swap_game(&path, &args, &config, SAMPLE_RATE, render) {
+                        Ok(swapped) => {
+                            emulator = swapped;
+                            filter.set_overscan(emulator.overscan());
+                            paced_by_vsync = vsync && vsync_paces(&emulator);
+                            title = WindowTitle::new(&emulator);
+                            window.set_title(&WindowTitle::first(&emulator));
+                        }
+                        Err(err) => emulator.notify_error(err),
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.trigger = state == ElementState::Pressed;
+                    }
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    if let Some(zapper) = emulator.input.zapper() {
+                        zapper.aim = None;
+                    }
+                }
+                _ => {}
+            },
+            Event::MainEventsCleared => {
+                emulator.input.microphone = controls.microphone_held();
+
+                // Adjust the audio rate based on how full the buffer is.
+                let audio_fill = audio.as_mut().map(|audio| {
+                    audio.recover();
+                    audio.fill_level()
+                });
+                if let Some(fill) = audio_fill {
+                    emulator.cpu.bus.audio().update_fill_level(fill);
+                }
+
+                let frame_time = emulator.run_frames();
+                perf.update(&emulator, audio_fill);
+                if let Some(text) = title.update(&emulator) {
+                    window.set_title(&text);
+                }
+                osd.update();
+
+                let samples = emulator.cpu.bus.audio().drain();
+                if let Some(audio) = audio.as_mut() {
+                    audio.queue(&samples);
+                }
+                window.request_redraw();
+
+                // Rendering waits for the display to refresh with vsync.
+                // Without, the timer waits until the frame is over on the
+                // console.
+                if !paced_by_vsync {
+                    timer.wait(frame_time);
+                }
+            }
+            Event::RedrawRequested(_) => {
+                let frame = frame.borrow();
+                if !frame.is_empty() {
+                    let size @ (w, h) = filter.size();
+                    if size != buffer_size {
+                        if let Err(err) = pixels.resize_buffer(w, h) {
+                            eprintln!("Failed to resize the frame: {}", err);
+                            emulator.exit();
+                        }
+                        renderer.resize(&pixels);
+                        buffer_size = size;
+                    }
+
+                    let scale = filter.scale() as usize;
+                    let filtered = filter.process(&frame);
+                    let rgb = perf
+                        .draw(osd.draw(filtered, w as usize, scale), w as usize, scale)
+                        .chunks_exact(3);
+                    for (rgba, rgb) in pixels.frame_mut().chunks_exact_mut(4).zip(rgb) {
+                        rgba[..3].copy_from_slice(rgb);
+                        rgba[3] = 0xFF;
+                    }
+                }
+
+                let size = window.inner_size();
+                let viewport = Viewport::new(&config.video, size.into(), filter.frame_size());
+                let rendered = pixels.render_with(|encoder, target, _| {
+                    renderer.render(encoder, target, viewport);
+                    Ok(())
+                });
+                if let Err(err) = rendered {
+                    eprintln!("Failed to render the frame: {}", err);
+                    emulator.exit();
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyBindings;
+    use crate::frontend::FAMILY_KEYBOARD_KEYS;
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(key_from_name("Keypad 8"), Some(VirtualKeyCode::Numpad8));
+        assert_eq!(key_from_name("return"), Some(VirtualKeyCode::Return));
+        assert_eq!(key_from_name("Foo"), None);
+
+        // Every default key and Family BASIC keyboard key has a winit key.
+        assert!(Controls::new(&KeyBindings::default(), key_from_name).is_ok());
+        for name in FAMILY_KEYBOARD_KEYS.iter().flatten() {
+            assert!(key_from_name(name).is_some(), "no key {}", name);
+        }
+    }
+}
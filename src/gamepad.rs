@@ -0,0 +1,245 @@
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+use crate::config::GamepadConfig;
+use crate::input::InputState;
+use crate::joypad::{
+    JOYPAD_BUTTON_A, JOYPAD_BUTTON_B, JOYPAD_DOWN, JOYPAD_LEFT, JOYPAD_RIGHT, JOYPAD_SELECT,
+    JOYPAD_START, JOYPAD_UP,
+};
+
+/// A controller button pressed by a gamepad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PadInput {
+    Button(u8),
+    Turbo(u8),
+}
+
+impl PadInput {
+    /// Returns the controller button pressed by a gamepad button, if any.
+    /// The face buttons follow the NES layout, with B left of A.
+    fn from_button(button: Button) -> Option<Self> {
+        match button {
+            Button::DPadUp => Some(PadInput::Button(JOYPAD_UP)),
+            Button::DPadDown => Some(PadInput::Button(JOYPAD_DOWN)),
+            Button::DPadLeft => Some(PadInput::Button(JOYPAD_LEFT)),
+            Button::DPadRight => Some(PadInput::Button(JOYPAD_RIGHT)),
+            Button::Select => Some(PadInput::Button(JOYPAD_SELECT)),
+            Button::Start => Some(PadInput::Button(JOYPAD_START)),
+            Button::East => Some(PadInput::Button(JOYPAD_BUTTON_A)),
+            Button::South => Some(PadInput::Button(JOYPAD_BUTTON_B)),
+            Button::North => Some(PadInput::Turbo(JOYPAD_BUTTON_A)),
+            Button::West => Some(PadInput::Turbo(JOYPAD_BUTTON_B)),
+            _ => None,
+        }
+    }
+
+    /// Returns the two D-pad directions of an axis, and whether each is
+    /// pressed by the given value, if the axis moves the D-pad.
+    fn from_axis(axis: Axis, value: f32, deadzone: f32) -> Option<[(Self, bool); 2]> {
+        let (negative, positive) = match axis {
+            Axis::LeftStickX | Axis::DPadX => (JOYPAD_LEFT, JOYPAD_RIGHT),
+            // Up is positive.
+            Axis::LeftStickY | Axis::DPadY => (JOYPAD_DOWN, JOYPAD_UP),
+            _ => return None,
+        };
+
+        Some([
+            (PadInput::Button(negative), value < -deadzone),
+            (PadInput::Button(positive), value > deadzone),
+        ])
+    }
+
+    /// Presses or releases the button on the controller of the given player.
+    fn apply(self, input: &mut InputState, player: u8, pressed: bool) {
+        match self {
+            PadInput::Button(button) => input.set_button_pressed(player, button, pressed),
+            PadInput::Turbo(button) => input.set_turbo_pressed(player, button, pressed),
+        }
+    }
+}
+
+/// Assigns gamepads to the controller ports, given the IDs of the gamepads.
+struct PortAssignment<T> {
+    ports: [Option<T>; 2],
+
+    /// Part of the name of the gamepads each port accepts, if limited.
+    names: [Option<String>; 2],
+}
+
+impl<T: Copy + PartialEq> PortAssignment<T> {
+    fn new(names: [Option<String>; 2]) -> Self {
+        PortAssignment {
+            ports: [None, None],
+            names,
+        }
+    }
+
+    /// Returns the player using the gamepad, if it's assigned to a port.
+    fn player(&self, id: T) -> Option<u8> {
+        let port = self.ports.iter().position(|&port| port == Some(id))?;
+        Some(port as u8 + 1)
+    }
+
+    /// Assigns the gamepad to the first free port accepting its name, and
+    /// returns the player using it, if any.
+    fn assign(&mut self, id: T, name: &str) -> Option<u8> {
+        if let Some(player) = self.player(id) {
+            return Some(player);
+        }
+
+        let port = (0..2).find(|&port| {
+            self.ports[port].is_none()
+                && self.names[port]
+                    .as_ref()
+                    .is_none_or(|pattern| name.contains(pattern.as_str()))
+        })?;
+        self.ports[port] = Some(id);
+        Some(port as u8 + 1)
+    }
+
+    /// Frees the port of the gamepad, and returns the player that used it.
+    fn release(&mut self, id: T) -> Option<u8> {
+        let player = self.player(id)?;
+        self.ports[player as usize - 1] = None;
+        Some(player)
+    }
+}
+
+/// Represents the gamepads connected, each used as the controller of the
+/// port it's assigned to. Gamepads are assigned to the first free port as
+/// they're connected, and free it when disconnected.
+///
+/// The D-pad may also be pressed with the left stick, once pushed past the
+/// deadzone.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    ports: PortAssignment<gilrs::GamepadId>,
+    deadzone: f32,
+}
+
+impl Gamepads {
+    /// Returns the gamepads, assigning those already connected.
+    pub fn new(config: &GamepadConfig) -> Result<Self, Box<gilrs::Error>> {
+        let mut gamepads = Gamepads {
+            gilrs: Gilrs::new().map_err(Box::new)?,
+            ports: PortAssignment::new([config.player1.clone(), config.player2.clone()]),
+            deadzone: config.deadzone,
+        };
+
+        let connected: Vec<_> = gamepads.gilrs.gamepads().map(|(id, _)| id).collect();
+        for id in connected {
+            gamepads.connect(id);
+        }
+
+        Ok(gamepads)
+    }
+
+    /// Assigns a connected gamepad to a port.
+    fn connect(&mut self, id: gilrs::GamepadId) {
+        if self.ports.player(id).is_some() {
+            return;
+        }
+
+        let name = self.gilrs.gamepad(id).name().to_string();
+        match self.ports.assign(id, &name) {
+            Some(player) => println!("Gamepad \"{}\" connected as player {}", name, player),
+            None => println!("Gamepad \"{}\" connected, but no port is free", name),
+        }
+    }
+
+    /// Handles the gamepad events since the last update, pressing the buttons
+    /// of the controllers they're assigned to.
+    pub fn update(&mut self, input: &mut InputState) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => self.connect(event.id),
+                EventType::Disconnected => {
+                    // Release every button, which may still be held.
+                    if let Some(player) = self.ports.release(event.id) {
+                        println!("Gamepad for player {} disconnected", player);
+                        input.release_buttons(player);
+                    }
+                }
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    let pressed = matches!(event.event, EventType::ButtonPressed(..));
+                    let player = self.ports.player(event.id);
+                    if let (Some(player), Some(pad_input)) = (player, PadInput::from_button(button))
+                    {
+                        pad_input.apply(input, player, pressed);
+                    }
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    let player = self.ports.player(event.id);
+                    let inputs = PadInput::from_axis(axis, value, self.deadzone);
+                    if let (Some(player), Some(inputs)) = (player, inputs) {
+                        for (pad_input, pressed) in inputs {
+                            pad_input.apply(input, player, pressed);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buttons() {
+        assert_eq!(
+            PadInput::from_button(Button::East),
+            Some(PadInput::Button(JOYPAD_BUTTON_A))
+        );
+        assert_eq!(
+            PadInput::from_button(Button::West),
+            Some(PadInput::Turbo(JOYPAD_BUTTON_B))
+        );
+        assert_eq!(PadInput::from_button(Button::Mode), None);
+    }
+
+    #[test]
+    fn test_axes() {
+        let left = PadInput::Button(JOYPAD_LEFT);
+        let right = PadInput::Button(JOYPAD_RIGHT);
+        assert_eq!(
+            PadInput::from_axis(Axis::LeftStickX, -0.8, 0.5),
+            Some([(left, true), (right, false)])
+        );
+        assert_eq!(
+            PadInput::from_axis(Axis::LeftStickX, 0.4, 0.5),
+            Some([(left, false), (right, false)])
+        );
+        assert_eq!(
+            PadInput::from_axis(Axis::LeftStickY, 0.6, 0.5),
+            Some([
+                (PadInput::Button(JOYPAD_DOWN), false),
+                (PadInput::Button(JOYPAD_UP), true)
+            ])
+        );
+        assert_eq!(PadInput::from_axis(Axis::RightStickX, 1.0, 0.5), None);
+    }
+
+    #[test]
+    fn test_port_assignment() {
+        let mut ports = PortAssignment::new([None, None]);
+        assert_eq!(ports.assign(10, "Pad"), Some(1));
+        assert_eq!(ports.assign(11, "Pad"), Some(2));
+        assert_eq!(ports.assign(10, "Pad"), Some(1));
+        assert_eq!(ports.assign(12, "Pad"), None);
+
+        // A gamepad connected later takes the freed port.
+        assert_eq!(ports.release(10), Some(1));
+        assert_eq!(ports.release(10), None);
+        assert_eq!(ports.assign(12, "Pad"), Some(1));
+        assert_eq!(ports.player(11), Some(2));
+
+        // Ports may only accept gamepads by name.
+        let mut ports = PortAssignment::new([Some("8BitDo".to_string()), None]);
+        assert_eq!(ports.assign(10, "Xbox Controller"), Some(2));
+        assert_eq!(ports.assign(11, "Xbox Controller"), None);
+        assert_eq!(ports.assign(12, "8BitDo SN30 Pro"), Some(1));
+    }
+}
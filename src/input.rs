@@ -0,0 +1,165 @@
+/// The state of a Zapper.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ZapperState {
+    pub trigger: bool,
+
+    /// The aimed position in the frame, or none when aimed off screen.
+    pub aim: Option<(usize, usize)>,
+}
+
+/// The device connected to a controller port, with its state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortDevice {
+    /// A standard controller, with a bit set for each button pressed and for
+    /// each button held with turbo.
+    Joypad { buttons: u8, turbo: u8 },
+
+    /// A Zapper, which is only supported on the second port.
+    Zapper(ZapperState),
+}
+
+/// The state of the input devices in a frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputState {
+    pub ports: [PortDevice; 2],
+
+    /// Whether the microphone on Famicom controller 2 hears sound.
+    pub microphone: bool,
+}
+
+impl InputState {
+    /// Returns the state of standard controllers, or a Zapper on the second
+    /// port, with nothing pressed.
+    pub fn new(zapper: bool) -> Self {
+        let joypad = PortDevice::Joypad {
+            buttons: 0,
+            turbo: 0,
+        };
+
+        InputState {
+            ports: match zapper {
+                true => [joypad, PortDevice::Zapper(ZapperState::default())],
+                false => [joypad, joypad],
+            },
+            microphone: false,
+        }
+    }
+
+    /// Returns the buttons pressed and held with turbo on the controller of
+    /// the given player, if it's connected.
+    fn joypad(&mut self, player: u8) -> Option<(&mut u8, &mut u8)> {
+        match &mut self.ports[player as usize - 1] {
+            PortDevice::Joypad { buttons, turbo } => Some((buttons, turbo)),
+            _ => None,
+        }
+    }
+
+    /// Sets the pressed state of a button on the controller of the given
+    /// player, if it's connected.
+    pub fn set_button_pressed(&mut self, player: u8, button: u8, pressed: bool) {
+        if let Some((buttons, _)) = self.joypad(player) {
+            set_bits(buttons, button, pressed);
+        }
+    }
+
+    /// Sets the pressed state of a button with turbo on the controller of
+    /// the given player, if it's connected.
+    pub fn set_turbo_pressed(&mut self, player: u8, button: u8, pressed: bool) {
+        if let Some((_, turbo)) = self.joypad(player) {
+            set_bits(turbo, button, pressed);
+        }
+    }
+
+    /// Releases every button on the controller of the given player, such
+    /// as when its gamepad is disconnected.
+    #[cfg(any(feature = "gamepad", test))]
+    pub fn release_buttons(&mut self, player: u8) {
+        if let Some((buttons, turbo)) = self.joypad(player) {
+            *buttons = 0;
+            *turbo = 0;
+        }
+    }
+
+    /// Returns the state of the Zapper, if one is connected.
+    pub fn zapper(&mut self) -> Option<&mut ZapperState> {
+        match &mut self.ports[1] {
+            PortDevice::Zapper(zapper) => Some(zapper),
+            _ => None,
+        }
+    }
+}
+
+/// Sets or clears the given bits.
+fn set_bits(status: &mut u8, bits: u8, set: bool) {
+    match set {
+        true => *status |= bits,
+        false => *status &= !bits,
+    }
+}
+
+/// A source of input, polled once per frame for the state of the devices
+/// connected. Frontends (such as SDL, WASM or libretro) provide the input
+/// of the player, and synthetic providers may inject input such as from a
+/// movie or a netplay peer.
+pub trait InputProvider {
+    /// Returns the state of the input devices for the next frame.
+    fn poll(&mut self) -> InputState;
+}
+
+/// A state kept up to date by a frontend's input events provides itself.
+impl InputProvider for InputState {
+    fn poll(&mut self) -> InputState {
+        *self
+    }
+}
+
+impl<F: FnMut() -> InputState> InputProvider for F {
+    fn poll(&mut self) -> InputState {
+        self()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::{JOYPAD_BUTTON_A, JOYPAD_BUTTON_B};
+
+    #[test]
+    fn test_buttons() {
+        let mut state = InputState::new(false);
+        state.set_button_pressed(1, JOYPAD_BUTTON_A | JOYPAD_BUTTON_B, true);
+        state.set_button_pressed(1, JOYPAD_BUTTON_B, false);
+        state.set_turbo_pressed(2, JOYPAD_BUTTON_B, true);
+        assert_eq!(
+            state.poll().ports,
+            [
+                PortDevice::Joypad {
+                    buttons: JOYPAD_BUTTON_A,
+                    turbo: 0
+                },
+                PortDevice::Joypad {
+                    buttons: 0,
+                    turbo: JOYPAD_BUTTON_B
+                }
+            ]
+        );
+
+        state.release_buttons(2);
+        assert_eq!(state.ports[1], InputState::new(false).ports[1]);
+        assert!(state.zapper().is_none());
+    }
+
+    #[test]
+    fn test_zapper() {
+        let mut state = InputState::new(true);
+        state.set_button_pressed(2, JOYPAD_BUTTON_A, true);
+        state.zapper().unwrap().trigger = true;
+        assert_eq!(
+            state.ports[1],
+            PortDevice::Zapper(ZapperState {
+                trigger: true,
+                aim: None
+            })
+        );
+    }
+}
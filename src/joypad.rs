@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 pub const JOYPAD_RIGHT: u8 = 0b10000000;
 pub const JOYPAD_LEFT: u8 = 0b01000000;
 pub const JOYPAD_DOWN: u8 = 0b00100000;
@@ -7,6 +9,9 @@ pub const JOYPAD_SELECT: u8 = 0b00000100;
 pub const JOYPAD_BUTTON_B: u8 = 0b00000010;
 pub const JOYPAD_BUTTON_A: u8 = 0b00000001;
 
+/// Default number of times per second turbo buttons are pressed.
+pub const DEFAULT_TURBO_RATE: f64 = 15.0;
+
 /// Represents a NES joypad.
 ///
 /// NES joypads report the status of one button at a time in this order:
@@ -18,48 +23,122 @@ pub const JOYPAD_BUTTON_A: u8 = 0b00000001;
 ///
 /// The controller operates in 2 modes:
 ///   - strobe bit on: controller reports only status of the button A on every
-///     read
-///   - strobe bit off: controller cycles through all buttons
+///     read, and keeps reloading its shift register with the buttons
+///   - strobe bit off: controller shifts out the buttons latched when the
+///     strobe bit was cleared, so presses made during a read don't show until
+///     the next strobe
+///
+/// Buttons may also be held with turbo, which presses them repeatedly at the
+/// turbo rate. The presses are timed in frames, so they're reproduced
+/// exactly wherever the buttons come from.
+///
+/// The buttons are sampled once per frame, when the game first reloads the
+/// shift register, and changes made later in the frame wait for the next
+/// one. This way the input of each frame can be recorded and played back
+/// exactly, without delaying input made before the game reads it.
+///
+/// See: https://www.nesdev.org/wiki/Standard_controller
 pub struct Joypad {
     strobe: bool,
-    button_index: u8,
+
+    /// Shift register holding the buttons yet to be reported, in bit 0
+    /// onwards. 1s are shifted in behind them.
+    shift: u8,
+
     button_status: u8,
+
+    /// Buttons held with turbo.
+    turbo_status: u8,
+
+    /// Number of turbo presses per second.
+    turbo_rate: f64,
+
+    /// Progress through the current turbo press, from 0 to 1. The buttons are
+    /// pressed for the first half.
+    turbo_phase: f64,
+
+    /// The buttons sampled in the current frame, if any.
+    frame_status: Option<u8>,
 }
 
+// The buttons pressed are input from the player, so aren't saved.
+snapshot!(Joypad, strobe, shift, turbo_phase, frame_status);
+
 impl Joypad {
     /// Returns an instantiated joypad.
     pub fn new() -> Self {
         Joypad {
             strobe: false,
-            button_index: 0,
+            shift: 0,
             button_status: 0b00000000,
+            turbo_status: 0,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            turbo_phase: 0.0,
+            frame_status: None,
         }
     }
 
+    /// Returns the buttons currently pressed, including turbo buttons while
+    /// they're pressed.
+    fn buttons(&self) -> u8 {
+        match self.turbo_phase < 0.5 {
+            true => self.button_status | self.turbo_status,
+            false => self.button_status,
+        }
+    }
+
+    /// Returns the buttons sampled in the current frame, sampling them if
+    /// they haven't been already.
+    fn sample(&mut self) -> u8 {
+        let buttons = self.buttons();
+        *self.frame_status.get_or_insert(buttons)
+    }
+
+    /// Returns the buttons pressed in the current frame, which are sampled
+    /// now if the game hasn't read them yet.
+    pub fn frame_status(&self) -> u8 {
+        self.frame_status.unwrap_or_else(|| self.buttons())
+    }
+
+    /// Sets the buttons pressed in the current frame, such as from an input
+    /// movie, in place of those pressed by the player.
+    pub fn set_frame_status(&mut self, status: u8) {
+        self.frame_status = Some(status);
+    }
+
     /// Writes the status of the joypad.
     pub fn write(&mut self, data: u8) {
-        self.strobe = data & 1 == 1;
+        let strobe = data & 1 == 1;
 
-        // Reset index back to A if strobe mode is on.
-        if self.strobe {
-            self.button_index = 0
+        // The buttons are reloaded while strobe mode is on, and stay latched
+        // once it is turned off.
+        if strobe || self.strobe {
+            self.shift = self.sample();
         }
+
+        self.strobe = strobe;
     }
 
     /// Returns the status of the current button.
     pub fn read(&mut self) -> u8 {
-        if self.button_index > 7 {
-            return 1;
-        }
-
-        let response = (self.button_status & (1 << self.button_index)) >> self.button_index;
-        if !self.strobe && self.button_index <= 7 {
-            self.button_index += 1;
+        if self.strobe {
+            return self.sample() & JOYPAD_BUTTON_A;
         }
 
+        let response = self.shift & 1;
+        self.shift = (self.shift >> 1) | 0x80;
         response
     }
 
+    /// Returns the status of the current button without moving on to the
+    /// next one.
+    pub fn peek(&self) -> u8 {
+        match self.strobe {
+            true => self.frame_status() & JOYPAD_BUTTON_A,
+            false => self.shift & 1,
+        }
+    }
+
     /// Sets the pressed state of the given button.
     pub fn set_button_pressed_status(&mut self, button: u8, pressed: bool) {
         if pressed {
@@ -68,6 +147,40 @@ impl Joypad {
             self.button_status &= !button;
         }
     }
+
+    /// Sets the pressed state of the given button with turbo.
+    pub fn set_turbo_pressed_status(&mut self, button: u8, pressed: bool) {
+        // Start a press as soon as turbo is first held.
+        if pressed && self.turbo_status == 0 {
+            self.turbo_phase = 0.0;
+        }
+
+        if pressed {
+            self.turbo_status |= button;
+        } else {
+            self.turbo_status &= !button;
+        }
+    }
+
+    /// Sets every button pressed, and every button held with turbo.
+    pub fn set_status(&mut self, buttons: u8, turbo: u8) {
+        self.set_button_pressed_status(!buttons, false);
+        self.set_button_pressed_status(buttons, true);
+        self.set_turbo_pressed_status(!turbo, false);
+        self.set_turbo_pressed_status(turbo, true);
+    }
+
+    /// Sets the number of times per second turbo buttons are pressed.
+    pub fn set_turbo_rate(&mut self, rate: f64) {
+        self.turbo_rate = rate;
+    }
+
+    /// Moves on to the next frame, given the number of frames per second,
+    /// where the buttons are sampled again.
+    pub fn end_frame(&mut self, frame_rate: f64) {
+        self.turbo_phase = (self.turbo_phase + self.turbo_rate / frame_rate).fract();
+        self.frame_status = None;
+    }
 }
 
 #[cfg(test)]
@@ -77,22 +190,36 @@ mod tests {
     #[test]
     fn test_strobe_mode() {
         let mut joypad = Joypad::new();
-        joypad.write(1);
         joypad.set_button_pressed_status(JOYPAD_BUTTON_A, true);
+        joypad.write(1);
         for _x in 0..10 {
             assert_eq!(joypad.read(), 1);
         }
     }
 
+    #[test]
+    fn test_peek() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        assert_eq!(joypad.peek(), 0);
+        assert_eq!(joypad.peek(), 0);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.peek(), 1);
+    }
+
     #[test]
     fn test_strobe_mode_on_off() {
         let mut joypad = Joypad::new();
 
-        joypad.write(0);
         joypad.set_button_pressed_status(JOYPAD_RIGHT, true);
         joypad.set_button_pressed_status(JOYPAD_LEFT, true);
         joypad.set_button_pressed_status(JOYPAD_SELECT, true);
         joypad.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+        joypad.write(1);
+        joypad.write(0);
 
         for _ in 0..=1 {
             assert_eq!(joypad.read(), 0);
@@ -111,4 +238,80 @@ mod tests {
             joypad.write(0);
         }
     }
+
+    #[test]
+    fn test_turbo() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo_rate(15.0);
+        joypad.set_turbo_pressed_status(JOYPAD_BUTTON_B, true);
+
+        // Pressed for 2 frames then released for 2, at 60 frames per second.
+        let mut presses = vec![];
+        for _ in 0..8 {
+            joypad.write(1);
+            joypad.write(0);
+            joypad.read();
+            presses.push(joypad.read());
+            joypad.end_frame(60.0);
+        }
+        assert_eq!(presses, [1, 1, 0, 0, 1, 1, 0, 0]);
+
+        // Held buttons stay pressed.
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+        joypad.write(1);
+        joypad.write(0);
+        joypad.read();
+        assert_eq!(joypad.read(), 1);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_A, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        // Changes after the strobe don't show until the next one.
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_A, false);
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+
+        // Strobing again reloads the buttons sampled in this frame, and the
+        // changes show from the next one.
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.frame_status(), JOYPAD_BUTTON_A);
+
+        joypad.end_frame(60.0);
+        assert_eq!(joypad.frame_status(), JOYPAD_BUTTON_B);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 1);
+    }
+
+    #[test]
+    fn test_set_status() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_B, true);
+        joypad.set_status(JOYPAD_BUTTON_A, JOYPAD_BUTTON_B);
+        assert_eq!(joypad.frame_status(), JOYPAD_BUTTON_A | JOYPAD_BUTTON_B);
+
+        joypad.set_status(0, 0);
+        assert_eq!(joypad.frame_status(), 0);
+    }
+
+    #[test]
+    fn test_set_frame_status() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JOYPAD_BUTTON_A, true);
+        joypad.set_frame_status(JOYPAD_BUTTON_B);
+
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 1);
+    }
 }
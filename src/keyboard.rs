@@ -0,0 +1,157 @@
+use crate::state::snapshot;
+
+/// Number of rows in the key matrix.
+pub const KEYBOARD_ROWS: usize = 9;
+
+/// Number of keys in each row, four in each of the two columns.
+pub const KEYBOARD_ROW_KEYS: usize = 8;
+
+/// Bits of $4017 reporting the keys of the selected column.
+const KEY_BITS: u8 = 0b00011110;
+
+/// Represents the Family BASIC keyboard, connected to the Famicom expansion
+/// port and used by Family BASIC and to edit levels in games such as Lode
+/// Runner.
+///
+/// The keys are wired in a matrix of 9 rows, each split into two columns of
+/// four keys. Writes to $4016 select the row and column, and reads of $4017
+/// report the keys in it, with a bit cleared for each key pressed.
+///
+/// 7  bit  0
+/// ---- ----
+/// xxxx xKCR
+///       |||
+///       ||+- Reset to the first row
+///       |+-- Column select, moving to the next row when cleared
+///       +--- Enable the keyboard
+///
+/// The keys in each row, from bit 4 to 1 of column 0 and then column 1:
+///
+/// | Row | Column 0                | Column 1                   |
+/// | --- | ----------------------- | -------------------------- |
+/// | 0   | ] [ RETURN F8           | STOP ¥ RSHIFT KANA         |
+/// | 1   | ; : @ F7                | ^ - / _                    |
+/// | 2   | K L O F6                | 0 P , .                    |
+/// | 3   | J U I F5                | 8 9 N M                    |
+/// | 4   | H G Y F4                | 6 7 V B                    |
+/// | 5   | D R T F3                | 4 5 C F                    |
+/// | 6   | A S W F2                | 3 E Z X                    |
+/// | 7   | CTR Q ESC F1            | 2 1 GRPH LSHIFT            |
+/// | 8   | LEFT RIGHT UP CLR       | INS DEL SPACE DOWN         |
+///
+/// See: https://www.nesdev.org/wiki/Family_BASIC_Keyboard
+#[derive(Default)]
+pub struct FamilyKeyboard {
+    /// The keys pressed in each row, with a bit set for each, in the order
+    /// of the table above.
+    keys: [u8; KEYBOARD_ROWS],
+
+    row: usize,
+    column: u8,
+    enabled: bool,
+}
+
+// The keys pressed are input from the player, so aren't saved.
+snapshot!(FamilyKeyboard, row, column, enabled);
+
+impl FamilyKeyboard {
+    /// Returns a keyboard with no keys pressed.
+    pub fn new() -> Self {
+        FamilyKeyboard {
+            keys: [0; KEYBOARD_ROWS],
+            row: 0,
+            column: 0,
+            enabled: false,
+        }
+    }
+
+    /// Sets whether a key is pressed, given its row and its position in the
+    /// row.
+    pub fn set_key_pressed(&mut self, row: usize, key: usize, pressed: bool) {
+        let bit = 0x80 >> key;
+        match pressed {
+            true => self.keys[row] |= bit,
+            false => self.keys[row] &= !bit,
+        }
+    }
+
+    /// Releases every key.
+    pub fn release_keys(&mut self) {
+        self.keys = [0; KEYBOARD_ROWS];
+    }
+
+    /// Selects the row and column from a write to $4016.
+    pub fn write(&mut self, data: u8) {
+        let column = (data >> 1) & 1;
+        if data & 1 == 1 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            self.row += 1;
+        }
+
+        self.column = column;
+        self.enabled = data & 0b100 != 0;
+    }
+
+    /// Returns the keys of the selected row and column for a read of $4017,
+    /// which are all released once past the last row. Nothing is reported
+    /// while the keyboard is disabled.
+    pub fn read(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let Some(keys) = self.keys.get(self.row) else {
+            return KEY_BITS;
+        };
+        let pressed = match self.column {
+            0 => keys >> 4,
+            _ => keys & 0x0F,
+        };
+
+        !(pressed << 1) & KEY_BITS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let mut keyboard = FamilyKeyboard::new();
+        assert_eq!(keyboard.read(), 0);
+
+        // RETURN is in row 0, column 0, and E in row 6, column 1.
+        keyboard.set_key_pressed(0, 2, true);
+        keyboard.set_key_pressed(6, 5, true);
+
+        keyboard.write(0b101);
+        keyboard.write(0b100);
+        assert_eq!(keyboard.read(), 0b00011010);
+        keyboard.write(0b110);
+        assert_eq!(keyboard.read(), KEY_BITS);
+
+        for _ in 0..6 {
+            keyboard.write(0b100);
+            keyboard.write(0b110);
+        }
+        assert_eq!(keyboard.read(), 0b00010110);
+
+        // Scanning past the last row finds no keys pressed.
+        for _ in 0..3 {
+            keyboard.write(0b100);
+            keyboard.write(0b110);
+        }
+        assert_eq!(keyboard.read(), KEY_BITS);
+
+        // Resetting returns to the first row, and disabling the keyboard
+        // leaves nothing reported.
+        keyboard.set_key_pressed(0, 2, false);
+        keyboard.write(0b101);
+        keyboard.write(0b100);
+        assert_eq!(keyboard.read(), KEY_BITS);
+        keyboard.write(0);
+        assert_eq!(keyboard.read(), 0);
+    }
+}
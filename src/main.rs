@@ -1,40 +1,66 @@
+// Built without a frontend, the emulator only runs headless, which leaves
+// the hotkeys, save states and video filters the frontends use unused.
+#![cfg_attr(
+    not(any(feature = "sdl", feature = "winit", feature = "terminal")),
+    allow(dead_code)
+)]
+
 extern crate core;
 
 mod apu;
+mod audio;
+mod battery;
 mod bus;
 mod cartridge;
+mod cheat;
+mod config;
 mod cpu;
+mod emulator;
 mod filters;
+mod frontend;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod input;
 mod instructions;
 mod joypad;
+mod keyboard;
 mod mapper;
+#[cfg(feature = "sdl")]
+mod microphone;
+mod movie;
+mod nsf;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod perf;
 mod ppu;
+mod ram_init;
+mod region;
 mod rom;
+mod run_ahead;
+mod save_slots;
+mod state;
+mod storage;
+mod thumbnail;
 mod timer;
 mod trace;
+mod video;
+mod zapper;
+
+use bus::hooks::MemoryWatch;
+use cheat::RamCheat;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use config::{Config, GameConfig};
+use frontend::Frontend;
+use ram_init::RamInit;
+use region::Region;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 
-use bus::SystemBus;
-use cartridge::Cartridge;
-use clap::Parser;
-use cpu::Cpu;
-use sdl2::audio::AudioSpecDesired;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::time::Duration;
-use timer::Timer;
-
-// Time between each frame (at 60fps)
-const SECS_PER_FRAME: f64 = 1.0 / 60.0;
-
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(
     version = "0.1.0",
     about = "A NES emulator implemented in Rust",
-    long_about = "A NES emulator implemented in Rust\n\nControls:\n\nUp arrow\t= D-pad up\nDown arrow\t= D-pad down\nLeft arrow\t= D-pad left\nRight arrow\t= D-pad right\nSpace bar\t= Select\nReturn\t\t= Start\nA\t\t= A\nS\t\t= B"
+    long_about = "A NES emulator implemented in Rust\n\nControls:\n\nUp arrow\t= D-pad up\nDown arrow\t= D-pad down\nLeft arrow\t= D-pad left\nRight arrow\t= D-pad right\nSpace bar\t= Select\nReturn\t\t= Start\nA\t\t= A\nS\t\t= B\nQ\t\t= Turbo A\nW\t\t= Turbo B\n\nPlayer 2 uses the keypad: 8, 2, 4 and 6 for the D-pad, - for Select, Enter for Start, 0 for A, . for B, 1 for turbo A and 3 for turbo B.\n\nWith --zapper, aim with the mouse and fire with the left button.\n\nHold M to make a sound into the microphone on Famicom controller 2, or use --mic-input.\n\nWith --family-keyboard, Scroll Lock sends the keys to the Family BASIC keyboard, and back to the controllers.\n\nWhen playing an NSF, the left and right arrows change song.\n\nDrop a game on the window to play it in place of the first.\n\nKeys can be rebound with --config.\n\nHotkeys:\n\nF1-F6\t\t= Mute pulse 1, pulse 2, triangle, noise, DMC, expansion audio\nShift+F1-F6\t= Solo pulse 1, pulse 2, triangle, noise, DMC, expansion audio\nF7\t\t= Save state to the selected slot\nF8\t\t= Load state from the selected slot\n0-9\t\t= Select save state slot\nF9\t\t= Start/stop recording audio\nF10\t\t= Start/stop recording audio channel stems\nF11\t\t= Toggle fullscreen\nTab\t\t= Fast forward while held\nBackspace\t= Start/stop slow motion\nP\t\t= Pause/resume\nK\t\t= Advance a frame while paused\nO\t\t= Start/stop recording video\nG\t\t= Save the last few seconds as a GIF\nI\t\t= Show/hide the performance overlay\nF12\t\t= Change the video filter\nEscape\t\t= Quit"
 )]
 struct Args {
     /// Width of emulator window.
@@ -49,142 +75,424 @@ struct Args {
     #[arg(short, long, default_value_t = 3.0)]
     pixel_scale: f32,
 
-    /// path/to/rom
-    #[arg(short, long)]
+    /// path/to/rom (or .zip or .nsf file)
+    #[arg(
+        short,
+        long,
+        required_unless_present = "write_default_config",
+        default_value = "",
+        hide_default_value = true
+    )]
     rom: String,
+
+    /// Speed to run the emulation at, as a percentage of the console's.
+    #[arg(long, default_value = "100")]
+    speed: NonZeroU32,
+
+    /// Remove the 8 sprites per scanline limit (reduces flicker).
+    #[arg(long, overrides_with = "sprite_limit")]
+    no_sprite_limit: bool,
+
+    /// Keep the 8 sprites per scanline limit, even if the config removes it.
+    #[arg(long, overrides_with = "no_sprite_limit")]
+    sprite_limit: bool,
+
+    /// Bypass the APU output filters.
+    #[arg(long, overrides_with = "audio_filters")]
+    no_audio_filters: bool,
+
+    /// Apply the APU output filters, even if the config bypasses them.
+    #[arg(long, overrides_with = "no_audio_filters")]
+    audio_filters: bool,
+
+    /// path/to/database of NES 2.0 headers, used to correct bad headers: a
+    /// text file of "CRC32 HEADER [NAME]" lines, in hex.
+    #[arg(long)]
+    rom_db: Option<String>,
+
+    /// path/to/IPS or BPS patch, applied to the ROM when it is loaded. By
+    /// default a .ips or .bps file next to the ROM is used.
+    #[arg(long)]
+    patch: Option<String>,
+
+    /// Console region to emulate, instead of the one given in the header.
+    #[arg(long, value_enum)]
+    region: Option<Region>,
+
+    /// RAM cheat forcing a byte each frame, as AAAA:VV in hex, or on write
+    /// as AAAA:VV:w. May be given more than once.
+    #[arg(long = "cheat")]
+    cheats: Vec<RamCheat>,
+
+    /// Pattern the CPU RAM, VRAM and OAM hold at power on.
+    #[arg(long, value_enum, default_value_t = RamInit::Zero)]
+    ram_init: RamInit,
+
+    /// Seed for the random RAM pattern, to reproduce a run. By default a
+    /// random seed is used.
+    #[arg(long)]
+    ram_seed: Option<u64>,
+
+    /// Number of times per second turbo buttons are pressed.
+    #[arg(long, default_value_t = joypad::DEFAULT_TURBO_RATE)]
+    turbo_rate: f64,
+
+    /// Connect a Zapper to the second controller port, aimed with the mouse
+    /// and fired with the left mouse button.
+    #[arg(long)]
+    zapper: bool,
+
+    /// path/to/file to record the controller input of each frame to, from
+    /// power on. The movie is saved on exit.
+    #[arg(long, conflicts_with_all = ["play_input", "input_script"])]
+    record_input: Option<String>,
+
+    /// path/to/input movie to play back from power on, in place of the
+    /// controllers. Give the same --region, --ram-init and --ram-seed as
+    /// when it was recorded.
+    #[arg(long, conflicts_with = "input_script")]
+    play_input: Option<String>,
+
+    /// path/to/input script to play back from power on, in place of the
+    /// controllers. Each line gives a frame, then the buttons held from it
+    /// on controllers 1 and 2, such as "120 Right+A -".
+    #[arg(long)]
+    input_script: Option<String>,
+
+    /// path/to/.y4m file to record every frame to from power on, with the
+    /// audio in a .wav file next to it. Videos can also be recorded with
+    /// the hotkey.
+    #[arg(long)]
+    record_video: Option<String>,
+
+    /// Connect a Family BASIC keyboard to the expansion port. Scroll Lock
+    /// starts and stops sending the keys to it.
+    #[arg(long)]
+    family_keyboard: bool,
+
+    /// Hear sound in the microphone on Famicom controller 2 from the default
+    /// recording device. M may be held instead.
+    #[arg(long)]
+    mic_input: bool,
+
+    /// path/to/TOML config, whose settings the arguments override. By
+    /// default the config in the platform's config directory is loaded, if
+    /// there is one, such as ~/.config/nesoxide/config.toml on Linux.
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Write the default config, with every setting described, to the
+    /// --config path or the default path, then exit.
+    #[arg(long)]
+    write_default_config: bool,
+
+    /// path/to/directory games' battery saves and save states are kept in.
+    /// By default the platform's data directory is used, such as
+    /// ~/.local/share/nesoxide on Linux.
+    #[arg(long)]
+    data_dir: Option<String>,
+
+    /// path/to/.pal file of the colours to draw, in place of the built-in
+    /// palette.
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// Fill the screen with the emulator window.
+    #[arg(long, overrides_with = "windowed")]
+    fullscreen: bool,
+
+    /// Open the emulator in a window, even if the config fills the screen.
+    #[arg(long, overrides_with = "fullscreen")]
+    windowed: bool,
+
+    /// path/to/file to write a log of every CPU instruction run to, in the
+    /// format of the nestest log.
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// path/to/file to write a log of every access of the PPU, APU and
+    /// controller registers to, with the frame and position of the PPU.
+    #[arg(long)]
+    log_registers: Option<String>,
+
+    /// Addresses whose writes are printed as they're made, as AAAA or
+    /// AAAA-BBBB in hex, or whose reads are with AAAA:r. May be given more
+    /// than once.
+    #[arg(long = "watch")]
+    watches: Vec<MemoryWatch>,
+
+    /// Frontend to run the emulator in.
+    #[arg(long, value_enum, default_value_t = Frontend::default())]
+    frontend: Frontend,
+
+    /// Number of frames to run the headless frontend for.
+    #[arg(long, required_if_eq("frontend", "headless"))]
+    frames: Option<u64>,
+
+    /// Print the hashes of every this many frames when headless, as well as
+    /// the last.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    hash_interval: Option<u64>,
+
+    /// IDs of the arguments given on the command line, which override the
+    /// configs.
+    #[arg(skip)]
+    given: Vec<String>,
 }
 
 impl Args {
-    fn scaled_window_w(&self) -> u32 {
-        (self.window_w as f32 * self.pixel_scale) as u32
+    /// Returns the size of a window showing frames of the given size at the
+    /// pixel scale.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    fn scaled_window_size(&self, (frame_w, frame_h): (u32, u32)) -> (u32, u32) {
+        (
+            (frame_w as f32 * self.pixel_scale) as u32,
+            (frame_h as f32 * self.pixel_scale) as u32,
+        )
     }
 
-    fn scaled_window_h(&self) -> u32 {
-        (self.window_h as f32 * self.pixel_scale) as u32
+    /// Records which arguments were given on the command line, rather than
+    /// left at their defaults.
+    fn set_given(&mut self, matches: &ArgMatches) {
+        self.given = matches
+            .ids()
+            .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect();
+    }
+
+    /// Returns true if any of the arguments with the given IDs were given on
+    /// the command line.
+    fn given(&self, ids: &[&str]) -> bool {
+        self.given.iter().any(|given| ids.contains(&given.as_str()))
+    }
+
+    /// Takes the settings not given on the command line from the config.
+    fn apply_config(&mut self, config: &Config) {
+        if !self.given(&["pixel_scale"]) {
+            self.pixel_scale = config.video.scale;
+        }
+        if !self.given(&["ram_init"]) {
+            self.ram_init = config.emulation.ram_init;
+        }
+
+        // Each flag is overridden by the one negating it given after it.
+        if !self.given(&["fullscreen", "windowed"]) {
+            self.fullscreen = config.video.fullscreen;
+        }
+        if !self.given(&["no_sprite_limit", "sprite_limit"]) {
+            self.no_sprite_limit = !config.emulation.sprite_limit;
+        }
+        if !self.given(&["no_audio_filters", "audio_filters"]) {
+            self.no_audio_filters = !config.audio.filters;
+        }
+
+        let paths = &config.paths;
+        self.palette = self.palette.take().or_else(|| config.video.palette.clone());
+        self.data_dir = self.data_dir.take().or_else(|| paths.data_dir.clone());
+        self.rom_db = self.rom_db.take().or_else(|| paths.rom_db.clone());
+    }
+
+    /// Returns the arguments with the settings of the given game config in
+    /// place of those taken from the config. Those given on the command line
+    /// are kept.
+    fn with_game_config(&self, config: &GameConfig) -> Args {
+        let sprite_limit = self.given(&["no_sprite_limit", "sprite_limit"]);
+        Args {
+            region: self.region.or(config.region),
+            zapper: or_game(self.given(&["zapper"]), self.zapper, config.zapper),
+            palette: or_game(
+                self.given(&["palette"]),
+                self.palette.clone(),
+                config.palette.clone().map(Some),
+            ),
+            no_sprite_limit: or_game(
+                sprite_limit,
+                self.no_sprite_limit,
+                config.sprite_limit.map(|limit| !limit),
+            ),
+            ram_init: or_game(self.given(&["ram_init"]), self.ram_init, config.ram_init),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the arguments to play the given game with in place of the
+    /// first, leaving out those given for the first game alone: its patch,
+    /// region, cheats, input movies, video recording and trace.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    fn for_game(&self, rom: &str) -> Args {
+        Args {
+            rom: rom.to_string(),
+            patch: None,
+            region: None,
+            cheats: vec![],
+            record_input: None,
+            play_input: None,
+            input_script: None,
+            record_video: None,
+            trace: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the path of the patch to apply to the ROM, if any.
+    fn patch_path(&self) -> Option<PathBuf> {
+        match &self.patch {
+            Some(path) => Some(PathBuf::from(path)),
+            None => ["ips", "bps"]
+                .iter()
+                .map(|extension| Path::new(&self.rom).with_extension(extension))
+                .find(|path| path.exists()),
+        }
+    }
+}
+
+/// Returns the value of an argument if it was given on the command line,
+/// or else the game's setting if it has one.
+fn or_game<T>(given: bool, value: T, game: Option<T>) -> T {
+    match (given, game) {
+        (false, Some(game)) => game,
+        _ => value,
     }
 }
 
 fn main() {
-    let args = Args::parse();
-
-    let window_w = args.scaled_window_w();
-
-    // Initialise SDL.
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-    let window = video_subsystem
-        .window(
-            "RES - Rustendo Entertainment System",
-            window_w,
-            args.scaled_window_h(),
-        )
-        .position_centered()
-        .build()
-        .unwrap();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    args.set_given(&matches);
 
-    // Initialise graphics.
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas
-        .set_scale(args.pixel_scale, args.pixel_scale)
-        .unwrap();
+    let config_path = match &args.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => storage::config_path(),
+    };
 
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, args.window_w, args.window_h)
-        .unwrap();
+    if args.write_default_config {
+        let Some(path) = config_path else {
+            eprintln!("Failed to find the config directory, give a path with --config");
+            std::process::exit(1);
+        };
 
-    // Initialise sound.
-    let buffer_size = 1024;
-    let sample_rate = 44100;
-    let spec = AudioSpecDesired {
-        freq: Some(sample_rate),
-        channels: Some(1),
-        samples: Some(buffer_size),
-    };
-    let queue = audio_subsystem.open_queue::<f32, _>(None, &spec).unwrap();
-    queue.resume();
-
-    // Samples stores the audio samples generated by the APU.
-    let mut samples = vec![0.0; 1024];
-    let volume = 1.0;
-
-    // Load ROM.
-    let bytes: Vec<u8> = std::fs::read(args.rom).unwrap();
-    let cart = Cartridge::new(&bytes).unwrap();
-
-    // Initialise joypad.
-    let mut key_map = HashMap::new();
-    key_map.insert(Keycode::Up, joypad::JOYPAD_UP);
-    key_map.insert(Keycode::Down, joypad::JOYPAD_DOWN);
-    key_map.insert(Keycode::Left, joypad::JOYPAD_LEFT);
-    key_map.insert(Keycode::Right, joypad::JOYPAD_RIGHT);
-    key_map.insert(Keycode::Space, joypad::JOYPAD_SELECT);
-    key_map.insert(Keycode::Return, joypad::JOYPAD_START);
-    key_map.insert(Keycode::A, joypad::JOYPAD_BUTTON_A);
-    key_map.insert(Keycode::S, joypad::JOYPAD_BUTTON_B);
-
-    let bus = SystemBus::new(
-        Rc::new(RefCell::new(cart)),
-        sample_rate as f32,
-        move |frame| {
-            texture.update(None, frame, window_w as usize).unwrap();
-
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-        },
-    );
-
-    let mut cpu = Cpu::new(bus);
-    cpu.reset();
-
-    let mut timer = Timer::new();
-    loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => std::process::exit(0),
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        cpu.set_button_pressed_status(*key, true);
-                    }
-                }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        cpu.set_button_pressed_status(*key, false);
-                    }
-                }
-                _ => { /* do nothing */ }
+        match Config::write_default(&path) {
+            Ok(()) => println!("Wrote the default config to {}", path.display()),
+            Err(err) => {
+                eprintln!("Failed to write config {}: {}", path.display(), err);
+                std::process::exit(1);
             }
         }
+        std::process::exit(0);
+    }
 
-        // Clock the CPU until a frame has been rendered.
-        let frame_count = cpu.bus.ppu_frame_count();
-        while cpu.bus.ppu_frame_count() == frame_count {
-            let halted = cpu.clock();
-            if halted {
-                std::process::exit(0);
+    // Load the config given, or the default config if there is one.
+    let config_path = config_path.filter(|path| args.config.is_some() || path.exists());
+    let config = match config_path {
+        Some(path) => match Config::load(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load config {}: {}", path.display(), err);
+                std::process::exit(1);
             }
+        },
+        None => Config::default(),
+    };
+    args.apply_config(&config);
+
+    match args.frontend {
+        Frontend::Headless => frontend::headless::run(&args, &config),
+        #[cfg(feature = "sdl")]
+        Frontend::Sdl => frontend::sdl::run(&args, &config),
+        #[cfg(feature = "winit")]
+        Frontend::Winit => frontend::winit_pixels::run(args, config),
+        #[cfg(feature = "terminal")]
+        Frontend::Terminal => frontend::terminal::run(&args, &config),
+        #[allow(unreachable_patterns)]
+        frontend => {
+            let name = frontend.to_possible_value().unwrap();
+            eprintln!(
+                "The {0} frontend isn't built, build with --features {0}",
+                name.get_name()
+            );
+            std::process::exit(1);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the arguments parsed from the given command line, with the
+    /// given config applied.
+    fn args_with_config(command_line: &[&str], config: &str) -> Args {
+        let matches = Args::command().get_matches_from(command_line);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.set_given(&matches);
+        args.apply_config(&Config::from_toml(config).unwrap());
+        args
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let config = "[video]\nfullscreen = true\n[emulation]\nsprite_limit = false\n";
+        let args = args_with_config(&["res", "--rom", "game.nes"], config);
+        assert!(args.fullscreen);
+        assert!(args.no_sprite_limit);
+        assert!(!args.no_audio_filters);
 
-        // Forcing 60FPS by waiting for the next frame (if not enough time has
-        // already elapsed).
-        timer.wait(Duration::from_secs_f64(SECS_PER_FRAME));
-        timer.reset();
+        // Flags given on the command line override the config, the last of
+        // a flag and its negation winning.
+        let command_line = [
+            "res",
+            "--rom",
+            "game.nes",
+            "--windowed",
+            "--no-sprite-limit",
+            "--sprite-limit",
+            "--no-audio-filters",
+        ];
+        let args = args_with_config(&command_line, config);
+        assert!(!args.fullscreen);
+        assert!(!args.no_sprite_limit);
+        assert!(args.no_audio_filters);
+    }
 
-        samples.append(&mut cpu.bus.audio_samples());
+    #[test]
+    fn test_with_game_config() {
+        let config = "[emulation]\nram_init = \"ff\"\n";
+        let game = GameConfig::from_toml(
+            "region = \"pal\"\nzapper = true\nsprite_limit = false\nram_init = \"striped\"\n",
+        )
+        .unwrap();
 
-        // Adjust the volume.
-        samples.iter_mut().for_each(|s| *s *= volume);
+        // The game's settings override the config.
+        let args = args_with_config(&["res", "--rom", "game.nes"], config).with_game_config(&game);
+        assert_eq!(args.region, Some(Region::Pal));
+        assert!(args.zapper);
+        assert!(args.no_sprite_limit);
+        assert_eq!(args.ram_init, RamInit::Striped);
+
+        // The command line overrides the game's settings.
+        let command_line = [
+            "res",
+            "--rom",
+            "game.nes",
+            "--region",
+            "ntsc",
+            "--sprite-limit",
+            "--ram-init",
+            "zero",
+        ];
+        let args = args_with_config(&command_line, config).with_game_config(&game);
+        assert_eq!(args.region, Some(Region::Ntsc));
+        assert!(!args.no_sprite_limit);
+        assert_eq!(args.ram_init, RamInit::Zero);
+    }
 
-        // Add the samples to the SDL audio queue.
-        queue.queue_audio(&samples).unwrap();
+    #[test]
+    fn test_speed() {
+        let args = Args::try_parse_from(["res", "--rom", "game.nes", "--speed", "50"]).unwrap();
+        assert_eq!(args.speed.get(), 50);
 
-        // Clear the samples buffer before the next frame.
-        samples.clear();
+        // The emulation can't run at no speed at all.
+        assert!(Args::try_parse_from(["res", "--rom", "game.nes", "--speed", "0"]).is_err());
     }
 }
@@ -1,26 +1,188 @@
+mod axrom;
+mod bnrom;
+mod camerica;
+mod cnrom;
+mod fme7;
 mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc4;
+mod namco163;
 mod nrom;
+mod nsf;
 mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
 
+pub use axrom::Axrom;
+pub use bnrom::Bnrom;
+pub use camerica::Camerica;
+pub use cnrom::Cnrom;
+pub use fme7::Fme7;
 pub use mmc1::MMC1;
+pub use mmc2::MMC2;
+pub use mmc3::MMC3;
+pub use mmc4::MMC4;
+pub use namco163::Namco163;
 pub use nrom::Nrom;
+pub use nsf::NsfMapper;
 pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
 
+use crate::apu::ExpansionAudio;
 use crate::cartridge::Mirroring;
+use crate::state::Snapshot;
 
-pub trait Mapper {
-    /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8;
+/// Represents the mapper (memory management controller) of a cartridge,
+/// which maps the cartridge's memory into the CPU and PPU address spaces.
+///
+/// Mappers save the state of their registers and RAM in save states.
+///
+/// See: https://www.nesdev.org/wiki/Mapper
+pub trait Mapper: Snapshot {
+    /// Returns a byte from the CPU address space ($4020-$FFFF) at the given
+    /// address. This covers the expansion area ($4020-$5FFF), PRG RAM
+    /// ($6000-$7FFF) and PRG ROM ($8000-$FFFF).
+    fn cpu_read(&mut self, addr: u16) -> u8;
 
-    /// Writes a byte to PRG ROM at the given address.
-    fn write_prg(&mut self, addr: u16, data: u8);
+    /// Writes a byte to the CPU address space ($4020-$FFFF) at the given
+    /// address. Mappers may place registers anywhere in this range.
+    fn cpu_write(&mut self, addr: u16, data: u8);
 
-    /// Returns a byte from CHR ROM at the given address.
-    fn read_chr(&self, addr: u16) -> u8;
+    /// Returns a byte from the CPU address space without any of the side
+    /// effects of a read, such as incrementing an address port.
+    ///
+    /// By default reads are assumed to have no side effects.
+    fn cpu_peek(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr)
+    }
 
-    /// Writes a byte to CHR ROM at the given address.
-    fn write_chr(&mut self, addr: u16, data: u8);
+    /// Returns true if the cartridge drives the data bus when the CPU reads
+    /// the given address. Reads of addresses which aren't driven, such as
+    /// disabled PRG RAM, return the open bus value.
+    ///
+    /// By default PRG RAM and ROM are assumed to fill $6000-$FFFF.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    /// Returns a byte from the PPU pattern tables ($0000-$1FFF) at the given
+    /// address.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    /// Writes a byte to the PPU pattern tables ($0000-$1FFF) at the given
+    /// address.
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    /// Returns a byte from the PPU pattern tables without any of the side
+    /// effects of a read, such as switching banks.
+    ///
+    /// By default reads are assumed to have no side effects.
+    fn ppu_peek(&mut self, addr: u16) -> u8 {
+        self.ppu_read(addr)
+    }
+
+    /// Returns a byte from the nametables ($2000-$3EFF) at the given address.
+    ///
+    /// By default the nametables are stored in the console's 2 KB of VRAM,
+    /// arranged by the mirroring mode. Mappers with their own nametable
+    /// memory override this.
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        vram[mirror_vram_addr(self.mirroring(), addr)]
+    }
+
+    /// Writes a byte to the nametables ($2000-$3EFF) at the given address.
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        vram[mirror_vram_addr(self.mirroring(), addr)] = data;
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF, if the cartridge has any, which
+    /// the battery of battery-backed cartridges keeps when switched off.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
 
     /// Returns the Mirroring mode.
     fn mirroring(&self) -> Mirroring;
+
+    /// Returns true if the mapper is asserting the CPU IRQ line.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Called on a (filtered) rising edge of PPU address line A12, which
+    /// scanline counting mappers use to clock their IRQ counter.
+    fn notify_a12_rising_edge(&mut self) {}
+
+    /// Clocks the mapper by one CPU cycle, which mappers with cycle based IRQ
+    /// counters override.
+    fn clock(&mut self) {}
+
+    /// Returns the expansion audio of the cartridge, if it has any.
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        None
+    }
+}
+
+/// Returns the index into the console's 2 KB of VRAM of a nametable address,
+/// based on the mirroring mode.
+///
+/// Horizontal:
+///   [ A ] [ a ]
+///   [ B ] [ b ]
+///
+/// Vertical:
+///   [ A ] [ B ]
+///   [ a ] [ b ]
+pub fn mirror_vram_addr(mirroring: Mirroring, addr: u16) -> usize {
+    // Mirror down 0x3000-0x3EFF to 0x2000 - 0x2EFF
+    let vram_index = ((addr & 0x2FFF) - 0x2000) as usize;
+    let name_table = vram_index / 0x400;
+
+    match mirroring {
+        Mirroring::Vertical => vram_index & 0x7FF,
+        Mirroring::Horizontal => match name_table {
+            0 | 1 => vram_index & 0x3FF,
+            _ => (vram_index & 0x3FF) + 0x400,
+        },
+        Mirroring::SingleScreenLo => vram_index & 0x3FF,
+        Mirroring::SingleScreenHi => (vram_index & 0x3FF) + 0x400,
+
+        // Four screen cartridges provide the extra 2 KB of VRAM, without it
+        // the upper nametables mirror the lower ones.
+        Mirroring::FourScreen => vram_index & 0x7FF,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_vram_addr() {
+        let addrs = [0x2000, 0x2400, 0x2800, 0x2C00, 0x3005];
+
+        let mirrored = |mirroring: Mirroring| addrs.map(|addr| mirror_vram_addr(mirroring, addr));
+
+        assert_eq!(
+            mirrored(Mirroring::Vertical),
+            [0x000, 0x400, 0x000, 0x400, 0x005]
+        );
+        assert_eq!(
+            mirrored(Mirroring::Horizontal),
+            [0x000, 0x000, 0x400, 0x400, 0x005]
+        );
+        assert_eq!(
+            mirrored(Mirroring::SingleScreenLo),
+            [0x000, 0x000, 0x000, 0x000, 0x005]
+        );
+        assert_eq!(
+            mirrored(Mirroring::SingleScreenHi),
+            [0x400, 0x400, 0x400, 0x400, 0x405]
+        );
+    }
 }
@@ -0,0 +1,141 @@
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// AxROM refers to the Nintendo cartridge boards NES-AMROM, NES-ANROM,
+/// NES-AN1ROM, NES-AOROM, their HVC counterparts, and clone boards.
+///
+/// A single 32 KB PRG ROM bank is switchable and the nametables are mirrored
+/// to one of the two pages of VRAM.
+///
+/// See: https://www.nesdev.org/wiki/AxROM
+pub struct Axrom {
+    rom: Rom,
+
+    // Bank select register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // xxxM xPPP
+    //    |  |||
+    //    |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+    //    +------ Select 1 KB VRAM page for all 4 nametables
+    bank: u8,
+
+    /// Boards without bus conflict prevention AND the written value with the
+    /// ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+}
+
+snapshot!(Axrom, rom, bank);
+
+impl Axrom {
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Self {
+        Axrom {
+            rom,
+            bank: 0,
+            bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // 32 KB switchable PRG ROM bank.
+            0x8000..=0xFFFF => {
+                let banks = (self.rom.prg.len() / PRG_BANK_SIZE).max(1);
+                let bank = (self.bank & 0x7) as usize % banks;
+                let index = bank * PRG_BANK_SIZE + (addr & 0x7FFF) as usize;
+                self.rom.prg[index % self.rom.prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x8000..=0xFFFF = addr {
+            self.bank = match self.bus_conflicts {
+                true => data & self.cpu_read(addr),
+                false => data,
+            };
+        }
+    }
+
+    /// Returns true for PRG ROM, as there is no PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[addr as usize]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            self.rom.chr[addr as usize] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        match self.bank & 0x10 != 0 {
+            true => Mirroring::SingleScreenHi,
+            false => Mirroring::SingleScreenLo,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an AxROM with the given number of 32 KB PRG ROM banks, where
+    /// the first byte of each bank holds its number and the second $FF.
+    fn test_axrom(banks: usize, bus_conflicts: bool) -> Axrom {
+        let prg = numbered_banks(banks, PRG_BANK_SIZE);
+
+        let rom = test_rom(banks * 2, prg, 0, vec![], None, None, None).unwrap();
+        Axrom::new(rom, bus_conflicts)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_axrom(8, false);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 0);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = test_axrom(8, true);
+
+        // $8000 holds the bank number 0, $8001 holds $FF.
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLo);
+
+        mapper.cpu_write(0x8001, 0x13);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_axrom(2, false);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLo);
+
+        mapper.cpu_write(0xFFFF, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+    }
+}
@@ -0,0 +1,192 @@
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// iNES mapper 34 refers to two unrelated boards which both switch a 32 KB
+/// PRG ROM bank: Nintendo's BNROM, and AVE's NINA-001 which also switches
+/// two 4 KB CHR ROM banks and carries 8 KB of PRG RAM.
+///
+/// BNROM selects the PRG bank by writing to $8000-$FFFF, and has CHR RAM.
+/// NINA-001 uses registers which overlap the end of its PRG RAM:
+///
+/// $7FFD: 32 KB PRG ROM bank (1 bit)
+/// $7FFE: 4 KB CHR ROM bank at PPU $0000 (4 bits)
+/// $7FFF: 4 KB CHR ROM bank at PPU $1000 (4 bits)
+///
+/// See: https://www.nesdev.org/wiki/INES_Mapper_034
+pub struct Bnrom {
+    rom: Rom,
+
+    /// True for the NINA-001 board.
+    nina001: bool,
+
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+    ram: Vec<u8>,
+}
+
+snapshot!(Bnrom, rom, prg_bank, chr_banks, ram);
+
+impl Bnrom {
+    pub fn new(rom: Rom, nina001: bool) -> Self {
+        Bnrom {
+            rom,
+            nina001,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x1000) as usize] as usize;
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+
+        bank % banks * CHR_BANK_SIZE + (addr & 0xFFF) as usize
+    }
+}
+
+impl Mapper for Bnrom {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF if self.nina001 => self.ram[(addr & 0x1FFF) as usize],
+
+            // 32 KB switchable PRG ROM bank.
+            0x8000..=0xFFFF => {
+                let banks = (self.rom.prg.len() / PRG_BANK_SIZE).max(1);
+                let bank = self.prg_bank as usize % banks;
+                let index = bank * PRG_BANK_SIZE + (addr & 0x7FFF) as usize;
+                self.rom.prg[index % self.rom.prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match (addr, self.nina001) {
+            (0x6000..=0x7FFF, true) => {
+                // The registers are also written to the PRG RAM beneath them.
+                self.ram[(addr & 0x1FFF) as usize] = data;
+
+                match addr {
+                    0x7FFD => self.prg_bank = data & 0x1,
+                    0x7FFE => self.chr_banks[0] = data & 0xF,
+                    0x7FFF => self.chr_banks[1] = data & 0xF,
+                    _ => {}
+                }
+            }
+            (0x8000..=0xFFFF, false) => self.prg_bank = data,
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, and the NINA-001's PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000 || self.nina001 && addr >= 0x6000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_index(addr)]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
+        }
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.rom.header.mirroring()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a mapper 34 board with 4 32 KB PRG ROM banks, where the first
+    /// byte of each PRG bank and 4 KB CHR bank holds its number. The NINA-001
+    /// has 64 KB of CHR ROM and the BNROM has CHR RAM.
+    fn test_bnrom(nina001: bool) -> Bnrom {
+        let prg = numbered_banks(4, PRG_BANK_SIZE);
+
+        let rom = match nina001 {
+            true => {
+                let chr = numbered_banks(16, CHR_BANK_SIZE);
+                test_rom(8, prg, 8, chr, None, None, None)
+            }
+            false => test_rom(8, prg, 0, vec![], None, None, None),
+        };
+
+        Bnrom::new(rom.unwrap(), nina001)
+    }
+
+    #[test]
+    fn test_bnrom_prg_banks() {
+        let mut mapper = test_bnrom(false);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+
+        // Bank numbers wrap around the PRG ROM.
+        mapper.cpu_write(0xFFFF, 6);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+
+        // The NINA-001 registers aren't present.
+        mapper.cpu_write(0x7FFD, 1);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0x7FFD), 0);
+    }
+
+    #[test]
+    fn test_bnrom_chr_ram() {
+        let mut mapper = test_bnrom(false);
+        mapper.ppu_write(0x1234, 0x42);
+        assert_eq!(mapper.ppu_read(0x1234), 0x42);
+    }
+
+    #[test]
+    fn test_nina001_banks() {
+        let mut mapper = test_bnrom(true);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        assert_eq!(mapper.ppu_read(0x1000), 1);
+
+        mapper.cpu_write(0x7FFD, 1);
+        mapper.cpu_write(0x7FFE, 5);
+        mapper.cpu_write(0x7FFF, 12);
+
+        assert_eq!(mapper.cpu_read(0x8000), 1);
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 12);
+        assert_eq!(mapper.cpu_read(0x7FFF), 12);
+
+        // Writes to the BNROM register are ignored.
+        mapper.cpu_write(0x8000, 0);
+        assert_eq!(mapper.cpu_read(0x8000), 1);
+    }
+
+    #[test]
+    fn test_nina001_ram() {
+        let mut mapper = test_bnrom(true);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+}
@@ -0,0 +1,131 @@
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
+
+/// Camerica refers to the BF9093 and related boards used by the Codemasters
+/// unlicensed library. The iNES format assigns mapper 71 to these boards.
+///
+/// Like UxROM, a 16 KB PRG ROM bank is switchable at $8000 and the last bank
+/// is fixed at $C000. The BF9097 board used by Fire Hawk can also select
+/// single-screen mirroring.
+///
+/// See: https://www.nesdev.org/wiki/INES_Mapper_071
+pub struct Camerica {
+    rom: Rom,
+    bank: usize,
+
+    /// Mirroring selected by the BF9097 mirroring register, once written.
+    mirroring: Option<Mirroring>,
+}
+
+snapshot!(Camerica, rom, bank, mirroring);
+
+impl Camerica {
+    pub fn new(rom: Rom) -> Self {
+        Camerica {
+            rom,
+            bank: 0,
+            mirroring: None,
+        }
+    }
+}
+
+impl Mapper for Camerica {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_PAGE_SIZE;
+        let bank = match addr {
+            // 16 KB switchable PRG ROM bank.
+            0x8000..=0xBFFF => self.bank % banks,
+
+            // 16 KB PRG ROM bank, fixed to the last bank.
+            0xC000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank * PRG_PAGE_SIZE + (addr & 0x3FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            // Mirroring (BF9097 only).
+            //
+            // 7  bit  0
+            // ---- ----
+            // xxxM xxxx
+            //    |
+            //    +----- Select 1 KB VRAM page for all 4 nametables
+            0x9000..=0x9FFF => {
+                self.mirroring = match data & 0x10 != 0 {
+                    true => Some(Mirroring::SingleScreenHi),
+                    false => Some(Mirroring::SingleScreenLo),
+                }
+            }
+
+            // 16 KB PRG ROM bank select.
+            0xC000..=0xFFFF => self.bank = (data & 0xF) as usize,
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, as there is no PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[addr as usize]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            self.rom.chr[addr as usize] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.unwrap_or(self.rom.header.mirroring())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a Camerica board where the first byte of each bank holds its
+    /// number.
+    fn test_camerica() -> Camerica {
+        let prg = numbered_banks(8, PRG_PAGE_SIZE);
+
+        let rom = test_rom(8, prg, 0, vec![], None, None, Some(Mirroring::Vertical)).unwrap();
+        Camerica::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_camerica();
+        mapper.cpu_write(0xC000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        // Writes below $C000 don't switch banks.
+        mapper.cpu_write(0x8000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_camerica();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        mapper.cpu_write(0x9000, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+        mapper.cpu_write(0x9000, 0x00);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLo);
+    }
+}
@@ -0,0 +1,154 @@
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom, rom::CHR_PAGE_SIZE};
+
+/// CNROM refers to the Nintendo cartridge boards NES-CNROM, their HVC
+/// counterparts, and clone boards, assigned to iNES mapper 3.
+///
+/// The PRG ROM is fixed as with NROM and writes to $8000-$FFFF select an 8 KB
+/// CHR ROM bank.
+///
+/// Mapper 185 is a copy protected CNROM, where the bank select instead
+/// enables or disables the CHR ROM. While it is disabled, reads from the
+/// pattern tables return open bus, which games check for at boot. Without a
+/// submapper to tell which value enables a board, any value with a non-zero
+/// low nibble other than $13 does.
+///
+/// See: https://www.nesdev.org/wiki/CNROM
+/// See: https://www.nesdev.org/wiki/INES_Mapper_185
+pub struct Cnrom {
+    rom: Rom,
+
+    /// True for mapper 185.
+    chr_protect: bool,
+
+    /// Boards without bus conflict prevention AND the written value with the
+    /// ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+
+    chr_bank: u8,
+    chr_enabled: bool,
+}
+
+snapshot!(Cnrom, rom, chr_bank, chr_enabled);
+
+impl Cnrom {
+    /// Returns a CNROM wired as the board of the given iNES mapper number.
+    pub fn new(rom: Rom, mapper: u16, bus_conflicts: bool) -> Self {
+        Cnrom {
+            rom,
+            chr_protect: mapper == 185,
+            bus_conflicts,
+            chr_bank: 0,
+            chr_enabled: true,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // 16 KB PRG ROM is mirrored into both halves.
+            0x8000..=0xFFFF => self.rom.prg[(addr & 0x7FFF) as usize % self.rom.prg.len()],
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x8000..=0xFFFF = addr {
+            let data = match self.bus_conflicts {
+                true => data & self.cpu_read(addr),
+                false => data,
+            };
+
+            match self.chr_protect {
+                true => self.chr_enabled = data & 0xF != 0 && data != 0x13,
+                false => self.chr_bank = data,
+            }
+        }
+    }
+
+    /// Returns true for PRG ROM, as there is no PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        // The pattern table data bus floats high while the CHR ROM is
+        // disabled.
+        if !self.chr_enabled {
+            return 0xFF;
+        }
+
+        let banks = self.rom.chr.len() / CHR_PAGE_SIZE;
+        let bank = self.chr_bank as usize % banks;
+        self.rom.chr[bank * CHR_PAGE_SIZE + addr as usize]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.rom.header.mirroring()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a CNROM for the given mapper with 4 8 KB CHR ROM banks, where
+    /// the first byte of each bank holds its number. The first two bytes of
+    /// PRG ROM hold $FF and $01.
+    fn test_cnrom(mapper: u16, bus_conflicts: bool) -> Cnrom {
+        let chr = numbered_banks(4, CHR_PAGE_SIZE);
+        let rom = test_rom(1, vec![0xFF, 0x01], 4, chr, None, None, None).unwrap();
+        Cnrom::new(rom, mapper, bus_conflicts)
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_cnrom(3, false);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+
+        // Bank numbers wrap around the CHR ROM.
+        mapper.cpu_write(0xFFFF, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = test_cnrom(3, true);
+
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+
+        // $8001 holds $01, so only bit 0 of the value survives.
+        mapper.cpu_write(0x8001, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        mapper.cpu_write(0xC001, 3);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn test_chr_protect() {
+        let mut mapper = test_cnrom(185, false);
+        for (data, enabled) in [(0x00, false), (0x13, false), (0x20, false), (0x11, true)] {
+            mapper.cpu_write(0x8000, data);
+
+            let expected = match enabled {
+                true => 0,
+                false => 0xFF,
+            };
+            assert_eq!(mapper.ppu_read(0x0000), expected, "data {:#04X}", data);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+mod audio;
+
+use self::audio::Sunsoft5bAudio;
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{apu::ExpansionAudio, cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Sunsoft's FME-7 mapper, and the 5B which adds expansion audio, assigned to
+/// iNES mapper 69.
+///
+/// It provides four switchable 8 KB PRG banks (the first of which can be PRG
+/// RAM), eight 1 KB CHR banks and a 16 bit CPU cycle IRQ counter. All of
+/// these are configured by writing a command to $8000 then its parameter to
+/// $A000.
+///
+/// The 5B audio registers are always present, as they are ignored by the
+/// FME-7 games.
+///
+/// See: https://www.nesdev.org/wiki/Sunsoft_FME-7
+pub struct Fme7 {
+    rom: Rom,
+
+    // Command register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // .... CCCC
+    //      ||||
+    //      ++++- The command number to invoke when writing to the parameter
+    //            register:
+    //            $0-$7: CHR bank of PPU $0000-$1FFF, 1 KB each
+    //            $8: PRG bank of CPU $6000-$7FFF
+    //            $9-$B: PRG banks of CPU $8000-$DFFF, 8 KB each
+    //            $C: Mirroring
+    //            $D: IRQ control
+    //            $E-$F: Low and high 8 bits of the IRQ counter
+    command: u8,
+
+    chr_banks: [u8; 8],
+
+    // PRG bank 0 register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // ERBB BBBB
+    // |||| ||||
+    // ||++-++++- The 8 KB bank of PRG ROM at CPU $6000
+    // |+-------- RAM / ROM select (0: PRG ROM; 1: PRG RAM)
+    // +--------- RAM enable (0: disabled; 1: enabled)
+    prg_bank_0: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+
+    // IRQ control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // C... ...T
+    // |       |
+    // |       +- IRQ enable
+    // +--------- IRQ counter enable
+    irq_control: u8,
+    irq_counter: u16,
+    interrupt: bool,
+
+    audio: Sunsoft5bAudio,
+    ram: Vec<u8>,
+}
+
+snapshot!(
+    Fme7,
+    rom,
+    command,
+    chr_banks,
+    prg_bank_0,
+    prg_banks,
+    mirroring,
+    irq_control,
+    irq_counter,
+    interrupt,
+    audio,
+    ram
+);
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Self {
+        Fme7 {
+            rom,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_bank_0: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_control: 0,
+            irq_counter: 0,
+            interrupt: false,
+            audio: Sunsoft5bAudio::new(),
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Writes the parameter of the current command.
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            c @ 0x0..=0x7 => self.chr_banks[c as usize] = data,
+            0x8 => self.prg_bank_0 = data,
+            c @ 0x9..=0xB => self.prg_banks[(c - 0x9) as usize] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLo,
+                    _ => Mirroring::SingleScreenHi,
+                }
+            }
+
+            // Writing the IRQ control register acknowledges the IRQ.
+            0xD => {
+                self.irq_control = data;
+                self.interrupt = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8),
+        }
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+
+        bank % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Fme7 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            // 8 KB PRG RAM bank, which is open bus while disabled.
+            0x6000..=0x7FFF if self.prg_bank_0 & 0x40 != 0 => {
+                return match self.prg_bank_0 & 0x80 != 0 {
+                    true => self.ram[(addr & 0x1FFF) as usize],
+                    false => 0,
+                };
+            }
+
+            // 8 KB switchable PRG ROM bank.
+            0x6000..=0x7FFF => (self.prg_bank_0 & 0x3F) as usize,
+
+            // 3x 8 KB switchable PRG ROM banks.
+            0x8000..=0xDFFF => self.prg_banks[((addr - 0x8000) / 0x2000) as usize] as usize,
+
+            // 8 KB PRG ROM bank, fixed to the last bank.
+            0xE000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank % banks * PRG_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_bank_0 & 0xC0 == 0xC0 => {
+                self.ram[(addr & 0x1FFF) as usize] = data;
+            }
+            0x8000..=0x9FFF => self.command = data & 0xF,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.write_address(data),
+            0xE000..=0xFFFF => self.audio.write_data(data),
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM and PRG RAM, unless PRG RAM is selected at
+    /// $6000 while disabled.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.prg_bank_0 & 0xC0 != 0x40,
+            _ => addr >= 0x8000,
+        }
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_index(addr)]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
+        }
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns true if the IRQ counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Clocks the IRQ counter, which raises an IRQ when it decrements from 0
+    /// to $FFFF.
+    fn clock(&mut self) {
+        if self.irq_control & 0x80 == 0 {
+            return;
+        }
+
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_control & 0x1 != 0 {
+            self.interrupt = true;
+        }
+    }
+
+    /// Returns the Sunsoft 5B sound channels.
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        Some(&mut self.audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an FME-7 where the first byte of each 8 KB PRG bank and 1 KB
+    /// CHR bank holds its number.
+    fn test_fme7() -> Fme7 {
+        let prg = numbered_banks(16, PRG_BANK_SIZE);
+        let chr = numbered_banks(32, CHR_BANK_SIZE);
+
+        let rom = test_rom(8, prg, 4, chr, None, None, None).unwrap();
+        Fme7::new(rom)
+    }
+
+    /// Writes the parameter of the given command.
+    fn command(mapper: &mut Fme7, command: u8, parameter: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_fme7();
+        command(&mut mapper, 0x8, 2);
+        command(&mut mapper, 0x9, 3);
+        command(&mut mapper, 0xA, 4);
+        command(&mut mapper, 0xB, 5);
+
+        assert_eq!(mapper.cpu_read(0x6000), 2);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = test_fme7();
+
+        // RAM selected but disabled.
+        command(&mut mapper, 0x8, 0x40);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        command(&mut mapper, 0x8, 0xC0);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_fme7();
+        command(&mut mapper, 0x1, 7);
+        command(&mut mapper, 0x7, 20);
+
+        assert_eq!(mapper.ppu_read(0x0400), 7);
+        assert_eq!(mapper.ppu_read(0x1C00), 20);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_fme7();
+        command(&mut mapper, 0xC, 0x1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        command(&mut mapper, 0xC, 0x3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = test_fme7();
+        command(&mut mapper, 0xE, 0x01);
+        command(&mut mapper, 0xF, 0x00);
+        command(&mut mapper, 0xD, 0x81);
+
+        mapper.clock();
+        assert!(!mapper.irq_pending());
+        mapper.clock();
+        assert!(mapper.irq_pending());
+
+        command(&mut mapper, 0xD, 0x00);
+        assert!(!mapper.irq_pending());
+
+        // The counter is halted while disabled.
+        mapper.clock();
+        command(&mut mapper, 0xD, 0x81);
+        mapper.clock();
+        assert!(!mapper.irq_pending());
+    }
+}
@@ -0,0 +1,407 @@
+use crate::apu::ExpansionAudio;
+use crate::state::snapshot;
+
+/// Number of CPU cycles between clocks of the tone, noise and envelope
+/// generators.
+const CLOCK_DIVIDER: u8 = 16;
+
+/// Represents one of the three 5B tone channels, a square wave with a 12 bit
+/// period.
+struct Tone {
+    period: u16,
+    counter: u16,
+    output: bool,
+
+    // Volume register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // ...E VVVV
+    //    | ||||
+    //    | ++++- Volume
+    //    +------ Use the envelope rather than the volume
+    volume: u8,
+}
+
+snapshot!(Tone, period, counter in 0..0x1000, output, volume in 0..0x20);
+
+impl Tone {
+    fn new() -> Self {
+        Tone {
+            period: 0,
+            counter: 0,
+            output: false,
+            volume: 0,
+        }
+    }
+
+    /// Writes the low 8 bits of the period.
+    fn write_period_lo(&mut self, data: u8) {
+        self.period = (self.period & 0xF00) | data as u16;
+    }
+
+    /// Writes the high 4 bits of the period.
+    fn write_period_hi(&mut self, data: u8) {
+        self.period = (self.period & 0xFF) | ((data as u16 & 0xF) << 8);
+    }
+
+    /// Clocks the channel, toggling the output every period clocks.
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.output = !self.output;
+        }
+    }
+
+    /// Returns the 5 bit level of the channel, from the envelope or from the
+    /// 4 bit volume which sits on every other envelope step.
+    fn level(&self, envelope: u8) -> u8 {
+        match self.volume {
+            v if v & 0x10 != 0 => envelope,
+            v if v & 0xF == 0 => 0,
+            v => (v & 0xF) * 2 + 1,
+        }
+    }
+}
+
+/// Represents the 5B envelope generator, which ramps a 5 bit level up or
+/// down in one of 16 shapes.
+struct Envelope {
+    period: u16,
+    counter: u16,
+
+    // Shape register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // .... CAAH
+    //      ||||
+    //      |||+- Hold the level at the end of the first ramp
+    //      ||+-- Alternate the ramp direction at the end of each ramp
+    //      |+--- Attack (0: ramp down; 1: ramp up)
+    //      +---- Continue after the first ramp (0: drop to 0 and hold)
+    shape: u8,
+
+    // Step of the current ramp, counting from 0 to 31.
+    step: u8,
+    rising: bool,
+    holding: bool,
+}
+
+snapshot!(
+    Envelope,
+    period,
+    counter in 0..u16::MAX,
+    shape,
+    step in 0..32,
+    rising,
+    holding
+);
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            period: 0,
+            counter: 0,
+            shape: 0,
+            step: 0,
+            rising: false,
+            holding: false,
+        }
+    }
+
+    /// Writes the shape register, restarting the envelope.
+    fn write_shape(&mut self, data: u8) {
+        self.shape = data & 0xF;
+        self.counter = 0;
+        self.step = 0;
+        self.rising = data & 0x4 != 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+
+        self.counter += 1;
+        if self.counter < self.period {
+            return;
+        }
+        self.counter = 0;
+
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+
+        // End of a ramp.
+        let continues = self.shape & 0x8 != 0;
+        let alternate = self.shape & 0x2 != 0;
+        let hold = self.shape & 0x1 != 0;
+
+        match (continues, hold) {
+            (false, _) => {
+                self.rising = false;
+                self.holding = true;
+            }
+            (true, true) => {
+                self.rising ^= alternate;
+                self.holding = true;
+            }
+            (true, false) => {
+                self.rising ^= alternate;
+                self.step = 0;
+            }
+        }
+
+        // A held envelope keeps the level it ended on.
+        if self.holding {
+            self.step = 31;
+        }
+    }
+
+    /// Returns the 5 bit level of the envelope.
+    fn level(&self) -> u8 {
+        match self.rising {
+            true => self.step,
+            false => 31 - self.step,
+        }
+    }
+}
+
+/// Represents the 5B noise generator, a 17 bit linear feedback shift register
+/// clocked at half the rate of the tone channels.
+struct Noise {
+    period: u8,
+    counter: u8,
+    shift: u32,
+}
+
+snapshot!(Noise, period in 0..0x20, counter in 0..0x40, shift);
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            period: 0,
+            counter: 0,
+            shift: 1,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter < self.period * 2 {
+            return;
+        }
+        self.counter = 0;
+
+        let feedback = (self.shift ^ (self.shift >> 3)) & 0x1;
+        self.shift = (self.shift >> 1) | (feedback << 16);
+    }
+
+    fn output(&self) -> bool {
+        self.shift & 0x1 != 0
+    }
+}
+
+/// The Sunsoft 5B expansion audio, a variant of the Yamaha YM2149F (itself a
+/// derivative of the General Instrument AY-3-8910) with three square wave
+/// channels, a noise generator and an envelope generator.
+///
+/// The registers are written by selecting one with the address port ($C000)
+/// and writing to the data port ($E000), where the registers are equal to:
+///
+/// $00-$05: Low 8 and high 4 bits of the period of channels A, B and C
+/// $06: Noise period (5 bits)
+/// $07: Mixer (bits 0-2 disable the tone and bits 3-5 the noise of each
+///      channel)
+/// $08-$0A: Volume of channels A, B and C
+/// $0B-$0C: Low and high 8 bits of the envelope period
+/// $0D: Envelope shape
+///
+/// See: https://www.nesdev.org/wiki/Sunsoft_5B_audio
+pub struct Sunsoft5bAudio {
+    tones: [Tone; 3],
+    noise: Noise,
+    envelope: Envelope,
+    mixer: u8,
+
+    address: u8,
+    cycles: u8,
+    levels: [f32; 32],
+}
+
+snapshot!(
+    Sunsoft5bAudio,
+    tones,
+    noise,
+    envelope,
+    mixer,
+    address,
+    cycles in 0..CLOCK_DIVIDER
+);
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Self {
+        // Each step of the 5 bit level is 1.5 dB, with level 0 silent.
+        let mut levels = [0.0; 32];
+        for (level, output) in levels.iter_mut().enumerate().skip(1) {
+            *output = 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0);
+        }
+
+        Sunsoft5bAudio {
+            tones: [Tone::new(), Tone::new(), Tone::new()],
+            noise: Noise::new(),
+            envelope: Envelope::new(),
+            mixer: 0,
+            address: 0,
+            cycles: 0,
+            levels,
+        }
+    }
+
+    /// Writes the address port ($C000-$DFFF).
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    /// Writes the register selected by the address port ($E000-$FFFF).
+    /// Addresses with any of the upper 4 bits set select no register.
+    pub fn write_data(&mut self, data: u8) {
+        match self.address {
+            0x00 => self.tones[0].write_period_lo(data),
+            0x01 => self.tones[0].write_period_hi(data),
+            0x02 => self.tones[1].write_period_lo(data),
+            0x03 => self.tones[1].write_period_hi(data),
+            0x04 => self.tones[2].write_period_lo(data),
+            0x05 => self.tones[2].write_period_hi(data),
+            0x06 => self.noise.period = data & 0x1F,
+            0x07 => self.mixer = data,
+            0x08..=0x0A => self.tones[(self.address - 0x08) as usize].volume = data & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | data as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0xFF) | ((data as u16) << 8),
+            0x0D => self.envelope.write_shape(data),
+            _ => {}
+        }
+    }
+}
+
+impl ExpansionAudio for Sunsoft5bAudio {
+    fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CLOCK_DIVIDER {
+            return;
+        }
+        self.cycles = 0;
+
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        self.noise.clock();
+        self.envelope.clock();
+    }
+
+    fn output(&self) -> f32 {
+        let envelope = self.envelope.level();
+        let noise = self.noise.output();
+
+        let output: f32 = self
+            .tones
+            .iter()
+            .enumerate()
+            .filter(|&(i, tone)| {
+                let tone_disabled = self.mixer & (1 << i) != 0;
+                let noise_disabled = self.mixer & (1 << (i + 3)) != 0;
+                (tone.output || tone_disabled) && (noise || noise_disabled)
+            })
+            .map(|(_, tone)| self.levels[tone.level(envelope) as usize])
+            .sum();
+
+        output / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the given register of the audio.
+    fn write(audio: &mut Sunsoft5bAudio, register: u8, data: u8) {
+        audio.write_address(register);
+        audio.write_data(data);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut audio = Sunsoft5bAudio::new();
+
+        // Channel A at full volume with a period of 2, so the output toggles
+        // every 32 CPU cycles.
+        write(&mut audio, 0x00, 0x02);
+        write(&mut audio, 0x07, 0x38);
+        write(&mut audio, 0x08, 0x0F);
+
+        let outputs: Vec<f32> = (0..128)
+            .map(|_| {
+                audio.clock();
+                audio.output()
+            })
+            .collect();
+
+        assert_eq!(outputs[0], 0.0);
+        assert_eq!(outputs[31], 1.0 / 3.0);
+        assert_eq!(outputs[63], 0.0);
+        assert_eq!(outputs.iter().filter(|&&o| o > 0.0).count(), 64);
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut audio = Sunsoft5bAudio::new();
+
+        // With the tone and noise disabled, the channel outputs its volume.
+        write(&mut audio, 0x07, 0x3F);
+        write(&mut audio, 0x08, 0x0F);
+        write(&mut audio, 0x09, 0x0D);
+        assert_eq!(audio.levels[31], 1.0);
+        assert_eq!(audio.output(), (audio.levels[31] + audio.levels[27]) / 3.0);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut envelope = Envelope::new();
+        envelope.period = 1;
+
+        // Attack, then hold at the top.
+        envelope.write_shape(0xD);
+        assert_eq!(envelope.level(), 0);
+        for _ in 0..31 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..64 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+
+        // Decay, then drop to 0 and hold.
+        envelope.write_shape(0x0);
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..32 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0);
+
+        // Triangle.
+        envelope.write_shape(0xE);
+        for _ in 0..32 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..31 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0);
+    }
+}
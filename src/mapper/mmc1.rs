@@ -1,17 +1,35 @@
 use super::Mapper;
-use crate::{cartridge::Mirroring, rom::Rom};
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// Size of the PRG ROM selected by the outer bank bit on SUROM boards.
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
 
 /// MMC1 is a memory mapper used in Nintendo's SxROM and NES-EVENT Game Pak
 /// boards.
+///
+/// Registers are written serially, one bit at a time, through a 5 bit shift
+/// register. The bank layout is derived from the registers on every access,
+/// so changing the bank modes takes effect without rewriting the banks.
+///
+/// See: https://www.nesdev.org/wiki/MMC1
 pub struct MMC1 {
     rom: Rom,
 
-    chr_lo: u8,
-    chr_hi: u8,
-    chr_8k: u8,
-    prg_lo: u8,
-    prg_hi: u8,
-    prg_32k: u8,
+    // Load register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // Rxxx xxxD
+    // |       |
+    // |       +- Data bit to be shifted into shift register, LSB first
+    // +--------- A write with bit set will reset shift register
+    //             and write Control with (Control OR $0C),
+    //             locking PRG ROM at $C000-$FFFF to the last bank.
+    shift: u8,
+    count: u8,
 
     // Control register.
     //
@@ -27,169 +45,307 @@ pub struct MMC1 {
     // +----- CHR ROM bank mode (0: switch 8 KB at a time; 1: switch two separate 4 KB banks)
     control: u8,
 
-    // Load register.
+    // CHR bank 0 and 1 registers.
     //
-    // 7  bit  0
-    // ---- ----
-    // Rxxx xxxD
-    // |       |
-    // |       +- Data bit to be shifted into shift register, LSB first
-    // +--------- A write with bit set will reset shift register
-    //             and write Control with (Control OR $0C),
-    //             locking PRG ROM at $C000-$FFFF to the last bank.
-    load: u8,
+    // 4bit0
+    // -----
+    // CCCCC
+    // |||||
+    // +++++- Select 4 KB or 8 KB CHR bank (low bit ignored in 8 KB mode). On
+    //        SUROM boards bit 4 selects the 256 KB PRG ROM bank.
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+
+    // PRG bank register.
+    //
+    // 4bit0
+    // -----
+    // RPPPP
+    // |||||
+    // |++++- Select 16 KB PRG ROM bank (low bit ignored in 32 KB mode)
+    // +----- PRG RAM chip enable (0: enabled; 1: disabled)
+    prg_bank: u8,
 
-    count: u8,
     ram: Vec<u8>,
-    mirroring: Mirroring,
 }
 
+snapshot!(MMC1, rom, shift, count in 0..5, control, chr_bank_0, chr_bank_1, prg_bank, ram);
+
 impl MMC1 {
     pub fn new(rom: Rom) -> Self {
-        let prg_hi = (rom.header.prg_size() - 1) as u8;
-
         MMC1 {
             rom,
 
-            chr_lo: 0,
-            chr_hi: 0,
-            chr_8k: 0,
-            prg_lo: 0,
-            prg_hi,
-            prg_32k: 0,
+            shift: 0,
+            count: 0,
 
             control: 0x0C,
-            count: 0,
-            load: 0,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
 
             ram: vec![0; 0x2000],
-            mirroring: Mirroring::Vertical,
         }
     }
+
+    /// Writes the value of the shift register to the register selected by
+    /// bits 13 and 14 of the address.
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match (addr >> 13) & 0x3 {
+            0 => self.control = data,
+            1 => self.chr_bank_0 = data,
+            2 => self.chr_bank_1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    /// Returns true if the PRG RAM chip is enabled.
+    fn ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    /// Returns the index into PRG ROM of the given CPU address.
+    fn prg_index(&self, addr: u16) -> usize {
+        // The 16 KB bank count within the selected 256 KB outer bank.
+        let prg_size = self.rom.prg.len();
+        let banks = (prg_size.min(PRG_OUTER_BANK_SIZE) / PRG_PAGE_SIZE).max(1);
+        let outer = match prg_size > PRG_OUTER_BANK_SIZE {
+            true => (self.chr_bank_0 & 0x10) as usize >> 4,
+            false => 0,
+        };
+
+        let bank = (self.prg_bank & 0xF) as usize;
+        let bank = match ((self.control >> 2) & 0x3, addr) {
+            // 32 KB mode, ignoring the low bit of the bank number.
+            (0 | 1, 0x8000..=0xBFFF) => bank & !0x1,
+            (0 | 1, _) => bank | 0x1,
+
+            // Fix first bank at $8000.
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+
+            // Fix last bank at $C000.
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => banks - 1,
+        };
+
+        let bank = outer * banks + bank % banks;
+        bank * PRG_PAGE_SIZE + (addr & 0x3FFF) as usize
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = match (self.control & 0x10 != 0, addr) {
+            // Two separate 4 KB banks.
+            (true, 0x0000..=0x0FFF) => self.chr_bank_0,
+            (true, _) => self.chr_bank_1,
+
+            // A single 8 KB bank, ignoring the low bit of the bank number.
+            (false, 0x0000..=0x0FFF) => self.chr_bank_0 & !0x1,
+            (false, _) => self.chr_bank_0 | 0x1,
+        };
+
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+        (bank as usize % banks) * CHR_BANK_SIZE + (addr & 0xFFF) as usize
+    }
 }
 
 impl Mapper for MMC1 {
-    /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
         match addr {
             // 8 KB PRG RAM bank.
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF if self.ram_enabled() => self.ram[(addr & 0x1FFF) as usize],
 
-            // 16 KB PRG ROM bank.
-            0x8000..=0xFFFF => {
-                // Switch PRG ROM bank based on the control register.
-                let index = if self.control & 0x8 != 0 {
-                    if addr >= 0x8000 && addr <= 0xBFFF {
-                        self.prg_lo as usize * 0x4000 + (addr & 0x3FFF) as usize
-                    } else {
-                        self.prg_hi as usize * 0x4000 + (addr & 0x3FFF) as usize
-                    }
-                } else {
-                    self.prg_32k as usize * 0x8000 + (addr & 0x7FFF) as usize
-                };
-
-                self.rom.prg[index]
-            }
+            // 2x 16 KB PRG ROM banks.
+            0x8000..=0xFFFF => self.rom.prg[self.prg_index(addr)],
             _ => 0,
         }
     }
 
-    /// Writes a byte to PRG ROM at the given address.
-    fn write_prg(&mut self, addr: u16, data: u8) {
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
         match addr {
             // 8 KB PRG RAM bank.
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = data,
+            0x6000..=0x7FFF if self.ram_enabled() => self.ram[(addr & 0x1FFF) as usize] = data,
 
-            // 16 KB PRG ROM bank.
+            // Load register.
             0x8000..=0xFFFF => {
                 if data & 0x80 != 0 {
                     self.control |= 0x0C;
+                    self.shift = 0;
+                    self.count = 0;
+                    return;
+                }
+
+                self.shift |= (data & 0x1) << self.count;
+                self.count += 1;
+
+                // The fifth write copies the shift register into the target
+                // register.
+                if self.count == 5 {
+                    self.write_register(addr, self.shift);
+                    self.shift = 0;
                     self.count = 0;
-                    self.load = 0;
-                } else {
-                    self.load |= (data & 0x1) << self.count;
-                    self.count += 1;
-
-                    if self.count == 5 {
-                        let target = (addr >> 13) & 0x3;
-                        let chr_4k_mode = self.control & 0x10 != 0;
-                        match target {
-                            0 => {
-                                self.control = self.load & 0x1F;
-                                self.mirroring = match self.control & 0x3 {
-                                    0 => Mirroring::SingleScreenLo,
-                                    1 => Mirroring::SingleScreenHi,
-                                    2 => Mirroring::Vertical,
-                                    _ => Mirroring::Horizontal,
-                                };
-                            }
-                            1 => {
-                                if chr_4k_mode {
-                                    self.chr_lo = self.load & 0x1F;
-                                } else {
-                                    self.chr_8k = (self.load & 0x1E) >> 1;
-                                }
-                            }
-                            2 => {
-                                if chr_4k_mode {
-                                    self.chr_hi = self.load & 0x1F;
-                                }
-                            }
-                            _ => {
-                                let prg_mode = (self.control >> 2) & 0x3;
-
-                                match prg_mode {
-                                    0 | 1 => self.prg_32k = (self.load & 0xE) >> 1,
-                                    2 => {
-                                        self.prg_lo = 0;
-                                        self.prg_hi = self.load & 0xF;
-                                    }
-                                    _ => {
-                                        self.prg_lo = self.load & 0xF;
-                                        self.prg_hi = (self.rom.header.prg_size() - 1) as u8;
-                                    }
-                                }
-                            }
-                        }
-
-                        self.count = 0;
-                        self.load = 0;
-                    }
                 }
             }
             _ => {}
         }
     }
 
-    /// Returns a byte from CHR ROM at the given address.
-    fn read_chr(&self, addr: u16) -> u8 {
-        if self.rom.header.chr_size() == 0 {
-            return self.rom.chr[addr as usize];
-        }
-
-        // Check if the CHR ROM bank mode is 8 KB or 4 KB.
-        let index = if self.control & 0x10 != 0 {
-            match addr {
-                0x0000..=0x0FFF => self.chr_lo as usize * 0x1000 + (addr & 0xFFF) as usize,
-                0x1000..=0x1FFF => self.chr_hi as usize * 0x1000 + (addr & 0xFFF) as usize,
-                _ => 0,
-            }
-        } else {
-            self.chr_8k as usize * 0x2000 + (addr & 0x1FFF) as usize
-        };
+    /// Returns true for PRG ROM, and PRG RAM while it is enabled.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000 || self.ram_enabled() && addr >= 0x6000
+    }
 
-        self.rom.chr[index]
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_index(addr)]
     }
 
-    /// Writes a byte to CHR ROM at the given address.
-    fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_size() == 0 {
-            self.rom.chr[addr as usize] = data;
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
         }
     }
 
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
     /// Returns the Mirroring mode.
     fn mirroring(&self) -> Mirroring {
-        self.rom.header.mirroring()
+        match self.control & 0x3 {
+            0 => Mirroring::SingleScreenLo,
+            1 => Mirroring::SingleScreenHi,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an MMC1 with the given number of 16 KB PRG ROM and 8 KB CHR
+    /// ROM pages, where the first byte of each bank holds its number.
+    fn test_mmc1(prg_size: usize, chr_size: usize) -> MMC1 {
+        let prg = numbered_banks(prg_size, PRG_PAGE_SIZE);
+        let chr = numbered_banks(chr_size * 2, CHR_BANK_SIZE);
+
+        let rom = test_rom(prg_size, prg, chr_size, chr, None, None, None).unwrap();
+        MMC1::new(rom)
+    }
+
+    /// Serially writes a 5 bit value to the register at the given address.
+    fn write_serial(mapper: &mut MMC1, addr: u16, data: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (data >> bit) & 0x1);
+        }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut mapper = test_mmc1(8, 1);
+        write_serial(&mut mapper, 0xE000, 0x15);
+        assert_eq!(mapper.prg_bank, 0x15);
+        assert_eq!(mapper.count, 0);
+
+        // Writing with bit 7 set resets the shift register.
+        mapper.cpu_write(0x8000, 0x1);
+        mapper.cpu_write(0x8000, 0x80);
+        assert_eq!(mapper.shift, 0);
+        assert_eq!(mapper.count, 0);
+        assert_eq!(mapper.control & 0x0C, 0x0C);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = test_mmc1(8, 1);
+
+        // Power on state fixes the last bank at $C000.
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        write_serial(&mut mapper, 0xE000, 0x3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        // Switching the mode applies to the previously selected bank.
+        write_serial(&mut mapper, 0x8000, 0x08);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+
+        // 32 KB mode ignores the low bit of the bank number.
+        write_serial(&mut mapper, 0x8000, 0x00);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_prg_outer_bank() {
+        let mut mapper = test_mmc1(32, 0);
+        assert_eq!(mapper.cpu_read(0xC000), 15);
+
+        write_serial(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.cpu_read(0x8000), 16);
+        assert_eq!(mapper.cpu_read(0xC000), 31);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mapper = test_mmc1(2, 4);
+
+        // 8 KB mode ignores the low bit of the bank number.
+        write_serial(&mut mapper, 0xA000, 0x5);
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+        assert_eq!(mapper.ppu_read(0x1000), 5);
+
+        write_serial(&mut mapper, 0x8000, 0x1C);
+        write_serial(&mut mapper, 0xC000, 0x2);
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 2);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = test_mmc1(2, 0);
+        write_serial(&mut mapper, 0x8000, 0x1C);
+        write_serial(&mut mapper, 0xC000, 0x2);
+
+        // Bank numbers wrap around the 8 KB of CHR RAM.
+        mapper.ppu_write(0x1000, 0x42);
+        assert_eq!(mapper.ppu_read(0x1000), 0x42);
+        assert_eq!(mapper.ppu_read(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_mmc1(2, 1);
+        let modes = [
+            Mirroring::SingleScreenLo,
+            Mirroring::SingleScreenHi,
+            Mirroring::Vertical,
+            Mirroring::Horizontal,
+        ];
+        for (data, mirroring) in modes.into_iter().enumerate() {
+            write_serial(&mut mapper, 0x8000, 0x0C | data as u8);
+            assert_eq!(mapper.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut mapper = test_mmc1(2, 1);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+
+        write_serial(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
     }
 }
@@ -0,0 +1,228 @@
+use super::Mapper;
+use crate::state::{snapshot, Snapshot, StateBuffer};
+use crate::{cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// The CHR latches shared by the MMC2 and MMC4.
+///
+/// Each 4 KB pattern table has two CHR bank registers, one selected when its
+/// latch holds $FD and the other when it holds $FE. The latches are switched
+/// by the PPU fetching the tiles at $xFD8 and $xFE8, which allows a game to
+/// swap pattern tables part way through the screen without an IRQ.
+pub struct ChrLatches {
+    /// CHR bank registers, indexed by pattern table and then by latch.
+    banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+}
+
+/// Each latch selects one of two banks, so holds 0 or 1.
+impl Snapshot for ChrLatches {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        self.banks.snapshot(state);
+        self.latches.snapshot(state);
+        if self.latches.iter().any(|&latch| latch > 1) {
+            state.invalid();
+        }
+    }
+}
+
+impl ChrLatches {
+    pub fn new() -> Self {
+        ChrLatches {
+            banks: [[0; 2]; 2],
+            latches: [1; 2],
+        }
+    }
+
+    /// Writes a CHR bank register, where the address selects the register:
+    ///
+    /// $B000: $FD/$0000, $C000: $FE/$0000, $D000: $FD/$1000, $E000: $FE/$1000
+    pub fn write(&mut self, addr: u16, data: u8) {
+        let register = ((addr - 0xB000) >> 12) as usize;
+        self.banks[register / 2][register % 2] = data & 0x1F;
+    }
+
+    /// Returns the index into CHR ROM of the given PPU address.
+    pub fn index(&self, addr: u16, chr_len: usize) -> usize {
+        let table = (addr >> 12) as usize & 0x1;
+        let bank = self.banks[table][self.latches[table]] as usize;
+
+        bank % (chr_len / CHR_BANK_SIZE) * CHR_BANK_SIZE + (addr & 0xFFF) as usize
+    }
+
+    /// Updates the latches after the PPU fetches from the given address.
+    ///
+    /// The MMC2 only switches latch 0 on the exact addresses $0FD8 and $0FE8,
+    /// whereas the MMC4 switches it on the ranges $0FD8-$0FDF and $0FE8-$0FEF
+    /// like latch 1.
+    pub fn fetch(&mut self, addr: u16, exact_latch_0: bool) {
+        let latch = match addr & 0x1FF8 {
+            0x0FD8 if !exact_latch_0 || addr == 0x0FD8 => (0, 0),
+            0x0FE8 if !exact_latch_0 || addr == 0x0FE8 => (0, 1),
+            0x1FD8 => (1, 0),
+            0x1FE8 => (1, 1),
+            _ => return,
+        };
+
+        self.latches[latch.0] = latch.1;
+    }
+}
+
+/// Returns the mirroring selected by a write to $F000-$FFFF.
+pub fn write_mirroring(data: u8) -> Mirroring {
+    match data & 0x1 {
+        0 => Mirroring::Vertical,
+        _ => Mirroring::Horizontal,
+    }
+}
+
+/// MMC2 is a memory mapper used in Nintendo's PxROM boards, which were made
+/// for Mike Tyson's Punch-Out!!.
+///
+/// It provides a switchable 8 KB PRG ROM bank at $8000 with the rest fixed to
+/// the last three banks, and two latch switched 4 KB CHR banks.
+///
+/// See: https://www.nesdev.org/wiki/MMC2
+pub struct MMC2 {
+    rom: Rom,
+    prg_bank: u8,
+    chr: ChrLatches,
+    mirroring: Mirroring,
+}
+
+snapshot!(MMC2, rom, prg_bank, chr, mirroring);
+
+impl MMC2 {
+    pub fn new(rom: Rom) -> Self {
+        let mirroring = rom.header.mirroring();
+
+        MMC2 {
+            rom,
+            prg_bank: 0,
+            chr: ChrLatches::new(),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for MMC2 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            // 8 KB switchable PRG ROM bank.
+            0x8000..=0x9FFF => self.prg_bank as usize % banks,
+
+            // 3x 8 KB PRG ROM banks, fixed to the last three banks.
+            0xA000..=0xFFFF => banks - 4 + ((addr - 0x8000) / 0x2000) as usize,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0xF,
+            0xB000..=0xEFFF => self.chr.write(addr, data),
+            0xF000..=0xFFFF => self.mirroring = write_mirroring(data),
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, as there is no PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    ///
+    /// The latches switch after the fetch, so the tile which triggers them is
+    /// read from the previous bank.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.rom.chr[self.chr.index(addr, self.rom.chr.len())];
+        self.chr.fetch(addr, true);
+        data
+    }
+
+    /// Returns a byte from the PPU pattern tables without switching the
+    /// latches.
+    fn ppu_peek(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr.index(addr, self.rom.chr.len())]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an MMC2 where the first byte of each bank holds its number.
+    fn test_mmc2() -> MMC2 {
+        let prg = numbered_banks(8, PRG_BANK_SIZE);
+        let chr = numbered_banks(8, CHR_BANK_SIZE);
+
+        let rom = test_rom(4, prg, 4, chr, None, None, None).unwrap();
+        MMC2::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_mmc2();
+        mapper.cpu_write(0xA000, 3);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 6);
+        assert_eq!(mapper.cpu_read(0xE000), 7);
+    }
+
+    #[test]
+    fn test_chr_latches() {
+        let mut mapper = test_mmc2();
+        mapper.cpu_write(0xB000, 1);
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xD000, 3);
+        mapper.cpu_write(0xE000, 4);
+
+        // Latches start out selecting the $FE banks.
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        // Latch 0 only switches on the exact address.
+        mapper.ppu_read(0x0FD9);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        mapper.ppu_read(0x0FD8);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+
+        // Latch 1 switches on a range of addresses.
+        mapper.ppu_read(0x1FDF);
+        assert_eq!(mapper.ppu_read(0x1000), 3);
+        mapper.ppu_read(0x1FE8);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        // Peeking doesn't switch the latches.
+        mapper.ppu_peek(0x1FD8);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_mmc2();
+        mapper.cpu_write(0xF000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.cpu_write(0xF000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+}
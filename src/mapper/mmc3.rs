@@ -0,0 +1,520 @@
+use super::{mirror_vram_addr, Mapper};
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// MMC3 is a memory mapper used in Nintendo's TxROM Game Pak boards.
+///
+/// It provides two switchable 8 KB PRG ROM banks, 2 KB and 1 KB switchable
+/// CHR banks, and a scanline counter which is clocked by rising edges of PPU
+/// address line A12 and can raise an IRQ.
+///
+/// The Namco 108 (iNES mapper 206), which the MMC3 was derived from, is also
+/// supported. It has only the bank select and bank data registers, without
+/// the PRG and CHR bank modes, mirroring control, PRG RAM or IRQ.
+///
+/// As are two boards which repurpose the upper bits of the CHR banks:
+///
+/// TxSROM (iNES mapper 118): Bit 7 of the CHR bank of each 1 KB of PPU
+/// $0000-$0FFF selects the VRAM page of the matching nametable.
+/// TQROM (iNES mapper 119): Bit 6 of each CHR bank selects 8 KB of CHR RAM
+/// rather than CHR ROM.
+///
+/// See: https://www.nesdev.org/wiki/MMC3
+/// See: https://www.nesdev.org/wiki/INES_Mapper_206
+/// See: https://www.nesdev.org/wiki/INES_Mapper_118
+/// See: https://www.nesdev.org/wiki/INES_Mapper_119
+pub struct MMC3 {
+    rom: Rom,
+
+    /// True for the Namco 108.
+    namco108: bool,
+
+    /// True for TxSROM, which controls the nametables with the CHR banks.
+    txsrom: bool,
+
+    /// True for TQROM, which has CHR RAM alongside its CHR ROM.
+    tqrom: bool,
+    chr_ram: Vec<u8>,
+
+    // Bank select register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // CPMx xRRR
+    // |||   |||
+    // |||   +++- Specify which bank register to update on next write to Bank Data register
+    // |||          000: R0: Select 2 KB CHR bank at PPU $0000-$07FF (or $1000-$17FF)
+    // |||          001: R1: Select 2 KB CHR bank at PPU $0800-$0FFF (or $1800-$1FFF)
+    // |||          010: R2: Select 1 KB CHR bank at PPU $1000-$13FF (or $0000-$03FF)
+    // |||          011: R3: Select 1 KB CHR bank at PPU $1400-$17FF (or $0400-$07FF)
+    // |||          100: R4: Select 1 KB CHR bank at PPU $1800-$1BFF (or $0800-$0BFF)
+    // |||          101: R5: Select 1 KB CHR bank at PPU $1C00-$1FFF (or $0C00-$0FFF)
+    // |||          110: R6: Select 8 KB PRG ROM bank at $8000-$9FFF (or $C000-$DFFF)
+    // |||          111: R7: Select 8 KB PRG ROM bank at $A000-$BFFF
+    // ||+------- Nothing on the MMC3, see MMC6
+    // |+-------- PRG ROM bank mode (0: $8000-$9FFF swappable,
+    // |                                $C000-$DFFF fixed to second-last bank;
+    // |                             1: $C000-$DFFF swappable,
+    // |                                $8000-$9FFF fixed to second-last bank)
+    // +--------- CHR A12 inversion (0: two 2 KB banks at $0000-$0FFF,
+    //                                  four 1 KB banks at $1000-$1FFF;
+    //                               1: two 2 KB banks at $1000-$1FFF,
+    //                                  four 1 KB banks at $0000-$0FFF)
+    bank_select: u8,
+    registers: [u8; 8],
+
+    mirroring: Mirroring,
+
+    // PRG RAM protect register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // RWxx xxxx
+    // ||
+    // |+-------- Write protection (0: allow writes; 1: deny writes)
+    // +--------- PRG RAM chip enable (0: disable; 1: enable)
+    ram_protect: u8,
+    ram: Vec<u8>,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    interrupt: bool,
+}
+
+snapshot!(
+    MMC3,
+    rom,
+    chr_ram,
+    bank_select,
+    registers,
+    mirroring,
+    ram_protect,
+    ram,
+    irq_latch,
+    irq_counter,
+    irq_reload,
+    irq_enabled,
+    interrupt
+);
+
+impl MMC3 {
+    /// Returns an MMC3 wired as the board of the given iNES mapper number.
+    pub fn new(rom: Rom, mapper: u16) -> Self {
+        let mirroring = rom.header.mirroring();
+        let namco108 = mapper == 206;
+
+        MMC3 {
+            rom,
+            namco108,
+            txsrom: mapper == 118,
+            tqrom: mapper == 119,
+            chr_ram: vec![0; 0x2000],
+
+            bank_select: 0,
+            registers: [0; 8],
+
+            mirroring,
+
+            // The Namco 108 has no PRG RAM.
+            ram_protect: match namco108 {
+                true => 0x00,
+                false => 0x80,
+            },
+            ram: vec![0; 0x2000],
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            interrupt: false,
+        }
+    }
+
+    /// Returns the index into PRG ROM of the given CPU address.
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let second_last = banks - 2;
+        let prg_mode = self.bank_select & 0x40 != 0;
+
+        let bank = match (prg_mode, addr) {
+            (false, 0x8000..=0x9FFF) => self.registers[6] as usize,
+            (true, 0x8000..=0x9FFF) => second_last,
+            (_, 0xA000..=0xBFFF) => self.registers[7] as usize,
+            (false, 0xC000..=0xDFFF) => second_last,
+            (true, 0xC000..=0xDFFF) => self.registers[6] as usize,
+            _ => banks - 1,
+        };
+
+        // R6 and R7 ignore the top two bits.
+        (bank & 0x3F) % banks * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+
+    /// Returns the CHR bank of the given PPU address.
+    fn chr_bank(&self, addr: u16) -> u8 {
+        // CHR A12 inversion swaps the 2 KB and 1 KB bank halves.
+        let inverted = match self.bank_select & 0x80 != 0 {
+            true => addr ^ 0x1000,
+            false => addr,
+        };
+
+        match inverted {
+            0x0000..=0x03FF => self.registers[0] & !0x1,
+            0x0400..=0x07FF => self.registers[0] | 0x1,
+            0x0800..=0x0BFF => self.registers[1] & !0x1,
+            0x0C00..=0x0FFF => self.registers[1] | 0x1,
+            0x1000..=0x13FF => self.registers[2],
+            0x1400..=0x17FF => self.registers[3],
+            0x1800..=0x1BFF => self.registers[4],
+            _ => self.registers[5],
+        }
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_bank(addr);
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+        bank as usize % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize
+    }
+
+    /// Returns the index into CHR RAM of the given PPU address, if it is
+    /// mapped to the CHR RAM of a TQROM.
+    fn chr_ram_index(&self, addr: u16) -> Option<usize> {
+        let bank = self.chr_bank(addr);
+
+        match self.tqrom && bank & 0x40 != 0 {
+            true => Some((bank & 0x7) as usize * CHR_BANK_SIZE + (addr & 0x3FF) as usize),
+            false => None,
+        }
+    }
+
+    /// Returns the index into VRAM of the given nametable address.
+    fn vram_index(&self, addr: u16) -> usize {
+        if !self.txsrom {
+            return mirror_vram_addr(self.mirroring, addr);
+        }
+
+        let nametable = (addr & 0xFFF) / 0x400;
+        let page = self.chr_bank(nametable * 0x400) >> 7;
+        page as usize * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    /// Returns true if the PRG RAM chip is enabled.
+    fn ram_enabled(&self) -> bool {
+        self.ram_protect & 0x80 != 0
+    }
+
+    /// Returns true if PRG RAM is writable.
+    fn ram_writable(&self) -> bool {
+        self.ram_enabled() && self.ram_protect & 0x40 == 0
+    }
+}
+
+impl Mapper for MMC3 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF if self.ram_enabled() => self.ram[(addr & 0x1FFF) as usize],
+
+            // 4x 8 KB PRG ROM banks.
+            0x8000..=0xFFFF => self.rom.prg[self.prg_index(addr)],
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    ///
+    /// Each 8 KB range from $8000 holds a pair of registers, selected by
+    /// whether the address is even or odd.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if self.namco108 && !(0x8000..=0x9FFF).contains(&addr) {
+            return;
+        }
+
+        match (addr, addr & 0x1 == 0) {
+            (0x6000..=0x7FFF, _) if self.ram_writable() => {
+                self.ram[(addr & 0x1FFF) as usize] = data
+            }
+
+            // Bank select and bank data.
+            (0x8000..=0x9FFF, true) if self.namco108 => self.bank_select = data & 0x7,
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => self.registers[(self.bank_select & 0x7) as usize] = data,
+
+            // Mirroring, which is hardwired on four screen boards.
+            (0xA000..=0xBFFF, true) if self.mirroring != Mirroring::FourScreen => {
+                self.mirroring = match data & 0x1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            (0xA000..=0xBFFF, false) => self.ram_protect = data,
+
+            // IRQ latch and reload.
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+
+            // IRQ disable (acknowledging any pending interrupt) and enable.
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.interrupt = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, and PRG RAM while it is enabled.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000 || self.ram_enabled() && addr >= 0x6000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        match self.chr_ram_index(addr) {
+            Some(index) => self.chr_ram[index],
+            None => self.rom.chr[self.chr_index(addr)],
+        }
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if let Some(index) = self.chr_ram_index(addr) {
+            self.chr_ram[index] = data;
+        } else if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
+        }
+    }
+
+    /// Returns a byte from the nametables at the given address.
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        vram[self.vram_index(addr)]
+    }
+
+    /// Writes a byte to the nametables at the given address.
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        vram[self.vram_index(addr)] = data;
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns true if the scanline counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Clocks the scanline counter, which is reloaded when it reaches zero
+    /// and raises an IRQ when it is decremented (or reloaded) to zero.
+    fn notify_a12_rising_edge(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.interrupt = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an MMC3 with the given number of 16 KB PRG ROM and 8 KB CHR
+    /// ROM pages, where the first byte of each bank holds its number.
+    fn test_mmc3(prg_size: usize, chr_size: usize) -> MMC3 {
+        test_board(prg_size, chr_size, 4)
+    }
+
+    /// Returns an MMC3 wired as the board of the given iNES mapper number.
+    fn test_board(prg_size: usize, chr_size: usize, mapper: u16) -> MMC3 {
+        let prg = numbered_banks(prg_size * 2, PRG_BANK_SIZE);
+        let chr = numbered_banks(chr_size * 8, CHR_BANK_SIZE);
+
+        let rom = test_rom(prg_size, prg, chr_size, chr, None, None, None).unwrap();
+        MMC3::new(rom, mapper)
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = test_mmc3(4, 1);
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 2);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 3);
+
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xA000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 6);
+        assert_eq!(mapper.cpu_read(0xE000), 7);
+
+        mapper.cpu_write(0x8000, 0x40);
+        assert_eq!(mapper.cpu_read(0x8000), 6);
+        assert_eq!(mapper.cpu_read(0xA000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 2);
+        assert_eq!(mapper.cpu_read(0xE000), 7);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mapper = test_mmc3(2, 2);
+        for (register, bank) in [3, 6, 8, 9, 10, 11].into_iter().enumerate() {
+            mapper.cpu_write(0x8000, register as u8);
+            mapper.cpu_write(0x8001, bank);
+        }
+
+        // 2 KB banks ignore the low bit of the bank number.
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x0400), 3);
+        assert_eq!(mapper.ppu_read(0x0800), 6);
+        assert_eq!(mapper.ppu_read(0x0C00), 7);
+        assert_eq!(mapper.ppu_read(0x1000), 8);
+        assert_eq!(mapper.ppu_read(0x1C00), 11);
+
+        mapper.cpu_write(0x8000, 0x80);
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.ppu_read(0x0C00), 11);
+        assert_eq!(mapper.ppu_read(0x1000), 2);
+        assert_eq!(mapper.ppu_read(0x1C00), 7);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_mmc3(2, 1);
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_ram_protect() {
+        let mut mapper = test_mmc3(2, 1);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+
+        // Write protection.
+        mapper.cpu_write(0xA001, 0xC0);
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+
+        // Disabled chip.
+        mapper.cpu_write(0xA001, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = test_mmc3(2, 1);
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        // Reload, then count down to zero.
+        mapper.notify_a12_rising_edge();
+        mapper.notify_a12_rising_edge();
+        assert!(!mapper.irq_pending());
+        mapper.notify_a12_rising_edge();
+        assert!(mapper.irq_pending());
+
+        // Disabling the IRQ acknowledges it.
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq_pending());
+
+        // The counter reloads from the latch after reaching zero.
+        mapper.notify_a12_rising_edge();
+        assert_eq!(mapper.irq_counter, 2);
+    }
+
+    #[test]
+    fn test_namco108() {
+        let mut mapper = test_board(4, 2, 206);
+        let mirroring = mapper.mirroring();
+
+        // The bank modes are ignored.
+        mapper.cpu_write(0x8000, 0xC6);
+        mapper.cpu_write(0x8001, 2);
+        mapper.cpu_write(0x8000, 0xC0);
+        mapper.cpu_write(0x8001, 4);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xC000), 6);
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+
+        // There is no mirroring control, PRG RAM or IRQ.
+        for data in [0x0, 0x1] {
+            mapper.cpu_write(0xA000, data);
+            assert_eq!(mapper.mirroring(), mirroring);
+        }
+
+        mapper.cpu_write(0xA001, 0x80);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xE001, 0);
+        mapper.notify_a12_rising_edge();
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_txsrom() {
+        let mut mapper = test_board(2, 2, 118);
+        let mut vram = [0; 0x800];
+
+        // The 2 KB banks select the page of two nametables each.
+        mapper.cpu_write(0x8000, 0);
+        mapper.cpu_write(0x8001, 0x80);
+        mapper.nametable_write(0x2400, 0x42, &mut vram);
+        assert_eq!(vram[0x400], 0x42);
+        assert_eq!(mapper.nametable_read(0x2800, &vram), 0);
+
+        // With CHR A12 inversion, each 1 KB bank selects one page.
+        for register in 2..=5 {
+            mapper.cpu_write(0x8000, 0x80 | register);
+            mapper.cpu_write(0x8001, (register & 0x1) << 7);
+        }
+        mapper.nametable_write(0x2C05, 0x12, &mut vram);
+        assert_eq!(vram[0x405], 0x12);
+        assert_eq!(mapper.nametable_read(0x2005, &vram), 0);
+    }
+
+    #[test]
+    fn test_tqrom() {
+        let mut mapper = test_board(2, 8, 119);
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0x8001, 0x41);
+        mapper.cpu_write(0x8000, 3);
+        mapper.cpu_write(0x8001, 0x05);
+
+        // CHR RAM bank 1 and CHR ROM bank 5.
+        mapper.ppu_write(0x1000, 0x42);
+        assert_eq!(mapper.ppu_read(0x1000), 0x42);
+        assert_eq!(mapper.chr_ram[0x400], 0x42);
+        assert_eq!(mapper.ppu_read(0x1400), 5);
+
+        // CHR ROM is read only.
+        mapper.ppu_write(0x1400, 0x42);
+        assert_eq!(mapper.ppu_read(0x1400), 5);
+    }
+}
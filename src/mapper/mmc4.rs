@@ -0,0 +1,136 @@
+use super::mmc2::{write_mirroring, ChrLatches};
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
+
+/// MMC4 is a memory mapper used in Nintendo's FxROM boards.
+///
+/// It has the same latch switched CHR banks as the MMC2, but switches a 16 KB
+/// PRG ROM bank at $8000 with the last bank fixed at $C000, and carries 8 KB
+/// of PRG RAM.
+///
+/// See: https://www.nesdev.org/wiki/MMC4
+pub struct MMC4 {
+    rom: Rom,
+    prg_bank: u8,
+    chr: ChrLatches,
+    mirroring: Mirroring,
+    ram: Vec<u8>,
+}
+
+snapshot!(MMC4, rom, prg_bank, chr, mirroring, ram);
+
+impl MMC4 {
+    pub fn new(rom: Rom) -> Self {
+        let mirroring = rom.header.mirroring();
+
+        MMC4 {
+            rom,
+            prg_bank: 0,
+            chr: ChrLatches::new(),
+            mirroring,
+            ram: vec![0; 0x2000],
+        }
+    }
+}
+
+impl Mapper for MMC4 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_PAGE_SIZE;
+        let bank = match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF => return self.ram[(addr & 0x1FFF) as usize],
+
+            // 16 KB switchable PRG ROM bank.
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+
+            // 16 KB PRG ROM bank, fixed to the last bank.
+            0xC000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank * PRG_PAGE_SIZE + (addr & 0x3FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = data,
+            0xA000..=0xAFFF => self.prg_bank = data & 0xF,
+            0xB000..=0xEFFF => self.chr.write(addr, data),
+            0xF000..=0xFFFF => self.mirroring = write_mirroring(data),
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.rom.chr[self.chr.index(addr, self.rom.chr.len())];
+        self.chr.fetch(addr, false);
+        data
+    }
+
+    /// Returns a byte from the PPU pattern tables without switching the
+    /// latches.
+    fn ppu_peek(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr.index(addr, self.rom.chr.len())]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns an MMC4 where the first byte of each bank holds its number.
+    fn test_mmc4() -> MMC4 {
+        let prg = numbered_banks(8, PRG_PAGE_SIZE);
+        let chr = numbered_banks(8, 0x1000);
+
+        let rom = test_rom(8, prg, 4, chr, None, None, None).unwrap();
+        MMC4::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_mmc4();
+        mapper.cpu_write(0xA000, 3);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+    }
+
+    #[test]
+    fn test_chr_latches() {
+        let mut mapper = test_mmc4();
+        mapper.cpu_write(0xB000, 1);
+        mapper.cpu_write(0xC000, 2);
+
+        // Unlike the MMC2, latch 0 switches on a range of addresses.
+        mapper.ppu_read(0x0FDF);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+        mapper.ppu_read(0x0FEA);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut mapper = test_mmc4();
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+}
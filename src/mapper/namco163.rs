@@ -0,0 +1,350 @@
+mod audio;
+
+use self::audio::Namco163Audio;
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{apu::ExpansionAudio, cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Bank numbers at or above this select a page of the console's VRAM rather
+/// than CHR ROM for the nametables.
+const CIRAM_BANKS: u8 = 0xE0;
+
+/// Namco 163 is a memory mapper used by Namco, assigned to iNES mapper 19.
+///
+/// It provides three switchable 8 KB PRG ROM banks, eight 1 KB CHR banks,
+/// nametables which can each be mapped to CHR ROM or VRAM, a 15 bit CPU cycle
+/// IRQ counter and up to 8 channels of wavetable expansion audio.
+///
+/// Using VRAM as pattern tables (CHR banks $E0-$FF) is not supported.
+///
+/// See: https://www.nesdev.org/wiki/INES_Mapper_019
+pub struct Namco163 {
+    rom: Rom,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+
+    // Write protect register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // KKKK DCBA
+    // |||| ||||
+    // |||| |||+- Write protect $6000-$67FF
+    // |||| ||+-- Write protect $6800-$6FFF
+    // |||| |+--- Write protect $7000-$77FF
+    // |||| +---- Write protect $7800-$7FFF
+    // ++++------ Must be 0100 for writes to be enabled
+    write_protect: u8,
+
+    // IRQ counter.
+    //
+    // 15 bit counter which increments every CPU cycle while enabled, raising
+    // an IRQ when it reaches $7FFF. Bit 15 holds the enable flag.
+    irq_counter: u16,
+    interrupt: bool,
+
+    audio: Namco163Audio,
+    ram: Vec<u8>,
+}
+
+snapshot!(
+    Namco163,
+    rom,
+    prg_banks,
+    chr_banks,
+    nametable_banks,
+    write_protect,
+    irq_counter,
+    interrupt,
+    audio,
+    ram
+);
+
+impl Namco163 {
+    pub fn new(rom: Rom) -> Self {
+        Namco163 {
+            rom,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [CIRAM_BANKS; 4],
+            write_protect: 0,
+            irq_counter: 0,
+            interrupt: false,
+            audio: Namco163Audio::new(),
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Returns the index into CHR ROM of the given 1 KB bank and address.
+    fn chr_index(&self, bank: u8, addr: u16) -> usize {
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+        bank as usize % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize
+    }
+
+    /// Returns true if the PRG RAM at the given address is writable.
+    fn ram_writable(&self, addr: u16) -> bool {
+        let section = (addr - 0x6000) / 0x800;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << section) == 0
+    }
+
+    /// Returns the nametable bank of the given nametable address.
+    fn nametable_bank(&self, addr: u16) -> u8 {
+        self.nametable_banks[((addr & 0xFFF) / 0x400) as usize]
+    }
+}
+
+impl Mapper for Namco163 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            0x4800..=0x4FFF => return self.audio.read_data(),
+            0x5000..=0x57FF => return self.irq_counter as u8,
+            0x5800..=0x5FFF => return (self.irq_counter >> 8) as u8,
+
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF => return self.ram[(addr & 0x1FFF) as usize],
+
+            // 3x 8 KB switchable PRG ROM banks.
+            0x8000..=0xDFFF => self.prg_banks[((addr - 0x8000) / 0x2000) as usize] as usize,
+
+            // 8 KB PRG ROM bank, fixed to the last bank.
+            0xE000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank % banks * PRG_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+
+    /// Returns a byte from the CPU address space without incrementing the
+    /// sound RAM address port.
+    fn cpu_peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.audio.peek_data(),
+            _ => self.cpu_read(addr),
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+
+            // Writing either half of the IRQ counter acknowledges the IRQ.
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+                self.interrupt = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8);
+                self.interrupt = false;
+            }
+
+            0x6000..=0x7FFF if self.ram_writable(addr) => {
+                self.ram[(addr & 0x1FFF) as usize] = data;
+            }
+
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) / 0x800) as usize] = data,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) / 0x800) as usize] = data,
+
+            // PRG bank $8000 and sound disable.
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0x3F;
+                self.audio.set_enabled(data & 0x40 == 0);
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = data & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+
+            // The write protect register doubles as the sound address port.
+            0xF800..=0xFFFF => {
+                self.write_protect = data;
+                self.audio.write_address(data);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns true for the sound data port, IRQ counter, PRG RAM and ROM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x4800
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr / 0x400) as usize];
+        self.rom.chr[self.chr_index(bank, addr)]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Returns a byte from the nametables, which are each mapped to a page of
+    /// VRAM or a bank of CHR ROM.
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        match self.nametable_bank(addr) {
+            bank if bank >= CIRAM_BANKS => {
+                vram[(bank & 0x1) as usize * 0x400 + (addr & 0x3FF) as usize]
+            }
+            bank => self.rom.chr[self.chr_index(bank, addr)],
+        }
+    }
+
+    /// Writes a byte to the nametables, ignoring writes to CHR ROM.
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        let bank = self.nametable_bank(addr);
+        if bank >= CIRAM_BANKS {
+            vram[(bank & 0x1) as usize * 0x400 + (addr & 0x3FF) as usize] = data;
+        }
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode from the header, as the nametable mapping
+    /// is controlled by the nametable banks.
+    fn mirroring(&self) -> Mirroring {
+        self.rom.header.mirroring()
+    }
+
+    /// Returns true if the IRQ counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Clocks the IRQ counter.
+    fn clock(&mut self) {
+        let enabled = self.irq_counter & 0x8000 != 0;
+        if !enabled || self.irq_counter & 0x7FFF == 0x7FFF {
+            return;
+        }
+
+        self.irq_counter += 1;
+        if self.irq_counter & 0x7FFF == 0x7FFF {
+            self.interrupt = true;
+        }
+    }
+
+    /// Returns the Namco 163 wavetable channels.
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        Some(&mut self.audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a Namco 163 where the first byte of each 8 KB PRG bank and 1 KB
+    /// CHR bank holds its number.
+    fn test_namco163() -> Namco163 {
+        let prg = numbered_banks(16, PRG_BANK_SIZE);
+        let chr = numbered_banks(32, CHR_BANK_SIZE);
+
+        let rom = test_rom(8, prg, 4, chr, None, None, None).unwrap();
+        Namco163::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0xE000, 3);
+        mapper.cpu_write(0xE800, 4);
+        mapper.cpu_write(0xF000, 5);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0x8800, 7);
+        mapper.cpu_write(0xB800, 20);
+
+        assert_eq!(mapper.ppu_read(0x0400), 7);
+        assert_eq!(mapper.ppu_read(0x1C00), 20);
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut mapper = test_namco163();
+        let mut vram = [0; 0x800];
+
+        // VRAM pages.
+        mapper.cpu_write(0xC000, 0xE1);
+        mapper.nametable_write(0x2000, 0x42, &mut vram);
+        assert_eq!(vram[0x400], 0x42);
+        assert_eq!(mapper.nametable_read(0x2000, &vram), 0x42);
+
+        // CHR ROM banks, which are read only.
+        mapper.cpu_write(0xC800, 9);
+        mapper.nametable_write(0x2400, 0x42, &mut vram);
+        assert_eq!(mapper.nametable_read(0x2400, &vram), 9);
+    }
+
+    #[test]
+    fn test_ram_write_protect() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        mapper.cpu_write(0xF800, 0x41);
+        mapper.cpu_write(0x6000, 0x42);
+        mapper.cpu_write(0x6800, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+        assert_eq!(mapper.cpu_read(0x6800), 0x42);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0x5000, 0xFD);
+        mapper.cpu_write(0x5800, 0xFF);
+
+        mapper.clock();
+        assert!(!mapper.irq_pending());
+        mapper.clock();
+        assert!(mapper.irq_pending());
+
+        // The counter stops at $7FFF.
+        mapper.clock();
+        assert_eq!(mapper.cpu_read(0x5000), 0xFF);
+        assert_eq!(mapper.cpu_read(0x5800), 0xFF);
+
+        mapper.cpu_write(0x5800, 0x00);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_sound_ram() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0xF800, 0x80 | 0x40);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0xF800, 0x40);
+        assert_eq!(mapper.cpu_read(0x4800), 0x12);
+    }
+
+    #[test]
+    fn test_sound_ram_peek() {
+        let mut mapper = test_namco163();
+        mapper.cpu_write(0xF800, 0x80 | 0x40);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0x4800, 0x34);
+
+        // Peeking doesn't auto-increment the address.
+        mapper.cpu_write(0xF800, 0x80 | 0x40);
+        assert_eq!(mapper.cpu_peek(0x4800), 0x12);
+        assert_eq!(mapper.cpu_read(0x4800), 0x12);
+        assert_eq!(mapper.cpu_read(0x4800), 0x34);
+    }
+}
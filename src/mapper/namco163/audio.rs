@@ -0,0 +1,241 @@
+use crate::apu::ExpansionAudio;
+use crate::state::snapshot;
+
+/// Number of CPU cycles spent updating each channel.
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// Maximum magnitude of a channel's output, (sample - 8) * volume.
+const MAX_CHANNEL_OUTPUT: f32 = 120.0;
+
+/// Address in sound RAM of the registers of channel 0. Each channel has 8
+/// bytes of registers, ending with channel 7 at $78-$7F.
+const CHANNEL_REGISTERS: usize = 0x40;
+
+/// The Namco 163 expansion audio, with up to 8 wavetable channels which play
+/// 4 bit samples stored in 128 bytes of internal sound RAM.
+///
+/// The chip updates a single enabled channel every 15 CPU cycles and outputs
+/// only that channel. Rather than reproduce the high pitched whine that
+/// multiplexing causes with many channels enabled, the last output of each
+/// enabled channel is averaged.
+///
+/// Where the registers of each channel are equal to:
+///
+/// $x0: Low 8 bits of frequency
+/// $x1: Low 8 bits of phase
+/// $x2: Middle 8 bits of frequency
+/// $x3: Middle 8 bits of phase
+/// $x4: LLLL LLFF (wave length 256 - 4 * L, high 2 bits of frequency)
+/// $x5: High 8 bits of phase
+/// $x6: Wave address
+/// $x7: CCCC VVVV (enabled channels - 1, in $7F only; volume)
+///
+/// See: https://www.nesdev.org/wiki/Namco_163_audio
+pub struct Namco163Audio {
+    ram: [u8; 128],
+
+    // Address port.
+    //
+    // 7  bit  0
+    // ---- ----
+    // IAAA AAAA
+    // |||| ||||
+    // |+++-++++- Address of the sound RAM
+    // +--------- Auto-increment the address after each data access
+    address: u8,
+
+    enabled: bool,
+    cycles: u8,
+
+    /// Channel currently being updated, counting down from 7.
+    channel: usize,
+    outputs: [f32; 8],
+}
+
+snapshot!(
+    Namco163Audio,
+    ram,
+    address,
+    enabled,
+    cycles in 0..CYCLES_PER_CHANNEL,
+    channel in 0..8,
+    outputs
+);
+
+impl Namco163Audio {
+    pub fn new() -> Self {
+        Namco163Audio {
+            ram: [0; 128],
+            address: 0,
+            enabled: true,
+            cycles: 0,
+            channel: 7,
+            outputs: [0.0; 8],
+        }
+    }
+
+    /// Writes the address port ($F800).
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    /// Returns the byte of sound RAM selected by the address port ($4800).
+    pub fn read_data(&mut self) -> u8 {
+        let data = self.peek_data();
+        self.increment_address();
+        data
+    }
+
+    /// Returns the byte of sound RAM selected by the address port ($4800)
+    /// without incrementing the address.
+    pub fn peek_data(&self) -> u8 {
+        self.ram[(self.address & 0x7F) as usize]
+    }
+
+    /// Writes the byte of sound RAM selected by the address port ($4800).
+    pub fn write_data(&mut self, data: u8) {
+        self.ram[(self.address & 0x7F) as usize] = data;
+        self.increment_address();
+    }
+
+    /// Increments the address port, if auto-increment is enabled.
+    fn increment_address(&mut self) {
+        if self.address & 0x80 != 0 {
+            self.address = 0x80 | (self.address.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    /// Enables or disables the sound output.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Returns the number of enabled channels (1-8), which are the highest
+    /// numbered channels.
+    fn enabled_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x7) as usize + 1
+    }
+
+    /// Returns the 4 bit sample at the given index of the sound RAM, where
+    /// each byte holds two samples, low nibble first.
+    fn sample(&self, index: u8) -> u8 {
+        let byte = self.ram[(index >> 1) as usize];
+        match index & 0x1 {
+            0 => byte & 0xF,
+            _ => byte >> 4,
+        }
+    }
+
+    /// Advances the phase of a channel, returning its output.
+    fn update_channel(&mut self, channel: usize) -> f32 {
+        let regs = CHANNEL_REGISTERS + channel * 8;
+        let reg = |i: usize| self.ram[regs + i] as u32;
+
+        let frequency = reg(0) | (reg(2) << 8) | ((reg(4) & 0x3) << 16);
+        let length = 256 - (reg(4) & 0xFC);
+        let phase = reg(1) | (reg(3) << 8) | (reg(5) << 16);
+        let wave_addr = reg(6);
+        let volume = (reg(7) & 0xF) as f32;
+
+        let phase = (phase + frequency) % (length << 16);
+        self.ram[regs + 1] = phase as u8;
+        self.ram[regs + 3] = (phase >> 8) as u8;
+        self.ram[regs + 5] = (phase >> 16) as u8;
+
+        let index = ((phase >> 16) + wave_addr) as u8;
+
+        (self.sample(index) as f32 - 8.0) * volume
+    }
+}
+
+impl ExpansionAudio for Namco163Audio {
+    fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycles = 0;
+
+        self.outputs[self.channel] = self.update_channel(self.channel);
+
+        // Cycle through the enabled channels, from channel 7 down.
+        let first = 8 - self.enabled_channels();
+        self.channel = match self.channel {
+            c if c <= first => 7,
+            c => c - 1,
+        };
+    }
+
+    fn output(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        let first = 8 - self.enabled_channels();
+        let channels = &self.outputs[first..];
+        channels.iter().sum::<f32>() / channels.len() as f32 / MAX_CHANNEL_OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_port() {
+        let mut audio = Namco163Audio::new();
+        audio.write_address(0x80 | 0x7F);
+        audio.write_data(0x12);
+        audio.write_data(0x34);
+
+        assert_eq!(audio.ram[0x7F], 0x12);
+        assert_eq!(audio.ram[0x00], 0x34);
+
+        audio.write_address(0x7F);
+        assert_eq!(audio.read_data(), 0x12);
+        assert_eq!(audio.read_data(), 0x12);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut audio = Namco163Audio::new();
+        audio.ram[0] = 0xA5;
+        assert_eq!(audio.sample(0), 0x5);
+        assert_eq!(audio.sample(1), 0xA);
+    }
+
+    #[test]
+    fn test_channel() {
+        let mut audio = Namco163Audio::new();
+
+        // A 4 sample wave of 0, 0, F, F.
+        audio.ram[0] = 0x00;
+        audio.ram[1] = 0xFF;
+
+        // Channel 7, stepping one sample per update through a wave of length
+        // 4 at address 0, at full volume.
+        audio.ram[0x7C] = 0xFC | 0x1;
+        audio.ram[0x7E] = 0x00;
+        audio.ram[0x7F] = 0x0F;
+
+        let outputs: Vec<f32> = (0..4).map(|_| audio.update_channel(7)).collect();
+        assert_eq!(outputs, [-120.0, 105.0, 105.0, -120.0]);
+    }
+
+    #[test]
+    fn test_enabled_channels() {
+        let mut audio = Namco163Audio::new();
+        audio.ram[0x7F] = 0x10;
+        assert_eq!(audio.enabled_channels(), 2);
+
+        // Channels 7 and 6 are updated in turn.
+        for _ in 0..CYCLES_PER_CHANNEL {
+            audio.clock();
+        }
+        assert_eq!(audio.channel, 6);
+        for _ in 0..CYCLES_PER_CHANNEL {
+            audio.clock();
+        }
+        assert_eq!(audio.channel, 7);
+    }
+}
@@ -1,5 +1,6 @@
 use super::Mapper;
-use crate::{cartridge::Mirroring, rom::Rom};
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
 
 /// NROM refers to the Nintendo cartridge boards NES-NROM-128, NES-NROM-256,
 /// their HVC counterparts, and clone boards. The iNES format assigns mapper 0
@@ -9,6 +10,8 @@ pub struct Nrom {
     ram: Vec<u8>,
 }
 
+snapshot!(Nrom, rom, ram);
+
 impl Nrom {
     /// Returns an instantiated NROM.
     pub fn new(rom: Rom) -> Self {
@@ -20,7 +23,7 @@ impl Nrom {
 
     /// Returns the PRG ROM mask used for PRG ROM bank switching.
     fn prg_mask(&self) -> u16 {
-        if self.rom.header.prg_size() > 1 {
+        if self.rom.header.prg_size() > PRG_PAGE_SIZE {
             0x7FFF
         } else {
             0x3FFF
@@ -29,35 +32,40 @@ impl Nrom {
 }
 
 impl Mapper for Nrom {
-    /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
         match addr {
             // Special case for "Family Basic".
             0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
 
-            _ => self.rom.prg[(addr & self.prg_mask()) as usize],
+            _ => self.rom.prg[(addr & self.prg_mask()) as usize % self.rom.prg.len()],
         }
     }
 
-    /// Writes a byte to PRG ROM at the given address.
-    fn write_prg(&mut self, addr: u16, data: u8) {
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
         if let 0x6000..=0x7FFF = addr {
             self.ram[(addr & 0x1FFF) as usize] = data;
         }
     }
 
-    /// Returns a byte from CHR ROM at the given address.
-    fn read_chr(&self, addr: u16) -> u8 {
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
         self.rom.chr[addr as usize]
     }
 
-    /// Writes a byte to CHR ROM at the given address.
-    fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_size() == 0 {
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
             self.rom.chr[addr as usize] = data;
         }
     }
 
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
     /// Returns the Mirroring mode.
     fn mirroring(&self) -> Mirroring {
         self.rom.header.mirroring()
@@ -0,0 +1,138 @@
+use super::Mapper;
+use crate::cartridge::Mirroring;
+use crate::state::snapshot;
+
+/// Size of a bank of PRG data in bytes.
+const BANK_SIZE: usize = 0x1000;
+
+/// Bank switching registers, one for each 4KB bank from $8000-$FFFF.
+const BANK_REGISTERS: u16 = 0x5FF8;
+const BANK_REGISTERS_END: u16 = 0x5FFF;
+
+/// Maps the data of an NSF file into the CPU address space.
+///
+/// If the NSF uses bank switching the data is split in to 4KB banks, which are
+/// mapped to $8000-$FFFF via the registers at $5FF8-$5FFF. Otherwise the data
+/// is loaded at the load address and is fixed in place.
+///
+/// See: https://www.nesdev.org/wiki/NSF#Bankswitching
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    ram: Vec<u8>,
+    chr: Vec<u8>,
+
+    banks: [u8; 8],
+}
+
+snapshot!(NsfMapper, ram, chr, banks);
+
+impl NsfMapper {
+    /// Returns an instantiated NSF mapper for the given data, load address and
+    /// initial bank values.
+    pub fn new(data: &[u8], load_addr: u16, init_banks: [u8; 8]) -> Self {
+        let bank_switched = init_banks.iter().any(|bank| *bank != 0);
+
+        // The data is padded so it starts at the correct offset in the
+        // first bank.
+        let (padding, banks) = match bank_switched {
+            true => ((load_addr & 0x0FFF) as usize, init_banks),
+            false => (
+                load_addr.saturating_sub(0x8000) as usize,
+                [0, 1, 2, 3, 4, 5, 6, 7],
+            ),
+        };
+
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(data);
+        prg.resize(prg.len().div_ceil(BANK_SIZE).max(1) * BANK_SIZE, 0);
+
+        NsfMapper {
+            prg,
+            ram: vec![0; 0x2000],
+            chr: vec![0; 0x2000],
+            banks,
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x8000..=0xFFFF => {
+                let bank = self.banks[((addr - 0x8000) as usize) / BANK_SIZE] as usize;
+                let offset = bank * BANK_SIZE + (addr as usize & (BANK_SIZE - 1));
+                self.prg[offset % self.prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            BANK_REGISTERS..=BANK_REGISTERS_END => {
+                self.banks[(addr - BANK_REGISTERS) as usize] = data;
+            }
+            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = data,
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr[addr as usize] = data;
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_load_address() {
+        let mut mapper = NsfMapper::new(&[0x01, 0x02], 0x8100, [0; 8]);
+        assert_eq!(mapper.cpu_read(0x8000), 0x00);
+        assert_eq!(mapper.cpu_read(0x8100), 0x01);
+        assert_eq!(mapper.cpu_read(0x8101), 0x02);
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut data = vec![0; BANK_SIZE * 2];
+        data[0] = 0xAA;
+        data[BANK_SIZE] = 0xBB;
+
+        let mut mapper = NsfMapper::new(&data, 0x8000, [0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(mapper.cpu_read(0x8000), 0xAA);
+        assert_eq!(mapper.cpu_read(0x9000), 0xBB);
+        assert_eq!(mapper.cpu_read(0xF000), 0xBB);
+
+        mapper.cpu_write(0x5FF8, 1);
+        assert_eq!(mapper.cpu_read(0x8000), 0xBB);
+    }
+
+    #[test]
+    fn test_bank_switching_padding() {
+        let mut mapper = NsfMapper::new(&[0xAA], 0x8010, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut mapper = NsfMapper::new(&[], 0x8000, [0; 8]);
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.cpu_read(0x6000), 0x12);
+    }
+}
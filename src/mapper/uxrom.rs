@@ -1,4 +1,5 @@
 use super::Mapper;
+use crate::state::{Snapshot, StateBuffer};
 use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
 
 const FIXED_BANK_START: u16 = 0xC000;
@@ -7,53 +8,93 @@ const PAGE_OFFSET_MASK: u16 = 0x3FFF;
 
 /// UxROM refers to the Nintendo cartridge boards NES-UNROM, NES-UOROM,
 /// HVC-UN1ROM their HVC counterparts, and clone boards.
+///
+/// The first 16 KB of PRG ROM is switchable and the last 16 KB is fixed to the
+/// last bank. Boards without CHR ROM carry 8 KB of CHR RAM instead.
+///
+/// See: https://www.nesdev.org/wiki/UxROM
 pub struct Uxrom {
     rom: Rom,
     bank: usize,
+
+    /// Boards without bus conflict prevention AND the written value with the
+    /// ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+}
+
+/// The bank is checked as it indexes PRG ROM without wrapping.
+impl Snapshot for Uxrom {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        self.rom.snapshot(state);
+        self.bank.snapshot(state);
+        if self.bank >= self.prg_banks() {
+            state.invalid();
+        }
+    }
 }
 
 impl Uxrom {
-    pub fn new(rom: Rom) -> Self {
-        Uxrom { rom, bank: 0 }
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Self {
+        Uxrom {
+            rom,
+            bank: 0,
+            bus_conflicts,
+        }
+    }
+
+    /// Returns the number of 16 KB PRG ROM banks.
+    fn prg_banks(&self) -> usize {
+        self.rom.prg.len() / PRG_PAGE_SIZE
     }
 }
 
 impl Mapper for Uxrom {
-    /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
         match addr {
             // 16 KB PRG ROM bank, fixed to the last bank
             FIXED_BANK_START..=FIXED_BANK_END => {
-                let index = (self.rom.header.prg_size() - 1) * PRG_PAGE_SIZE
-                    + (addr & PAGE_OFFSET_MASK) as usize;
+                let index =
+                    (self.prg_banks() - 1) * PRG_PAGE_SIZE + (addr & PAGE_OFFSET_MASK) as usize;
                 self.rom.prg[index]
             }
 
             // 16 KB switchable PRG ROM bank.
-            _ => {
+            0x8000..=0xBFFF => {
                 let index = self.bank * PRG_PAGE_SIZE + (addr & PAGE_OFFSET_MASK) as usize;
                 self.rom.prg[index]
             }
+            _ => 0,
         }
     }
 
-    /// Writes a byte to PRG ROM at the given address.
-    fn write_prg(&mut self, addr: u16, data: u8) {
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
         // Writes in the range 0x8000-0xFFFF select the 16 KB PRG ROM bank.
         // (UNROM uses bits 2-0; UOROM uses bits 3-0).
         if let 0x8000..=0xFFFF = addr {
-            self.bank = (data & 0xF) as usize;
+            let data = match self.bus_conflicts {
+                true => data & self.cpu_read(addr),
+                false => data,
+            };
+
+            self.bank = data as usize % self.prg_banks();
         }
     }
 
-    /// Returns a byte from CHR ROM at the given address.
-    fn read_chr(&self, addr: u16) -> u8 {
+    /// Returns true for PRG ROM, as there is no PRG RAM.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
         self.rom.chr[addr as usize]
     }
 
-    /// Writes a byte to CHR ROM at the given address.
-    fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_size() == 0 {
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
             self.rom.chr[addr as usize] = data;
         }
     }
@@ -63,3 +104,52 @@ impl Mapper for Uxrom {
         self.rom.header.mirroring()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a UxROM with the given number of 16 KB PRG ROM pages, where the
+    /// first byte of each bank holds its number.
+    fn test_uxrom(prg_size: usize, bus_conflicts: bool) -> Uxrom {
+        let prg = numbered_banks(prg_size, PRG_PAGE_SIZE);
+
+        let rom = test_rom(prg_size, prg, 0, vec![], None, None, None).unwrap();
+        Uxrom::new(rom, bus_conflicts)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_uxrom(8, false);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        mapper.cpu_write(0x8000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        // Bank numbers wrap around the size of PRG ROM.
+        mapper.cpu_write(0x8000, 10);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = test_uxrom(8, true);
+
+        // $C000 holds the bank number 7, $C001 holds $FF.
+        mapper.cpu_write(0xC000, 0x0E);
+        assert_eq!(mapper.cpu_read(0x8000), 6);
+
+        mapper.cpu_write(0xC001, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = test_uxrom(2, false);
+        mapper.ppu_write(0x1234, 0x42);
+        assert_eq!(mapper.ppu_read(0x1234), 0x42);
+    }
+}
@@ -0,0 +1,287 @@
+use super::vrc_irq::VrcIrq;
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Konami's VRC2 and VRC4 mappers, assigned to iNES mappers 21, 22, 23 and 25.
+///
+/// The boards connect the mapper's two register select lines to different CPU
+/// address lines. Without a submapper to tell the variants of a mapper number
+/// apart, the address lines of both variants are combined, which works as the
+/// games only write to one set of addresses. Mapper 22 is the VRC2a, the
+/// others are treated as a VRC4, as its registers are a superset of the VRC2.
+///
+/// See: https://www.nesdev.org/wiki/VRC2_and_VRC4
+pub struct Vrc4 {
+    rom: Rom,
+
+    /// The CPU address lines connected to register select lines 0 and 1.
+    address_lines: ([u16; 2], [u16; 2]),
+
+    /// True for the VRC2a, whose CHR banks are in 2 KB units.
+    vrc2a: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u16; 8],
+
+    // Mirroring control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // xxxx xxMM
+    //        ||
+    //        ++- Mirroring (0: vertical; 1: horizontal;
+    //                       2: one-screen, lower bank; 3: one-screen, upper bank)
+    mirroring: u8,
+
+    // PRG swap mode register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // xxxx xxPx
+    //        |
+    //        +-- PRG ROM bank mode (0: $8000-$9FFF swappable,
+    //                                  $C000-$DFFF fixed to second-last bank;
+    //                               1: $C000-$DFFF swappable,
+    //                                  $8000-$9FFF fixed to second-last bank)
+    prg_mode: u8,
+
+    irq: VrcIrq,
+    ram: Vec<u8>,
+}
+
+snapshot!(Vrc4, rom, prg_banks, chr_banks, mirroring, prg_mode, irq, ram);
+
+impl Vrc4 {
+    /// Returns a VRC2/4 wired as the board of the given iNES mapper number.
+    pub fn new(rom: Rom, mapper: u16) -> Self {
+        let address_lines = match mapper {
+            21 => ([1, 6], [2, 7]),
+            22 => ([1, 1], [0, 0]),
+            23 => ([0, 2], [1, 3]),
+            _ => ([1, 3], [0, 2]),
+        };
+
+        Vrc4 {
+            rom,
+            address_lines,
+            vrc2a: mapper == 22,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            mirroring: 0,
+            prg_mode: 0,
+            irq: VrcIrq::new(),
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Returns the register (0-3) selected by the address lines of the given
+    /// address.
+    fn register(&self, addr: u16) -> u8 {
+        let line = |lines: [u16; 2]| ((addr >> lines[0]) | (addr >> lines[1])) as u8 & 0x1;
+        line(self.address_lines.0) | (line(self.address_lines.1) << 1)
+    }
+
+    /// Writes half of a CHR bank register, where the low register holds bits
+    /// 0-3 and the high register holds bits 4-8.
+    fn write_chr_bank(&mut self, bank: usize, high: bool, data: u8) {
+        let data = data as u16;
+        self.chr_banks[bank] = match high {
+            true => (self.chr_banks[bank] & 0x0F) | ((data & 0x1F) << 4),
+            false => (self.chr_banks[bank] & 0x1F0) | (data & 0x0F),
+        };
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+
+        // The VRC2a ignores the low bit of the bank number.
+        let bank = match self.vrc2a {
+            true => bank >> 1,
+            false => bank,
+        };
+
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+        bank % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc4 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = match (self.prg_mode & 0x2 != 0, addr) {
+            // 8 KB PRG RAM bank.
+            (_, 0x6000..=0x7FFF) => return self.ram[(addr & 0x1FFF) as usize],
+
+            (false, 0x8000..=0x9FFF) => self.prg_banks[0] as usize,
+            (true, 0x8000..=0x9FFF) => banks - 2,
+            (_, 0xA000..=0xBFFF) => self.prg_banks[1] as usize,
+            (false, 0xC000..=0xDFFF) => banks - 2,
+            (true, 0xC000..=0xDFFF) => self.prg_banks[0] as usize,
+            (_, 0xE000..=0xFFFF) => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank % banks * PRG_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let register = self.register(addr);
+
+        match (addr & 0xF000, register) {
+            (0x6000 | 0x7000, _) => self.ram[(addr & 0x1FFF) as usize] = data,
+
+            (0x8000, _) => self.prg_banks[0] = data & 0x1F,
+            (0x9000, 0 | 1) if self.vrc2a => self.mirroring = data & 0x1,
+            (0x9000, 0 | 1) => self.mirroring = data & 0x3,
+            (0x9000, 2) => self.prg_mode = data,
+            (0xA000, _) => self.prg_banks[1] = data & 0x1F,
+
+            // CHR bank registers, two banks per 4 KB range from $B000.
+            (0xB000..=0xE000, _) => {
+                let bank = ((addr & 0xF000) - 0xB000) as usize / 0x800 + (register as usize >> 1);
+                self.write_chr_bank(bank, register & 0x1 != 0, data);
+            }
+
+            (0xF000, 0) => self.irq.write_latch_lo(data),
+            (0xF000, 1) => self.irq.write_latch_hi(data),
+            (0xF000, 2) => self.irq.write_control(data),
+            (0xF000, _) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_index(addr)]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
+        }
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLo,
+            _ => Mirroring::SingleScreenHi,
+        }
+    }
+
+    /// Returns true if the IRQ counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq.interrupt()
+    }
+
+    /// Clocks the IRQ counter.
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a VRC2/4 for the given mapper, where the first byte of each
+    /// bank holds its number.
+    fn test_vrc4(mapper: u16) -> Vrc4 {
+        let prg = numbered_banks(16, PRG_BANK_SIZE);
+        let chr = numbered_banks(32, CHR_BANK_SIZE);
+
+        let rom = test_rom(8, prg, 4, chr, None, None, None).unwrap();
+        Vrc4::new(rom, mapper)
+    }
+
+    #[test]
+    fn test_register_select() {
+        let vrc4 = test_vrc4(21);
+        assert_eq!(vrc4.register(0x9002), 1);
+        assert_eq!(vrc4.register(0x9004), 2);
+        assert_eq!(vrc4.register(0x9040), 1);
+        assert_eq!(vrc4.register(0x90C0), 3);
+
+        let vrc4 = test_vrc4(25);
+        assert_eq!(vrc4.register(0x9001), 2);
+        assert_eq!(vrc4.register(0x9008), 1);
+
+        let vrc2 = test_vrc4(22);
+        assert_eq!(vrc2.register(0x9001), 2);
+        assert_eq!(vrc2.register(0x9002), 1);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = test_vrc4(23);
+        mapper.cpu_write(0x8000, 3);
+        mapper.cpu_write(0xA000, 4);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        mapper.cpu_write(0x9002, 0x2);
+        assert_eq!(mapper.cpu_read(0x8000), 14);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_vrc4(23);
+        mapper.cpu_write(0xB000, 0x5);
+        mapper.cpu_write(0xB001, 0x1);
+        mapper.cpu_write(0xE002, 0x7);
+
+        assert_eq!(mapper.ppu_read(0x0000), 0x15);
+        assert_eq!(mapper.ppu_read(0x1C00), 7);
+
+        // The VRC2a CHR banks are in 2 KB units.
+        let mut mapper = test_vrc4(22);
+        mapper.cpu_write(0xC000, 0x6);
+        assert_eq!(mapper.ppu_read(0x0800), 3);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_vrc4(23);
+        mapper.cpu_write(0x9000, 3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+
+        let mut mapper = test_vrc4(22);
+        mapper.cpu_write(0x9000, 3);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = test_vrc4(23);
+        mapper.cpu_write(0xF000, 0xF);
+        mapper.cpu_write(0xF001, 0xF);
+        mapper.cpu_write(0xF002, 0x6);
+
+        mapper.clock();
+        assert!(mapper.irq_pending());
+
+        mapper.cpu_write(0xF003, 0);
+        assert!(!mapper.irq_pending());
+    }
+}
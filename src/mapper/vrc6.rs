@@ -0,0 +1,274 @@
+mod audio;
+
+use self::audio::Vrc6Audio;
+use super::vrc_irq::VrcIrq;
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{apu::ExpansionAudio, cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Konami's VRC6 mapper, assigned to iNES mappers 24 (VRC6a) and 26 (VRC6b)
+/// which differ only in the order of the register select lines.
+///
+/// It provides a 16 KB and an 8 KB switchable PRG ROM bank, eight 1 KB CHR
+/// banks, the VRC IRQ counter and expansion audio with two pulse channels and
+/// a sawtooth channel.
+///
+/// Only the PPU banking mode used by all the commercial games (1 KB CHR banks
+/// with the nametables in console VRAM) is supported.
+///
+/// See: https://www.nesdev.org/wiki/VRC6
+pub struct Vrc6 {
+    rom: Rom,
+
+    /// True for the VRC6b, which swaps the A0 and A1 register select lines.
+    swap_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+
+    // PPU banking style register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // W.PN MMDD
+    // |    ||
+    // |    ++--- Mirroring (0: vertical; 1: horizontal;
+    // |                     2: one-screen, lower bank; 3: one-screen, upper bank)
+    // +--------- PRG RAM enable
+    banking_style: u8,
+
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+    ram: Vec<u8>,
+}
+
+snapshot!(
+    Vrc6,
+    rom,
+    prg_16k,
+    prg_8k,
+    chr_banks,
+    banking_style,
+    irq,
+    audio,
+    ram
+);
+
+impl Vrc6 {
+    /// Returns a VRC6 wired as the board of the given iNES mapper number.
+    pub fn new(rom: Rom, mapper: u16) -> Self {
+        Vrc6 {
+            rom,
+            swap_lines: mapper == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking_style: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Returns the register address ($x000-$x003) selected by the given
+    /// address.
+    fn register(&self, addr: u16) -> u16 {
+        let lines = match self.swap_lines {
+            true => ((addr >> 1) & 0x1) | ((addr & 0x1) << 1),
+            false => addr & 0x3,
+        };
+
+        (addr & 0xF000) | lines
+    }
+
+    /// Returns true if the PRG RAM is enabled.
+    fn ram_enabled(&self) -> bool {
+        self.banking_style & 0x80 != 0
+    }
+}
+
+impl Mapper for Vrc6 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let prg_len = self.rom.prg.len();
+
+        let index = match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF if self.ram_enabled() => {
+                return self.ram[(addr & 0x1FFF) as usize];
+            }
+
+            // 16 KB switchable PRG ROM bank.
+            0x8000..=0xBFFF => {
+                (self.prg_16k & 0xF) as usize * PRG_PAGE_SIZE + (addr & 0x3FFF) as usize
+            }
+
+            // 8 KB switchable PRG ROM bank.
+            0xC000..=0xDFFF => {
+                (self.prg_8k & 0x1F) as usize * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+            }
+
+            // 8 KB PRG ROM bank, fixed to the last bank.
+            0xE000..=0xFFFF => prg_len - PRG_BANK_SIZE + (addr & 0x1FFF) as usize,
+            _ => return 0,
+        };
+
+        self.rom.prg[index % prg_len]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.ram_enabled() {
+                self.ram[(addr & 0x1FFF) as usize] = data;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = data,
+            reg @ 0x9000..=0xB002 => self.audio.write(reg, data),
+            0xB003 => self.banking_style = data,
+            0xC000..=0xC003 => self.prg_8k = data,
+            reg @ 0xD000..=0xD003 => self.chr_banks[(reg & 0x3) as usize] = data,
+            reg @ 0xE000..=0xE003 => self.chr_banks[4 + (reg & 0x3) as usize] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, and PRG RAM while it is enabled.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000 || self.ram_enabled() && addr >= 0x6000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+
+        self.rom.chr[bank % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking_style >> 2) & 0x3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLo,
+            _ => Mirroring::SingleScreenHi,
+        }
+    }
+
+    /// Returns true if the IRQ counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq.interrupt()
+    }
+
+    /// Clocks the IRQ counter.
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    /// Returns the VRC6 pulse and sawtooth channels.
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        Some(&mut self.audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a VRC6 for the given mapper, where the first byte of each 8 KB
+    /// PRG bank and 1 KB CHR bank holds its number.
+    fn test_vrc6(mapper: u16) -> Vrc6 {
+        let prg = numbered_banks(16, PRG_BANK_SIZE);
+        let chr = numbered_banks(32, CHR_BANK_SIZE);
+
+        let rom = test_rom(8, prg, 4, chr, None, None, None).unwrap();
+        Vrc6::new(rom, mapper)
+    }
+
+    #[test]
+    fn test_register_select() {
+        assert_eq!(test_vrc6(24).register(0xB003), 0xB003);
+        assert_eq!(test_vrc6(24).register(0x9001), 0x9001);
+        assert_eq!(test_vrc6(26).register(0x9001), 0x9002);
+        assert_eq!(test_vrc6(26).register(0x9002), 0x9001);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_vrc6(24);
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0xC000, 9);
+
+        assert_eq!(mapper.cpu_read(0x8000), 4);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 9);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_vrc6(26);
+        mapper.cpu_write(0xD002, 7);
+        mapper.cpu_write(0xE003, 12);
+
+        assert_eq!(mapper.ppu_read(0x0400), 7);
+        assert_eq!(mapper.ppu_read(0x1C00), 12);
+    }
+
+    #[test]
+    fn test_mirroring_and_ram() {
+        let mut mapper = test_vrc6(24);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        mapper.cpu_write(0xB003, 0x84);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = test_vrc6(24);
+        mapper.cpu_write(0xF000, 0xFF);
+        mapper.cpu_write(0xF001, 0x6);
+
+        mapper.clock();
+        assert!(mapper.irq_pending());
+
+        mapper.cpu_write(0xF002, 0);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut mapper = test_vrc6(24);
+        mapper.cpu_write(0x9000, 0x8F);
+        mapper.cpu_write(0x9002, 0x80);
+
+        let audio = mapper.expansion_audio().unwrap();
+        audio.clock();
+        assert!(audio.output() > 0.0);
+    }
+}
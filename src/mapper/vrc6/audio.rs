@@ -0,0 +1,324 @@
+use crate::apu::ExpansionAudio;
+use crate::state::snapshot;
+
+/// Maximum combined output of the two pulse channels (0-15 each) and the
+/// sawtooth channel (0-31).
+const MAX_OUTPUT: f32 = 61.0;
+
+/// Represents one of the two VRC6 pulse channels, which have 8 duty cycles
+/// and a direct 4 bit volume rather than an envelope.
+struct Pulse {
+    // Control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // MDDD VVVV
+    // |||| ||||
+    // |||| ++++- Volume
+    // |+++------ Duty cycle (1/16 to 8/16)
+    // +--------- Mode (1: ignore duty and output the volume constantly)
+    control: u8,
+
+    enabled: bool,
+    period: u16,
+    timer: u16,
+
+    // Step of the duty cycle, counting down from 15.
+    step: u8,
+}
+
+snapshot!(Pulse, control, enabled, period, timer, step);
+
+impl Pulse {
+    fn new() -> Self {
+        Pulse {
+            control: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    /// Writes the low 8 bits of the period.
+    fn write_period_lo(&mut self, data: u8) {
+        self.period = (self.period & 0xF00) | data as u16;
+    }
+
+    /// Writes the high 4 bits of the period.
+    ///
+    /// Where data is equal to:
+    ///
+    /// E--- FFFF
+    /// E: Enable (0: the duty cycle is reset and the channel is silenced)
+    /// F: High 4 bits of the period
+    fn write_period_hi(&mut self, data: u8) {
+        self.period = (self.period & 0xFF) | ((data as u16 & 0xF) << 8);
+        self.enabled = data & 0x80 != 0;
+
+        if !self.enabled {
+            self.step = 15;
+        }
+    }
+
+    /// Clocks the channel timer, where shift is the frequency scaling of the
+    /// audio control register.
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0xF;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        let duty = (self.control >> 4) & 0x7;
+        let mode = self.control & 0x80 != 0;
+
+        match self.enabled && (mode || self.step <= duty) {
+            true => self.control & 0xF,
+            false => 0,
+        }
+    }
+}
+
+/// Represents the VRC6 sawtooth channel, which repeatedly adds a rate to an
+/// accumulator and outputs its top 5 bits.
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+
+    accumulator: u8,
+
+    // Number of timer clocks since the accumulator was reset. The accumulator
+    // is added to on every other clock and reset on the 14th.
+    step: u8,
+}
+
+snapshot!(Sawtooth, rate, enabled, period, timer, accumulator, step in 0..14);
+
+impl Sawtooth {
+    fn new() -> Self {
+        Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            accumulator: 0,
+            step: 0,
+        }
+    }
+
+    /// Writes the accumulator rate.
+    ///
+    /// Where data is equal to:
+    ///
+    /// --AA AAAA
+    /// A: Accumulator rate
+    fn write_rate(&mut self, data: u8) {
+        self.rate = data & 0x3F;
+    }
+
+    /// Writes the low 8 bits of the period.
+    fn write_period_lo(&mut self, data: u8) {
+        self.period = (self.period & 0xF00) | data as u16;
+    }
+
+    /// Writes the high 4 bits of the period, and the enable flag in bit 7.
+    fn write_period_hi(&mut self, data: u8) {
+        self.period = (self.period & 0xFF) | ((data as u16 & 0xF) << 8);
+        self.enabled = data & 0x80 != 0;
+
+        if !self.enabled {
+            self.accumulator = 0;
+            self.step = 0;
+        }
+    }
+
+    /// Clocks the channel timer, where shift is the frequency scaling of the
+    /// audio control register.
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period >> shift;
+        self.step += 1;
+
+        match self.step {
+            14 => {
+                self.accumulator = 0;
+                self.step = 0;
+            }
+            step if step % 2 == 0 => self.accumulator = self.accumulator.wrapping_add(self.rate),
+            _ => {}
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// The VRC6 expansion audio, made up of two pulse channels and a sawtooth
+/// channel which are mixed linearly.
+///
+/// See: https://www.nesdev.org/wiki/VRC6_audio
+pub struct Vrc6Audio {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    sawtooth: Sawtooth,
+
+    // Frequency control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // .... .ABH
+    //       |||
+    //       ||+- Halt all channels
+    //       |+-- 16x frequency (4 octaves up)
+    //       +--- 256x frequency (8 octaves up)
+    frequency_control: u8,
+}
+
+snapshot!(Vrc6Audio, pulse_1, pulse_2, sawtooth, frequency_control);
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio {
+            pulse_1: Pulse::new(),
+            pulse_2: Pulse::new(),
+            sawtooth: Sawtooth::new(),
+            frequency_control: 0,
+        }
+    }
+
+    /// Writes an audio register, where the address is one of $9000-$9003,
+    /// $A000-$A002 or $B000-$B002 after normalising the register select lines.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9000 => self.pulse_1.control = data,
+            0x9001 => self.pulse_1.write_period_lo(data),
+            0x9002 => self.pulse_1.write_period_hi(data),
+            0x9003 => self.frequency_control = data & 0x7,
+            0xA000 => self.pulse_2.control = data,
+            0xA001 => self.pulse_2.write_period_lo(data),
+            0xA002 => self.pulse_2.write_period_hi(data),
+            0xB000 => self.sawtooth.write_rate(data),
+            0xB001 => self.sawtooth.write_period_lo(data),
+            0xB002 => self.sawtooth.write_period_hi(data),
+            _ => {}
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn clock(&mut self) {
+        let shift = match self.frequency_control {
+            f if f & 0x1 != 0 => return,
+            f if f & 0x4 != 0 => 8,
+            f if f & 0x2 != 0 => 4,
+            _ => 0,
+        };
+
+        self.pulse_1.clock(shift);
+        self.pulse_2.clock(shift);
+        self.sawtooth.clock(shift);
+    }
+
+    fn output(&self) -> f32 {
+        let output = self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output();
+        output as f32 / MAX_OUTPUT
+    }
+
+    /// The VRC6 at full volume is about 60% as loud as the APU, which puts
+    /// its pulse channels on par with the APU pulse channels.
+    fn volume(&self) -> f32 {
+        0.6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse() {
+        let mut audio = Vrc6Audio::new();
+
+        // 50% duty at full volume, with a period of 1 CPU cycle per step.
+        audio.write(0x9000, 0x7F);
+        audio.write(0x9001, 0x00);
+        audio.write(0x9002, 0x80);
+
+        let outputs: Vec<f32> = (0..16)
+            .map(|_| {
+                audio.clock();
+                audio.output()
+            })
+            .collect();
+
+        let high = outputs.iter().filter(|&&o| o > 0.0).count();
+        assert_eq!(high, 8);
+        assert_eq!(outputs[14], 15.0 / MAX_OUTPUT);
+    }
+
+    #[test]
+    fn test_pulse_mode() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x85);
+        audio.write(0x9002, 0x80);
+
+        for _ in 0..32 {
+            audio.clock();
+            assert_eq!(audio.output(), 5.0 / MAX_OUTPUT);
+        }
+
+        // Disabling the channel silences it.
+        audio.write(0x9002, 0x00);
+        assert_eq!(audio.output(), 0.0);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 0x2A);
+        audio.write(0xB002, 0x80);
+
+        let mut outputs = vec![];
+        for _ in 0..14 {
+            audio.clock();
+            outputs.push(audio.sawtooth.output());
+        }
+
+        assert_eq!(
+            outputs,
+            [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]
+        );
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 0x2A);
+        audio.write(0xB002, 0x80);
+        audio.write(0x9003, 0x1);
+
+        for _ in 0..14 {
+            audio.clock();
+        }
+        assert_eq!(audio.output(), 0.0);
+    }
+}
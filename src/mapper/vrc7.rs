@@ -0,0 +1,248 @@
+mod audio;
+
+use self::audio::Vrc7Audio;
+use super::vrc_irq::VrcIrq;
+use super::Mapper;
+use crate::state::snapshot;
+use crate::{apu::ExpansionAudio, cartridge::Mirroring, rom::Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Konami's VRC7 mapper, assigned to iNES mapper 85.
+///
+/// It provides three switchable 8 KB PRG ROM banks, eight 1 KB CHR banks, the
+/// VRC IRQ counter and, on the VRC7a board used by Lagrange Point, FM
+/// synthesis expansion audio. The VRC7a selects registers with A4 and the
+/// VRC7b with A3, so both lines are combined.
+///
+/// See: https://www.nesdev.org/wiki/VRC7
+pub struct Vrc7 {
+    rom: Rom,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+
+    // Control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // RS.. ..MM
+    // ||     ||
+    // ||     ++- Mirroring (0: vertical; 1: horizontal;
+    // ||                    2: one-screen, lower bank; 3: one-screen, upper bank)
+    // |+-------- Silence expansion sound
+    // +--------- PRG RAM enable
+    control: u8,
+
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+    ram: Vec<u8>,
+}
+
+snapshot!(Vrc7, rom, prg_banks, chr_banks, control, irq, audio, ram);
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Self {
+        Vrc7 {
+            rom,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc7Audio::new(),
+            ram: vec![0; 0x2000],
+        }
+    }
+
+    /// Returns true if the PRG RAM is enabled.
+    fn ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    /// Returns the index into CHR memory of the given PPU address.
+    fn chr_index(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        let banks = self.rom.chr.len() / CHR_BANK_SIZE;
+
+        bank % banks * CHR_BANK_SIZE + (addr & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc7 {
+    /// Returns a byte from the CPU address space at the given address.
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let banks = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF if self.ram_enabled() => {
+                return self.ram[(addr & 0x1FFF) as usize];
+            }
+
+            // 3x 8 KB switchable PRG ROM banks.
+            0x8000..=0xDFFF => self.prg_banks[((addr - 0x8000) / 0x2000) as usize] as usize,
+
+            // 8 KB PRG ROM bank, fixed to the last bank.
+            0xE000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+
+        self.rom.prg[bank % banks * PRG_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+
+    /// Writes a byte to the CPU address space at the given address.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let high = addr & 0x18 != 0;
+
+        match (addr & 0xF000, high) {
+            (0x6000 | 0x7000, _) if self.ram_enabled() => {
+                self.ram[(addr & 0x1FFF) as usize] = data;
+            }
+
+            (0x8000, false) => self.prg_banks[0] = data & 0x3F,
+            (0x8000, true) => self.prg_banks[1] = data & 0x3F,
+            (0x9000, false) => self.prg_banks[2] = data & 0x3F,
+
+            // Audio register select and data.
+            (0x9000, true) => match addr & 0x30 {
+                0x10 => self.audio.write_address(data),
+                0x30 => self.audio.write_data(data),
+                _ => {}
+            },
+
+            // CHR bank registers, two banks per 4 KB range from $A000.
+            (0xA000..=0xD000, _) => {
+                let bank = ((addr & 0xF000) - 0xA000) as usize / 0x800 + high as usize;
+                self.chr_banks[bank] = data;
+            }
+
+            (0xE000, false) => {
+                self.control = data;
+                self.audio.set_silenced(data & 0x40 != 0);
+            }
+            (0xE000, true) => self.irq.write_latch(data),
+            (0xF000, false) => self.irq.write_control(data),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    /// Returns true for PRG ROM, and PRG RAM while it is enabled.
+    fn drives_cpu_bus(&self, addr: u16) -> bool {
+        addr >= 0x8000 || self.ram_enabled() && addr >= 0x6000
+    }
+
+    /// Returns a byte from the PPU pattern tables at the given address.
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_index(addr)]
+    }
+
+    /// Writes a byte to the PPU pattern tables at the given address.
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.rom.has_chr_ram() {
+            let index = self.chr_index(addr);
+            self.rom.chr[index] = data;
+        }
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLo,
+            _ => Mirroring::SingleScreenHi,
+        }
+    }
+
+    /// Returns true if the IRQ counter has raised an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq.interrupt()
+    }
+
+    /// Clocks the IRQ counter.
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    /// Returns the VRC7 FM synthesizer.
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        Some(&mut self.audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{numbered_banks, test_rom};
+
+    /// Returns a VRC7 with CHR RAM, where the first byte of each 8 KB PRG bank
+    /// holds its number.
+    fn test_vrc7() -> Vrc7 {
+        let prg = numbered_banks(16, PRG_BANK_SIZE);
+
+        let rom = test_rom(8, prg, 0, vec![], None, None, None).unwrap();
+        Vrc7::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = test_vrc7();
+        mapper.cpu_write(0x8000, 3);
+        mapper.cpu_write(0x8010, 4);
+        mapper.cpu_write(0x9000, 5);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        // The VRC7b selects the register with A3.
+        mapper.cpu_write(0x8008, 6);
+        assert_eq!(mapper.cpu_read(0xA000), 6);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = test_vrc7();
+        mapper.cpu_write(0xA010, 10);
+        mapper.cpu_write(0xB000, 2);
+        mapper.cpu_write(0xD000, 5);
+
+        // CHR RAM bank numbers wrap around the 8 KB of CHR RAM.
+        mapper.ppu_write(0x0400, 0x42);
+        assert_eq!(mapper.ppu_read(0x0800), 0x42);
+        assert_eq!(mapper.chr_banks[6], 5);
+    }
+
+    #[test]
+    fn test_control() {
+        let mut mapper = test_vrc7();
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        mapper.cpu_write(0xE000, 0x83);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenHi);
+
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = test_vrc7();
+        mapper.cpu_write(0xE010, 0xFF);
+        mapper.cpu_write(0xF000, 0x6);
+
+        mapper.clock();
+        assert!(mapper.irq_pending());
+
+        mapper.cpu_write(0xF010, 0);
+        assert!(!mapper.irq_pending());
+    }
+}
@@ -0,0 +1,572 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::apu::ExpansionAudio;
+use crate::state::{snapshot, snapshot_enum};
+
+/// Number of CPU cycles per sample of the FM synthesizer, which runs at
+/// 3.58 MHz / 72 (~49.7 kHz).
+const CYCLES_PER_SAMPLE: u8 = 36;
+
+/// Number of bits in the phase counter of each operator.
+const PHASE_BITS: u32 = 19;
+const PHASE_MASK: u32 = (1 << PHASE_BITS) - 1;
+
+/// Maximum attenuation of the envelope generator, in 0.375 dB steps.
+const ENVELOPE_MAX: f32 = 127.0;
+
+/// Output level of a single channel at full volume, relative to the APU.
+const CHANNEL_LEVEL: f32 = 0.15;
+
+/// Frequency multipliers, doubled so that the 1/2 multiplier is an integer.
+const MULTIPLIER_TABLE: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level attenuation in dB for the top 4 bits of the frequency, at
+/// the highest block.
+const KSL_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+/// Tremolo (AM) depth in dB and rate in Hz.
+const TREMOLO_DEPTH: f32 = 4.8;
+const TREMOLO_RATE: f32 = 3.7;
+
+/// Vibrato (FM) depth as a fraction of the frequency and rate in Hz.
+const VIBRATO_DEPTH: f32 = 0.004;
+const VIBRATO_RATE: f32 = 6.4;
+
+/// Sample rate of the FM synthesizer.
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+
+/// The built-in instrument patches of the VRC7. Patch 0 is the custom
+/// instrument, which is programmed through registers $00-$07.
+///
+/// See: https://www.nesdev.org/wiki/VRC7_audio#Instruments
+const PATCHES: [[u8; 8]; 16] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// Index of the two operators of a channel.
+const MODULATOR: usize = 0;
+const CARRIER: usize = 1;
+
+/// Stage of an operator's envelope generator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+snapshot_enum!(
+    EnvelopeStage,
+    [
+        EnvelopeStage::Attack,
+        EnvelopeStage::Decay,
+        EnvelopeStage::Sustain,
+        EnvelopeStage::Release,
+        EnvelopeStage::Off
+    ]
+);
+
+/// Represents one of the two operators (modulator or carrier) of a channel,
+/// which generates a sine wave shaped by its envelope.
+#[derive(Clone, Copy)]
+struct Operator {
+    phase: u32,
+    stage: EnvelopeStage,
+
+    /// Envelope attenuation, in 0.375 dB steps.
+    envelope: f32,
+
+    /// The last two outputs, used for the modulator's feedback.
+    history: [f32; 2],
+}
+
+snapshot!(Operator, phase in 0..=PHASE_MASK, stage, envelope, history);
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            phase: 0,
+            stage: EnvelopeStage::Off,
+            envelope: ENVELOPE_MAX,
+            history: [0.0; 2],
+        }
+    }
+}
+
+/// Represents one of the six FM channels.
+///
+/// Where the channel registers are equal to:
+///
+/// $10-$15: LLLL LLLL
+/// L: Low 8 bits of the frequency
+///
+/// $20-$25: --SK BBBH
+/// S: Sustain (release at a slow fixed rate after key off)
+/// K: Key on
+/// B: Block (octave)
+/// H: High bit of the frequency
+///
+/// $30-$35: IIII VVVV
+/// I: Instrument patch
+/// V: Volume (attenuation in 3 dB steps)
+#[derive(Clone, Copy)]
+struct Channel {
+    frequency: u16,
+    block: u8,
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    volume: u8,
+    operators: [Operator; 2],
+}
+
+snapshot!(
+    Channel,
+    frequency in 0..0x200,
+    block in 0..8,
+    sustain,
+    key_on,
+    instrument in 0..16,
+    volume in 0..16,
+    operators
+);
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            frequency: 0,
+            block: 0,
+            sustain: false,
+            key_on: false,
+            instrument: 0,
+            volume: 0,
+            operators: [Operator::new(); 2],
+        }
+    }
+
+    /// Sets the key on flag, restarting the envelopes on key on and releasing
+    /// them on key off.
+    fn set_key_on(&mut self, key_on: bool) {
+        match (self.key_on, key_on) {
+            (false, true) => {
+                for op in self.operators.iter_mut() {
+                    op.phase = 0;
+                    op.stage = EnvelopeStage::Attack;
+                }
+            }
+            (true, false) => {
+                for op in self.operators.iter_mut() {
+                    if op.stage != EnvelopeStage::Off {
+                        op.stage = EnvelopeStage::Release;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.key_on = key_on;
+    }
+
+    /// Returns the key scale rate offset of the channel, which shortens the
+    /// envelope for higher notes.
+    fn key_scale_rate(&self, ksr: bool) -> u8 {
+        let ks = (self.block << 1) | (self.frequency >> 8) as u8;
+        match ksr {
+            true => ks,
+            false => ks >> 2,
+        }
+    }
+
+    /// Returns the key scale level attenuation of the channel in dB, for the
+    /// given KSL setting of the operator.
+    fn key_scale_level(&self, ksl: u8) -> f32 {
+        if ksl == 0 {
+            return 0.0;
+        }
+
+        let level = KSL_TABLE[(self.frequency >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        level.max(0.0) / (1 << (3 - ksl)) as f32
+    }
+}
+
+/// Returns the number of envelope steps per sample for the given rate (0-63).
+fn envelope_steps(rate: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+
+    let rate = rate.min(63);
+    (4 + (rate & 0x3)) as f32 / 4.0 / 2f32.powi(14 - (rate >> 2) as i32)
+}
+
+/// The VRC7 expansion audio, a cut-down Yamaha YM2413 (OPLL) with six
+/// two-operator FM channels and 15 built-in instruments.
+///
+/// The operators are synthesised directly in floating point rather than with
+/// the chip's log-sine and exponent tables.
+///
+/// See: https://www.nesdev.org/wiki/VRC7_audio
+pub struct Vrc7Audio {
+    address: u8,
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+
+    silenced: bool,
+    cycles: u8,
+
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+
+    output: f32,
+}
+
+snapshot!(
+    Vrc7Audio,
+    address,
+    custom_patch,
+    channels,
+    silenced,
+    cycles in 0..CYCLES_PER_SAMPLE,
+    tremolo_phase,
+    vibrato_phase,
+    output
+);
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Vrc7Audio {
+            address: 0,
+            custom_patch: [0; 8],
+            channels: [Channel::new(); 6],
+            silenced: false,
+            cycles: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /// Selects the register written by the next data write ($9010).
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    /// Writes to the selected register ($9030).
+    pub fn write_data(&mut self, data: u8) {
+        let channel = (self.address & 0xF) as usize;
+
+        match self.address {
+            0x00..=0x07 => self.custom_patch[self.address as usize] = data,
+            0x10..=0x15 => {
+                let ch = &mut self.channels[channel];
+                ch.frequency = (ch.frequency & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let ch = &mut self.channels[channel];
+                ch.frequency = (ch.frequency & 0xFF) | ((data as u16 & 0x1) << 8);
+                ch.block = (data >> 1) & 0x7;
+                ch.sustain = data & 0x20 != 0;
+                ch.set_key_on(data & 0x10 != 0);
+            }
+            0x30..=0x35 => {
+                let ch = &mut self.channels[channel];
+                ch.instrument = data >> 4;
+                ch.volume = data & 0xF;
+            }
+            _ => {}
+        }
+    }
+
+    /// Silences and resets the channels while set.
+    pub fn set_silenced(&mut self, silenced: bool) {
+        if silenced {
+            self.channels = [Channel::new(); 6];
+            self.output = 0.0;
+        }
+
+        self.silenced = silenced;
+    }
+
+    /// Returns the instrument patch of the given channel.
+    fn patch(&self, channel: &Channel) -> [u8; 8] {
+        match channel.instrument {
+            0 => self.custom_patch,
+            i => PATCHES[i as usize],
+        }
+    }
+
+    /// Generates the next sample of all the channels.
+    fn sample(&mut self) -> f32 {
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_RATE / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / SAMPLE_RATE).fract();
+
+        // Tremolo is a triangle wave between 0 and 1, vibrato a sine wave.
+        let tremolo = 1.0 - (2.0 * self.tremolo_phase - 1.0).abs();
+        let vibrato = (TAU * self.vibrato_phase).sin();
+
+        let mut output = 0.0;
+        for i in 0..self.channels.len() {
+            let mut channel = self.channels[i];
+            let patch = self.patch(&channel);
+
+            let modulation = clock_operator(&mut channel, &patch, MODULATOR, 0.0, tremolo, vibrato);
+            output += clock_operator(&mut channel, &patch, CARRIER, modulation, tremolo, vibrato);
+
+            self.channels[i] = channel;
+        }
+
+        output * CHANNEL_LEVEL
+    }
+}
+
+/// Clocks an operator of the channel by one sample, returning its output.
+///
+/// The carrier's phase is modulated by the modulator's output, and the
+/// modulator by its own previous output (feedback).
+fn clock_operator(
+    channel: &mut Channel,
+    patch: &[u8; 8],
+    index: usize,
+    modulation: f32,
+    tremolo: f32,
+    vibrato: f32,
+) -> f32 {
+    let flags = patch[index];
+    let am = flags & 0x80 != 0;
+    let vib = flags & 0x40 != 0;
+    let sustained = flags & 0x20 != 0;
+    let ksr = flags & 0x10 != 0;
+    let multiplier = MULTIPLIER_TABLE[(flags & 0xF) as usize];
+
+    let ksl = patch[2 + index] >> 6;
+    let rectified = match index {
+        MODULATOR => patch[3] & 0x08 != 0,
+        _ => patch[3] & 0x10 != 0,
+    };
+
+    let attack = patch[4 + index] >> 4;
+    let decay = patch[4 + index] & 0xF;
+    let sustain_level = (patch[6 + index] >> 4) as f32 * 8.0;
+    let release = patch[6 + index] & 0xF;
+
+    // Effective envelope rate, including the key scale rate.
+    let ks = channel.key_scale_rate(ksr);
+    let rate = |r: u8| match r {
+        0 => 0,
+        r => r * 4 + ks,
+    };
+
+    let ksl = channel.key_scale_level(ksl);
+    let op = &mut channel.operators[index];
+
+    // Envelope generator.
+    match op.stage {
+        EnvelopeStage::Attack => {
+            if rate(attack) >= 60 {
+                op.envelope = 0.0;
+            } else {
+                op.envelope -= (op.envelope / 8.0 + 1.0) * envelope_steps(rate(attack));
+            }
+
+            if op.envelope <= 0.0 {
+                op.envelope = 0.0;
+                op.stage = EnvelopeStage::Decay;
+            }
+        }
+        EnvelopeStage::Decay => {
+            op.envelope += envelope_steps(rate(decay));
+            if op.envelope >= sustain_level {
+                op.envelope = sustain_level;
+                op.stage = EnvelopeStage::Sustain;
+            }
+        }
+        EnvelopeStage::Sustain => {
+            // Percussive instruments keep decaying at the release rate.
+            if !sustained {
+                op.envelope += envelope_steps(rate(release));
+            }
+        }
+        EnvelopeStage::Release => {
+            let rate = match channel.sustain {
+                true => 5 * 4 + ks,
+                false => rate(release),
+            };
+            op.envelope += envelope_steps(rate);
+        }
+        EnvelopeStage::Off => {}
+    }
+
+    if op.envelope >= ENVELOPE_MAX {
+        op.envelope = ENVELOPE_MAX;
+        if op.stage == EnvelopeStage::Release {
+            op.stage = EnvelopeStage::Off;
+        }
+    }
+
+    // Phase generator.
+    let increment = (((channel.frequency as u32) << channel.block) * multiplier) >> 1;
+    let increment = match vib {
+        true => increment as f32 * (1.0 + VIBRATO_DEPTH * vibrato),
+        false => increment as f32,
+    };
+    op.phase = (op.phase + increment as u32) & PHASE_MASK;
+
+    // Total attenuation in dB.
+    let level = match index {
+        MODULATOR => (patch[2] & 0x3F) as f32 * 0.75,
+        _ => channel.volume as f32 * 3.0,
+    };
+    let am = match am {
+        true => tremolo * TREMOLO_DEPTH,
+        false => 0.0,
+    };
+    let attenuation = op.envelope * 0.375 + level + ksl + am;
+
+    // A modulator at full output shifts the phase of the carrier by 4π, and
+    // the feedback of the modulator by up to 4π.
+    let offset = match index {
+        MODULATOR => match patch[3] & 0x7 {
+            0 => 0.0,
+            fb => (op.history[0] + op.history[1]) / 2.0 * PI * 2f32.powi(fb as i32 - 5),
+        },
+        _ => modulation * 4.0 * PI,
+    };
+
+    let phase = TAU * op.phase as f32 / (1 << PHASE_BITS) as f32 + offset;
+    let wave = match phase.sin() {
+        w if rectified && w < 0.0 => 0.0,
+        w => w,
+    };
+
+    let output = match op.stage {
+        EnvelopeStage::Off => 0.0,
+        _ => wave * 10f32.powf(-attenuation / 20.0),
+    };
+
+    op.history = [output, op.history[0]];
+    output
+}
+
+impl ExpansionAudio for Vrc7Audio {
+    fn clock(&mut self) {
+        if self.silenced {
+            return;
+        }
+
+        self.cycles += 1;
+        if self.cycles == CYCLES_PER_SAMPLE {
+            self.cycles = 0;
+            self.output = self.sample();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a value to an audio register.
+    fn write(audio: &mut Vrc7Audio, register: u8, data: u8) {
+        audio.write_address(register);
+        audio.write_data(data);
+    }
+
+    /// Clocks the audio for the given number of samples, returning the peak
+    /// output.
+    fn run(audio: &mut Vrc7Audio, samples: usize) -> f32 {
+        let mut peak: f32 = 0.0;
+        for _ in 0..samples * CYCLES_PER_SAMPLE as usize {
+            audio.clock();
+            peak = peak.max(audio.output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn test_channel_registers() {
+        let mut audio = Vrc7Audio::new();
+        write(&mut audio, 0x12, 0xAB);
+        write(&mut audio, 0x22, 0x3B);
+        write(&mut audio, 0x32, 0x45);
+
+        let channel = &audio.channels[2];
+        assert_eq!(channel.frequency, 0x1AB);
+        assert_eq!(channel.block, 5);
+        assert!(channel.key_on);
+        assert!(channel.sustain);
+        assert_eq!(channel.instrument, 4);
+        assert_eq!(channel.volume, 5);
+    }
+
+    #[test]
+    fn test_custom_patch() {
+        let mut audio = Vrc7Audio::new();
+        write(&mut audio, 0x03, 0x17);
+        assert_eq!(audio.patch(&audio.channels[0])[3], 0x17);
+
+        write(&mut audio, 0x30, 0x30);
+        assert_eq!(audio.patch(&audio.channels[0]), PATCHES[3]);
+    }
+
+    #[test]
+    fn test_key_on() {
+        let mut audio = Vrc7Audio::new();
+        write(&mut audio, 0x10, 0x20);
+        write(&mut audio, 0x30, 0x30);
+        assert_eq!(run(&mut audio, 100), 0.0);
+
+        // Keying on plays the note.
+        write(&mut audio, 0x20, 0x18);
+        assert!(run(&mut audio, 500) > 0.01);
+
+        // Keying off releases the note until it is silent.
+        write(&mut audio, 0x20, 0x28);
+        run(&mut audio, 100_000);
+        let op = audio.channels[0].operators[CARRIER];
+        assert_eq!(op.stage, EnvelopeStage::Off);
+        assert_eq!(run(&mut audio, 100), 0.0);
+    }
+
+    #[test]
+    fn test_silenced() {
+        let mut audio = Vrc7Audio::new();
+        write(&mut audio, 0x10, 0x20);
+        write(&mut audio, 0x30, 0x30);
+        write(&mut audio, 0x20, 0x18);
+
+        audio.set_silenced(true);
+        assert_eq!(run(&mut audio, 100), 0.0);
+        assert!(!audio.channels[0].key_on);
+    }
+
+    #[test]
+    fn test_envelope_steps() {
+        assert_eq!(envelope_steps(0), 0.0);
+        assert_eq!(envelope_steps(60), 2.0);
+        assert_eq!(envelope_steps(4), 1.0 / 8192.0);
+    }
+}
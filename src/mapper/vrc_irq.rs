@@ -0,0 +1,177 @@
+use crate::state::snapshot;
+
+/// Number of PPU cycles in a scanline, which the prescaler counts down in
+/// steps of 3 (one CPU cycle).
+const PRESCALER_PERIOD: i16 = 341;
+
+/// The IRQ counter shared by Konami's VRC4, VRC6 and VRC7 mappers.
+///
+/// An 8 bit counter is incremented either every scanline (approximated by a
+/// prescaler clocked by the CPU) or every CPU cycle, reloading from the latch
+/// and raising an IRQ when it overflows.
+///
+/// See: https://www.nesdev.org/wiki/VRC_IRQ
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+
+    // IRQ control register.
+    //
+    // 7  bit  0
+    // ---- ----
+    // xxxx xMEA
+    //       |||
+    //       ||+- IRQ enable after acknowledgement
+    //       |+-- IRQ enable
+    //       +--- IRQ mode (0: scanline mode; 1: CPU cycle mode)
+    control: u8,
+    interrupt: bool,
+}
+
+snapshot!(
+    VrcIrq,
+    latch,
+    counter,
+    prescaler in -PRESCALER_PERIOD..=PRESCALER_PERIOD,
+    control,
+    interrupt
+);
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            control: 0,
+            interrupt: false,
+        }
+    }
+
+    /// Writes the low 4 bits of the latch.
+    pub fn write_latch_lo(&mut self, data: u8) {
+        self.latch = (self.latch & 0xF0) | (data & 0x0F);
+    }
+
+    /// Writes the high 4 bits of the latch.
+    pub fn write_latch_hi(&mut self, data: u8) {
+        self.latch = (self.latch & 0x0F) | (data << 4);
+    }
+
+    /// Writes the whole latch, for mappers with an 8 bit latch register.
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    /// Writes the control register, acknowledging any pending interrupt and
+    /// reloading the counter if the IRQ is enabled.
+    pub fn write_control(&mut self, data: u8) {
+        self.control = data & 0x7;
+        self.interrupt = false;
+
+        if self.enabled() {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    /// Acknowledges any pending interrupt, copying the "enable after
+    /// acknowledgement" bit into the enable bit.
+    pub fn acknowledge(&mut self) {
+        self.interrupt = false;
+
+        let enable = (self.control & 0x1) << 1;
+        self.control = (self.control & !0x2) | enable;
+    }
+
+    /// Returns true if the IRQ is enabled.
+    fn enabled(&self) -> bool {
+        self.control & 0x2 != 0
+    }
+
+    /// Clocks the IRQ by one CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+
+        // CPU cycle mode clocks the counter every cycle, scanline mode only
+        // when the prescaler expires.
+        if self.control & 0x4 != 0 {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    /// Increments the counter, reloading it and raising an IRQ on overflow.
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.interrupt = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    /// Returns true if the IRQ has been raised.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch_lo(0xE);
+        irq.write_latch_hi(0xF);
+        irq.write_control(0x7);
+
+        irq.clock();
+        assert!(!irq.interrupt());
+        irq.clock();
+        assert!(irq.interrupt());
+        assert_eq!(irq.counter, 0xFE);
+    }
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.latch = 0xFF;
+        irq.write_control(0x2);
+
+        // The first scanline takes 114 CPU cycles (341 / 3 rounded up).
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.interrupt());
+        irq.clock();
+        assert!(irq.interrupt());
+    }
+
+    #[test]
+    fn test_acknowledge() {
+        let mut irq = VrcIrq::new();
+        irq.latch = 0xFF;
+        irq.write_control(0x7);
+        irq.clock();
+        assert!(irq.interrupt());
+
+        irq.acknowledge();
+        assert!(!irq.interrupt());
+        assert!(irq.enabled());
+
+        irq.write_control(0x6);
+        irq.acknowledge();
+        assert!(!irq.enabled());
+    }
+}
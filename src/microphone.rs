@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+/// Level of the captured audio, as the root mean square of the samples,
+/// above which the microphone hears sound.
+const SOUND_THRESHOLD: f32 = 0.05;
+
+/// Captures audio from the host's default recording device, so the player
+/// can speak or blow into the microphone on Famicom controller 2.
+pub struct Microphone {
+    // Capture stops when the device is dropped.
+    _device: AudioDevice<LevelMeter>,
+    hears_sound: Arc<AtomicBool>,
+}
+
+impl Microphone {
+    /// Opens the default recording device and starts capturing.
+    pub fn open(audio: &AudioSubsystem) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+
+        let hears_sound = Arc::new(AtomicBool::new(false));
+        let meter = LevelMeter {
+            hears_sound: Arc::clone(&hears_sound),
+        };
+        let device = audio.open_capture(None, &spec, |_| meter)?;
+        device.resume();
+
+        Ok(Microphone {
+            _device: device,
+            hears_sound,
+        })
+    }
+
+    /// Returns true if the last audio captured was loud enough to be heard.
+    pub fn hears_sound(&self) -> bool {
+        self.hears_sound.load(Ordering::Relaxed)
+    }
+}
+
+/// Measures the level of each buffer of captured audio.
+struct LevelMeter {
+    hears_sound: Arc<AtomicBool>,
+}
+
+impl AudioCallback for LevelMeter {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        let heard = level(samples) > SOUND_THRESHOLD;
+        self.hears_sound.store(heard, Ordering::Relaxed);
+    }
+}
+
+/// Returns the root mean square of the samples.
+fn level(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let sum: f32 = samples.iter().map(|sample| sample * sample).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(&[]), 0.0);
+        assert_eq!(level(&[0.0; 64]), 0.0);
+        assert_eq!(level(&[0.5, -0.5, 0.5, -0.5]), 0.5);
+        assert!(level(&[0.01, -0.02, 0.01]) < SOUND_THRESHOLD);
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+
+use crate::joypad::{
+    JOYPAD_BUTTON_A, JOYPAD_BUTTON_B, JOYPAD_DOWN, JOYPAD_LEFT, JOYPAD_RIGHT, JOYPAD_SELECT,
+    JOYPAD_START, JOYPAD_UP,
+};
+
+/// Tag at the start of an input movie file.
+const MOVIE_TAG: [u8; 4] = *b"RMV\x1A";
+
+/// The buttons of a controller by their names in input scripts.
+const BUTTON_NAMES: [(&str, u8); 8] = [
+    ("Up", JOYPAD_UP),
+    ("Down", JOYPAD_DOWN),
+    ("Left", JOYPAD_LEFT),
+    ("Right", JOYPAD_RIGHT),
+    ("Select", JOYPAD_SELECT),
+    ("Start", JOYPAD_START),
+    ("A", JOYPAD_BUTTON_A),
+    ("B", JOYPAD_BUTTON_B),
+];
+
+/// Represents an error reading an input movie.
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    /// The data doesn't start with the movie tag.
+    InvalidTag,
+
+    /// The data ends part way through a frame.
+    Truncated,
+
+    /// A line of an input script can't be read.
+    InvalidScript { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::InvalidTag => write!(f, "not an input movie"),
+            MovieError::Truncated => write!(f, "input movie is truncated"),
+            MovieError::InvalidScript { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// Whether a movie is being recorded or played back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MovieState {
+    Stopped,
+    Recording,
+
+    /// Playing back, with the index of the next frame.
+    Playing(usize),
+}
+
+/// An input movie, the states of both controllers in each frame, which
+/// replays a run exactly when played back from power on with the same
+/// options (such as the RAM pattern and its seed).
+///
+/// Only the standard controllers are recorded.
+///
+/// Movies are stored as the tag "RMV" followed by $1A, then the states of
+/// controllers 1 and 2 for each frame, with a bit set for each button
+/// pressed.
+pub struct InputMovie {
+    frames: Vec<[u8; 2]>,
+    state: MovieState,
+}
+
+impl InputMovie {
+    /// Returns an empty, stopped movie.
+    pub fn new() -> Self {
+        InputMovie {
+            frames: vec![],
+            state: MovieState::Stopped,
+        }
+    }
+
+    /// Returns the stopped movie stored in the given bytes.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, MovieError> {
+        let frames = raw.strip_prefix(&MOVIE_TAG).ok_or(MovieError::InvalidTag)?;
+        if frames.len() % 2 != 0 {
+            return Err(MovieError::Truncated);
+        }
+
+        Ok(InputMovie {
+            frames: frames.chunks(2).map(|frame| [frame[0], frame[1]]).collect(),
+            state: MovieState::Stopped,
+        })
+    }
+
+    /// Returns the stopped movie written as the given input script.
+    ///
+    /// Input scripts are text, with a line for each change of input giving
+    /// the frame it starts on, then the buttons held on controller 1 and,
+    /// optionally, controller 2. Buttons are joined by '+', or given as '-'
+    /// for none, and are held until the next line. The movie ends after the
+    /// frame of the last line. Blank lines and lines starting with '#' are
+    /// ignored. For example:
+    ///
+    /// ```text
+    /// # Press Start, then run right and jump.
+    /// 60 Start
+    /// 62 -
+    /// 120 Right+B
+    /// 150 Right+B+A
+    /// 160 -
+    /// ```
+    pub fn from_script(script: &str) -> Result<Self, MovieError> {
+        let mut frames: Vec<[u8; 2]> = vec![];
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason| MovieError::InvalidScript {
+                line: index + 1,
+                reason,
+            };
+
+            let mut fields = line.split_whitespace();
+            let frame: usize = fields
+                .next()
+                .and_then(|frame| frame.parse().ok())
+                .ok_or_else(|| invalid("expected a frame number"))?;
+            if frame < frames.len() {
+                return Err(invalid("frame is not after the previous line's"));
+            }
+
+            let mut states = [0; 2];
+            for (state, buttons) in states.iter_mut().zip(fields.by_ref()) {
+                *state = parse_buttons(buttons).ok_or_else(|| invalid("unknown button"))?;
+            }
+            if fields.next().is_some() {
+                return Err(invalid("expected at most two controllers"));
+            }
+
+            // The previous line's buttons are held until this frame.
+            let held = frames.last().copied().unwrap_or_default();
+            frames.resize(frame, held);
+            frames.push(states);
+        }
+
+        Ok(InputMovie {
+            frames,
+            state: MovieState::Stopped,
+        })
+    }
+
+    /// Returns the bytes storing the movie.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        MOVIE_TAG
+            .iter()
+            .chain(self.frames.iter().flatten())
+            .copied()
+            .collect()
+    }
+
+    /// Returns the number of frames in the movie.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Clears the movie and starts recording.
+    pub fn start_recording(&mut self) {
+        self.frames.clear();
+        self.state = MovieState::Recording;
+    }
+
+    /// Starts playing back the movie from the first frame.
+    pub fn start_playback(&mut self) {
+        self.state = MovieState::Playing(0);
+    }
+
+    /// Stops recording or playing back.
+    pub fn stop(&mut self) {
+        self.state = MovieState::Stopped;
+    }
+
+    /// Returns true if the movie is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.state == MovieState::Recording
+    }
+
+    /// Returns true if the movie is being played back.
+    pub fn is_playing(&self) -> bool {
+        matches!(self.state, MovieState::Playing(_))
+    }
+
+    /// Records the controller states of a frame, if recording.
+    pub fn record(&mut self, states: [u8; 2]) {
+        if self.is_recording() {
+            self.frames.push(states);
+        }
+    }
+
+    /// Returns the controller states of the next frame, if playing back.
+    /// Playback stops after the last frame.
+    pub fn next_frame(&mut self) -> Option<[u8; 2]> {
+        let MovieState::Playing(frame) = self.state else {
+            return None;
+        };
+
+        match self.frames.get(frame) {
+            Some(&states) => {
+                self.state = MovieState::Playing(frame + 1);
+                Some(states)
+            }
+            None => {
+                self.stop();
+                None
+            }
+        }
+    }
+}
+
+/// Returns the state of a controller holding the buttons of an input script,
+/// or none if a button is unknown.
+fn parse_buttons(buttons: &str) -> Option<u8> {
+    if buttons == "-" {
+        return Some(0);
+    }
+
+    buttons.split('+').try_fold(0, |state, button| {
+        BUTTON_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(button))
+            .map(|(_, bit)| state | bit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_playback() {
+        let mut movie = InputMovie::new();
+        movie.record([1, 2]);
+        assert_eq!(movie.len(), 0);
+
+        movie.start_recording();
+        movie.record([1, 2]);
+        movie.record([3, 4]);
+        movie.stop();
+        movie.record([5, 6]);
+        assert_eq!(movie.len(), 2);
+
+        assert_eq!(movie.next_frame(), None);
+        movie.start_playback();
+        assert_eq!(movie.next_frame(), Some([1, 2]));
+        assert_eq!(movie.next_frame(), Some([3, 4]));
+        assert!(movie.is_playing());
+        assert_eq!(movie.next_frame(), None);
+        assert!(!movie.is_playing());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut movie = InputMovie::new();
+        movie.start_recording();
+        movie.record([0x81, 0x00]);
+        movie.record([0x00, 0x10]);
+
+        let raw = movie.to_bytes();
+        assert_eq!(raw, b"RMV\x1A\x81\x00\x00\x10");
+        assert_eq!(InputMovie::from_bytes(&raw).unwrap().frames, movie.frames);
+
+        assert_eq!(
+            InputMovie::from_bytes(b"NES\x1A").err(),
+            Some(MovieError::InvalidTag)
+        );
+        assert_eq!(
+            InputMovie::from_bytes(b"RMV\x1A\x00").err(),
+            Some(MovieError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_script() {
+        let script = "# Start, then right and jump\n\n2 Start\n3 -\n5 right+A B\n6 -\n";
+        let movie = InputMovie::from_script(script).unwrap();
+        assert_eq!(
+            movie.frames,
+            [
+                [0, 0],
+                [0, 0],
+                [JOYPAD_START, 0],
+                [0, 0],
+                [0, 0],
+                [JOYPAD_RIGHT | JOYPAD_BUTTON_A, JOYPAD_BUTTON_B],
+                [0, 0],
+            ]
+        );
+
+        let error = |line, reason| Some(MovieError::InvalidScript { line, reason });
+        assert_eq!(
+            InputMovie::from_script("Start").err(),
+            error(1, "expected a frame number")
+        );
+        assert_eq!(
+            InputMovie::from_script("2 A\n2 B").err(),
+            error(2, "frame is not after the previous line's")
+        );
+        assert_eq!(
+            InputMovie::from_script("\n2 A+C").err(),
+            error(2, "unknown button")
+        );
+        assert_eq!(
+            InputMovie::from_script("2 A B A").err(),
+            error(1, "expected at most two controllers")
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::time::Duration;
+
+use crate::bus::SystemBus;
+use crate::cpu::{Bus, Cpu, Memory};
+use crate::region::Region;
+
+pub const NSF_TAG: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A];
+const HEADER_SIZE: usize = 0x80;
+
+/// Bank switching registers, one for each 4KB bank from $8000-$FFFF.
+const BANK_REGISTERS: u16 = 0x5FF8;
+
+/// Play speed of the PAL frame rate, for NSFs which leave the PAL speed out
+/// as they're only for NTSC consoles.
+const PAL_PLAY_SPEED: u16 = 19997;
+
+/// Represents an NSF (NES Sound Format) file, which contains the music code
+/// and data ripped from a game.
+///
+/// 0x00    5   "NESM" followed by MS-DOS end-of-file
+/// 0x05    1   Version number
+/// 0x06    1   Total songs
+/// 0x07    1   Starting song (1 based)
+/// 0x08    2   Load address of data ($8000-FFFF)
+/// 0x0A    2   Init address of data ($8000-FFFF)
+/// 0x0C    2   Play address of data ($8000-FFFF)
+/// 0x0E    32  Song name
+/// 0x2E    32  Artist
+/// 0x4E    32  Copyright holder
+/// 0x6E    2   Play speed, in 1/1000000th sec ticks, NTSC
+/// 0x70    8   Bankswitch init values
+/// 0x78    2   Play speed, in 1/1000000th sec ticks, PAL
+/// 0x7A    1   PAL/NTSC bits
+/// 0x7B    1   Extra sound chip support
+/// 0x7C    4   Reserved
+/// 0x80    ... Music program/data
+///
+/// See: https://www.nesdev.org/wiki/NSF
+pub struct Nsf {
+    pub total_songs: u8,
+    pub starting_song: u8,
+
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+
+    /// Time between calls to the play routine on NTSC and PAL consoles, in
+    /// microseconds.
+    pub play_speed: u16,
+    pub pal_play_speed: u16,
+
+    /// The region the NSF is for. NSFs for either region play as NTSC.
+    pub region: Region,
+
+    /// Initial values of the bank switching registers. If all are zero the
+    /// NSF does not use bank switching.
+    pub banks: [u8; 8],
+
+    pub data: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn new(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.len() < HEADER_SIZE || raw[0..5] != NSF_TAG {
+            return Err("File is not in NSF file format".to_string());
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&raw[0x70..0x78]);
+
+        Ok(Nsf {
+            total_songs: raw[0x06],
+            starting_song: raw[0x07],
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            name: read_string(&raw[0x0E..0x2E]),
+            artist: read_string(&raw[0x2E..0x4E]),
+            copyright: read_string(&raw[0x4E..0x6E]),
+            play_speed: word(0x6E),
+            pal_play_speed: word(0x78),
+            region: match raw[0x7A] & 0x3 {
+                0x1 => Region::Pal,
+                _ => Region::Ntsc,
+            },
+            banks,
+            data: raw[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    /// Returns true if the NSF uses bank switching.
+    pub fn bank_switched(&self) -> bool {
+        self.banks.iter().any(|bank| *bank != 0)
+    }
+}
+
+/// Reads a null terminated string from a fixed size header field.
+fn read_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+/// Plays the songs of an NSF file on the CPU and APU.
+///
+/// The NSF init routine is called to set up a song, then the play routine is
+/// called at the rate given in the header. The PPU is not used.
+///
+/// See: https://www.nesdev.org/wiki/NSF#Initializing_a_tune
+pub struct NsfPlayer {
+    nsf: Nsf,
+    song: u8,
+    region: Region,
+}
+
+impl NsfPlayer {
+    /// Returns a new player for the given NSF, starting at its first song in
+    /// the NSF's region.
+    pub fn new(nsf: Nsf) -> Self {
+        let song = nsf.starting_song.clamp(1, nsf.total_songs.max(1));
+        let region = nsf.region;
+        NsfPlayer { nsf, song, region }
+    }
+
+    /// Sets the region of the console the NSF plays on, which sets the speed
+    /// of the play routine.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Returns the NSF being played.
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    /// Returns the current song number (1 based).
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Returns the time between calls to the play routine.
+    pub fn play_period(&self) -> Duration {
+        Duration::from_micros(self.play_speed() as u64)
+    }
+
+    /// Returns the time between calls to the play routine in the region, in
+    /// microseconds.
+    fn play_speed(&self) -> u16 {
+        match (self.region, self.nsf.pal_play_speed) {
+            (Region::Ntsc, _) => self.nsf.play_speed,
+            (Region::Pal | Region::Dendy, 0) => PAL_PLAY_SPEED,
+            (Region::Pal | Region::Dendy, speed) => speed,
+        }
+    }
+
+    /// Initialises the given song (1 based), returning true if the CPU
+    /// should be shut down.
+    pub fn init(&mut self, cpu: &mut Cpu<SystemBus>, song: u8) -> bool {
+        self.song = song.clamp(1, self.nsf.total_songs.max(1));
+
+        // Clear RAM and the APU registers.
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            cpu.bus.mem_write_byte(addr, 0);
+        }
+        for addr in 0x4000..0x4014 {
+            cpu.bus.mem_write_byte(addr, 0);
+        }
+        cpu.bus.mem_write_byte(0x4015, 0x00);
+        cpu.bus.mem_write_byte(0x4015, 0x0F);
+        cpu.bus.mem_write_byte(0x4017, 0x40);
+
+        if self.nsf.bank_switched() {
+            for (i, bank) in self.nsf.banks.iter().enumerate() {
+                cpu.bus.mem_write_byte(BANK_REGISTERS + i as u16, *bank);
+            }
+        }
+
+        // The init routine takes the song number (0 based) in A and the
+        // region in X (0: NTSC, 1: PAL).
+        cpu.reset();
+        cpu.a = self.song - 1;
+        cpu.x = match self.region {
+            Region::Ntsc => 0,
+            Region::Pal | Region::Dendy => 1,
+        };
+
+        cpu.call_subroutine(self.nsf.init_addr)
+    }
+
+    /// Moves to the next or previous song, wrapping around at either end,
+    /// returning true if the CPU should be shut down.
+    pub fn skip(&mut self, cpu: &mut Cpu<SystemBus>, forward: bool) -> bool {
+        let total = self.nsf.total_songs.max(1);
+        let song = match forward {
+            true => self.song % total + 1,
+            false => (self.song + total - 2) % total + 1,
+        };
+
+        self.init(cpu, song)
+    }
+
+    /// Calls the play routine, then idles the CPU until the next call is due,
+    /// returning true if the CPU should be shut down.
+    pub fn play(&mut self, cpu: &mut Cpu<SystemBus>) -> bool {
+        let clock_rate = self.region.cpu_clock_rate();
+        let period = (clock_rate * self.play_speed() as f64 / 1_000_000.0) as u64;
+        let next_play = cpu.bus.cpu_cycles() + period;
+
+        if cpu.call_subroutine(self.nsf.play_addr) {
+            return true;
+        }
+
+        while cpu.bus.cpu_cycles() < next_play {
+            cpu.bus.tick(1);
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    /// Returns a raw NSF file with the given program loaded at $8000.
+    fn test_nsf(songs: u8, init: u16, play: u16, program: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0; HEADER_SIZE];
+        raw[0..5].copy_from_slice(&NSF_TAG);
+        raw[0x05] = 1;
+        raw[0x06] = songs;
+        raw[0x07] = 1;
+        raw[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0A..0x0C].copy_from_slice(&init.to_le_bytes());
+        raw[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+        raw[0x0E..0x13].copy_from_slice(b"Title");
+        raw[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        raw.extend_from_slice(program);
+
+        raw
+    }
+
+    #[test]
+    fn test_new() {
+        let nsf = Nsf::new(&test_nsf(3, 0x8000, 0x8004, &[0x60])).unwrap();
+        assert_eq!(nsf.total_songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.load_addr, 0x8000);
+        assert_eq!(nsf.init_addr, 0x8000);
+        assert_eq!(nsf.play_addr, 0x8004);
+        assert_eq!(nsf.name, "Title");
+        assert_eq!(nsf.artist, "");
+        assert_eq!(nsf.play_speed, 16639);
+        assert_eq!(nsf.pal_play_speed, 0);
+        assert_eq!(nsf.region, Region::Ntsc);
+        assert!(!nsf.bank_switched());
+        assert_eq!(nsf.data, vec![0x60]);
+    }
+
+    #[test]
+    fn test_region() {
+        let mut raw = test_nsf(1, 0x8000, 0x8004, &[0x60]);
+        for (flags, region) in [(0, Region::Ntsc), (1, Region::Pal), (3, Region::Ntsc)] {
+            raw[0x7A] = flags;
+            assert_eq!(Nsf::new(&raw).unwrap().region, region, "flags {}", flags);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        match Nsf::new(&[0; HEADER_SIZE]) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => assert_eq!(err, "File is not in NSF file format"),
+        }
+    }
+
+    #[test]
+    fn test_player() {
+        // init: STA $00; RTS
+        // play: INC $01; RTS
+        let program = [0x85, 0x00, 0x60, 0x00, 0xE6, 0x01, 0x60];
+        let nsf = Nsf::new(&test_nsf(3, 0x8000, 0x8004, &program)).unwrap();
+
+        let cart = Cartridge::from_nsf(&nsf);
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let mut cpu = Cpu::new(bus);
+
+        let mut player = NsfPlayer::new(nsf);
+        assert!(!player.init(&mut cpu, 2));
+        assert_eq!(cpu.bus.mem_read_byte(0x00), 1);
+
+        let start = cpu.bus.cpu_cycles();
+        assert!(!player.play(&mut cpu));
+        assert!(!player.play(&mut cpu));
+        assert_eq!(cpu.bus.mem_read_byte(0x01), 2);
+        assert!(cpu.bus.cpu_cycles() - start >= 2 * 29780);
+
+        assert!(!player.skip(&mut cpu, true));
+        assert_eq!(player.song(), 3);
+        assert!(!player.skip(&mut cpu, true));
+        assert_eq!(player.song(), 1);
+        assert!(!player.skip(&mut cpu, false));
+        assert_eq!(player.song(), 3);
+        assert_eq!(cpu.bus.mem_read_byte(0x00), 2);
+        assert_eq!(cpu.bus.mem_read_byte(0x01), 0);
+    }
+
+    #[test]
+    fn test_pal_player() {
+        // init: STX $00; RTS
+        let program = [0x86, 0x00, 0x60, 0x00, 0x60];
+        let mut raw = test_nsf(1, 0x8000, 0x8004, &program);
+        raw[0x7A] = 0x1;
+        let nsf = Nsf::new(&raw).unwrap();
+
+        let cart = Cartridge::from_nsf(&nsf);
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let mut cpu = Cpu::new(bus);
+
+        // PAL NSFs are told they play on PAL consoles, and leaving the PAL
+        // speed out plays them at the PAL frame rate.
+        let mut player = NsfPlayer::new(nsf);
+        assert!(!player.init(&mut cpu, 1));
+        assert_eq!(cpu.bus.mem_read_byte(0x00), 1);
+        assert_eq!(player.play_period(), Duration::from_micros(19997));
+
+        // The region can be overridden.
+        player.set_region(Region::Ntsc);
+        assert!(!player.init(&mut cpu, 1));
+        assert_eq!(cpu.bus.mem_read_byte(0x00), 0);
+        assert_eq!(player.play_period(), Duration::from_micros(16639));
+    }
+}
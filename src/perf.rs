@@ -0,0 +1,228 @@
+use std::time::{Duration, Instant};
+
+use crate::apu::AudioChannel;
+use crate::emulator::Emulator;
+use crate::video::{draw_text, LINE_HEIGHT};
+
+/// How often the figures shown are worked out again, so they can be read.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Margin between the figures and the edges of the frame, in unscaled
+/// pixels.
+const MARGIN: usize = 4;
+
+/// Height of the waveform drawn for each audio channel, in unscaled pixels.
+const WAVE_HEIGHT: usize = 16;
+
+/// An overlay showing how the emulator is performing, drawn over the frames
+/// when shown: the frames emulated per second, how long the frontend's
+/// frames take, how full the audio buffer is and the speed of the
+/// emulation, over the recent output of each audio channel.
+pub struct PerfOverlay {
+    shown: bool,
+
+    /// When the figures were last worked out, the frames run by then, and
+    /// the frames shown since, with the longest.
+    since: Instant,
+    frames_run: u64,
+    frames_shown: u32,
+    max_frame_time: Duration,
+    last_frame: Instant,
+
+    lines: Vec<String>,
+    waves: Vec<Vec<f32>>,
+    overlaid: Vec<u8>,
+}
+
+impl PerfOverlay {
+    /// Returns a new overlay, hidden.
+    pub fn new() -> Self {
+        PerfOverlay {
+            shown: false,
+            since: Instant::now(),
+            frames_run: 0,
+            frames_shown: 0,
+            max_frame_time: Duration::ZERO,
+            last_frame: Instant::now(),
+            lines: vec![],
+            waves: vec![],
+            overlaid: vec![],
+        }
+    }
+
+    /// Shows or hides the overlay over the given emulator.
+    pub fn toggle(&mut self, emulator: &Emulator) {
+        self.shown = !self.shown;
+        self.lines.clear();
+        self.since = Instant::now();
+        self.frames_run = emulator.frames_run();
+        self.frames_shown = 0;
+        self.max_frame_time = Duration::ZERO;
+        self.last_frame = self.since;
+    }
+
+    /// Counts a frame shown by the frontend, with the fill level of its
+    /// audio buffer from 0 to 1 if it has one, and works out the figures
+    /// again when they're due.
+    pub fn update(&mut self, emulator: &Emulator, audio_fill: Option<f64>) {
+        if !self.shown {
+            return;
+        }
+
+        self.waves = AudioChannel::ALL
+            .iter()
+            .map(|&channel| emulator.cpu.bus.channel_history(channel))
+            .collect();
+
+        let now = Instant::now();
+        self.frames_shown += 1;
+        self.max_frame_time = self.max_frame_time.max(now - self.last_frame);
+        self.last_frame = now;
+
+        let elapsed = now - self.since;
+        if elapsed < UPDATE_INTERVAL {
+            return;
+        }
+
+        // A game swapped in starts counting its frames again.
+        let frames_run = emulator.frames_run().saturating_sub(self.frames_run);
+        let fps = frames_run as f64 / elapsed.as_secs_f64();
+        let frame_time = elapsed / self.frames_shown;
+        let speed = speed_text(emulator);
+        self.lines = lines(fps, frame_time, self.max_frame_time, audio_fill, &speed);
+
+        self.frames_run = emulator.frames_run();
+        self.since = now;
+        self.frames_shown = 0;
+        self.max_frame_time = Duration::ZERO;
+    }
+
+    /// Returns the given RGB frame, of the given width, with the figures
+    /// drawn over it at the given scale if the overlay is shown.
+    pub fn draw<'a>(&'a mut self, frame: &'a [u8], frame_w: usize, scale: usize) -> &'a [u8] {
+        if !self.shown {
+            return frame;
+        }
+
+        self.overlaid.clear();
+        self.overlaid.extend_from_slice(frame);
+        for (i, line) in self.lines.iter().enumerate() {
+            let position = (MARGIN * scale, (MARGIN + i * LINE_HEIGHT) * scale);
+            draw_text(&mut self.overlaid, frame_w, position, scale, line);
+        }
+
+        // The waveforms are stacked at the bottom of the frame, the first
+        // channel at the top.
+        let frame_h = self.overlaid.len() / (frame_w * 3);
+        let top = frame_h.saturating_sub(self.waves.len() * WAVE_HEIGHT * scale);
+        for ((i, wave), channel) in self.waves.iter().enumerate().zip(AudioChannel::ALL) {
+            let y = top + i * WAVE_HEIGHT * scale;
+            draw_wave(&mut self.overlaid, frame_w, y, scale, wave);
+            let position = (MARGIN * scale, y);
+            draw_text(&mut self.overlaid, frame_w, position, scale, channel.name());
+        }
+        &self.overlaid
+    }
+}
+
+/// Draws the given levels, from 0 to 1, as a white waveform across the width
+/// of an RGB frame, in a strip starting at the given row.
+fn draw_wave(frame: &mut [u8], frame_w: usize, y: usize, scale: usize, levels: &[f32]) {
+    if levels.is_empty() {
+        return;
+    }
+
+    let frame_h = frame.len() / (frame_w * 3);
+    let height = (WAVE_HEIGHT - 1) * scale;
+    for x in 0..frame_w {
+        let level = levels[x * levels.len() / frame_w].clamp(0.0, 1.0);
+        let row = y + height - (level * height as f32) as usize;
+        if row < frame_h {
+            let start = (row * frame_w + x) * 3;
+            frame[start..start + 3].fill(0xFF);
+        }
+    }
+}
+
+/// Returns the speed of the emulation as shown.
+fn speed_text(emulator: &Emulator) -> String {
+    let speed = match emulator.speed() {
+        _ if emulator.is_paused() => return "PAUSED".to_string(),
+        speed if speed.is_infinite() => "SPEED MAX".to_string(),
+        speed => format!("SPEED {:.2}X", speed),
+    };
+    match emulator.is_fast_forwarding() {
+        true => format!("{} FAST FORWARD", speed),
+        false => speed,
+    }
+}
+
+/// Returns the lines of figures shown.
+fn lines(
+    fps: f64,
+    frame_time: Duration,
+    max_frame_time: Duration,
+    audio_fill: Option<f64>,
+    speed: &str,
+) -> Vec<String> {
+    let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+    let mut lines = vec![
+        format!("FPS {:.1}", fps),
+        format!(
+            "FRAME {:.1} MS MAX {:.1}",
+            ms(frame_time),
+            ms(max_frame_time)
+        ),
+    ];
+    if let Some(fill) = audio_fill {
+        lines.push(format!("AUDIO {:.0}%", fill * 100.0));
+    }
+    lines.push(speed.to_string());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let frame_time = Duration::from_micros(16_640);
+        let max_frame_time = Duration::from_micros(20_000);
+        let shown = lines(60.1, frame_time, max_frame_time, Some(0.5), "SPEED 1.00X");
+        assert_eq!(
+            shown,
+            [
+                "FPS 60.1",
+                "FRAME 16.6 MS MAX 20.0",
+                "AUDIO 50%",
+                "SPEED 1.00X"
+            ]
+        );
+
+        let shown = lines(240.0, frame_time, max_frame_time, None, "PAUSED");
+        assert_eq!(shown, ["FPS 240.0", "FRAME 16.6 MS MAX 20.0", "PAUSED"]);
+    }
+
+    #[test]
+    fn test_draw() {
+        let frame = vec![0x80; 64 * 32 * 3];
+        let mut overlay = PerfOverlay::new();
+        assert_eq!(overlay.draw(&frame, 64, 1), frame);
+
+        overlay.shown = true;
+        overlay.lines = vec!["FPS".to_string()];
+        assert_ne!(overlay.draw(&frame, 64, 1), frame);
+    }
+
+    #[test]
+    fn test_draw_wave() {
+        let mut frame = vec![0; 4 * WAVE_HEIGHT * 3];
+        draw_wave(&mut frame, 4, 0, 1, &[0.0, 0.0, 1.0, 1.0]);
+
+        let white = |x: usize, row: usize| frame[(row * 4 + x) * 3] == 0xFF;
+        assert!(white(0, WAVE_HEIGHT - 1) && white(1, WAVE_HEIGHT - 1));
+        assert!(white(2, 0) && white(3, 0));
+        assert_eq!(frame.iter().filter(|&&v| v == 0xFF).count(), 4 * 3);
+    }
+}
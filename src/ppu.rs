@@ -1,45 +1,74 @@
 mod control;
 mod frame;
 mod mask;
+mod open_bus;
 mod palette;
 mod scroll;
 mod sprite;
 mod status;
 mod tile;
 
+use rand::rngs::StdRng;
+
 use crate::bus::Memory;
+use crate::ram_init::RamInit;
+use crate::region::Region;
 use control::Control;
 use mask::Mask;
+use open_bus::OpenBus;
 use scroll::Scroll;
 use status::Status;
 
-use self::frame::Frame;
+pub use self::frame::Frame;
 use self::palette::Rgb;
-use self::palette::COLOUR_PALETTE;
+use self::palette::EMPHASIS_PALETTE;
+pub use self::palette::{from_pal_file, Palette};
 use self::sprite::Sprite;
 use self::tile::Tile;
+use crate::state::snapshot;
 
 const OAM_SIZE: usize = 0x100;
 const OAM2_SIZE: usize = 0x8;
 
+/// Maximum number of sprites on a scanline when the sprite limit is lifted.
+const MAX_SPRITES: usize = OAM_SIZE / 4;
+
+/// Number of PPU cycles address line A12 must be held low before a rising edge
+/// is reported to the mapper.
+///
+/// The MMC3 filters out short pulses of A12 (e.g. between sprite pattern
+/// fetches) by ignoring rises that happen less than ~3 CPU cycles after A12 went
+/// low.
+const A12_FILTER_CYCLES: u64 = 10;
+
 type RenderFn<'rcall> = Box<dyn FnMut(&[u8]) + 'rcall>;
 
 /// Represents the NES PPU.
 pub struct NesPpu<'rcall> {
     /// Bus to allow PPU to interact with RAM/ROM.
     bus: Box<dyn Memory>,
-    open_bus: u8,
-    open_bus_timer: u32,
+
+    /// I/O latch between the CPU and PPU.
+    open_bus: OpenBus,
 
     /// Object attribute memory (sprites).
     oam_addr: u8,
     oam_data: [u8; OAM_SIZE],
-    oam2_data: [Sprite; OAM2_SIZE],
+    oam2_data: [Sprite; MAX_SPRITES],
     clearing_oam: bool,
     sprite_0_rendering: bool,
     sprite_count: usize,
-    fg_lo_shift: [u8; OAM2_SIZE],
-    fg_hi_shift: [u8; OAM2_SIZE],
+    fg_lo_shift: [u8; MAX_SPRITES],
+    fg_hi_shift: [u8; MAX_SPRITES],
+
+    /// Limit the number of sprites per scanline to 8 like the real hardware?
+    sprite_limit: bool,
+
+    /// Colours of the palette indexes, with each emphasis combination.
+    palette: Palette,
+
+    /// Console region, which sets the number of scanlines per frame.
+    region: Region,
 
     /// Registers.
     ctrl: Control,
@@ -68,6 +97,13 @@ pub struct NesPpu<'rcall> {
     bg_attr_lo_shift: u16,
     bg_attr_hi_shift: u16,
 
+    /// Total number of cycles the PPU has been clocked.
+    clock_count: u64,
+
+    /// PPU cycle on which address line A12 last went low, None if A12 is
+    /// currently high.
+    a12_low_since: Option<u64>,
+
     /// Number of frames rendered by the PPU.
     frame_count: u128,
     odd_frame: bool,
@@ -77,8 +113,44 @@ pub struct NesPpu<'rcall> {
 
     /// Callback to render frame.
     render_callback: RenderFn<'rcall>,
+    render_enabled: bool,
 }
 
+snapshot!(
+    NesPpu<'_>,
+    bus,
+    open_bus,
+    oam_addr,
+    oam_data,
+    oam2_data,
+    clearing_oam,
+    sprite_0_rendering,
+    sprite_count in 0..=MAX_SPRITES,
+    fg_lo_shift,
+    fg_hi_shift,
+    ctrl,
+    mask,
+    scroll,
+    status,
+    nmi_interrupt,
+    buf,
+    addr_toggle,
+    v_addr,
+    xfine in 0..8,
+    scanline in -1..=Region::Pal.last_scanline(),
+    cycle in 0..=340,
+    next_tile,
+    bg_lo_shift,
+    bg_hi_shift,
+    bg_attr_lo_shift,
+    bg_attr_hi_shift,
+    clock_count,
+    a12_low_since,
+    frame_count,
+    odd_frame,
+    frame
+);
+
 pub trait Ppu {
     fn write_addr(&mut self, value: u8);
     fn write_ctrl(&mut self, value: u8);
@@ -87,10 +159,11 @@ pub trait Ppu {
     fn write_data(&mut self, value: u8);
     fn write_oam_addr(&mut self, value: u8);
     fn write_oam_data(&mut self, value: u8);
-    fn write_oam_dma(&mut self, value: &[u8; 256]);
     fn read_data(&mut self) -> u8;
     fn read_status(&mut self) -> u8;
     fn read_oam_data(&mut self) -> u8;
+    fn read_open_bus(&mut self) -> u8;
+    fn peek(&mut self, addr: u16) -> u8;
     fn read_frame_count(&self) -> u128;
 }
 
@@ -102,16 +175,18 @@ impl<'a> NesPpu<'a> {
     {
         NesPpu {
             bus,
-            open_bus: 0,
-            open_bus_timer: 0,
+            open_bus: OpenBus::new(),
             oam_addr: 0,
             oam_data: [0; OAM_SIZE],
-            oam2_data: [Sprite::default(); OAM2_SIZE],
+            oam2_data: [Sprite::default(); MAX_SPRITES],
             clearing_oam: false,
             sprite_0_rendering: false,
             sprite_count: 0,
-            fg_lo_shift: [0; OAM2_SIZE],
-            fg_hi_shift: [0; OAM2_SIZE],
+            fg_lo_shift: [0; MAX_SPRITES],
+            fg_hi_shift: [0; MAX_SPRITES],
+            sprite_limit: true,
+            palette: *EMPHASIS_PALETTE,
+            region: Region::Ntsc,
             buf: 0,
             addr_toggle: false,
             v_addr: Scroll::new(),
@@ -128,10 +203,13 @@ impl<'a> NesPpu<'a> {
             bg_attr_lo_shift: 0,
             bg_attr_hi_shift: 0,
             nmi_interrupt: None,
+            clock_count: 0,
+            a12_low_since: Some(0),
             frame_count: 0,
             odd_frame: false,
             frame: Frame::new(),
             render_callback: Box::from(render_callback),
+            render_enabled: true,
         }
     }
 
@@ -144,6 +222,59 @@ impl<'a> NesPpu<'a> {
         self.v_addr.set_raw(new_addr);
     }
 
+    /// Sets whether the number of sprites per scanline is limited to 8.
+    ///
+    /// Lifting the limit renders every sprite in range of the scanline, which
+    /// eliminates the flicker games use to work around the limit.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    /// Sets the colours drawn for each palette index, in place of the
+    /// built-in palette.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Sets whether frames are passed to the render callback. Frames which
+    /// aren't shown, such as when running ahead, needn't be rendered.
+    pub fn set_render_enabled(&mut self, enabled: bool) {
+        self.render_enabled = enabled;
+    }
+
+    /// Sets the console region, which determines the length of the frame and
+    /// when the vertical blank starts.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Fills the OAM and VRAM with their power on pattern.
+    pub fn init_ram(&mut self, init: RamInit, rng: &mut StdRng) {
+        init.fill(&mut self.oam_data, rng);
+        self.bus.init_ram(init, rng);
+    }
+
+    /// Returns the maximum number of sprites which can be drawn on a
+    /// scanline.
+    fn max_sprites(&self) -> usize {
+        match self.sprite_limit {
+            true => OAM2_SIZE,
+            false => MAX_SPRITES,
+        }
+    }
+
+    /// Returns the current scanline, where the pre render scanline is -1, and
+    /// the current cycle (dot) within it.
+    pub fn position(&self) -> (i32, usize) {
+        (self.scanline, self.cycle)
+    }
+
+    /// Returns the RGB pixels of the frame being drawn, where the lines yet
+    /// to be drawn hold the previous frame.
+    pub fn frame(&self) -> &[u8] {
+        self.frame.pixels()
+    }
+
     /// Poll the NMI flag set by the Ppu
     pub fn poll_nmi(&mut self) -> bool {
         self.nmi_interrupt.take().is_some()
@@ -151,12 +282,14 @@ impl<'a> NesPpu<'a> {
 
     /// Returns true if a frame has been completed.
     pub fn clock(&mut self) {
-        // Update the open bus timer
-        self.update_open_bus();
-
         // Every odd frame on the first scanline, the first cycle is skipped if
         // background rendering is enabled. A flag is updated every frame.
-        if self.odd_frame && self.scanline == 0 && self.cycle == 0 && self.rendering_enabled() {
+        if self.region.skips_odd_frame_cycle()
+            && self.odd_frame
+            && self.scanline == 0
+            && self.cycle == 0
+            && self.rendering_enabled()
+        {
             self.cycle = 1;
         }
 
@@ -177,8 +310,8 @@ impl<'a> NesPpu<'a> {
             self.render_scanline()
         }
 
-        // Set NMI if enabled on cycle 241
-        if self.scanline == 241 && self.cycle == 1 {
+        // Set NMI if enabled at the start of the vertical blank
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
             self.status.set_vblank_status(true);
             if self.ctrl.nmi_enabled() {
                 self.nmi_interrupt = Some(true)
@@ -186,7 +319,9 @@ impl<'a> NesPpu<'a> {
 
             self.frame_count = self.frame_count.wrapping_add(1);
 
-            (self.render_callback)(self.frame.pixels());
+            if self.render_enabled {
+                (self.render_callback)(self.frame.pixels());
+            }
         }
 
         // Calculate the pixel color
@@ -223,7 +358,10 @@ impl<'a> NesPpu<'a> {
             };
 
             // Get the color from palette RAM
-            let colour = self.get_colour(palette, pixel);
+            let colour = match self.rendering_enabled() {
+                true => self.get_colour(palette, pixel),
+                false => self.get_backdrop_colour(),
+            };
 
             self.frame
                 .set_pixel(self.cycle - 1, self.scanline as usize, colour);
@@ -231,6 +369,7 @@ impl<'a> NesPpu<'a> {
 
         // Update cycle count
         self.cycle += 1;
+        self.clock_count = self.clock_count.wrapping_add(1);
 
         // Last cycle
         if self.cycle > 340 {
@@ -238,28 +377,61 @@ impl<'a> NesPpu<'a> {
             self.scanline += 1;
 
             // Last scanline
-            if self.scanline > 260 {
+            if self.scanline > self.region.last_scanline() {
                 self.scanline = -1;
                 self.odd_frame = !self.odd_frame;
             }
         }
     }
 
-    /// Refresh open bus latch timer
-    fn update_open_bus(&mut self) {
-        match self.open_bus_timer > 0 {
-            true => self.open_bus_timer -= 1,
-            false => self.open_bus = 0,
-        }
-    }
-
     /// Refresh open bus latch value
     pub fn refresh_open_bus(&mut self, data: u8) -> u8 {
-        self.open_bus = data;
-        self.open_bus_timer = 7777;
+        self.refresh_open_bus_bits(data, 0xFF)
+    }
+
+    /// Refresh the bits of the open bus latch selected by mask.
+    fn refresh_open_bus_bits(&mut self, data: u8, mask: u8) -> u8 {
+        self.open_bus.refresh(data, mask, self.frame_count);
         data
     }
 
+    /// Returns the byte of OAM selected by the OAM address.
+    fn oam_byte(&self) -> u8 {
+        // Bits 2, 3 and 4 do not exist in the PPU if reading byte 2.
+        let mask = match self.oam_addr & 0x3 {
+            2 => 0xE3,
+            _ => 0xFF,
+        };
+
+        self.oam_data[self.oam_addr as usize] & mask
+    }
+
+    /// Reads from the PPU bus during rendering, watching address line A12.
+    ///
+    /// Mappers such as the MMC3 count scanlines by watching for A12 rising
+    /// edges, which happen when the PPU switches from fetching the background
+    /// pattern table to the sprite pattern table (or vice versa).
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.bus.read_data(addr)
+    }
+
+    /// Notifies the bus of filtered rising edges of address line A12.
+    fn watch_a12(&mut self, addr: u16) {
+        match (addr & 0x1000 != 0, self.a12_low_since) {
+            // Rising edge, only reported if A12 has been low long enough.
+            (true, Some(since)) => {
+                if self.clock_count.wrapping_sub(since) >= A12_FILTER_CYCLES {
+                    self.bus.notify_a12_rising_edge();
+                }
+                self.a12_low_since = None;
+            }
+            // Falling edge.
+            (false, None) => self.a12_low_since = Some(self.clock_count),
+            _ => {}
+        }
+    }
+
     /// Returns if the rendering is enabled or not
     fn rendering_enabled(&self) -> bool {
         self.mask.show_sprites() | self.mask.show_background()
@@ -287,6 +459,10 @@ impl<'a> NesPpu<'a> {
 
     /// Returns pixel value, palette index and attribute byte of current
     /// foreground pixel.
+    ///
+    /// Only the first opaque sprite pixel (by OAM index) is considered, even if
+    /// it is behind the background. This means a behind-background sprite
+    /// still hides any later front-priority sprites at the same location.
     fn get_fg_pixel_info(&mut self) -> (u8, u8, u8) {
         if self.mask.show_sprites() && (self.mask.leftmost_8pxl_sprite() || self.cycle >= 9) {
             self.sprite_0_rendering = false;
@@ -338,24 +514,34 @@ impl<'a> NesPpu<'a> {
     /// Returns the RBG value of the pixel with greyscale and colour emphasis
     /// applied.
     fn get_colour(&mut self, palette: u8, pixel: u8) -> Rgb {
-        let index = self
-            .bus
-            .read_data(0x3F00 + ((palette as u16) << 2) + pixel as u16)
-            & self.mask.grayscale_mask();
+        self.get_palette_colour(0x3F00 + ((palette as u16) << 2) + pixel as u16)
+    }
+
+    /// Returns the RGB value of the backdrop colour drawn while rendering is
+    /// disabled.
+    ///
+    /// Normally this is the colour at $3F00, but if the VRAM address points
+    /// into palette RAM the colour at that address is drawn instead (the
+    /// "background palette hack").
+    ///
+    /// See: https://www.nesdev.org/wiki/PPU_palettes#The_background_palette_hack
+    fn get_backdrop_colour(&mut self) -> Rgb {
+        let addr = self.v_addr.raw();
+        match addr & 0x3F00 == 0x3F00 {
+            true => self.get_palette_colour(addr),
+            false => self.get_palette_colour(0x3F00),
+        }
+    }
 
-        let c = COLOUR_PALETTE[(index as usize) & 0x3F];
+    /// Returns the RGB value of the palette entry at the given address.
+    fn get_palette_colour(&mut self, addr: u16) -> Rgb {
+        let index = self.bus.read_data(addr) & self.mask.grayscale_mask();
 
-        match self.mask.colour_emphasis_enabled() {
-            false => c,
-            true => {
-                let (r, g, b) = self.mask.emphasise();
-                Rgb(
-                    (c.0 as f64 * r) as u8,
-                    (c.1 as f64 * g) as u8,
-                    (c.2 as f64 * b) as u8,
-                )
-            }
+        let mut emphasis = self.mask.emphasis();
+        if self.region.swaps_red_green_emphasis() {
+            emphasis = (emphasis & 0b100) | (emphasis & 0b001) << 1 | (emphasis & 0b010) >> 1;
         }
+        self.palette[emphasis][(index as usize) & 0x3F]
     }
 
     /// Process the current cycle of a rendering scanline.
@@ -385,12 +571,12 @@ impl<'a> NesPpu<'a> {
 
                     // At the address is the id of the pattern to draw
                     let vaddr = self.v_addr.tile_addr();
-                    self.next_tile.id = self.bus.read_data(vaddr);
+                    self.next_tile.id = self.fetch(vaddr);
                 }
                 2 => {
                     // Get the address of the tile attribute
                     let vaddr = self.v_addr.tile_attr_addr();
-                    self.next_tile.attr = self.bus.read_data(vaddr);
+                    self.next_tile.attr = self.fetch(vaddr);
 
                     // Attribute byte: BRBL TRTL
                     // BR: Bottom right metatile
@@ -430,7 +616,7 @@ impl<'a> NesPpu<'a> {
                         + ((self.next_tile.id as u16) << 4)
                         + self.v_addr.yfine() as u16;
 
-                    self.next_tile.lo = self.bus.read_data(vaddr);
+                    self.next_tile.lo = self.fetch(vaddr);
                 }
                 6 => {
                     // Same thing but + 8 for the high bitplane
@@ -439,7 +625,7 @@ impl<'a> NesPpu<'a> {
                         + self.v_addr.yfine() as u16
                         + 8;
 
-                    self.next_tile.hi = self.bus.read_data(vaddr);
+                    self.next_tile.hi = self.fetch(vaddr);
                 }
                 // Increment horizontal scroll
                 7 => self.increment_xscroll(),
@@ -511,7 +697,7 @@ impl<'a> NesPpu<'a> {
                 if (0..sprite_size).contains(&diff) {
                     // If the sprite is visible and there is less than 8 sprite
                     // already visible, add it to secondary OAM.
-                    if sprite_count < 8 {
+                    if sprite_count < self.max_sprites() {
                         self.oam2_data[sprite_count].y = self.oam_data[index];
                         self.oam2_data[sprite_count].id = self.oam_data[index + 1];
                         self.oam2_data[sprite_count].attr = self.oam_data[index + 2];
@@ -525,11 +711,13 @@ impl<'a> NesPpu<'a> {
                 }
             }
 
-            // If more than 8 sprites, set the sprite overflow bit.
-            self.status.set_sprite_overflow(sprite_count > 8);
+            // If more than 8 sprites, set the sprite overflow bit. This is
+            // set even if the sprite limit is lifted, as games use it for
+            // timing.
+            self.status.set_sprite_overflow(sprite_count > OAM2_SIZE);
 
             // Visible sprite count.
-            self.sprite_count = if sprite_count > 8 { 8 } else { sprite_count };
+            self.sprite_count = sprite_count.min(self.max_sprites());
         }
 
         if self.cycle == 321 {
@@ -656,8 +844,8 @@ impl<'a> NesPpu<'a> {
                 }
             };
 
-            let sprite_lo = self.bus.read_data(sprite_addr);
-            let sprite_hi = self.bus.read_data(sprite_addr.wrapping_add(8));
+            let sprite_lo = self.fetch(sprite_addr);
+            let sprite_hi = self.fetch(sprite_addr.wrapping_add(8));
 
             // Flip horizontal closure.
             let flip_h = |mut v: u8| {
@@ -677,6 +865,16 @@ impl<'a> NesPpu<'a> {
                 false => sprite_hi,
             };
         }
+
+        // Unused sprite slots still fetch tile $FF, which is what clocks
+        // A12 based scanline counters on lines without sprites.
+        let dummy_addr = match self.ctrl.sprite_size() {
+            true => 0x1FF0,
+            false => self.ctrl.sprite_pattern_addr() | 0xFF0,
+        };
+        for _ in self.sprite_count..OAM2_SIZE {
+            self.watch_a12(dummy_addr);
+        }
     }
 }
 
@@ -745,16 +943,14 @@ impl Ppu for NesPpu<'_> {
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
-    fn write_oam_dma(&mut self, data: &[u8; 256]) {
-        for x in data.iter() {
-            self.oam_data[self.oam_addr as usize] = *x;
-            self.oam_addr = self.oam_addr.wrapping_add(1);
-        }
-    }
-
     /// Returns the PPU status register and resets VBLANK + addr.
     fn read_status(&mut self) -> u8 {
-        let data = self.status.snapshot() | (self.open_bus & 0x1F);
+        // Only the top 3 bits are driven by the status register, the rest come
+        // from the open bus latch.
+        let status = self.status.snapshot() & 0xE0;
+        let data = status | (self.open_bus.read(self.frame_count) & 0x1F);
+        self.refresh_open_bus_bits(status, 0xE0);
+
         self.status.reset_vblank_status();
         self.nmi_interrupt = None;
         self.addr_toggle = false;
@@ -765,16 +961,39 @@ impl Ppu for NesPpu<'_> {
         match self.clearing_oam {
             // Always returns 0xFF when clearing secondary OAM
             true => 0xFF,
-            false => {
-                // Bits 2, 3 and 4 do not exist in the PPU if reading byte 2.
-                let mask = match self.oam_addr & 0x3 {
-                    2 => 0xE3,
-                    _ => 0xFF,
-                };
-
-                // Read from OAM and refresh open bus
-                self.refresh_open_bus(self.oam_data[self.oam_addr as usize] & mask)
+
+            // Read from OAM and refresh open bus
+            false => self.refresh_open_bus(self.oam_byte()),
+        }
+    }
+
+    /// Returns the value of the open bus latch, used when reading write-only
+    /// registers.
+    fn read_open_bus(&mut self) -> u8 {
+        self.open_bus.read(self.frame_count)
+    }
+
+    /// Returns the value a read of the given register would return, without
+    /// any of the side effects of the read, such as clearing the VBLANK flag
+    /// or incrementing the VRAM address.
+    fn peek(&mut self, addr: u16) -> u8 {
+        let open_bus = self.open_bus.peek(self.frame_count);
+
+        match addr & 0x7 {
+            2 => (self.status.snapshot() & 0xE0) | (open_bus & 0x1F),
+            4 if self.clearing_oam => 0xFF,
+            4 => self.oam_byte(),
+            7 => {
+                let addr = self.v_addr.raw();
+                match addr & 0x3F00 == 0x3F00 {
+                    true => {
+                        let palette = self.bus.peek_data(addr) & 0x3F & self.mask.grayscale_mask();
+                        (open_bus & 0xC0) | palette
+                    }
+                    false => self.buf,
+                }
             }
+            _ => open_bus,
         }
     }
 
@@ -803,14 +1022,18 @@ impl Ppu for NesPpu<'_> {
 
         // If the data read in from palette RAM, it only takes 1 read
         if (self.v_addr.raw() & 0x3F00) == 0x3F00 {
-            // Put the buffer data which was just read on the bus
-            result = (self.open_bus & 0xC0) | (self.buf & 0x3F);
-
-            // Add the geryscale mask if enabled.
-            result &= self.mask.grayscale_mask();
+            // Put the buffer data which was just read on the bus, with the
+            // geryscale mask if enabled.
+            let palette = self.buf & 0x3F & self.mask.grayscale_mask();
+
+            // Palette RAM is only 6 bits wide, the top 2 bits come from the
+            // open bus latch.
+            result = (self.open_bus.read(self.frame_count) & 0xC0) | palette;
+            self.refresh_open_bus_bits(palette, 0x3F);
+        } else {
+            self.refresh_open_bus(result);
         }
 
-        self.refresh_open_bus(result);
         self.increment_vram_addr();
 
         result
@@ -827,6 +1050,7 @@ pub mod tests {
     };
 
     use super::*;
+    use crate::state::{self, Snapshot, StateBuffer, StateError};
 
     /// Returns an instatiated PPU with an empty ROM loaded.
     pub fn new_empty_rom_ppu(mirroring: Option<Mirroring>) -> NesPpu<'static> {
@@ -836,6 +1060,84 @@ pub mod tests {
         NesPpu::new(Box::new(bus), |_| {})
     }
 
+    /// PPU bus which counts A12 rising edges.
+    struct A12CountingBus {
+        edges: Rc<RefCell<u32>>,
+    }
+
+    impl Snapshot for A12CountingBus {
+        fn snapshot(&mut self, _state: &mut StateBuffer) {}
+    }
+
+    impl Memory for A12CountingBus {
+        fn write_data(&mut self, _addr: u16, _value: u8) {}
+
+        fn read_data(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn notify_a12_rising_edge(&mut self) {
+            *self.edges.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn test_a12_rising_edges() {
+        let edges = Rc::new(RefCell::new(0));
+        let bus = A12CountingBus {
+            edges: Rc::clone(&edges),
+        };
+        let mut ppu = NesPpu::new(Box::new(bus), |_| {});
+
+        // Background from $0000, sprites from $1000.
+        ppu.write_ctrl(0b0000_1000);
+        ppu.write_mask(0b0001_1000);
+
+        let frame_count = ppu.read_frame_count();
+        while ppu.read_frame_count() == frame_count {
+            ppu.clock();
+        }
+        *edges.borrow_mut() = 0;
+
+        let frame_count = ppu.read_frame_count();
+        while ppu.read_frame_count() == frame_count {
+            ppu.clock();
+        }
+
+        // One edge for each visible scanline and the pre-render scanline.
+        assert_eq!(*edges.borrow(), 241);
+    }
+
+    #[test]
+    fn test_region_frame_length() {
+        for (region, scanlines, vblank) in [
+            (Region::Ntsc, 262, 241),
+            (Region::Pal, 312, 241),
+            (Region::Dendy, 312, 291),
+        ] {
+            let mut ppu = new_empty_rom_ppu(None);
+            ppu.set_region(region);
+
+            let frame_count = ppu.read_frame_count();
+            while ppu.read_frame_count() == frame_count {
+                ppu.clock();
+            }
+            assert_eq!(ppu.scanline, vblank, "{:?}", region);
+
+            let clock_count = ppu.clock_count;
+            let frame_count = ppu.read_frame_count();
+            while ppu.read_frame_count() == frame_count {
+                ppu.clock();
+            }
+            assert_eq!(
+                ppu.clock_count - clock_count,
+                scanlines * 341,
+                "{:?}",
+                region
+            );
+        }
+    }
+
     #[test]
     fn test_ppu_vram_writes() {
         let mut ppu = new_empty_rom_ppu(None);
@@ -984,37 +1286,176 @@ pub mod tests {
     }
 
     #[test]
-    fn test_oam_read_write() {
+    fn test_peek() {
         let mut ppu = new_empty_rom_ppu(None);
-        ppu.write_oam_addr(0x10);
-        ppu.write_oam_data(0x66);
-        ppu.write_oam_data(0x77);
+        ppu.write_ctrl(0);
+        ppu.bus.write_data(0x2305, 0x66);
+        ppu.write_addr(0x23);
+        ppu.write_addr(0x05);
+        ppu.read_data();
+        ppu.status.set_vblank_status(true);
 
-        ppu.write_oam_addr(0x10);
-        assert_eq!(ppu.read_oam_data(), 0x66);
+        // Peeking the status doesn't clear VBLANK.
+        assert_eq!(ppu.peek(0x2002) >> 7, 1);
+        assert_eq!(ppu.status.snapshot() >> 7, 1);
 
-        ppu.write_oam_addr(0x11);
-        assert_eq!(ppu.read_oam_data(), 0x77);
+        // Peeking the data returns the read buffer without incrementing the
+        // address.
+        assert_eq!(ppu.peek(0x2007), 0x66);
+        assert_eq!(ppu.v_addr.raw(), 0x2306);
+        assert_eq!(ppu.read_data(), 0x66);
     }
 
     #[test]
-    fn test_oam_dma() {
+    fn test_read_status_open_bus() {
         let mut ppu = new_empty_rom_ppu(None);
+        ppu.refresh_open_bus(0x1F);
+        ppu.status.set_vblank_status(true);
 
-        let mut data = [0x66; 256];
-        data[0] = 0x77;
-        data[255] = 0x88;
+        assert_eq!(ppu.read_status(), 0x9F);
+        assert_eq!(ppu.read_open_bus(), 0x9F);
+    }
 
-        ppu.write_oam_addr(0x10);
-        ppu.write_oam_dma(&data);
+    #[test]
+    fn test_open_bus_decay() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.refresh_open_bus(0xAA);
+        assert_eq!(ppu.read_open_bus(), 0xAA);
+
+        ppu.frame_count = 60;
+        assert_eq!(ppu.read_open_bus(), 0x00);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.bus.write_data(0x3F10, 0x11);
+        ppu.bus.write_data(0x3F25, 0x22);
+
+        assert_eq!(ppu.bus.read_data(0x3F00), 0x11);
+        assert_eq!(ppu.bus.read_data(0x3FF0), 0x11);
+        assert_eq!(ppu.bus.read_data(0x3F05), 0x22);
+        assert_eq!(ppu.bus.read_data(0x3FE5), 0x22);
+    }
+
+    #[test]
+    fn test_backdrop_palette_hack() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.bus.write_data(0x3F00, 0x01);
+        ppu.bus.write_data(0x3F05, 0x21);
+
+        // Rendering is disabled with v pointing into palette RAM.
+        ppu.write_addr(0x3F);
+        ppu.write_addr(0x05);
+
+        ppu.clock();
+        ppu.clock();
+
+        let colour = EMPHASIS_PALETTE[0][0x21];
+        assert_eq!(ppu.frame.pixels()[0..3], [colour.0, colour.1, colour.2]);
+    }
+
+    #[test]
+    fn test_pal_emphasis() {
+        for (region, emphasis) in [(Region::Ntsc, 0b001), (Region::Pal, 0b010)] {
+            let mut ppu = new_empty_rom_ppu(None);
+            ppu.set_region(region);
+            ppu.bus.write_data(0x3F00, 0x21);
+
+            // Bit 5 emphasises red on NTSC consoles and green on PAL ones.
+            ppu.write_mask(0b0010_0000);
+            let colour = EMPHASIS_PALETTE[emphasis][0x21];
+            assert_eq!(ppu.get_palette_colour(0x3F00), colour, "{:?}", region);
+        }
+    }
+
+    #[test]
+    fn test_behind_sprite_hides_front_sprite() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.write_mask(0b0001_0100);
+        ppu.cycle = 1;
+
+        // Sprite 0 is behind the background, sprite 1 is in front.
+        ppu.sprite_count = 2;
+        ppu.oam2_data[0] = Sprite {
+            x: 0,
+            attr: 0x21,
+            ..Default::default()
+        };
+        ppu.oam2_data[1] = Sprite {
+            x: 0,
+            attr: 0x02,
+            index: 4,
+            ..Default::default()
+        };
+        ppu.fg_lo_shift[0] = 0x80;
+        ppu.fg_lo_shift[1] = 0x80;
+
+        assert_eq!(ppu.get_fg_pixel_info(), (1, 5, 0));
+    }
+
+    /// Fills OAM with 10 sprites on the first scanlines and runs sprite
+    /// evaluation for scanline 0.
+    fn evaluate_ten_sprites(ppu: &mut NesPpu) {
+        for _ in 0..256 {
+            ppu.write_oam_data(0xFF);
+        }
 
-        ppu.write_oam_addr(0xF);
-        assert_eq!(ppu.read_oam_data(), 0x88);
+        for i in 0..10 {
+            ppu.write_oam_addr(i * 4);
+            ppu.write_oam_data(0);
+            ppu.write_oam_data(0);
+            ppu.write_oam_data(0);
+            ppu.write_oam_data(i * 8);
+        }
+
+        ppu.scanline = 0;
+        ppu.cycle = 257;
+        ppu.render_scanline_sprites();
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let mut ppu = new_empty_rom_ppu(None);
+        evaluate_ten_sprites(&mut ppu);
+
+        assert_eq!(ppu.sprite_count, 8);
+        assert_eq!(ppu.status.snapshot() & 0x20, 0x20);
+    }
 
+    #[test]
+    fn test_no_sprite_limit() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.set_sprite_limit(false);
+        evaluate_ten_sprites(&mut ppu);
+
+        assert_eq!(ppu.sprite_count, 10);
+        assert_eq!(ppu.status.snapshot() & 0x20, 0x20);
+    }
+
+    #[test]
+    fn test_corrupt_state() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.sprite_count = MAX_SPRITES + 1;
+        let state = state::save(&mut ppu);
+
+        // More sprites than secondary OAM holds aren't loaded.
+        ppu.sprite_count = 0;
+        assert_eq!(state::load(&mut ppu, &state), Err(StateError::InvalidValue));
+        assert_eq!(ppu.sprite_count, 0);
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = new_empty_rom_ppu(None);
         ppu.write_oam_addr(0x10);
-        assert_eq!(ppu.read_oam_data(), 0x77);
+        ppu.write_oam_data(0x66);
+        ppu.write_oam_data(0x77);
 
-        ppu.write_oam_addr(0x11);
+        ppu.write_oam_addr(0x10);
         assert_eq!(ppu.read_oam_data(), 0x66);
+
+        ppu.write_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x77);
     }
 }
@@ -1,5 +1,6 @@
+use crate::state::snapshot;
+
 const NMI_ENABLED: u8 = 0b10000000;
-const MASTER_SLAVE: u8 = 0b01000000;
 const SPRITE_SIZE: u8 = 0b00100000;
 const BG_ADDRESS: u8 = 0b00010000;
 const SPRITE_ADDRESS: u8 = 0b00001000;
@@ -28,6 +29,8 @@ pub struct Control {
     bits: u8,
 }
 
+snapshot!(Control, bits);
+
 impl Control {
     /// Returns an instantiated control register.
     pub fn new() -> Self {
@@ -1,13 +1,16 @@
 use super::palette;
+use crate::state::snapshot;
 
 /// Frame represents one rendered frame of pixels.
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
+snapshot!(Frame, data);
+
 impl Frame {
-    const WIDTH: usize = 256;
-    const HEIGHT: usize = 240;
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
 
     /// Returns a new frame.
     pub fn new() -> Self {
@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 const GRAYSCALE: u8 = 0b00000001;
 const LEFTMOST_8PXL_BACKGROUND: u8 = 0b00000010;
 const LEFTMOST_8PXL_SPRITE: u8 = 0b00000100;
@@ -24,6 +26,8 @@ pub struct Mask {
     bits: u8,
 }
 
+snapshot!(Mask, bits);
+
 impl Mask {
     /// Returns a new mask register.
     pub fn new() -> Self {
@@ -58,31 +62,11 @@ impl Mask {
         (self.bits & SHOW_SPRITES) == SHOW_SPRITES
     }
 
-    /// Returns the current colour emphasis.
-    pub fn emphasise(&self) -> (f64, f64, f64) {
-        let mut r = 1.0;
-        let mut g = 1.0;
-        let mut b = 1.0;
-
-        if (self.bits & EMPHASISE_RED) == EMPHASISE_RED {
-            g = 0.75;
-            b = 0.75;
-        }
-        if (self.bits & EMPHASISE_GREEN) == EMPHASISE_GREEN {
-            r = 0.75;
-            b = 0.75;
-        }
-        if (self.bits & EMPHASISE_BLUE) == EMPHASISE_BLUE {
-            r = 0.75;
-            b = 0.75;
-        }
-
-        (r, g, b)
-    }
-
-    /// Returns true if one of the color emphasis bits is set.
-    pub fn colour_emphasis_enabled(&self) -> bool {
-        self.bits & (EMPHASISE_RED | EMPHASISE_GREEN | EMPHASISE_BLUE) != 0
+    /// Returns the colour emphasis bits shifted down to the range 0-7.
+    ///
+    /// Bit 0: red, bit 1: green, bit 2: blue.
+    pub fn emphasis(&self) -> usize {
+        ((self.bits & (EMPHASISE_RED | EMPHASISE_GREEN | EMPHASISE_BLUE)) >> 5) as usize
     }
 
     /// Updates the state of the register.
@@ -0,0 +1,94 @@
+use crate::state::snapshot;
+
+/// Number of frames before a bit of the open bus latch decays to 0.
+///
+/// The latch is a capacitive bus, so bits that are not refreshed fade out after
+/// roughly 600ms, which is ~36 frames at 60Hz.
+const DECAY_FRAMES: u128 = 36;
+
+/// Represents the PPU I/O latch (or "open bus").
+///
+/// Every write to a PPU register and every read of a readable register drives
+/// the data bus between the CPU and PPU. The latch holds the value last driven
+/// on to the bus, which is returned when reading write-only registers or the
+/// unused bits of partially driven registers.
+///
+/// See: https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+pub struct OpenBus {
+    value: u8,
+
+    /// Frame in which each bit of the latch was last refreshed.
+    refreshed: [u128; 8],
+}
+
+snapshot!(OpenBus, value, refreshed);
+
+impl OpenBus {
+    /// Returns a new open bus latch.
+    pub fn new() -> Self {
+        OpenBus {
+            value: 0,
+            refreshed: [0; 8],
+        }
+    }
+
+    /// Drives the bits selected by mask on to the bus, refreshing their decay
+    /// timers.
+    pub fn refresh(&mut self, data: u8, mask: u8, frame: u128) {
+        self.value = (self.value & !mask) | (data & mask);
+
+        for (bit, refreshed) in self.refreshed.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed = frame;
+            }
+        }
+    }
+
+    /// Returns the current value of the latch, decaying any bits which have not
+    /// been refreshed recently.
+    pub fn read(&mut self, frame: u128) -> u8 {
+        self.value = self.peek(frame);
+        self.value
+    }
+
+    /// Returns the current value of the latch, as decayed by the given frame,
+    /// without storing the decay.
+    pub fn peek(&self, frame: u128) -> u8 {
+        let mut value = self.value;
+        for (bit, refreshed) in self.refreshed.iter().enumerate() {
+            if frame.wrapping_sub(*refreshed) > DECAY_FRAMES {
+                value &= !(1 << bit);
+            }
+        }
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh() {
+        let mut open_bus = OpenBus::new();
+        open_bus.refresh(0xFF, 0xFF, 0);
+        assert_eq!(open_bus.read(0), 0xFF);
+
+        open_bus.refresh(0x00, 0xE0, 0);
+        assert_eq!(open_bus.read(0), 0x1F);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut open_bus = OpenBus::new();
+        open_bus.refresh(0xFF, 0xFF, 0);
+        open_bus.refresh(0xFF, 0x0F, 30);
+
+        assert_eq!(open_bus.read(DECAY_FRAMES), 0xFF);
+        assert_eq!(open_bus.peek(DECAY_FRAMES + 1), 0x0F);
+        assert_eq!(open_bus.peek(DECAY_FRAMES), 0xFF);
+        assert_eq!(open_bus.read(DECAY_FRAMES + 1), 0x0F);
+        assert_eq!(open_bus.read(30 + DECAY_FRAMES + 1), 0x00);
+    }
+}
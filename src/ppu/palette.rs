@@ -1,5 +1,9 @@
+use std::fmt;
+
+use lazy_static::lazy_static;
+
 // Represents a NES colour.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rgb(pub u8, pub u8, pub u8);
 
 /// NES colour palette.
@@ -17,3 +21,163 @@ pub static COLOUR_PALETTE: [Rgb; 0x40] = [
     Rgb(236, 238, 236), Rgb(168, 204, 236), Rgb(188, 188, 236), Rgb(212, 178, 236), Rgb(236, 174, 236), Rgb(236, 174, 212), Rgb(236, 180, 176), Rgb(228, 196, 144),
     Rgb(204, 210, 120), Rgb(180, 222, 120), Rgb(168, 226, 144), Rgb(152, 226, 180), Rgb(160, 214, 228), Rgb(160, 162, 160), Rgb(0, 0, 0),       Rgb(0, 0, 0),
 ];
+
+/// Number of colour emphasis combinations (one per combination of the three
+/// emphasis bits in the mask register).
+pub const EMPHASIS_COMBINATIONS: usize = 8;
+
+/// Attenuation applied to the non-emphasised colour channels.
+const EMPHASIS_ATTENUATION: f64 = 0.75;
+
+/// Size of a palette file of the 64 colours, in bytes.
+const PAL_FILE_SIZE: usize = 0x40 * 3;
+
+/// A palette with every colour emphasis combination pre-applied.
+///
+/// The table is indexed by the emphasis bits of the mask register (bits 5-7
+/// shifted down) and then the palette index, so resolving the colour of a
+/// pixel is a single table lookup.
+pub type Palette = [[Rgb; 0x40]; EMPHASIS_COMBINATIONS];
+
+/// Represents an error reading a palette file.
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    /// The file isn't the size of 64 colours, or of 64 colours with each
+    /// emphasis combination.
+    InvalidSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::InvalidSize(size) => write!(
+                f,
+                "palette is {} bytes, expected {} or {}",
+                size,
+                PAL_FILE_SIZE,
+                PAL_FILE_SIZE * EMPHASIS_COMBINATIONS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+lazy_static! {
+    /// Lookup table of the NES colour palette with every colour emphasis
+    /// combination pre-applied.
+    pub static ref EMPHASIS_PALETTE: Palette = apply_emphasis(&COLOUR_PALETTE);
+}
+
+/// Returns the palette of the given colours darkened by each colour emphasis
+/// combination.
+fn apply_emphasis(palette: &[Rgb; 0x40]) -> Palette {
+    let mut table = [[Rgb(0, 0, 0); 0x40]; EMPHASIS_COMBINATIONS];
+
+    for (emphasis, colours) in table.iter_mut().enumerate() {
+        // Each emphasis bit darkens the other two colour channels.
+        //
+        // Bit 0: red, bit 1: green, bit 2: blue.
+        let mut r = 1.0;
+        let mut g = 1.0;
+        let mut b = 1.0;
+
+        if emphasis & 0x1 != 0 {
+            g *= EMPHASIS_ATTENUATION;
+            b *= EMPHASIS_ATTENUATION;
+        }
+        if emphasis & 0x2 != 0 {
+            r *= EMPHASIS_ATTENUATION;
+            b *= EMPHASIS_ATTENUATION;
+        }
+        if emphasis & 0x4 != 0 {
+            r *= EMPHASIS_ATTENUATION;
+            g *= EMPHASIS_ATTENUATION;
+        }
+
+        for (colour, c) in colours.iter_mut().zip(palette.iter()) {
+            *colour = Rgb(
+                (c.0 as f64 * r) as u8,
+                (c.1 as f64 * g) as u8,
+                (c.2 as f64 * b) as u8,
+            );
+        }
+    }
+
+    table
+}
+
+/// Returns the palette stored in a .pal file, of the RGB values of the 64
+/// colours, or of the 64 colours with each emphasis combination in turn. The
+/// emphasis is applied to files of only the 64 colours.
+pub fn from_pal_file(raw: &[u8]) -> Result<Palette, PaletteError> {
+    let mut colours = raw.chunks_exact(3).map(|rgb| Rgb(rgb[0], rgb[1], rgb[2]));
+    match raw.len() {
+        PAL_FILE_SIZE => {
+            let mut palette = [Rgb(0, 0, 0); 0x40];
+            palette.fill_with(|| colours.next().unwrap());
+            Ok(apply_emphasis(&palette))
+        }
+        size if size == PAL_FILE_SIZE * EMPHASIS_COMBINATIONS => {
+            let mut table = [[Rgb(0, 0, 0); 0x40]; EMPHASIS_COMBINATIONS];
+            table
+                .iter_mut()
+                .flatten()
+                .for_each(|colour| *colour = colours.next().unwrap());
+            Ok(table)
+        }
+        size => Err(PaletteError::InvalidSize(size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_emphasis_matches_palette() {
+        for (colour, c) in EMPHASIS_PALETTE[0].iter().zip(COLOUR_PALETTE.iter()) {
+            assert_eq!(colour, c);
+        }
+    }
+
+    #[test]
+    fn test_red_emphasis() {
+        let c = COLOUR_PALETTE[0x20];
+        let colour = EMPHASIS_PALETTE[0x1][0x20];
+
+        assert_eq!(colour.0, c.0);
+        assert_eq!(colour.1, (c.1 as f64 * 0.75) as u8);
+        assert_eq!(colour.2, (c.2 as f64 * 0.75) as u8);
+    }
+
+    #[test]
+    fn test_all_emphasis() {
+        let c = COLOUR_PALETTE[0x20];
+        let colour = EMPHASIS_PALETTE[0x7][0x20];
+
+        assert_eq!(colour.0, (c.0 as f64 * 0.75 * 0.75) as u8);
+        assert_eq!(colour.1, (c.1 as f64 * 0.75 * 0.75) as u8);
+        assert_eq!(colour.2, (c.2 as f64 * 0.75 * 0.75) as u8);
+    }
+
+    #[test]
+    fn test_pal_file() {
+        let raw: Vec<_> = (0..PAL_FILE_SIZE).map(|i| i as u8).collect();
+        let palette = from_pal_file(&raw).unwrap();
+        assert_eq!(palette[0][0x01], Rgb(3, 4, 5));
+        assert_eq!(palette[0x1][0x20], Rgb(96, 72, 73));
+
+        let raw: Vec<_> = (0..PAL_FILE_SIZE * EMPHASIS_COMBINATIONS)
+            .map(|i| (i / 3) as u8)
+            .collect();
+        let palette = from_pal_file(&raw).unwrap();
+        assert_eq!(palette[0][0x01], Rgb(1, 1, 1));
+        assert_eq!(palette[0x7][0x3F], Rgb(0xFF, 0xFF, 0xFF));
+
+        assert_eq!(
+            from_pal_file(&raw[..100]),
+            Err(PaletteError::InvalidSize(100))
+        );
+    }
+}
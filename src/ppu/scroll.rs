@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 const XCOARSE_MASK: u16 = 0b11111;
 const YCOARSE_MASK: u16 = 0b11111;
 const NTA_H_MASK: u16 = 0b1;
@@ -20,6 +22,15 @@ pub struct Scroll {
     yfine: u8,
 }
 
+snapshot!(
+    Scroll,
+    xcoarse in 0..32,
+    ycoarse in 0..32,
+    nta_h,
+    nta_v,
+    yfine in 0..8
+);
+
 impl Scroll {
     /// Returns a new scroll register.
     pub fn new() -> Self {
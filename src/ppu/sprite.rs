@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 /// Represents a sprite from OAM.
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Sprite {
@@ -28,3 +30,5 @@ pub struct Sprite {
     /// +--------------- Flip sprite vertically
     pub attr: u8,
 }
+
+snapshot!(Sprite, id, x, y, index, attr);
@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 const SPRITE_OVERFLOW: u8 = 0b00100000;
 const SPRITE_ZERO_HIT: u8 = 0b01000000;
 const VBLANK_STARTED: u8 = 0b10000000;
@@ -27,6 +29,8 @@ pub struct Status {
     bits: u8,
 }
 
+snapshot!(Status, bits);
+
 impl Status {
     /// Returns a new status register.
     pub fn new() -> Self {
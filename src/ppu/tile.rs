@@ -1,3 +1,5 @@
+use crate::state::snapshot;
+
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Tile {
     pub lo: u8,
@@ -5,3 +7,5 @@ pub struct Tile {
     pub attr: u8,
     pub id: u8,
 }
+
+snapshot!(Tile, lo, hi, attr, id);
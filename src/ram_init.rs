@@ -0,0 +1,79 @@
+use rand::rngs::StdRng;
+use rand::RngCore;
+
+/// Number of bytes in each stripe of the striped pattern.
+const STRIPE_SIZE: usize = 4;
+
+/// The pattern RAM holds at power on.
+///
+/// The contents of RAM are undefined at power on, and vary between consoles.
+/// A few games (accidentally) depend on them, such as by seeding a random
+/// number generator from uninitialized RAM.
+///
+/// See: https://www.nesdev.org/wiki/CPU_power_up_state
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RamInit {
+    /// Every byte is $00.
+    Zero,
+
+    /// Every byte is $FF.
+    Ff,
+
+    /// Alternating runs of 4 bytes of $00 and $FF, as commonly found on real
+    /// consoles.
+    Striped,
+
+    /// Random bytes, from a seeded generator so runs can be reproduced.
+    Random,
+}
+
+impl RamInit {
+    /// Fills the given RAM with the pattern, drawing random bytes from rng.
+    pub fn fill(&self, ram: &mut [u8], rng: &mut StdRng) {
+        match self {
+            RamInit::Zero => ram.fill(0x00),
+            RamInit::Ff => ram.fill(0xFF),
+            RamInit::Striped => {
+                for (i, stripe) in ram.chunks_mut(STRIPE_SIZE).enumerate() {
+                    stripe.fill(if i % 2 == 0 { 0x00 } else { 0xFF });
+                }
+            }
+            RamInit::Random => rng.fill_bytes(ram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut ram = [0x42; 10];
+
+        RamInit::Zero.fill(&mut ram, &mut rng);
+        assert_eq!(ram, [0x00; 10]);
+
+        RamInit::Ff.fill(&mut ram, &mut rng);
+        assert_eq!(ram, [0xFF; 10]);
+
+        RamInit::Striped.fill(&mut ram, &mut rng);
+        assert_eq!(ram, [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn test_fill_random() {
+        let mut first = [0; 64];
+        let mut second = [0; 64];
+        RamInit::Random.fill(&mut first, &mut StdRng::seed_from_u64(1));
+        RamInit::Random.fill(&mut second, &mut StdRng::seed_from_u64(1));
+        assert_eq!(first, second);
+
+        RamInit::Random.fill(&mut second, &mut StdRng::seed_from_u64(2));
+        assert_ne!(first, second);
+    }
+}
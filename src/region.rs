@@ -0,0 +1,145 @@
+use crate::rom::Timing;
+
+/// The console region, which determines the clock rates of the CPU, PPU and
+/// APU, and the number of scanlines in each frame.
+///
+/// NTSC consoles derive their clocks from a 21.477272 MHz master clock, PAL
+/// consoles and Dendy clones (PAL famiclones) from a 26.601712 MHz one.
+///
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// Returns the region to emulate for the timing given in a ROM header.
+    ///
+    /// Multi-region games run on either console, so NTSC is used.
+    pub fn from_timing(timing: Timing) -> Self {
+        match timing {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    /// Returns the name of the region, as shown to users.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    /// Returns the CPU (and APU) clock rate in Hz.
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1789773.0,
+            Region::Pal => 1662607.0,
+            Region::Dendy => 1773448.0,
+        }
+    }
+
+    /// Returns the number of frames rendered per second.
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Returns the number of master clock cycles in a CPU cycle.
+    pub fn cpu_divider(&self) -> u8 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Returns the number of master clock cycles in a PPU cycle (dot).
+    pub fn ppu_divider(&self) -> u8 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Returns the number of the last scanline in a frame, where the pre
+    /// render scanline is -1.
+    pub fn last_scanline(&self) -> i32 {
+        match self {
+            Region::Ntsc => 260,
+            Region::Pal | Region::Dendy => 310,
+        }
+    }
+
+    /// Returns the scanline on which the vertical blank starts.
+    ///
+    /// Dendy clones run 51 idle scanlines after the picture before the
+    /// vertical blank, so the NMI arrives at the same point in the frame as
+    /// on an NTSC console.
+    pub fn vblank_scanline(&self) -> i32 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Returns true if the first cycle of every odd frame is skipped while
+    /// rendering is enabled, which only NTSC consoles do.
+    pub fn skips_odd_frame_cycle(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    /// Returns true if the PPU's red and green emphasis bits are swapped, so
+    /// bit 5 of PPUMASK emphasises green and bit 6 red, as on PAL consoles
+    /// and Dendy clones.
+    ///
+    /// See: https://www.nesdev.org/wiki/PPU_registers#PPUMASK
+    pub fn swaps_red_green_emphasis(&self) -> bool {
+        *self != Region::Ntsc
+    }
+
+    /// Returns true if the APU uses the PAL noise, DMC and frame counter
+    /// timings. Dendy clones use the NTSC ones.
+    pub fn pal_apu(&self) -> bool {
+        *self == Region::Pal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_timing() {
+        assert_eq!(Region::from_timing(Timing::Ntsc), Region::Ntsc);
+        assert_eq!(Region::from_timing(Timing::Pal), Region::Pal);
+        assert_eq!(Region::from_timing(Timing::MultiRegion), Region::Ntsc);
+        assert_eq!(Region::from_timing(Timing::Dendy), Region::Dendy);
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        // The CPU clock rate divided by the cycles in a frame gives the frame
+        // rate, to within the rounding of the published figures.
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let scanlines = (region.last_scanline() + 2) as f64;
+            let dots_per_cpu_cycle = region.cpu_divider() as f64 / region.ppu_divider() as f64;
+            let cpu_cycles = scanlines * 341.0 / dots_per_cpu_cycle;
+
+            let frame_rate = region.cpu_clock_rate() / cpu_cycles;
+            assert!(
+                (frame_rate - region.frame_rate()).abs() < 0.01,
+                "{:?}",
+                region
+            );
+        }
+    }
+}
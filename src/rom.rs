@@ -1,10 +1,24 @@
+mod archive;
+mod database;
+mod patch;
+
+pub use archive::read_file;
+pub use database::{crc32, RomDatabase};
+pub use patch::apply_patch;
+
 use crate::cartridge::Mirroring;
+use crate::state::{Snapshot, StateBuffer};
+use std::fmt;
+use std::io;
+use std::path::Path;
 
 const INES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 pub const PRG_PAGE_SIZE: usize = 16384;
 pub const CHR_PAGE_SIZE: usize = 8192;
 
-/// Represents the iNES header.
+/// Represents the iNES header, or the NES 2.0 header which extends it.
 ///
 /// 0-3     Constant $4E $45 $53 $1A (ASCII "NES" followed by MS-DOS end-of-file)
 /// 4       Size of PRG ROM in 16 KB units
@@ -15,6 +29,19 @@ pub const CHR_PAGE_SIZE: usize = 8192;
 /// 9       Flags 9 – TV system (rarely used extension)
 /// 10      Flags 10 – TV system, PRG-RAM presence (unofficial, rarely used extension)
 /// 11-15   Unused padding (should be filled with zero, but some rippers put their name across bytes 7-15)
+///
+/// NES 2.0 headers replace bytes 8-15 with:
+///
+/// 8       Mapper MSB / submapper
+/// 9       PRG ROM / CHR ROM size MSB
+/// 10      PRG RAM / PRG NVRAM size
+/// 11      CHR RAM / CHR NVRAM size
+/// 12      CPU/PPU timing
+/// 13      Vs. System type, or extended console type
+/// 14      Miscellaneous ROMs
+/// 15      Default expansion device
+///
+/// See: https://www.nesdev.org/wiki/NES_2.0
 pub struct Header {
     /// Size of PRG ROM in 16 KB units
     prg_size: u8,
@@ -40,6 +67,7 @@ pub struct Header {
     /// ||||||||
     /// |||||||+- VS Unisystem
     /// ||||||+-- PlayChoice-10 (8 KB of Hint Screen data stored after CHR data)
+    /// ||||||    (NES 2.0: both bits set is an extended console type)
     /// ||||++--- If equal to 2, flags 8-15 are in NES 2.0 format
     /// ++++----- Upper nybble of mapper number
     flags_7: u8,
@@ -49,6 +77,13 @@ pub struct Header {
     /// 76543210
     /// ||||||||
     /// ++++++++- PRG RAM size
+    ///
+    /// NES 2.0: Mapper MSB / submapper
+    ///
+    /// 76543210
+    /// ||||||||
+    /// ||||++++- Mapper number bits 8-11
+    /// ++++----- Submapper number
     flags_8: u8,
 
     /// Flags 9 – TV system (rarely used extension)
@@ -57,6 +92,13 @@ pub struct Header {
     /// ||||||||
     /// |||||||+- TV system (0: NTSC; 1: PAL)
     /// +++++++-- Reserved, set to zero
+    ///
+    /// NES 2.0: PRG ROM / CHR ROM size MSB
+    ///
+    /// 76543210
+    /// ||||||||
+    /// ||||++++- PRG ROM size MSB
+    /// ++++----- CHR ROM size MSB
     flags_9: u8,
 
     /// Flags 10 – TV system, PRG-RAM presence (unofficial, rarely used extension)
@@ -66,13 +108,114 @@ pub struct Header {
     ///   ||  ++- TV system (0: NTSC; 2: PAL; 1/3: dual compatible)
     ///   |+----- PRG RAM ($6000-$7FFF) (0: present; 1: not present)
     ///   +------ 0: Board has no bus conflicts; 1: Board has bus conflict
+    ///
+    /// NES 2.0: PRG RAM / PRG NVRAM size, as shift counts where the size is
+    /// 64 << shift bytes (0 means none)
+    ///
+    /// 76543210
+    /// ||||||||
+    /// ||||++++- PRG RAM (volatile) shift count
+    /// ++++----- PRG NVRAM/EEPROM (non-volatile) shift count
     flags_10: u8,
+
+    /// NES 2.0: CHR RAM / CHR NVRAM size, as shift counts like flags 10
+    ///
+    /// 76543210
+    /// ||||||||
+    /// ||||++++- CHR RAM (volatile) shift count
+    /// ++++----- CHR NVRAM (non-volatile) shift count
+    flags_11: u8,
+
+    /// NES 2.0: CPU/PPU timing
+    ///
+    /// 76543210
+    ///       ||
+    ///       ++- CPU/PPU timing mode (0: NTSC; 1: PAL; 2: multiple-region; 3: Dendy)
+    flags_12: u8,
+
+    /// NES 2.0: Vs. System type, when the console type is Vs. System
+    ///
+    /// 76543210
+    /// ||||||||
+    /// ||||++++- Vs. PPU type
+    /// ++++----- Vs. hardware type
+    ///
+    /// Or the extended console type in the low nybble, when the console type
+    /// is extended.
+    flags_13: u8,
+
+    /// Bytes 14-15, which are only checked to tell archaic iNES headers with
+    /// garbage in bytes 7-15 apart.
+    flags_14: u8,
+    flags_15: u8,
+}
+
+/// The CPU/PPU timing of the console the ROM was made for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// The type of console the ROM was made for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu: u8, hardware: u8 },
+    PlayChoice10,
+    Extended(u8),
 }
 
 impl Header {
+    /// Returns true if the header is in the NES 2.0 format.
+    pub fn nes2(&self) -> bool {
+        self.ines_version() == 2
+    }
+
+    /// Returns true if the header is in the archaic iNES format, where bytes
+    /// 7-15 may hold garbage such as a ripper's name (e.g. "DiskDude!").
+    fn archaic(&self) -> bool {
+        !self.nes2()
+            && (self.ines_version() != 0
+                || [self.flags_12, self.flags_13, self.flags_14, self.flags_15] != [0; 4])
+    }
+
     /// Returns the mapper number.
-    pub fn mapper(&self) -> u8 {
-        (self.flags_7 & 0xF0) | (self.flags_6 >> 4)
+    ///
+    /// Archaic iNES headers only provide the lower nybble, as the upper
+    /// nybble is usually garbage.
+    pub fn mapper(&self) -> u16 {
+        let lower = (self.flags_6 >> 4) as u16;
+
+        match (self.nes2(), self.archaic()) {
+            (true, _) => ((self.flags_8 as u16 & 0xF) << 8) | (self.flags_7 & 0xF0) as u16 | lower,
+            (false, false) => (self.flags_7 & 0xF0) as u16 | lower,
+            (false, true) => lower,
+        }
+    }
+
+    /// Returns the submapper number, which is always 0 without NES 2.0.
+    pub fn submapper(&self) -> u8 {
+        match self.nes2() {
+            true => self.flags_8 >> 4,
+            false => 0,
+        }
+    }
+
+    /// Returns true if the board of a discrete logic mapper (UxROM, CNROM or
+    /// AxROM) has bus conflicts, as given by its NES 2.0 submapper. Boards of
+    /// the same mapper number differ, so the given default is returned when
+    /// the submapper doesn't say.
+    ///
+    /// See: https://www.nesdev.org/wiki/NES_2.0_submappers
+    pub fn bus_conflicts(&self, default: bool) -> bool {
+        match self.submapper() {
+            1 => false,
+            2 => true,
+            _ => default,
+        }
     }
 
     /// Returns true if the ROM provides four-screen VRAM.
@@ -90,18 +233,108 @@ impl Header {
         }
     }
 
+    /// Returns true if the cartridge has battery-backed memory.
+    pub fn battery(&self) -> bool {
+        self.flags_6 & 0x2 != 0
+    }
+
     /// Returns the size of the PRG ROM in bytes.
     pub fn prg_size(&self) -> usize {
-        self.prg_size as usize
+        let msb = match self.nes2() {
+            true => self.flags_9 & 0xF,
+            false => 0,
+        };
+
+        rom_size(self.prg_size, msb, PRG_PAGE_SIZE)
     }
 
     /// Returns the size of the CHR ROM in bytes.
     pub fn chr_size(&self) -> usize {
-        self.chr_size as usize
+        let msb = match self.nes2() {
+            true => self.flags_9 >> 4,
+            false => 0,
+        };
+
+        rom_size(self.chr_size, msb, CHR_PAGE_SIZE)
+    }
+
+    /// Returns the size of the volatile PRG RAM in bytes. iNES headers are
+    /// assumed to have 8 KB, unless it is battery-backed.
+    pub fn prg_ram_size(&self) -> usize {
+        match (self.nes2(), self.battery()) {
+            (true, _) => ram_size(self.flags_10 & 0xF),
+            (false, false) => 0x2000,
+            (false, true) => 0,
+        }
+    }
+
+    /// Returns the size of the non-volatile PRG RAM in bytes. iNES headers
+    /// are assumed to have 8 KB if the battery flag is set.
+    pub fn prg_nvram_size(&self) -> usize {
+        match (self.nes2(), self.battery()) {
+            (true, _) => ram_size(self.flags_10 >> 4),
+            (false, true) => 0x2000,
+            (false, false) => 0,
+        }
+    }
+
+    /// Returns the size of the volatile CHR RAM in bytes. iNES headers are
+    /// assumed to have 8 KB if there is no CHR ROM.
+    pub fn chr_ram_size(&self) -> usize {
+        match (self.nes2(), self.chr_size()) {
+            (true, _) => ram_size(self.flags_11 & 0xF),
+            (false, 0) => CHR_PAGE_SIZE,
+            (false, _) => 0,
+        }
+    }
+
+    /// Returns the size of the non-volatile CHR RAM in bytes, which only NES
+    /// 2.0 headers can specify.
+    pub fn chr_nvram_size(&self) -> usize {
+        match self.nes2() {
+            true => ram_size(self.flags_11 >> 4),
+            false => 0,
+        }
+    }
+
+    /// Returns the CPU/PPU timing. iNES headers use the rarely set TV system
+    /// bit of flags 9, and archaic headers are assumed to be NTSC.
+    pub fn timing(&self) -> Timing {
+        match (self.nes2(), self.archaic()) {
+            (true, _) => match self.flags_12 & 0x3 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            },
+            (false, false) if self.flags_9 & 0x1 != 0 => Timing::Pal,
+            _ => Timing::Ntsc,
+        }
+    }
+
+    /// Returns the console type.
+    pub fn console_type(&self) -> ConsoleType {
+        if self.archaic() {
+            return ConsoleType::Nes;
+        }
+
+        match (self.nes2(), self.flags_7 & 0x3) {
+            (true, 1) => ConsoleType::VsSystem {
+                ppu: self.flags_13 & 0xF,
+                hardware: self.flags_13 >> 4,
+            },
+            (false, 1) => ConsoleType::VsSystem {
+                ppu: 0,
+                hardware: 0,
+            },
+            (_, 2) => ConsoleType::PlayChoice10,
+            (true, 3) => ConsoleType::Extended(self.flags_13 & 0xF),
+            _ => ConsoleType::Nes,
+        }
     }
 
     /// Returns true if the ROM contains a trainer.
-    pub fn skip_trainer(&self) -> bool {
+    pub fn has_trainer(&self) -> bool {
         self.flags_6 & 0x4 != 0
     }
 
@@ -120,52 +353,256 @@ impl Header {
             flags_8: bytes[8],
             flags_9: bytes[9],
             flags_10: bytes[10],
+            flags_11: bytes[11],
+            flags_12: bytes[12],
+            flags_13: bytes[13],
+            flags_14: bytes[14],
+            flags_15: bytes[15],
         }
     }
 }
 
-/// Represents a ROM in the iNES format.
+impl fmt::Display for Header {
+    /// Formats a summary of the header, such as "mapper 4, 256 KB PRG ROM,
+    /// 128 KB CHR ROM, 8 KB PRG NVRAM, NTSC".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mapper {}", self.mapper())?;
+        if self.nes2() {
+            write!(f, ".{}", self.submapper())?;
+        }
+
+        let memory = [
+            (self.prg_size(), "PRG ROM"),
+            (self.chr_size(), "CHR ROM"),
+            (self.prg_ram_size(), "PRG RAM"),
+            (self.prg_nvram_size(), "PRG NVRAM"),
+            (self.chr_ram_size(), "CHR RAM"),
+            (self.chr_nvram_size(), "CHR NVRAM"),
+        ];
+        for (size, name) in memory.iter().filter(|(size, _)| *size > 0) {
+            match size % 1024 {
+                0 => write!(f, ", {} KB {}", size / 1024, name)?,
+                _ => write!(f, ", {} bytes {}", size, name)?,
+            }
+        }
+
+        match self.timing() {
+            Timing::Ntsc => write!(f, ", NTSC")?,
+            Timing::Pal => write!(f, ", PAL")?,
+            Timing::MultiRegion => write!(f, ", multiple-region")?,
+            Timing::Dendy => write!(f, ", Dendy")?,
+        }
+
+        match self.console_type() {
+            ConsoleType::Nes => Ok(()),
+            ConsoleType::VsSystem { ppu, hardware } => write!(
+                f,
+                ", Vs. System (PPU type {}, hardware type {})",
+                ppu, hardware
+            ),
+            ConsoleType::PlayChoice10 => write!(f, ", PlayChoice-10"),
+            ConsoleType::Extended(console) => write!(f, ", extended console type {}", console),
+        }
+    }
+}
+
+/// Returns the size in bytes of a PRG or CHR ROM from the LSB and MSB of its
+/// size in units. An MSB of $F means the LSB holds the size in exponent-
+/// multiplier notation (EEEE EEMM), where the size is 2^E * (MM * 2 + 1).
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    match msb {
+        0xF => {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0x3) as usize * 2 + 1;
+            2usize.saturating_pow(exponent).saturating_mul(multiplier)
+        }
+        _ => (((msb as usize) << 8) | lsb as usize) * unit,
+    }
+}
+
+/// Returns the size in bytes of a NES 2.0 RAM from its shift count.
+fn ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        _ => 64 << shift,
+    }
+}
+
+/// Returns the name of the game in the ROM file at the given path, from its
+/// file name without the extension or the tags of ROM sets, such as
+/// "(USA)" and "[!]".
+pub fn game_name(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .map_or("".into(), |stem| stem.to_string_lossy());
+    let name = stem.split(['(', '[']).next().unwrap_or_default().trim();
+    match name.is_empty() {
+        true => stem.trim().to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// Represents an error loading a ROM.
+#[derive(Debug, PartialEq)]
+pub enum RomError {
+    /// The file doesn't start with the iNES tag.
+    BadMagic,
+
+    /// The header is truncated or describes an impossible ROM.
+    InvalidHeader(&'static str),
+
+    /// The file holds fewer bytes of PRG ROM than the header specifies.
+    TruncatedPrg { expected: usize, actual: usize },
+
+    /// The file holds fewer bytes of CHR ROM than the header specifies.
+    TruncatedChr { expected: usize, actual: usize },
+
+    /// The PRG ROM is smaller than the banks its mapper maps at once.
+    PrgTooSmall { minimum: usize, actual: usize },
+
+    /// The CHR ROM is smaller than the pattern tables.
+    ChrTooSmall { minimum: usize, actual: usize },
+
+    /// The ROM uses a mapper which isn't emulated.
+    UnsupportedMapper(u16),
+
+    /// The ROM couldn't be read.
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "file is not in iNES or NES 2.0 format"),
+            RomError::InvalidHeader(reason) => write!(f, "invalid header, {}", reason),
+            RomError::TruncatedPrg { expected, actual } => write!(
+                f,
+                "PRG ROM is truncated, expected {} bytes but found {}",
+                expected, actual
+            ),
+            RomError::TruncatedChr { expected, actual } => write!(
+                f,
+                "CHR ROM is truncated, expected {} bytes but found {}",
+                expected, actual
+            ),
+            RomError::PrgTooSmall { minimum, actual } => write!(
+                f,
+                "PRG ROM is too small for its mapper, expected at least {} bytes but found {}",
+                minimum, actual
+            ),
+            RomError::ChrTooSmall { minimum, actual } => write!(
+                f,
+                "CHR ROM is too small, expected at least {} bytes but found {}",
+                minimum, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            RomError::Io(kind) => write!(f, "failed to read the ROM, {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// Represents a ROM in the iNES or NES 2.0 format.
 ///
 /// See: https://www.nesdev.org/wiki/INES
 pub struct Rom {
-    /// The ROM header in iNES format.
+    /// The ROM header in iNES or NES 2.0 format.
     pub header: Header,
 
     /// Contains program code.
     pub prg: Vec<u8>,
 
-    /// Contains pattern tables and graphics.
+    /// Contains pattern tables and graphics, in CHR ROM or CHR RAM.
     pub chr: Vec<u8>,
+
+    /// Contains the 512 byte trainer, which is loaded into $7000-$71FF.
+    /// Trainers hold code added by copiers, such as patches for ROMs which
+    /// were converted to run on a different mapper.
+    pub trainer: Option<Vec<u8>>,
 }
 
 impl Rom {
-    pub fn new(raw: &[u8]) -> Result<Rom, String> {
-        if raw[0..4] != INES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if !raw.starts_with(&INES_TAG) {
+            return Err(RomError::BadMagic);
+        }
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::InvalidHeader("the header is truncated"));
         }
 
         let header = Header::from_bytes(raw);
-        if header.ines_version() != 0 {
-            return Err("NES2.0 format is not supported".to_string());
-        }
 
-        // PRG is sized in 16kb units.
-        let prg_size = header.prg_size() * PRG_PAGE_SIZE;
+        let prg_size = header.prg_size();
+        let chr_size = header.chr_size();
+        if prg_size == 0 {
+            return Err(RomError::InvalidHeader("the PRG ROM size is 0"));
+        }
 
-        // CHR is sized in 8kb units.
-        let chr_size = header.chr_size() * CHR_PAGE_SIZE;
+        // The trainer sits between the header and the PRG ROM.
+        let trainer = match header.has_trainer() {
+            true => match raw.get(HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE) {
+                Some(trainer) => Some(trainer.to_vec()),
+                None => return Err(RomError::InvalidHeader("the trainer is truncated")),
+            },
+            false => None,
+        };
 
-        let prg_start = 16 + if header.skip_trainer() { 512 } else { 0 };
+        let prg_start = HEADER_SIZE + trainer.as_ref().map_or(0, |t| t.len());
         let chr_start = prg_start + prg_size;
 
-        let prg = raw[prg_start..(prg_start + prg_size)].to_vec();
-        let chr = if header.chr_size() > 0 {
-            raw[chr_start..(chr_start + chr_size)].to_vec()
+        let section = |start: usize, size: usize| {
+            let available = raw.len().saturating_sub(start).min(size);
+            match available == size {
+                true => Ok(raw[start..start + size].to_vec()),
+                false => Err((size, available)),
+            }
+        };
+
+        let prg = section(prg_start, prg_size)
+            .map_err(|(expected, actual)| RomError::TruncatedPrg { expected, actual })?;
+
+        // Boards without CHR ROM have CHR RAM instead, which is at least 8 KB
+        // so the pattern tables are always backed.
+        let chr = if chr_size > 0 {
+            section(chr_start, chr_size)
+                .map_err(|(expected, actual)| RomError::TruncatedChr { expected, actual })?
         } else {
-            vec![0; CHR_PAGE_SIZE]
+            let chr_ram_size = header.chr_ram_size() + header.chr_nvram_size();
+            vec![0; chr_ram_size.max(CHR_PAGE_SIZE)]
+        };
+
+        Ok(Rom {
+            header,
+            prg,
+            chr,
+            trainer,
+        })
+    }
+
+    /// Returns true if the cartridge has CHR RAM rather than CHR ROM, so the
+    /// pattern tables are writable.
+    pub fn has_chr_ram(&self) -> bool {
+        self.header.chr_size() == 0
+    }
+
+    /// Returns the CRC32 of the PRG and CHR ROM, which identifies the game
+    /// whatever its header.
+    pub fn crc32(&self) -> u32 {
+        let chr: &[u8] = match self.has_chr_ram() {
+            true => &[],
+            false => &self.chr,
         };
+        database::crc32(&[self.prg.as_slice(), chr].concat())
+    }
+}
 
-        Ok(Rom { header, prg, chr })
+/// Only CHR RAM is saved, the rest of the ROM never changes.
+impl Snapshot for Rom {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        if self.has_chr_ram() {
+            self.chr.snapshot(state);
+        }
     }
 }
 
@@ -177,6 +614,18 @@ pub mod tests {
     const HEADER_TRAINER_ENABLED: u8 = 0b00110100;
     const HEADER_NES_2_0: u8 = 0b00001000;
 
+    /// Returns the given number of ROM banks of the given size, where the
+    /// first byte of each bank holds its number and the second $FF, for
+    /// telling which bank a mapper has switched in.
+    pub fn numbered_banks(banks: usize, bank_size: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * bank_size];
+        for (bank, chunk) in rom.chunks_mut(bank_size).enumerate() {
+            chunk[0] = bank as u8;
+            chunk[1] = 0xFF;
+        }
+        rom
+    }
+
     /// Creates a new test ROM with given values.
     pub fn test_rom(
         prg_size: usize,
@@ -186,7 +635,7 @@ pub mod tests {
         trainer: Option<Vec<u8>>,
         flags_7: Option<u8>,
         mirroring: Option<Mirroring>,
-    ) -> Result<Rom, String> {
+    ) -> Result<Rom, RomError> {
         // Zero-pad PRG ROM up to the 16KB page size.
         let mut prg_rom = prg.clone();
         prg_rom.resize(prg_size * PRG_PAGE_SIZE, 0);
@@ -274,8 +723,8 @@ pub mod tests {
             prg_size,
             vec![0xA9, 0x05],
             chr_size,
-            vec![0x00, 0x00],
-            Some(vec![0; 512]),
+            vec![0x12, 0x34],
+            Some(vec![0x42; 512]),
             None,
             None,
         )
@@ -283,14 +732,22 @@ pub mod tests {
 
         assert_eq!(rom.prg[0..2], vec![0xA9, 0x05]);
         assert_eq!(rom.prg.len(), prg_size * PRG_PAGE_SIZE);
-        assert_eq!(rom.chr[0..2], vec![0x00, 0x00]);
+        assert_eq!(rom.chr[0..2], vec![0x12, 0x34]);
         assert_eq!(rom.chr.len(), chr_size * CHR_PAGE_SIZE);
+        assert_eq!(rom.trainer, Some(vec![0x42; 512]));
         assert_eq!(rom.header.mapper(), 3);
         assert_eq!(rom.header.mirroring(), Mirroring::Horizontal);
     }
 
+    /// Returns a header from the given bytes 4-15.
+    fn test_header(bytes: [u8; 12]) -> Header {
+        let mut header = INES_TAG.to_vec();
+        header.extend(bytes);
+        Header::from_bytes(&header)
+    }
+
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2() {
         let rom = test_rom(
             1,
             vec![0xA9, 0x05],
@@ -299,11 +756,196 @@ pub mod tests {
             None,
             Some(HEADER_NES_2_0),
             None,
+        )
+        .unwrap();
+
+        assert!(rom.header.nes2());
+        assert_eq!(rom.prg[0..2], vec![0xA9, 0x05]);
+        assert_eq!(rom.header.prg_size(), PRG_PAGE_SIZE);
+        assert_eq!(rom.header.chr_size(), CHR_PAGE_SIZE);
+        assert_eq!(rom.header.prg_ram_size(), 0);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let header = test_header([
+            0x02, 0x00, 0x52, 0x18, 0x31, 0x01, 0x70, 0x07, 0x01, 0x00, 0x00, 0x00,
+        ]);
+
+        assert_eq!(header.mapper(), 0x115);
+        assert_eq!(header.submapper(), 3);
+        assert_eq!(header.prg_size(), 0x102 * PRG_PAGE_SIZE);
+        assert_eq!(header.chr_size(), 0);
+        assert_eq!(header.prg_ram_size(), 0);
+        assert_eq!(header.prg_nvram_size(), 0x2000);
+        assert_eq!(header.chr_ram_size(), 0x2000);
+        assert_eq!(header.chr_nvram_size(), 0);
+        assert_eq!(header.timing(), Timing::Pal);
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+        assert_eq!(
+            header.to_string(),
+            "mapper 277.3, 4128 KB PRG ROM, 8 KB PRG NVRAM, 8 KB CHR RAM, PAL"
         );
+    }
 
-        match rom {
-            Ok(_) => unreachable!("should not load rom"),
-            Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+    #[test]
+    fn test_nes2_bus_conflicts() {
+        let header = test_header([
+            0x02, 0x01, 0x20, 0x08, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert!(!header.bus_conflicts(true));
+
+        let header = test_header([
+            0x02, 0x01, 0x20, 0x08, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert!(header.bus_conflicts(false));
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        // 2^10 * 3 bytes of PRG ROM.
+        let header = test_header([
+            0x29, 0x00, 0x00, 0x08, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert_eq!(header.prg_size(), 3072);
+    }
+
+    #[test]
+    fn test_nes2_console_types() {
+        let header = test_header([
+            0x01, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x00,
+        ]);
+        assert_eq!(
+            header.console_type(),
+            ConsoleType::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+
+        let header = test_header([
+            0x01, 0x00, 0x00, 0x0B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00,
+        ]);
+        assert_eq!(header.console_type(), ConsoleType::Extended(3));
+    }
+
+    #[test]
+    fn test_ines_heuristics() {
+        let header = test_header([
+            0x02, 0x00, 0x12, 0x40, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert_eq!(header.mapper(), 0x41);
+        assert_eq!(header.submapper(), 0);
+        assert!(header.bus_conflicts(true));
+        assert_eq!(header.prg_ram_size(), 0);
+        assert_eq!(header.prg_nvram_size(), 0x2000);
+        assert_eq!(header.chr_ram_size(), CHR_PAGE_SIZE);
+        assert_eq!(header.timing(), Timing::Pal);
+
+        // Archaic headers with garbage in bytes 7-15 ignore the upper nybble
+        // of the mapper.
+        let mut bytes = [0; 12];
+        bytes[..2].copy_from_slice(&[0x02, 0x01]);
+        bytes[2] = 0x10;
+        bytes[3..].copy_from_slice(b"DiskDude!");
+        let header = test_header(bytes);
+        assert_eq!(header.mapper(), 1);
+        assert_eq!(header.timing(), Timing::Ntsc);
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let rom = test_rom(1, vec![], 0, vec![], None, None, None).unwrap();
+        assert!(rom.has_chr_ram());
+        assert_eq!(rom.chr.len(), CHR_PAGE_SIZE);
+
+        // NES 2.0 headers specify the size of the CHR RAM.
+        let mut raw = INES_TAG.to_vec();
+        raw.extend([1, 0, 0, HEADER_NES_2_0, 0, 0, 0, 0x09, 0, 0, 0, 0]);
+        raw.resize(HEADER_SIZE + PRG_PAGE_SIZE, 0);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert!(rom.has_chr_ram());
+        assert_eq!(rom.chr.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_crc32() {
+        let rom = test_rom(1, vec![1, 2, 3], 1, vec![4], None, None, None).unwrap();
+        let mut data = vec![1, 2, 3];
+        data.resize(PRG_PAGE_SIZE, 0);
+        data.push(4);
+        data.resize(PRG_PAGE_SIZE + CHR_PAGE_SIZE, 0);
+        assert_eq!(rom.crc32(), database::crc32(&data));
+
+        // The header doesn't change the CRC.
+        let vertical = test_rom(
+            1,
+            vec![1, 2, 3],
+            1,
+            vec![4],
+            None,
+            None,
+            Some(Mirroring::Vertical),
+        );
+        assert_eq!(vertical.unwrap().crc32(), rom.crc32());
+
+        let other = test_rom(1, vec![1, 2, 4], 1, vec![4], None, None, None).unwrap();
+        assert_ne!(other.crc32(), rom.crc32());
+    }
+
+    #[test]
+    fn test_game_name() {
+        assert_eq!(
+            game_name("roms/Super Mario Bros. (World).nes"),
+            "Super Mario Bros."
+        );
+        assert_eq!(game_name("Zelda (U) (PRG1) [!].zip"), "Zelda");
+        assert_eq!(game_name("/path/to/nestest.nes"), "nestest");
+        assert_eq!(game_name("[BIOS] Test.nes"), "[BIOS] Test");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Rom::new(b"NSF").err(), Some(RomError::BadMagic));
+        assert_eq!(
+            Rom::new(&INES_TAG).err(),
+            Some(RomError::InvalidHeader("the header is truncated"))
+        );
+
+        let rom = test_rom(0, vec![], 0, vec![], None, None, None);
+        assert_eq!(
+            rom.err(),
+            Some(RomError::InvalidHeader("the PRG ROM size is 0"))
+        );
+
+        let mut raw = INES_TAG.to_vec();
+        raw.extend([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.resize(HEADER_SIZE + PRG_PAGE_SIZE, 0);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::TruncatedPrg {
+                expected: 2 * PRG_PAGE_SIZE,
+                actual: PRG_PAGE_SIZE
+            })
+        );
+
+        raw.resize(HEADER_SIZE + 2 * PRG_PAGE_SIZE + 0x100, 0);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::TruncatedChr {
+                expected: CHR_PAGE_SIZE,
+                actual: 0x100
+            })
+        );
+
+        // Set the trainer flag.
+        raw[6] = 0x4;
+        raw.truncate(HEADER_SIZE + 0x100);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::InvalidHeader("the trainer is truncated"))
+        );
     }
 }
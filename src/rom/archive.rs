@@ -0,0 +1,80 @@
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use zip::ZipArchive;
+
+/// Signature at the start of a zip archive (the first local file header).
+const ZIP_TAG: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+
+/// Reads a ROM file. If the file is a zip archive, the first .nes file in it
+/// is extracted instead, as most ROM collections are zipped.
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    match bytes.starts_with(&ZIP_TAG) {
+        true => extract_rom(&bytes),
+        false => Ok(bytes),
+    }
+}
+
+/// Returns the contents of the first .nes file in the given zip archive.
+fn extract_rom(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if !file.is_file() || !file.name().to_ascii_lowercase().ends_with(".nes") {
+            continue;
+        }
+
+        let mut rom = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut rom)?;
+        return Ok(rom);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no .nes file in the zip archive",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    /// Returns a zip archive holding the given files, compressed with deflate.
+    fn test_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, data) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_rom() {
+        let zip = test_zip(&[
+            ("readme.txt", b"not a rom"),
+            ("roms/Game (U).NES", b"NES\x1a first"),
+            ("Game (E).nes", b"NES\x1a second"),
+        ]);
+        assert!(zip.starts_with(&ZIP_TAG));
+        assert_eq!(extract_rom(&zip).unwrap(), b"NES\x1a first");
+    }
+
+    #[test]
+    fn test_extract_rom_missing() {
+        let zip = test_zip(&[("readme.txt", b"not a rom")]);
+        let err = extract_rom(&zip).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{HEADER_SIZE, INES_TAG, TRAINER_SIZE};
+
+/// A database of known good NES 2.0 headers, used to correct the headers of
+/// old dumps which specify the wrong mapper, mirroring or RAM sizes.
+///
+/// Each ROM is identified by the CRC32 of the data following its header and
+/// trainer. The database is a text file with a line per ROM, holding the CRC32
+/// and the corrected 16 byte header in hex, optionally followed by the name
+/// of the game. Blank lines and lines starting with # are ignored:
+///
+/// # CRC32   NES 2.0 header                   Name
+/// 1A2B3C4D 4E45531A080008120000070000000000 Super Mario Bros.
+///
+/// See: https://www.nesdev.org/wiki/NES_2.0
+pub struct RomDatabase {
+    headers: HashMap<u32, [u8; HEADER_SIZE]>,
+    names: HashMap<u32, String>,
+}
+
+impl RomDatabase {
+    /// Loads a database from the given file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        RomDatabase::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Parses a database from its text.
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut headers = HashMap::new();
+        let mut names = HashMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let entry = match (fields.next(), fields.next()) {
+                (Some(crc), Some(header)) => parse_entry(crc, header),
+                _ => None,
+            };
+
+            let Some((crc, header)) = entry else {
+                return Err(format!("invalid ROM database entry on line {}", number + 1));
+            };
+            headers.insert(crc, header);
+
+            // The rest of the line, if any, is the name.
+            let name = fields.collect::<Vec<_>>().join(" ");
+            if !name.is_empty() {
+                names.insert(crc, name);
+            }
+        }
+
+        Ok(RomDatabase { headers, names })
+    }
+
+    /// Returns the name of the game in the given ROM file, or None if the
+    /// ROM isn't in the database or has no name there.
+    pub fn name(&self, raw: &[u8]) -> Option<&str> {
+        self.names.get(&data_crc32(raw)?).map(String::as_str)
+    }
+
+    /// Returns the given ROM file with its header replaced by the one in the
+    /// database, or None if the ROM isn't in the database. The trainer flag
+    /// of the original header is kept, as the data was identified by it.
+    pub fn fix_header(&self, raw: &[u8]) -> Option<Vec<u8>> {
+        let header = self.headers.get(&data_crc32(raw)?)?;
+
+        let trainer = raw[6] & 0x4;
+        let mut fixed = raw.to_vec();
+        fixed[..HEADER_SIZE].copy_from_slice(header);
+        fixed[6] = (header[6] & !0x4) | trainer;
+
+        Some(fixed)
+    }
+}
+
+/// Returns the CRC32 of the data following the header and trainer of the
+/// given ROM file, which identifies it in the database, or None if it isn't
+/// an iNES file.
+fn data_crc32(raw: &[u8]) -> Option<u32> {
+    if raw.len() < HEADER_SIZE || !raw.starts_with(&INES_TAG) {
+        return None;
+    }
+
+    let trainer = raw[6] & 0x4;
+    let data_start = HEADER_SIZE + if trainer != 0 { TRAINER_SIZE } else { 0 };
+    Some(crc32(raw.get(data_start..)?))
+}
+
+/// Returns the CRC32 and header of a database entry, if they are valid.
+fn parse_entry(crc: &str, header: &str) -> Option<(u32, [u8; HEADER_SIZE])> {
+    let crc = u32::from_str_radix(crc, 16).ok()?;
+    if header.len() != HEADER_SIZE * 2 || !header.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; HEADER_SIZE];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&header[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    match bytes.starts_with(&INES_TAG) {
+        true => Some((crc, bytes)),
+        false => None,
+    }
+}
+
+/// Returns the CRC32 (as used by zip and the NES 2.0 database) of the given
+/// data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 0x1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xEDB88320,
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_parse() {
+        let database = RomDatabase::parse(
+            "# CRC32   NES 2.0 header\n\
+             \n\
+             CBF43926 4E45531A010008080000000000000000\n",
+        )
+        .unwrap();
+        assert_eq!(database.headers[&0xCBF43926][6], 0x08);
+        assert!(database.names.is_empty());
+
+        let database =
+            RomDatabase::parse("CBF43926 4E45531A010008080000000000000000  Super Mario Bros. ")
+                .unwrap();
+        assert_eq!(database.names[&0xCBF43926], "Super Mario Bros.");
+
+        for text in [
+            "CBF43926",
+            "CBF43926 4E45531A01000808",
+            "XYZ 4E45531A010008080000000000000000",
+            "CBF43926 00000000010008080000000000000000",
+        ] {
+            assert!(RomDatabase::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_fix_header() {
+        let database = RomDatabase::parse("CBF43926 4E45531A010008080000000000000000").unwrap();
+
+        // Mapper 3 with a trainer, corrected to mapper 0 with four screen VRAM.
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x34, 0x00];
+        raw.resize(HEADER_SIZE + TRAINER_SIZE, 0);
+        raw.extend(b"123456789");
+
+        let fixed = database.fix_header(&raw).unwrap();
+        assert_eq!(fixed[6], 0x0C);
+        assert_eq!(fixed[7], 0x08);
+        assert_eq!(fixed[HEADER_SIZE..], raw[HEADER_SIZE..]);
+
+        // Without the trainer the data doesn't match.
+        raw[6] = 0x30;
+        assert_eq!(database.fix_header(&raw), None);
+    }
+
+    #[test]
+    fn test_name() {
+        let database =
+            RomDatabase::parse("CBF43926 4E45531A010008080000000000000000 Game").unwrap();
+
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A];
+        raw.resize(HEADER_SIZE, 0);
+        raw.extend(b"123456789");
+        assert_eq!(database.name(&raw), Some("Game"));
+
+        raw.push(0);
+        assert_eq!(database.name(&raw), None);
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+
+use super::database::crc32;
+
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_TAG: &[u8] = b"BPS1";
+
+/// Size of the BPS footer, holding the CRC32s of the source, target and patch.
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// Most bytes reserved up front for a BPS patched file, whatever size the
+/// patch gives, so a corrupt size can't exhaust the memory on its own.
+const BPS_MAX_RESERVE: usize = 16 * 1024 * 1024;
+
+/// Represents an error applying a patch.
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// The patch is neither in IPS nor BPS format.
+    UnknownFormat,
+
+    /// The patch ends part way through a record.
+    Truncated,
+
+    /// The patch copies data from outside of the ROM.
+    OutOfBounds,
+
+    /// The patch holds a number too large to use, or writes more than the
+    /// size it gives for the patched ROM.
+    Corrupt,
+
+    /// The CRC32 of the ROM doesn't match the one the BPS patch was made for.
+    SourceMismatch,
+
+    /// The CRC32 of the patched ROM doesn't match the one in the BPS patch.
+    TargetMismatch,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "patch is not in IPS or BPS format"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::OutOfBounds => write!(f, "patch reads beyond the end of the ROM"),
+            PatchError::Corrupt => write!(f, "patch is corrupt"),
+            PatchError::SourceMismatch => write!(f, "patch was made for a different ROM"),
+            PatchError::TargetMismatch => write!(f, "patched ROM has the wrong checksum"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Applies an IPS or BPS patch to a ROM file (including its header),
+/// returning the patched file.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_TAG) {
+        apply_ips(rom, &patch[IPS_TAG.len()..])
+    } else if patch.starts_with(BPS_TAG) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+/// Reads bytes from the front of a patch.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Returns the next n bytes.
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PatchError> {
+        if self.data.len() < n {
+            return Err(PatchError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Returns the next n bytes as a big endian number.
+    fn number(&mut self, n: usize) -> Result<usize, PatchError> {
+        let bytes = self.bytes(n)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// Returns the next variable length number, as used by BPS. Each byte
+    /// holds 7 bits, with bit 7 set on the last byte.
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+
+        loop {
+            let byte = self.bytes(1)?[0] as usize;
+            value = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(PatchError::Corrupt)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Corrupt)?;
+            value = value.checked_add(shift).ok_or(PatchError::Corrupt)?;
+        }
+    }
+}
+
+/// Applies the records of an IPS patch, following the "PATCH" tag.
+///
+/// Each record holds a 3 byte offset, a 2 byte size and the data to write. A
+/// size of 0 marks a run length encoded record, holding a 2 byte length and
+/// the byte to repeat. The records end with "EOF", which may be followed by a
+/// 3 byte size to truncate the file to.
+///
+/// See: https://zerosoft.zophar.net/ips.php
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut output = rom.to_vec();
+    let mut reader = Reader { data: patch };
+
+    loop {
+        if reader.data.starts_with(IPS_EOF) && reader.data.len() <= 6 {
+            reader.bytes(IPS_EOF.len())?;
+            break;
+        }
+
+        let offset = reader.number(3)?;
+        let data = match reader.number(2)? {
+            0 => {
+                let length = reader.number(2)?;
+                vec![reader.bytes(1)?[0]; length]
+            }
+            size => reader.bytes(size)?.to_vec(),
+        };
+
+        let end = offset + data.len();
+        if output.len() < end {
+            output.resize(end, 0);
+        }
+        output[offset..end].copy_from_slice(&data);
+    }
+
+    if !reader.data.is_empty() {
+        output.truncate(reader.number(3)?);
+    }
+
+    Ok(output)
+}
+
+/// Applies a BPS patch.
+///
+/// BPS patches build the patched file from a series of actions, which copy
+/// data from the original file, the patch, or earlier in the patched file.
+/// The patch ends with the CRC32s of the original file, patched file and the
+/// patch itself.
+///
+/// See: https://www.romhacking.net/documents/746/
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_TAG.len() + BPS_FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_SIZE);
+    let checksum = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    if crc32(rom) != checksum(0) {
+        return Err(PatchError::SourceMismatch);
+    }
+
+    let mut reader = Reader {
+        data: &body[BPS_TAG.len()..],
+    };
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    let mut output = Vec::with_capacity(target_size.min(BPS_MAX_RESERVE));
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while !reader.data.is_empty() {
+        let action = reader.varint()?;
+        let length = (action >> 2) + 1;
+
+        // Lengths are checked against the size given for the patched file,
+        // which bounds the offsets of its reads and copies.
+        if length > target_size - output.len() {
+            return Err(PatchError::Corrupt);
+        }
+
+        match action & 0x3 {
+            // Source read: copy from the same offset in the original file.
+            0 => {
+                let start = output.len();
+                let data = rom.get(start..start + length);
+                output.extend_from_slice(data.ok_or(PatchError::OutOfBounds)?);
+            }
+
+            // Target read: copy from the patch.
+            1 => output.extend_from_slice(reader.bytes(length)?),
+
+            // Source copy: copy from a relative offset in the original file.
+            2 => {
+                source_offset = relative_offset(source_offset, reader.varint()?)?;
+                let end = source_offset.checked_add(length);
+                let data = end.and_then(|end| rom.get(source_offset..end));
+                output.extend_from_slice(data.ok_or(PatchError::OutOfBounds)?);
+                source_offset += length;
+            }
+
+            // Target copy: copy from a relative offset in the patched file. The
+            // ranges can overlap, to repeat a pattern, so this goes byte by byte.
+            _ => {
+                target_offset = relative_offset(target_offset, reader.varint()?)?;
+                for _ in 0..length {
+                    let byte = *output.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    match crc32(&output) == checksum(4) {
+        true => Ok(output),
+        false => Err(PatchError::TargetMismatch),
+    }
+}
+
+/// Moves a BPS copy offset by a relative amount, where bit 0 holds the sign.
+fn relative_offset(offset: usize, data: usize) -> Result<usize, PatchError> {
+    let delta = data >> 1;
+    match data & 0x1 {
+        0 => offset.checked_add(delta),
+        _ => offset.checked_sub(delta),
+    }
+    .ok_or(PatchError::OutOfBounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a BPS variable length number.
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte | 0x80);
+                return bytes;
+            }
+
+            bytes.push(byte);
+            value -= 1;
+        }
+    }
+
+    /// Returns a BPS patch from the given source to target, made of the given
+    /// actions.
+    fn bps_patch(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_TAG.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(0));
+        patch.extend(actions);
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, 16511, 16512, 1 << 20, usize::MAX] {
+            let bytes = varint(value);
+            assert_eq!(Reader { data: &bytes }.varint(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_ips() {
+        let mut patch = IPS_TAG.to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        // Run length encoded record, which extends the file.
+        patch.extend([0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend(IPS_EOF);
+
+        let patched = apply_patch(&[0, 1, 2, 3], &patch).unwrap();
+        assert_eq!(patched, [0, 0xAA, 0xBB, 3, 0, 0xCC, 0xCC, 0xCC]);
+
+        // Truncation after the EOF marker.
+        patch.extend([0x00, 0x00, 0x02]);
+        assert_eq!(apply_patch(&[0, 1, 2, 3], &patch).unwrap(), [0, 0xAA]);
+    }
+
+    #[test]
+    fn test_ips_eof_offset() {
+        // A record at offset $454F46 ("EOF") isn't the end of the patch.
+        let mut patch = IPS_TAG.to_vec();
+        patch.extend(IPS_EOF);
+        patch.extend([0x00, 0x01, 0x42]);
+        patch.extend(IPS_EOF);
+
+        let patched = apply_patch(&[], &patch).unwrap();
+        assert_eq!(patched.len(), 0x454F47);
+        assert_eq!(patched[0x454F46], 0x42);
+    }
+
+    #[test]
+    fn test_ips_truncated() {
+        let mut patch = IPS_TAG.to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x04, 0xAA]);
+        assert_eq!(apply_patch(&[0; 8], &patch), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"Hello, world!";
+        let target = b"Hello, NES!!!!!!world";
+
+        let mut actions = vec![];
+        // Source read "Hello, ".
+        actions.extend(varint((7 - 1) << 2));
+        // Target read "NES!".
+        actions.extend(varint(((4 - 1) << 2) | 1));
+        actions.extend(b"NES!");
+        // Target copy "!!!!!" from the last "!", overlapping the output.
+        actions.extend(varint(((5 - 1) << 2) | 3));
+        actions.extend(varint(10 << 1));
+        // Source copy "world" from offset 7.
+        actions.extend(varint(((5 - 1) << 2) | 2));
+        actions.extend(varint(7 << 1));
+
+        let patch = bps_patch(source, target, &actions);
+        assert_eq!(apply_patch(source, &patch).unwrap(), target);
+
+        assert_eq!(
+            apply_patch(b"Goodbye", &patch),
+            Err(PatchError::SourceMismatch)
+        );
+    }
+
+    #[test]
+    fn test_bps_target_mismatch() {
+        let mut actions = varint(((2 - 1) << 2) | 1);
+        actions.extend(b"AB");
+
+        let patch = bps_patch(b"", b"AC", &actions);
+        assert_eq!(apply_patch(b"", &patch), Err(PatchError::TargetMismatch));
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        // A number with more bits than fit in a usize.
+        let mut bytes = vec![0x7F; 10];
+        bytes.push(0x80);
+        assert_eq!(Reader { data: &bytes }.varint(), Err(PatchError::Corrupt));
+
+        // A number with no last byte.
+        assert_eq!(
+            Reader { data: &[0x00] }.varint(),
+            Err(PatchError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_bps_truncated() {
+        let mut actions = varint(((4 - 1) << 2) | 1);
+        actions.extend(b"AB");
+
+        let patch = bps_patch(b"", b"ABCD", &actions);
+        assert_eq!(apply_patch(b"", &patch), Err(PatchError::Truncated));
+        assert_eq!(apply_patch(b"", &patch[..8]), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn test_bps_corrupt() {
+        let source = b"Hello";
+
+        // A target size far larger than the patch, which isn't reserved.
+        let mut patch = BPS_TAG.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(1 << 40));
+        patch.extend(varint(0));
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend([0xFF; 8]);
+        assert_eq!(apply_patch(source, &patch), Err(PatchError::TargetMismatch));
+
+        // A source read longer than the target.
+        let actions = varint((6 - 1) << 2);
+        let patch = bps_patch(source, b"Hello", &actions);
+        assert_eq!(apply_patch(source, &patch), Err(PatchError::Corrupt));
+
+        // A source read whose length overflows.
+        let actions = varint(usize::MAX & !0x3);
+        let patch = bps_patch(source, b"Hello", &actions);
+        assert_eq!(apply_patch(source, &patch), Err(PatchError::Corrupt));
+
+        // A source copy from far beyond the end of the ROM.
+        let mut patch = BPS_TAG.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(usize::MAX));
+        patch.extend(varint(0));
+        patch.extend(varint(usize::MAX & !0x1));
+        patch.extend(varint(usize::MAX & !0x1));
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend([0xFF; 8]);
+        assert_eq!(apply_patch(source, &patch), Err(PatchError::OutOfBounds));
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert_eq!(apply_patch(&[], b"UPS1"), Err(PatchError::UnknownFormat));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::bus::SystemBus;
+use crate::cpu::Cpu;
+use crate::state::StateSnapshot;
+
+/// Most frames the console can run ahead.
+pub const MAX_RUN_AHEAD: u8 = 4;
+
+/// Runs the console ahead of the frame shown, which hides the input lag
+/// of games that take a frame or more to respond to the controllers.
+///
+/// Each frame is emulated with the input polled, and a snapshot of the
+/// console taken. The following frames are then emulated predicting that
+/// the input won't change, and the last of them is shown before rolling
+/// back to the snapshot. When the input does change, the frames ahead are
+/// emulated again from the snapshot with the new input, so the player sees
+/// its effect that many frames sooner.
+///
+/// Only the first frame is heard, as the frames ahead are emulated again
+/// each frame. Running N frames ahead emulates N + 1 frames for each frame
+/// shown, so needs N + 1 times the CPU time.
+///
+/// See: https://docs.libretro.com/guides/runahead/
+pub struct RunAhead {
+    frames: u8,
+    snapshot: StateSnapshot,
+}
+
+impl RunAhead {
+    /// Returns a run-ahead of the given number of frames, where 0 runs the
+    /// console normally.
+    pub fn new(frames: u8) -> Self {
+        RunAhead {
+            frames: frames.min(MAX_RUN_AHEAD),
+            snapshot: StateSnapshot::new(),
+        }
+    }
+
+    /// Returns the number of frames the console runs ahead.
+    pub fn frames(&self) -> u8 {
+        self.frames
+    }
+
+    /// Emulates a frame with the input already polled, showing the frame
+    /// the given number of frames ahead of it. Returns true if the CPU
+    /// should be shut down.
+    pub fn run_frame(&mut self, cpu: &mut Cpu<SystemBus>) -> bool {
+        if self.frames == 0 {
+            return cpu.run_frame();
+        }
+
+        cpu.bus.set_render_enabled(false);
+        let halted = cpu.run_frame();
+        cpu.bus.set_render_enabled(true);
+        if halted {
+            return true;
+        }
+
+        // The CPU shutting down ahead is left for when the frame is reached.
+        self.snapshot.take(cpu);
+        cpu.bus.set_audio_enabled(false);
+        for frame in 1..=self.frames {
+            cpu.bus.set_render_enabled(frame == self.frames);
+            if cpu.run_frame() {
+                break;
+            }
+        }
+        cpu.bus.set_render_enabled(true);
+        cpu.bus.set_audio_enabled(true);
+        self.snapshot.restore(cpu);
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_cartridge;
+    use crate::cpu::Memory;
+    use crate::input::InputState;
+    use crate::joypad::JOYPAD_BUTTON_A;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Returns a console counting the reads of A pressed in $00, and the
+    /// frames it renders.
+    fn console(rendered: &Rc<RefCell<u32>>) -> Cpu<SystemBus<'static>> {
+        let mut prg = vec![0; 16384];
+        let program = [
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe the controllers.
+            0xA9, 0x00, 0x8D, 0x16, 0x40, //
+            0xAD, 0x16, 0x40, 0x29, 0x01, // Read A.
+            0x18, 0x65, 0x00, 0x85, 0x00, // Add it to $00.
+            0x4C, 0x00, 0x80, // Loop.
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        let cart = test_cartridge(prg, None).unwrap();
+        let rendered = Rc::clone(rendered);
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, move |_| {
+            *rendered.borrow_mut() += 1
+        });
+
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.pc = 0x8000;
+        cpu
+    }
+
+    #[test]
+    fn test_run_ahead() {
+        let rendered = Rc::new(RefCell::new(0));
+        let mut cpu = console(&rendered);
+        let mut expected = console(&Rc::new(RefCell::new(0)));
+        let mut run_ahead = RunAhead::new(2);
+        assert_eq!(run_ahead.frames(), 2);
+
+        let mut input = InputState::new(false);
+        for frame in 0..10 {
+            input.set_button_pressed(1, JOYPAD_BUTTON_A, frame >= 5);
+            cpu.bus.poll_input(&mut input);
+            assert!(!run_ahead.run_frame(&mut cpu));
+            expected.bus.poll_input(&mut input);
+            assert!(!expected.run_frame());
+
+            // The console is left at the frame emulated, having shown one
+            // frame for it.
+            assert_eq!(cpu.save_state(), expected.save_state());
+            assert_eq!(*rendered.borrow(), frame + 1);
+        }
+
+        // Only the audio of the frames emulated is heard.
+        assert_eq!(cpu.bus.audio().len(), expected.bus.audio().len());
+        assert_ne!(cpu.bus.mem_read_byte(0x00), 0);
+    }
+
+    #[test]
+    fn test_no_run_ahead() {
+        let rendered = Rc::new(RefCell::new(0));
+        let mut cpu = console(&rendered);
+        let mut run_ahead = RunAhead::new(0);
+        run_ahead.run_frame(&mut cpu);
+        assert_eq!(cpu.bus.ppu_frame_count(), 1);
+        assert_eq!(*rendered.borrow(), 1);
+        assert!(run_ahead.snapshot.is_empty());
+
+        assert_eq!(RunAhead::new(10).frames(), MAX_RUN_AHEAD);
+    }
+}
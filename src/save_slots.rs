@@ -0,0 +1,175 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::storage::GameStorage;
+use crate::thumbnail::Thumbnail;
+
+/// Number of save state slots for each game.
+pub const SLOT_COUNT: u8 = 10;
+
+/// A slot holding a save state, for frontends to list.
+#[derive(Debug)]
+pub struct SlotInfo {
+    pub slot: u8,
+
+    /// When the state was saved.
+    pub modified: SystemTime,
+
+    /// A thumbnail of the frame the state was saved on, if it has one.
+    pub thumbnail: Option<Thumbnail>,
+}
+
+impl SlotInfo {
+    /// Returns how long ago the state was saved, such as "5 minutes ago".
+    pub fn saved_ago(&self) -> String {
+        let age = SystemTime::now()
+            .duration_since(self.modified)
+            .unwrap_or_default();
+        ago_text(age)
+    }
+}
+
+/// The numbered save state slots of a game, each stored in a file in the
+/// game's storage. States of a different game, or a different version of
+/// the same game, are never found in its slots.
+pub struct SaveSlots {
+    storage: GameStorage,
+
+    /// The slot saved to and loaded from by the hotkeys.
+    selected: u8,
+}
+
+impl SaveSlots {
+    /// Returns the slots of the game, kept in its storage.
+    pub fn new(storage: GameStorage) -> Self {
+        SaveSlots {
+            storage,
+            selected: 0,
+        }
+    }
+
+    /// Returns the path of the file storing the slot.
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.storage.state_path(slot)
+    }
+
+    /// Returns the slot used by the hotkeys.
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+
+    /// Selects the slot used by the hotkeys, from 0 to 9.
+    pub fn select(&mut self, slot: u8) {
+        assert!(slot < SLOT_COUNT, "no save state slot {}", slot);
+        self.selected = slot;
+    }
+
+    /// Returns the slots which hold a save state.
+    pub fn used(&self) -> Vec<u8> {
+        (0..SLOT_COUNT)
+            .filter(|&slot| self.path(slot).is_file())
+            .collect()
+    }
+
+    /// Returns the slots which hold a save state, with when each was saved
+    /// and its thumbnail, such as for a menu of the states to load.
+    pub fn list(&self) -> Vec<SlotInfo> {
+        self.used()
+            .into_iter()
+            .filter_map(|slot| {
+                let modified = self.path(slot).metadata().and_then(|m| m.modified()).ok()?;
+                let thumbnail = self
+                    .load(slot)
+                    .ok()
+                    .and_then(|state| Thumbnail::from_state(&state));
+                Some(SlotInfo {
+                    slot,
+                    modified,
+                    thumbnail,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores a save state in the slot, replacing any already there.
+    pub fn save(&self, slot: u8, state: &[u8]) -> io::Result<()> {
+        self.storage.create()?;
+        std::fs::write(self.path(slot), state)
+    }
+
+    /// Returns the save state stored in the slot.
+    pub fn load(&self, slot: u8) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path(slot))
+    }
+}
+
+/// Returns the given time in the past in its largest whole unit.
+fn ago_text(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    match count {
+        1 => format!("1 {} ago", unit),
+        _ => format!("{} {}s ago", count, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        let slots = SaveSlots::new(GameStorage::new("data", "roms/Zelda.nes", 0x3FE272FB));
+        assert_eq!(
+            slots.path(1),
+            PathBuf::from("data/games/Zelda-3fe272fb/slot1.state")
+        );
+    }
+
+    #[test]
+    fn test_save_load() {
+        let data_dir = std::env::temp_dir().join("res_test_slots");
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let slots = SaveSlots::new(GameStorage::new(&data_dir, "Zelda.nes", 1));
+        let other_game = SaveSlots::new(GameStorage::new(&data_dir, "Zelda.nes", 2));
+
+        slots.save(3, &[1, 2, 3]).unwrap();
+        slots.save(9, &[4]).unwrap();
+        assert_eq!(slots.load(3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(slots.used(), vec![3, 9]);
+
+        let list = slots.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].slot, 3);
+        assert_eq!(list[0].thumbnail, None);
+
+        // The slots of another game are separate.
+        assert!(other_game.load(3).is_err());
+        assert!(!other_game.used().contains(&3));
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_ago_text() {
+        assert_eq!(ago_text(Duration::from_secs(59)), "just now");
+        assert_eq!(ago_text(Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(ago_text(Duration::from_secs(3599)), "59 minutes ago");
+        assert_eq!(ago_text(Duration::from_secs(7200)), "2 hours ago");
+        assert_eq!(ago_text(Duration::from_secs(86400 * 3)), "3 days ago");
+    }
+
+    #[test]
+    fn test_select() {
+        let mut slots = SaveSlots::new(GameStorage::new("data", "Zelda.nes", 0));
+        assert_eq!(slots.selected(), 0);
+        slots.select(9);
+        assert_eq!(slots.selected(), 9);
+    }
+}
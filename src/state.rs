@@ -0,0 +1,853 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Tag at the start of a save state file.
+const STATE_TAG: [u8; 4] = *b"RST\x1A";
+
+/// Identifies a chunk of a save state, such as "CPU ".
+pub type ChunkId = [u8; 4];
+
+/// Upgrades a chunk saved by an older version of the emulator to the next
+/// version of its format.
+pub struct Migration {
+    pub chunk: ChunkId,
+
+    /// Version of the chunk upgraded.
+    pub from: u16,
+
+    pub migrate: fn(&[u8]) -> Result<Vec<u8>, StateError>,
+}
+
+/// Migrations of each chunk whose format has changed, which are applied in
+/// turn to bring a chunk saved by an older version up to date.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Represents an error loading a save state.
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    /// The state ends before every component is loaded.
+    Truncated,
+
+    /// The state holds more than the components loaded, so it was saved by
+    /// a different console or game.
+    TrailingData,
+
+    /// The state holds a value a component can't take, such as memory of a
+    /// different size than the cartridge's.
+    InvalidValue,
+
+    /// The file doesn't start with the save state tag.
+    InvalidTag,
+
+    /// The state was saved with a different game.
+    WrongGame,
+
+    /// A chunk is missing from the state.
+    MissingChunk(ChunkId),
+
+    /// A chunk has a version that can't be loaded, such as one saved by a
+    /// newer version of the emulator.
+    UnsupportedVersion { chunk: ChunkId, version: u16 },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::TrailingData => write!(f, "save state has unexpected trailing data"),
+            StateError::InvalidValue => write!(f, "save state doesn't match the console"),
+            StateError::InvalidTag => write!(f, "not a save state"),
+            StateError::WrongGame => write!(f, "save state is of a different game"),
+            StateError::MissingChunk(chunk) => {
+                write!(f, "save state has no {} chunk", chunk_name(chunk))
+            }
+            StateError::UnsupportedVersion { chunk, version } => write!(
+                f,
+                "save state has version {} of the {} chunk, which isn't supported",
+                version,
+                chunk_name(chunk)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Returns the ID of a chunk as text, such as "CPU".
+fn chunk_name(chunk: &ChunkId) -> Cow<'_, str> {
+    match String::from_utf8_lossy(chunk) {
+        Cow::Borrowed(name) => Cow::Borrowed(name.trim_end()),
+        Cow::Owned(name) => Cow::Owned(name.trim_end().to_string()),
+    }
+}
+
+/// A buffer the state of the console is saved to or loaded from.
+///
+/// Each component saves and loads its state with the same method, passing
+/// its fields to the buffer in a fixed order. When saving they're appended
+/// to the buffer, and when loading they're overwritten from it, so a
+/// component doesn't list its fields twice.
+pub enum StateBuffer<'a> {
+    Save(&'a mut Vec<u8>),
+
+    /// The state yet to be loaded, and the first error loading it, after
+    /// which nothing more is loaded.
+    Load {
+        data: &'a [u8],
+        error: Option<StateError>,
+    },
+}
+
+impl<'a> StateBuffer<'a> {
+    /// Returns a buffer which appends the state to the given bytes.
+    pub fn save(buffer: &'a mut Vec<u8>) -> Self {
+        StateBuffer::Save(buffer)
+    }
+
+    /// Returns a buffer which loads the state from the given bytes.
+    pub fn load(data: &'a [u8]) -> Self {
+        StateBuffer::Load { data, error: None }
+    }
+
+    /// Saves the bytes, or overwrites them with the next bytes of the state.
+    pub fn bytes(&mut self, bytes: &mut [u8]) {
+        match self {
+            StateBuffer::Save(buffer) => buffer.extend_from_slice(bytes),
+            StateBuffer::Load { data, error } => {
+                if error.is_some() {
+                    return;
+                }
+
+                match data.split_at_checked(bytes.len()) {
+                    Some((loaded, rest)) => {
+                        bytes.copy_from_slice(loaded);
+                        *data = rest;
+                    }
+                    None => *error = Some(StateError::Truncated),
+                }
+            }
+        }
+    }
+
+    /// Stops loading the state, which holds a value that can't be loaded.
+    pub fn invalid(&mut self) {
+        if let StateBuffer::Load { error, .. } = self {
+            error.get_or_insert(StateError::InvalidValue);
+        }
+    }
+
+    /// Returns the first error loading the state, if any, once every
+    /// component has loaded.
+    pub fn finish(self) -> Result<(), StateError> {
+        match self {
+            StateBuffer::Save(_) => Ok(()),
+            StateBuffer::Load {
+                error: Some(error), ..
+            } => Err(error),
+            StateBuffer::Load { data, .. } if !data.is_empty() => Err(StateError::TrailingData),
+            StateBuffer::Load { .. } => Ok(()),
+        }
+    }
+}
+
+/// A component of the console with state that's saved in save states.
+///
+/// Only the state of the emulated hardware is saved. Data which never
+/// changes, such as ROM and lookup tables, and the settings of the
+/// emulator are left as they are when a state is loaded.
+pub trait Snapshot {
+    /// Saves the state to the buffer, or loads it from the buffer.
+    fn snapshot(&mut self, state: &mut StateBuffer);
+
+    /// Saves or loads the state of each value in a slice. Bytes are copied
+    /// all at once, which keeps snapshots of memory fast.
+    fn snapshot_slice(values: &mut [Self], state: &mut StateBuffer)
+    where
+        Self: Sized,
+    {
+        for value in values {
+            value.snapshot(state);
+        }
+    }
+}
+
+/// Implements Snapshot for numbers, which are saved in little endian.
+macro_rules! snapshot_numbers {
+    ($($type:ty),*) => {
+        $(
+            impl Snapshot for $type {
+                fn snapshot(&mut self, state: &mut StateBuffer) {
+                    let mut bytes = self.to_le_bytes();
+                    state.bytes(&mut bytes);
+                    *self = <$type>::from_le_bytes(bytes);
+                }
+            }
+        )*
+    };
+}
+
+snapshot_numbers!(u16, u32, u64, u128, i16, i32, f32, f64);
+
+impl Snapshot for u8 {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        state.bytes(std::slice::from_mut(self));
+    }
+
+    fn snapshot_slice(values: &mut [Self], state: &mut StateBuffer) {
+        state.bytes(values);
+    }
+}
+
+/// Sizes are saved as 64 bit numbers, so states load on any platform.
+impl Snapshot for usize {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        let mut value = *self as u64;
+        value.snapshot(state);
+        match usize::try_from(value) {
+            Ok(value) => *self = value,
+            Err(_) => state.invalid(),
+        }
+    }
+}
+
+impl Snapshot for bool {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        let mut value = *self as u8;
+        value.snapshot(state);
+        *self = value != 0;
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        T::snapshot_slice(self, state);
+    }
+}
+
+/// Vectors are saved with their length, and are only loaded into vectors
+/// of the same length, such as the RAM of the same cartridge.
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        let mut len = self.len();
+        len.snapshot(state);
+        if len != self.len() {
+            state.invalid();
+            return;
+        }
+
+        T::snapshot_slice(self, state);
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        let mut some = self.is_some();
+        some.snapshot(state);
+        match (some, self.as_mut()) {
+            (true, Some(value)) => value.snapshot(state),
+            (true, None) => {
+                let mut value = T::default();
+                value.snapshot(state);
+                *self = Some(value);
+            }
+            (false, _) => *self = None,
+        }
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        self.0.snapshot(state);
+        self.1.snapshot(state);
+    }
+}
+
+impl<T: Snapshot + ?Sized> Snapshot for &mut T {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        (**self).snapshot(state);
+    }
+}
+
+impl<T: Snapshot + ?Sized> Snapshot for Box<T> {
+    fn snapshot(&mut self, state: &mut StateBuffer) {
+        (**self).snapshot(state);
+    }
+}
+
+/// Implements Snapshot for a struct by saving or loading the given fields,
+/// in order. A field may be followed by the range of values it can take, as
+/// in `duty_cycle in 0..4`, when others would index out of bounds or
+/// overflow, so a state holding one isn't loaded.
+macro_rules! snapshot {
+    ($type:ty, $($field:ident $(in $range:expr)?),+ $(,)?) => {
+        impl $crate::state::Snapshot for $type {
+            fn snapshot(&mut self, state: &mut $crate::state::StateBuffer) {
+                $(
+                    $crate::state::Snapshot::snapshot(&mut self.$field, state);
+                    $(
+                        if !($range).contains(&self.$field) {
+                            state.invalid();
+                        }
+                    )?
+                )+
+            }
+        }
+    };
+}
+
+pub(crate) use snapshot;
+
+/// Implements Snapshot for a fieldless enum by saving the index of the
+/// variant, given every variant in order.
+macro_rules! snapshot_enum {
+    ($type:ty, [$($variant:expr),+ $(,)?]) => {
+        impl $crate::state::Snapshot for $type {
+            fn snapshot(&mut self, state: &mut $crate::state::StateBuffer) {
+                let variants = [$($variant),+];
+                let mut index = variants
+                    .iter()
+                    .position(|variant| variant == self)
+                    .unwrap_or_default() as u8;
+                $crate::state::Snapshot::snapshot(&mut index, state);
+                match variants.get(index as usize) {
+                    Some(&variant) => *self = variant,
+                    None => state.invalid(),
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use snapshot_enum;
+
+/// A component whose state is saved in chunks, each holding the state of
+/// one of its parts. Chunks are versioned separately, so when the format
+/// of one changes, states saved before can still be loaded by migrating
+/// just that chunk.
+pub trait Chunked {
+    /// Calls the function with the ID, version and state of each chunk, in
+    /// order.
+    fn chunks(&mut self, visit: &mut dyn FnMut(ChunkId, u16, &mut dyn Snapshot));
+}
+
+/// Saves or loads the state of each chunk, in order, without their IDs and
+/// versions.
+pub fn snapshot_chunks<T: Chunked + ?Sized>(component: &mut T, state: &mut StateBuffer) {
+    component.chunks(&mut |_, _, chunk| chunk.snapshot(state));
+}
+
+/// A state of a component held in memory, which is fast enough to take and
+/// restore hundreds of times a second, such as for runahead and rollback.
+///
+/// Unlike save state files, snapshots have no header or chunks and aren't
+/// checked when restored, as they're only restored into the component they
+/// were taken of. The buffer is reused, so once it has grown to the size of
+/// the state, taking a snapshot doesn't allocate.
+pub struct StateSnapshot {
+    data: Vec<u8>,
+}
+
+impl StateSnapshot {
+    /// Returns an empty snapshot.
+    pub fn new() -> Self {
+        StateSnapshot { data: vec![] }
+    }
+
+    /// Returns true if no state has been taken.
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Takes the state of the component, replacing the state held.
+    pub fn take<T: Snapshot + ?Sized>(&mut self, component: &mut T) {
+        self.data.clear();
+        component.snapshot(&mut StateBuffer::save(&mut self.data));
+    }
+
+    /// Restores the state held into the component it was taken of.
+    ///
+    /// Panics if the state was taken of a different component.
+    pub fn restore<T: Snapshot + ?Sized>(&self, component: &mut T) {
+        let mut state = StateBuffer::load(&self.data);
+        component.snapshot(&mut state);
+        if let Err(err) = state.finish() {
+            panic!("snapshot restored into a different component: {}", err);
+        }
+    }
+}
+
+/// Returns the state of a component.
+pub fn save<T: Snapshot + ?Sized>(component: &mut T) -> Vec<u8> {
+    let mut buffer = vec![];
+    component.snapshot(&mut StateBuffer::save(&mut buffer));
+    buffer
+}
+
+/// Loads the state of a component. If the state can't be loaded the
+/// component is left as it was.
+pub fn load<T: Snapshot + ?Sized>(component: &mut T, data: &[u8]) -> Result<(), StateError> {
+    restore_on_error(component, |component| {
+        let mut state = StateBuffer::load(data);
+        component.snapshot(&mut state);
+        state.finish()
+    })
+}
+
+/// Runs a function loading the state of a component, putting the
+/// component back as it was if it fails.
+fn restore_on_error<T, F>(component: &mut T, load: F) -> Result<(), StateError>
+where
+    T: Snapshot + ?Sized,
+    F: FnOnce(&mut T) -> Result<(), StateError>,
+{
+    let backup = save(component);
+    load(component).inspect_err(|_| {
+        let mut state = StateBuffer::load(&backup);
+        component.snapshot(&mut state);
+    })
+}
+
+/// Returns the save state file of a component, given the CRC32 of the game
+/// it's running.
+///
+/// Files start with the tag "RST" followed by $1A, then the version of the
+/// emulator which saved it (as its length and text) and the CRC32 of the
+/// game. Each chunk follows, as its ID, version, length and state. Numbers
+/// are little endian.
+pub fn save_file<T: Chunked + ?Sized>(component: &mut T, crc32: u32) -> Vec<u8> {
+    let version = env!("CARGO_PKG_VERSION");
+    let mut file = STATE_TAG.to_vec();
+    file.push(version.len() as u8);
+    file.extend(version.as_bytes());
+    file.extend(crc32.to_le_bytes());
+
+    component.chunks(&mut |id, version, chunk| append_chunk(&mut file, id, version, chunk));
+    file
+}
+
+/// Appends a chunk to a save state file, such as information about the
+/// state which isn't part of the component saved. Chunks the component
+/// doesn't have are ignored when the file is loaded.
+pub fn append_chunk(file: &mut Vec<u8>, id: ChunkId, version: u16, chunk: &mut dyn Snapshot) {
+    file.extend(id);
+    file.extend(version.to_le_bytes());
+
+    let start = file.len();
+    file.extend(0u32.to_le_bytes());
+    chunk.snapshot(&mut StateBuffer::save(file));
+    let len = (file.len() - start - 4) as u32;
+    file[start..start + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Loads a single chunk of a save state file, migrated to the given
+/// version, without checking the game it was saved from. If the chunk can't
+/// be loaded the component is left as it was.
+pub fn load_chunk(
+    file: &[u8],
+    id: ChunkId,
+    version: u16,
+    chunk: &mut dyn Snapshot,
+) -> Result<(), StateError> {
+    let data = SavedFile::parse(file)?.chunk(id, version, MIGRATIONS)?;
+    load(chunk, &data)
+}
+
+/// Loads a save state file into a component, given the CRC32 of the game
+/// it's running. Chunks saved by older versions of the emulator are
+/// migrated, and chunks of parts the component no longer has are ignored.
+/// If the state can't be loaded the component is left as it was.
+pub fn load_file<T>(component: &mut T, crc32: u32, file: &[u8]) -> Result<(), StateError>
+where
+    T: Chunked + Snapshot + ?Sized,
+{
+    load_file_with(component, crc32, file, MIGRATIONS)
+}
+
+fn load_file_with<T>(
+    component: &mut T,
+    crc32: u32,
+    file: &[u8],
+    migrations: &[Migration],
+) -> Result<(), StateError>
+where
+    T: Chunked + Snapshot + ?Sized,
+{
+    let saved = SavedFile::parse(file)?;
+    if saved.crc32 != crc32 {
+        return Err(StateError::WrongGame);
+    }
+
+    restore_on_error(component, |component| {
+        let mut result = Ok(());
+        component.chunks(&mut |id, version, chunk| {
+            if result.is_ok() {
+                result = saved.chunk(id, version, migrations).and_then(|data| {
+                    let mut state = StateBuffer::load(&data);
+                    chunk.snapshot(&mut state);
+                    state.finish()
+                });
+            }
+        });
+        result
+    })
+}
+
+/// A chunk of a save state file.
+struct SavedChunk<'a> {
+    id: ChunkId,
+    version: u16,
+    data: &'a [u8],
+}
+
+/// The game and chunks of a save state file.
+struct SavedFile<'a> {
+    crc32: u32,
+    chunks: Vec<SavedChunk<'a>>,
+}
+
+impl<'a> SavedFile<'a> {
+    fn parse(mut file: &'a [u8]) -> Result<Self, StateError> {
+        let mut take = |len: usize| -> Result<&'a [u8], StateError> {
+            let (taken, rest) = file.split_at_checked(len).ok_or(StateError::Truncated)?;
+            file = rest;
+            Ok(taken)
+        };
+
+        if take(STATE_TAG.len()).map_err(|_| StateError::InvalidTag)? != STATE_TAG {
+            return Err(StateError::InvalidTag);
+        }
+
+        // The version of the emulator is only informational.
+        let version_len = take(1)?[0];
+        take(version_len as usize)?;
+        let crc32 = u32::from_le_bytes(take(4)?.try_into().unwrap());
+
+        let mut chunks = vec![];
+        while let Ok(id) = take(4) {
+            let version = u16::from_le_bytes(take(2)?.try_into().unwrap());
+            let len = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            chunks.push(SavedChunk {
+                id: id.try_into().unwrap(),
+                version,
+                data: take(len as usize)?,
+            });
+        }
+
+        Ok(SavedFile { crc32, chunks })
+    }
+
+    /// Returns the state in the chunk with the given ID, migrated to the
+    /// given version.
+    fn chunk(
+        &self,
+        id: ChunkId,
+        version: u16,
+        migrations: &[Migration],
+    ) -> Result<Cow<'a, [u8]>, StateError> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.id == id)
+            .ok_or(StateError::MissingChunk(id))?;
+
+        let mut data = Cow::Borrowed(chunk.data);
+        for from in chunk.version..version {
+            let migration = migrations
+                .iter()
+                .find(|migration| migration.chunk == id && migration.from == from)
+                .ok_or(StateError::UnsupportedVersion {
+                    chunk: id,
+                    version: chunk.version,
+                })?;
+            data = Cow::Owned((migration.migrate)(&data)?);
+        }
+
+        match chunk.version > version {
+            true => Err(StateError::UnsupportedVersion {
+                chunk: id,
+                version: chunk.version,
+            }),
+            false => Ok(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    enum Mode {
+        #[default]
+        Off,
+        On,
+    }
+
+    snapshot_enum!(Mode, [Mode::Off, Mode::On]);
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Component {
+        register: u16,
+        flag: bool,
+        mode: Mode,
+        pending: Option<(u8, i32)>,
+        ram: Vec<u8>,
+        table: [f32; 2],
+    }
+
+    snapshot!(Component, register, flag, mode, pending, ram, table);
+
+    fn component() -> Component {
+        Component {
+            register: 0x1234,
+            flag: true,
+            mode: Mode::On,
+            pending: Some((7, -3)),
+            ram: vec![1, 2, 3],
+            table: [0.5, -1.0],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = save(&mut component());
+
+        let mut loaded = Component {
+            ram: vec![0; 3],
+            ..Default::default()
+        };
+        assert_eq!(load(&mut loaded, &data), Ok(()));
+        assert_eq!(loaded, component());
+
+        // Saving leaves the component as it was.
+        assert_eq!(save(&mut loaded), data);
+    }
+
+    #[test]
+    fn test_errors() {
+        let data = save(&mut component());
+
+        let mut loaded = Component {
+            ram: vec![0; 3],
+            ..Default::default()
+        };
+        assert_eq!(
+            load(&mut loaded, &data[..data.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(
+            load(&mut loaded, &[data.as_slice(), &[0]].concat()),
+            Err(StateError::TrailingData)
+        );
+
+        // The state of a component with a different amount of RAM isn't
+        // loaded.
+        let mut other = Component {
+            ram: vec![0; 4],
+            ..Default::default()
+        };
+        assert_eq!(load(&mut other, &data), Err(StateError::InvalidValue));
+
+        // Components are left as they were when loading fails.
+        assert_eq!(loaded.register, 0);
+        assert_eq!(other.ram, vec![0; 4]);
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Sequencer {
+        step: u8,
+    }
+
+    snapshot!(Sequencer, step in 0..4);
+
+    #[test]
+    fn test_out_of_range() {
+        let mut sequencer = Sequencer { step: 3 };
+        assert_eq!(load(&mut sequencer, &[2]), Ok(()));
+        assert_eq!(sequencer.step, 2);
+
+        // Values outside of a field's range aren't loaded.
+        assert_eq!(load(&mut sequencer, &[4]), Err(StateError::InvalidValue));
+        assert_eq!(sequencer.step, 2);
+    }
+
+    /// A component saved in two chunks. Version 1 of the counter chunk held
+    /// a 16 bit counter, which is now 32 bits.
+    #[derive(Debug, Default, PartialEq)]
+    struct Machine {
+        counter: u32,
+        component: Component,
+    }
+
+    impl Chunked for Machine {
+        fn chunks(&mut self, visit: &mut dyn FnMut(ChunkId, u16, &mut dyn Snapshot)) {
+            visit(*b"CNT ", 2, &mut self.counter);
+            visit(*b"COMP", 1, &mut self.component);
+        }
+    }
+
+    impl Snapshot for Machine {
+        fn snapshot(&mut self, state: &mut StateBuffer) {
+            snapshot_chunks(self, state);
+        }
+    }
+
+    const WIDEN_COUNTER: Migration = Migration {
+        chunk: *b"CNT ",
+        from: 1,
+        migrate: |data| Ok([data, &[0, 0]].concat()),
+    };
+
+    fn machine() -> Machine {
+        Machine {
+            counter: 0x12345678,
+            component: component(),
+        }
+    }
+
+    /// Returns a save state file with the given chunks.
+    fn state_file(crc32: u32, chunks: &[(&ChunkId, u16, &[u8])]) -> Vec<u8> {
+        let mut file = [STATE_TAG.as_slice(), &[3], b"0.1", &crc32.to_le_bytes()].concat();
+        for (id, version, data) in chunks {
+            file.extend(*id);
+            file.extend(version.to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(*data);
+        }
+        file
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let file = save_file(&mut machine(), 7);
+        assert!(file.starts_with(&STATE_TAG));
+
+        let mut loaded = Machine {
+            component: Component {
+                ram: vec![0; 3],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(load_file(&mut loaded, 7, &file), Ok(()));
+        assert_eq!(loaded, machine());
+
+        // The chunks hold the same state as the component.
+        assert_eq!(save(&mut loaded), save(&mut machine()));
+    }
+
+    #[test]
+    fn test_extra_chunk() {
+        let mut file = save_file(&mut machine(), 7);
+        append_chunk(&mut file, *b"NOTE", 1, &mut 0xABu8);
+
+        // Loading the component ignores the extra chunk.
+        let mut loaded = machine();
+        loaded.counter = 0;
+        assert_eq!(load_file(&mut loaded, 7, &file), Ok(()));
+        assert_eq!(loaded, machine());
+
+        // Which is read on its own.
+        let mut note = 0u8;
+        assert_eq!(load_chunk(&file, *b"NOTE", 1, &mut note), Ok(()));
+        assert_eq!(note, 0xAB);
+        assert_eq!(
+            load_chunk(&file, *b"MISS", 1, &mut note),
+            Err(StateError::MissingChunk(*b"MISS"))
+        );
+    }
+
+    #[test]
+    fn test_file_errors() {
+        let mut loaded = machine();
+        let file = save_file(&mut machine(), 7);
+        assert_eq!(load_file(&mut loaded, 8, &file), Err(StateError::WrongGame));
+        assert_eq!(
+            load_file(&mut loaded, 7, b"RMV\x1A"),
+            Err(StateError::InvalidTag)
+        );
+        assert_eq!(
+            load_file(&mut loaded, 7, &file[..file.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        let component = save(&mut component());
+        let file = state_file(7, &[(b"CNT ", 2, &[1, 0, 0, 0])]);
+        assert_eq!(
+            load_file(&mut loaded, 7, &file),
+            Err(StateError::MissingChunk(*b"COMP"))
+        );
+        assert_eq!(
+            StateError::MissingChunk(*b"CNT ").to_string(),
+            "save state has no CNT chunk"
+        );
+
+        // Chunks saved by a newer version aren't loaded.
+        let file = state_file(7, &[(b"CNT ", 3, &[1, 0, 0, 0]), (b"COMP", 1, &component)]);
+        assert_eq!(
+            load_file(&mut loaded, 7, &file),
+            Err(StateError::UnsupportedVersion {
+                chunk: *b"CNT ",
+                version: 3
+            })
+        );
+
+        // The machine is left as it was.
+        assert_eq!(loaded, machine());
+    }
+
+    #[test]
+    fn test_migration() {
+        let component = save(&mut component());
+        let file = state_file(
+            7,
+            &[
+                (b"CNT ", 1, &[0x34, 0x12]),
+                (b"OLD ", 1, &[1, 2, 3]),
+                (b"COMP", 1, &component),
+            ],
+        );
+
+        // Without a migration, the old counter can't be loaded.
+        let mut loaded = machine();
+        assert_eq!(
+            load_file(&mut loaded, 7, &file),
+            Err(StateError::UnsupportedVersion {
+                chunk: *b"CNT ",
+                version: 1
+            })
+        );
+
+        // Chunks of parts the machine no longer has are ignored.
+        loaded.counter = 0;
+        assert_eq!(
+            load_file_with(&mut loaded, 7, &file, &[WIDEN_COUNTER]),
+            Ok(())
+        );
+        assert_eq!(loaded.counter, 0x1234);
+        assert_eq!(loaded.component, machine().component);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut snapshot = StateSnapshot::new();
+        assert!(snapshot.is_empty());
+
+        let mut machine = machine();
+        snapshot.take(&mut machine);
+        assert_eq!(snapshot.data, save(&mut machine));
+
+        machine.counter = 0;
+        machine.component.ram[1] = 9;
+        snapshot.restore(&mut machine);
+        assert_eq!(machine, self::machine());
+
+        // Taking another snapshot reuses the buffer.
+        let buffer = snapshot.data.as_ptr();
+        machine.component.flag = false;
+        snapshot.take(&mut machine);
+        assert_eq!(snapshot.data.as_ptr(), buffer);
+        assert_eq!(snapshot.data, save(&mut machine));
+    }
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the emulator's directory in the platform's data directory.
+const APP_NAME: &str = "nesoxide";
+
+/// Returns the platform's directory for the emulator's data, such as
+/// ~/.local/share/nesoxide on Linux, ~/Library/Application Support/nesoxide
+/// on macOS or %APPDATA%\nesoxide on Windows.
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_NAME))
+}
+
+/// Returns the path of the emulator's config in the platform's config
+/// directory, such as ~/.config/nesoxide/config.toml on Linux.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_NAME).join("config.toml"))
+}
+
+/// Resolves where the files kept for a game are stored: its battery save,
+/// save states, screenshots, recordings and config.
+///
+/// Each game has a directory of its own in the data directory, named after
+/// the ROM and its CRC32, such as "games/Zelda-3fe272fb". Files are kept
+/// there rather than alongside the ROM, which may be read-only or in a zip,
+/// and a different version of the same game never finds them.
+#[derive(Clone, Debug)]
+pub struct GameStorage {
+    dir: PathBuf,
+}
+
+impl GameStorage {
+    /// Returns the storage of the game with the ROM at the given path, in the
+    /// given data directory.
+    pub fn new<D: AsRef<Path>, R: AsRef<Path>>(data_dir: D, rom: R, crc32: u32) -> Self {
+        let name = rom
+            .as_ref()
+            .file_stem()
+            .map_or("game".into(), |stem| stem.to_string_lossy());
+
+        GameStorage {
+            dir: data_dir
+                .as_ref()
+                .join("games")
+                .join(format!("{}-{:08x}", name, crc32)),
+        }
+    }
+
+    /// Creates the directory the game's files are stored in, if it doesn't
+    /// exist yet.
+    pub fn create(&self) -> io::Result<()> {
+        std::fs::create_dir_all(self.screenshot_dir())?;
+        std::fs::create_dir_all(self.recording_dir())
+    }
+
+    /// Returns the path of the save file of the game's battery RAM.
+    pub fn battery_path(&self) -> PathBuf {
+        self.dir.join("battery.sav")
+    }
+
+    /// Returns the path of the save state in the given slot.
+    pub fn state_path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("slot{}.state", slot))
+    }
+
+    /// Returns the directory screenshots of the game are saved in.
+    pub fn screenshot_dir(&self) -> PathBuf {
+        self.dir.join("screenshots")
+    }
+
+    /// Returns the directory audio and video recordings and GIFs of the game
+    /// are saved in.
+    pub fn recording_dir(&self) -> PathBuf {
+        self.dir.join("recordings")
+    }
+
+    /// Returns the path of the game's config, which overrides the settings
+    /// of the emulator config for the game.
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.join("config.toml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let storage = GameStorage::new("data", "roms/Zelda.zip", 0x3FE272FB);
+        let dir = Path::new("data/games/Zelda-3fe272fb");
+        assert_eq!(storage.dir, dir);
+        assert_eq!(storage.battery_path(), dir.join("battery.sav"));
+        assert_eq!(storage.state_path(3), dir.join("slot3.state"));
+        assert_eq!(storage.screenshot_dir(), dir.join("screenshots"));
+        assert_eq!(storage.recording_dir(), dir.join("recordings"));
+        assert_eq!(storage.config_path(), dir.join("config.toml"));
+    }
+
+    #[test]
+    fn test_create() {
+        let data_dir = std::env::temp_dir().join("res_test_storage");
+        let _ = std::fs::remove_dir_all(&data_dir);
+
+        let storage = GameStorage::new(&data_dir, "Zelda.nes", 1);
+        storage.create().unwrap();
+        assert!(storage.screenshot_dir().is_dir());
+        assert!(storage.recording_dir().is_dir());
+        storage.create().unwrap();
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
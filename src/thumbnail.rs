@@ -0,0 +1,115 @@
+use crate::ppu::Frame;
+use crate::state::{self, snapshot, ChunkId};
+
+/// Number of frame pixels averaged into each thumbnail pixel, across and
+/// down.
+const SCALE: usize = 4;
+
+/// Width of a thumbnail in pixels.
+pub const THUMBNAIL_WIDTH: usize = Frame::WIDTH / SCALE;
+
+/// Height of a thumbnail in pixels.
+pub const THUMBNAIL_HEIGHT: usize = Frame::HEIGHT / SCALE;
+
+/// ID of the save state chunk holding the thumbnail.
+pub const THUMBNAIL_CHUNK: ChunkId = *b"THMB";
+
+/// A small picture of the frame a save state was saved on, stored in the
+/// state file so frontends can show what's in each slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    /// The RGB pixels, row by row.
+    pixels: Vec<u8>,
+}
+
+snapshot!(Thumbnail, pixels);
+
+impl Thumbnail {
+    /// Returns the thumbnail of a frame of RGB pixels, each of its pixels
+    /// the average of a square of the frame's.
+    pub fn from_frame(frame: &[u8]) -> Self {
+        let mut pixels = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3);
+        for y in 0..THUMBNAIL_HEIGHT {
+            for x in 0..THUMBNAIL_WIDTH {
+                let mut sum = [0u32; 3];
+                for row in y * SCALE..(y + 1) * SCALE {
+                    let start = (row * Frame::WIDTH + x * SCALE) * 3;
+                    for rgb in frame[start..start + SCALE * 3].chunks_exact(3) {
+                        for (sum, &value) in sum.iter_mut().zip(rgb) {
+                            *sum += value as u32;
+                        }
+                    }
+                }
+
+                pixels.extend(sum.map(|sum| (sum / (SCALE * SCALE) as u32) as u8));
+            }
+        }
+
+        Thumbnail { pixels }
+    }
+
+    /// Returns the thumbnail stored in a save state file, if it has one.
+    /// States saved by older versions of the emulator have none.
+    pub fn from_state(file: &[u8]) -> Option<Self> {
+        let mut thumbnail = Thumbnail {
+            pixels: vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3],
+        };
+        state::load_chunk(file, THUMBNAIL_CHUNK, 1, &mut thumbnail).ok()?;
+        Some(thumbnail)
+    }
+
+    /// Appends the thumbnail to a save state file.
+    pub fn append_to_state(&mut self, file: &mut Vec<u8>) {
+        state::append_chunk(file, THUMBNAIL_CHUNK, 1, self);
+    }
+
+    /// Returns the RGB pixels of the thumbnail, row by row.
+    #[cfg(any(feature = "sdl", feature = "winit", test))]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Chunked, Snapshot};
+
+    /// A component with nothing to save.
+    struct Empty;
+
+    impl Chunked for Empty {
+        fn chunks(&mut self, _visit: &mut dyn FnMut(ChunkId, u16, &mut dyn Snapshot)) {}
+    }
+
+    #[test]
+    fn test_from_frame() {
+        // Stripes of black and white average to grey, with the right half
+        // of the frame red.
+        let mut frame = vec![0; Frame::WIDTH * Frame::HEIGHT * 3];
+        for (i, pixel) in frame.chunks_exact_mut(3).enumerate() {
+            let (x, y) = (i % Frame::WIDTH, i / Frame::WIDTH);
+            match x < Frame::WIDTH / 2 {
+                true if y % 2 == 0 => pixel.copy_from_slice(&[0xFF, 0xFF, 0xFF]),
+                true => {}
+                false => pixel.copy_from_slice(&[0xFF, 0, 0]),
+            }
+        }
+
+        let thumbnail = Thumbnail::from_frame(&frame);
+        assert_eq!(thumbnail.pixels().len(), 64 * 60 * 3);
+        assert_eq!(thumbnail.pixels()[..3], [0x7F, 0x7F, 0x7F]);
+        assert_eq!(thumbnail.pixels()[63 * 3..64 * 3], [0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut thumbnail = Thumbnail::from_frame(&vec![0x10; Frame::WIDTH * Frame::HEIGHT * 3]);
+        let mut file = state::save_file(&mut Empty, 0);
+        assert_eq!(Thumbnail::from_state(&file), None);
+
+        thumbnail.append_to_state(&mut file);
+        assert_eq!(Thumbnail::from_state(&file), Some(thumbnail));
+        assert_eq!(Thumbnail::from_state(b"not a state"), None);
+    }
+}
@@ -1,34 +1,80 @@
 use spin_sleep::SpinSleeper;
 use std::time::{Duration, Instant};
 
-/// A timer that performs accurate waits.
+#[cfg(any(feature = "sdl", feature = "winit", test))]
+use crate::audio::MAX_RATE_DELTA;
+
+/// How far behind schedule frames may fall before the schedule starts again,
+/// rather than frames being run as fast as possible to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// A timer that paces frames to the frame rate of the console.
 pub struct Timer {
-    start: Instant,
+    due: Instant,
     sleeper: SpinSleeper,
 }
 
 impl Timer {
-    /// Returns a new timer.
+    /// Returns a new timer, with the first frame starting now.
     pub fn new() -> Self {
         Self {
-            start: Instant::now(),
+            due: Instant::now(),
             sleeper: SpinSleeper::default(),
         }
     }
 
-    /// Resets the timer.
-    pub fn reset(&mut self) {
-        self.start = Instant::now();
+    /// Accurately waits until the frame of the given length, following the
+    /// last, is over. Each frame is due a frame time after the last was due,
+    /// not after the last wait ended, so the frame rate doesn't drift by the
+    /// time waits overshoot by.
+    pub fn wait(&mut self, frame_time: Duration) {
+        self.due += frame_time;
+
+        let now = Instant::now();
+        if self.due > now {
+            self.sleeper.sleep(self.due - now);
+        } else if now - self.due > MAX_LAG {
+            self.due = now;
+        }
     }
+}
 
-    /// Accurately waits for the given time.
-    pub fn wait(&self, dur: Duration) {
-        let elapsed = Instant::now() - self.start;
-        if dur > elapsed {
-            let wait_time = dur - elapsed;
-            if wait_time.as_millis() > 1 {
-                self.sleeper.sleep(wait_time);
-            }
+/// Returns true if frames at the given rate can be paced by the vsync of a
+/// display at the given refresh rate, both in Hz. The refresh rate must be
+/// close enough for dynamic rate control to keep the audio in sync.
+#[cfg(any(feature = "sdl", feature = "winit", test))]
+pub fn vsync_matches(frame_rate: f64, refresh_rate: f64) -> bool {
+    (refresh_rate / frame_rate - 1.0).abs() <= MAX_RATE_DELTA
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vsync_matches() {
+        assert!(vsync_matches(60.0988, 60.0));
+        assert!(vsync_matches(60.0988, 59.94));
+        assert!(vsync_matches(50.007, 50.0));
+        assert!(!vsync_matches(60.0988, 59.0));
+        assert!(!vsync_matches(60.0988, 144.0));
+        assert!(!vsync_matches(50.007, 60.0));
+    }
+
+    #[test]
+    fn test_wait() {
+        let frame_time = Duration::from_millis(5);
+        let start = Instant::now();
+        let mut timer = Timer::new();
+        for _ in 0..4 {
+            timer.wait(frame_time);
         }
+        assert!(start.elapsed() >= frame_time * 4);
+
+        // Frames far behind schedule aren't caught up.
+        std::thread::sleep(MAX_LAG * 2);
+        let start = Instant::now();
+        timer.wait(frame_time);
+        assert!(start.elapsed() < frame_time);
     }
 }
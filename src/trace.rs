@@ -1,11 +1,11 @@
 use crate::cpu::AddressingMode;
+use crate::cpu::Bus;
 use crate::cpu::Cpu;
-use crate::cpu::Memory;
 use crate::instructions::OPCODES;
 
-pub fn trace(cpu: &mut Cpu) -> String {
+pub fn trace<B: Bus>(cpu: &mut Cpu<B>) -> String {
     // Get the current opcode.
-    let code = cpu.mem_read_byte(cpu.pc);
+    let code = cpu.bus.mem_peek_byte(cpu.pc);
     let op = *OPCODES.get(&code).unwrap();
 
     let begin = cpu.pc;
@@ -16,8 +16,16 @@ pub fn trace(cpu: &mut Cpu) -> String {
     let (mem_addr, stored_value) = match op.mode {
         AddressingMode::Immediate | AddressingMode::Implied => (0, 0),
         _ => {
-            let (addr, _) = cpu.get_operand_mode_address(&op.mode, begin + 1);
-            (addr, cpu.mem_read_byte(addr))
+            let (addr, _) = cpu.peek_operand_address(&op.mode, begin + 1);
+            match addr {
+                // Registers aren't read, which may have side effects. The
+                // PPU registers are shown as 0, and the APU and I/O
+                // registers as FF like Nintendulator, which made the golden
+                // log the trace is compared against.
+                0x2000..=0x3FFF => (addr, 0),
+                0x4000..=0x401F => (addr, 0xFF),
+                _ => (addr, cpu.bus.mem_peek_byte(addr)),
+            }
         }
     };
 
@@ -28,7 +36,7 @@ pub fn trace(cpu: &mut Cpu) -> String {
             _ => String::from(""),
         },
         2 => {
-            let address: u8 = cpu.mem_read_byte(begin + 1);
+            let address: u8 = cpu.bus.mem_peek_byte(begin + 1);
             hex_dump.push(address);
 
             match op.mode {
@@ -69,22 +77,24 @@ pub fn trace(cpu: &mut Cpu) -> String {
             }
         }
         3 => {
-            let address_lo = cpu.mem_read_byte(begin + 1);
-            let address_hi = cpu.mem_read_byte(begin + 2);
+            let address_lo = cpu.bus.mem_peek_byte(begin + 1);
+            let address_hi = cpu.bus.mem_peek_byte(begin + 2);
             hex_dump.push(address_lo);
             hex_dump.push(address_hi);
 
-            let address = cpu.mem_read_word(begin + 1);
+            let address = u16::from_le_bytes([address_lo, address_hi]);
 
             match op.mode {
                 AddressingMode::Implied => {
                     if op.code == 0x6C {
                         let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read_byte(address);
-                            let hi = cpu.mem_read_byte(address & 0xFF00);
+                            let lo = cpu.bus.mem_peek_byte(address);
+                            let hi = cpu.bus.mem_peek_byte(address & 0xFF00);
                             (hi as u16) << 8 | (lo as u16)
                         } else {
-                            cpu.mem_read_word(address)
+                            let lo = cpu.bus.mem_peek_byte(address);
+                            let hi = cpu.bus.mem_peek_byte(address.wrapping_add(1));
+                            u16::from_le_bytes([lo, hi])
                         };
 
                         format!("(${:04x}) = {:04x}", address, jmp_addr)
@@ -136,6 +146,7 @@ mod tests {
     use super::*;
     use crate::bus::SystemBus;
     use crate::cartridge::tests::test_cartridge;
+    use crate::cpu::Memory;
 
     #[test]
     fn test_format_trace() {
@@ -0,0 +1,269 @@
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod crt;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod font;
+mod gif;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod osd;
+mod overscan;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod scale;
+mod viewport;
+mod y4m;
+
+use std::fmt;
+
+use serde::Deserialize;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use crate::config::VideoConfig;
+use crate::thumbnail::Thumbnail;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use self::crt::{Crt, CRT_SCALE};
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub use self::font::{draw_text, LINE_HEIGHT};
+pub use self::gif::GifClip;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub use self::osd::Osd;
+pub use self::overscan::Overscan;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use self::scale::ScaleX;
+pub use self::viewport::PixelAspect;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub use self::viewport::Viewport;
+pub use self::y4m::Y4mWriter;
+
+/// A message to show on screen, with the thumbnail of the save state it's
+/// about, if any.
+pub struct Message {
+    pub text: String,
+    // Only the on-screen display draws thumbnails.
+    #[cfg_attr(not(any(feature = "sdl", feature = "winit")), allow(dead_code))]
+    pub thumbnail: Option<Thumbnail>,
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message {
+            text,
+            thumbnail: None,
+        }
+    }
+}
+
+/// The filters frames can be drawn through.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// The frames as they are, in square pixels.
+    #[default]
+    None,
+
+    /// Upscaled by 2, smoothing diagonal edges.
+    Scale2x,
+
+    /// Upscaled by 3, smoothing diagonal edges.
+    Scale3x,
+
+    /// Drawn like a CRT.
+    Crt,
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl Filter {
+    /// The filters in the order they're cycled through.
+    const ALL: [Filter; 4] = [Filter::None, Filter::Scale2x, Filter::Scale3x, Filter::Crt];
+
+    /// Returns the filter after this one, going back to the first after the
+    /// last.
+    pub fn next(self) -> Self {
+        let index = Filter::ALL
+            .iter()
+            .position(|&filter| filter == self)
+            .unwrap();
+        Filter::ALL[(index + 1) % Filter::ALL.len()]
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::Scale2x => write!(f, "Scale2x"),
+            Filter::Scale3x => write!(f, "Scale3x"),
+            Filter::Crt => write!(f, "CRT"),
+        }
+    }
+}
+
+/// The post-process of a filter.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+enum Stage {
+    None,
+    Scale(ScaleX),
+    Crt(Crt),
+}
+
+/// Post-processes the frames of the console before they're shown, cropping
+/// the overscan then drawing them through the filter selected, which may
+/// upscale them.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub struct VideoFilter {
+    config: VideoConfig,
+    overscan: Overscan,
+    uncropped_w: u32,
+    uncropped_h: u32,
+    cropped: Vec<u8>,
+
+    // The size of the frames once cropped.
+    frame_w: usize,
+    frame_h: usize,
+
+    filter: Filter,
+    stage: Stage,
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl VideoFilter {
+    /// Returns the post-process of frames of the given size, cropping the
+    /// given overscan, through the filter in the config.
+    pub fn new(config: &VideoConfig, overscan: Overscan, frame_w: u32, frame_h: u32) -> Self {
+        let mut video_filter = VideoFilter {
+            config: config.clone(),
+            overscan: Overscan::default(),
+            uncropped_w: frame_w,
+            uncropped_h: frame_h,
+            cropped: vec![],
+            frame_w: frame_w as usize,
+            frame_h: frame_h as usize,
+            filter: config.filter,
+            stage: Stage::None,
+        };
+        video_filter.set_overscan(overscan);
+
+        video_filter
+    }
+
+    /// Returns the overscan cropped from the frames.
+    pub fn overscan(&self) -> Overscan {
+        self.overscan
+    }
+
+    /// Crops the given overscan from the frames.
+    pub fn set_overscan(&mut self, overscan: Overscan) {
+        let (frame_w, frame_h) = overscan.size(self.uncropped_w, self.uncropped_h);
+        self.overscan = overscan;
+        self.frame_w = frame_w as usize;
+        self.frame_h = frame_h as usize;
+        self.set_filter(self.filter);
+    }
+
+    /// Returns the width and height of the frames once cropped, before
+    /// they're filtered.
+    pub fn frame_size(&self) -> (u32, u32) {
+        (self.frame_w as u32, self.frame_h as u32)
+    }
+
+    /// Selects the filter frames are drawn through.
+    pub fn set_filter(&mut self, filter: Filter) {
+        let (frame_w, frame_h) = (self.frame_w, self.frame_h);
+        self.filter = filter;
+        self.stage = match filter {
+            Filter::None => Stage::None,
+            Filter::Scale2x => Stage::Scale(ScaleX::new(2, frame_w, frame_h)),
+            Filter::Scale3x => Stage::Scale(ScaleX::new(3, frame_w, frame_h)),
+            Filter::Crt => Stage::Crt(Crt::new(&self.config.crt, frame_w, frame_h)),
+        };
+    }
+
+    /// Selects the next filter, returning it.
+    pub fn cycle(&mut self) -> Filter {
+        self.set_filter(self.filter.next());
+        self.filter
+    }
+
+    /// Returns the number of output pixels across and down each pixel of
+    /// the frame.
+    pub fn scale(&self) -> u32 {
+        match &self.stage {
+            Stage::None => 1,
+            Stage::Scale(scaler) => scaler.factor() as u32,
+            Stage::Crt(_) => CRT_SCALE as u32,
+        }
+    }
+
+    /// Returns the width and height of the output, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        let (w, h) = match &self.stage {
+            Stage::None => (self.frame_w, self.frame_h),
+            Stage::Scale(scaler) => (scaler.width(), scaler.height()),
+            Stage::Crt(crt) => (crt.width(), crt.height()),
+        };
+
+        (w as u32, h as u32)
+    }
+
+    /// Returns the RGB pixels of the given RGB frame post-processed.
+    pub fn process<'a>(&'a mut self, frame: &'a [u8]) -> &'a [u8] {
+        let frame = match self.overscan == Overscan::default() {
+            true => frame,
+            false => {
+                let frame_w = self.uncropped_w as usize;
+                self.overscan.crop(frame, frame_w, &mut self.cropped);
+                &self.cropped
+            }
+        };
+
+        match &mut self.stage {
+            Stage::None => frame,
+            Stage::Scale(scaler) => scaler.process(frame),
+            Stage::Crt(crt) => crt.process(frame),
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "sdl", feature = "winit")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle() {
+        let overscan = Overscan::default();
+        let mut filter = VideoFilter::new(&VideoConfig::default(), overscan, 256, 240);
+        assert_eq!(filter.filter, Filter::None);
+        assert_eq!(filter.size(), (256, 240));
+
+        assert_eq!(filter.cycle(), Filter::Scale2x);
+        assert_eq!((filter.size(), filter.scale()), ((512, 480), 2));
+        assert_eq!(filter.cycle(), Filter::Scale3x);
+        assert_eq!(filter.cycle(), Filter::Crt);
+        assert_eq!((filter.size(), filter.scale()), ((768, 720), 3));
+        assert_eq!(filter.cycle(), Filter::None);
+
+        let frame = vec![7; 256 * 240 * 3];
+        assert_eq!(filter.process(&frame), frame);
+    }
+
+    #[test]
+    fn test_overscan() {
+        let overscan = Overscan {
+            top: 8,
+            bottom: 8,
+            left: 4,
+            right: 4,
+        };
+        let mut filter = VideoFilter::new(&VideoConfig::default(), overscan, 256, 240);
+        assert_eq!(filter.frame_size(), (248, 224));
+        assert_eq!(filter.size(), (248, 224));
+
+        let frame = vec![7; 256 * 240 * 3];
+        assert_eq!(filter.process(&frame).len(), 248 * 224 * 3);
+        filter.set_filter(Filter::Scale2x);
+        assert_eq!(filter.size(), (496, 448));
+
+        filter.set_overscan(Overscan::default());
+        assert_eq!(filter.frame_size(), (256, 240));
+        assert_eq!(filter.size(), (512, 480));
+    }
+}
@@ -0,0 +1,165 @@
+use std::f32::consts::PI;
+
+use crate::config::CrtConfig;
+
+/// Number of output pixels across and down each pixel of the frame, enough
+/// for the scanlines and shadow mask to show.
+pub const CRT_SCALE: usize = 3;
+
+/// How far the corners of the frame are pulled in at full curvature.
+const MAX_CURVATURE: f32 = 0.15;
+
+/// A post-process which draws frames like a CRT: upscaled, with dark gaps
+/// between the scanlines, an aperture grille shadow mask tinting each column
+/// red, green or blue, the screen curved, and its edges darkened.
+///
+/// The effect doesn't change between frames, so the pixel of the frame each
+/// output pixel shows and how bright it's drawn are worked out once.
+pub struct Crt {
+    width: usize,
+
+    /// The offset of the pixel of the frame each output pixel shows, and its
+    /// brightness, which is 0 off the edges of the curved screen.
+    samples: Vec<(usize, f32)>,
+
+    /// Brightness of the colour channels each column of the mask blocks.
+    mask: f32,
+
+    output: Vec<u8>,
+}
+
+impl Crt {
+    /// Returns the post-process of frames of the given size, with the
+    /// intensity of each effect from the config.
+    pub fn new(config: &CrtConfig, frame_w: usize, frame_h: usize) -> Self {
+        let width = frame_w * CRT_SCALE;
+        let height = frame_h * CRT_SCALE;
+        let curvature = config.curvature * MAX_CURVATURE;
+
+        let mut samples = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // The position on the screen, from -1 to 1 across and down.
+                let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+
+                // Bending the screen away pulls in the middle of each edge.
+                let u = u * (1.0 + curvature * v * v);
+                let v = v * (1.0 + curvature * u * u);
+                if u.abs() >= 1.0 || v.abs() >= 1.0 {
+                    samples.push((0, 0.0));
+                    continue;
+                }
+
+                let frame_x = (u + 1.0) / 2.0 * frame_w as f32;
+                let frame_y = (v + 1.0) / 2.0 * frame_h as f32;
+
+                // The beam is brightest in the middle of each scanline.
+                let beam = (frame_y.fract() * PI).sin();
+                let scanline = 1.0 - config.scanlines * (1.0 - beam * beam);
+                let vignette = 1.0 - config.vignette * (u * u + v * v) / 2.0;
+
+                let offset = (frame_y as usize * frame_w + frame_x as usize) * 3;
+                samples.push((offset, scanline * vignette));
+            }
+        }
+
+        Crt {
+            width,
+            samples,
+            mask: 1.0 - config.mask,
+            output: vec![0; width * height * 3],
+        }
+    }
+
+    /// Returns the width of the output, in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the output, in pixels.
+    pub fn height(&self) -> usize {
+        self.samples.len() / self.width
+    }
+
+    /// Draws the given RGB frame, returning the RGB pixels of the output.
+    pub fn process(&mut self, frame: &[u8]) -> &[u8] {
+        let pixels = self.output.chunks_exact_mut(3).zip(&self.samples);
+        for (i, (pixel, &(offset, brightness))) in pixels.enumerate() {
+            let column = i % self.width % 3;
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                let mask = match channel == column {
+                    true => 1.0,
+                    false => self.mask,
+                };
+                *value = (frame[offset + channel] as f32 * brightness * mask) as u8;
+            }
+        }
+
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the CRT output of a 4x2 frame of a single colour.
+    fn process(config: &CrtConfig) -> Vec<u8> {
+        let frame = [100, 150, 200].repeat(8);
+        let mut crt = Crt::new(config, 4, 2);
+        assert_eq!((crt.width(), crt.height()), (12, 6));
+        crt.process(&frame).to_vec()
+    }
+
+    /// Returns the RGB value of the output pixel at the given position.
+    fn pixel(output: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * 12 + x) * 3;
+        [output[offset], output[offset + 1], output[offset + 2]]
+    }
+
+    #[test]
+    fn test_no_effects() {
+        let config = CrtConfig {
+            scanlines: 0.0,
+            mask: 0.0,
+            curvature: 0.0,
+            vignette: 0.0,
+        };
+        assert_eq!(process(&config), [100, 150, 200].repeat(72));
+    }
+
+    #[test]
+    fn test_scanlines_and_mask() {
+        let config = CrtConfig {
+            scanlines: 0.5,
+            mask: 0.5,
+            curvature: 0.0,
+            vignette: 0.0,
+        };
+        let output = process(&config);
+
+        // The middle of a scanline is at full brightness, and each column
+        // passes one channel.
+        assert_eq!(pixel(&output, 0, 1), [100, 75, 100]);
+        assert_eq!(pixel(&output, 1, 1), [50, 150, 100]);
+        assert_eq!(pixel(&output, 2, 4), [50, 75, 200]);
+
+        // Its edges are darker.
+        assert_eq!(pixel(&output, 0, 0), [62, 46, 62]);
+    }
+
+    #[test]
+    fn test_curvature() {
+        let config = CrtConfig {
+            curvature: 1.0,
+            ..CrtConfig::default()
+        };
+        let output = process(&config);
+
+        // The corners are off the screen.
+        assert_eq!(pixel(&output, 0, 0), [0, 0, 0]);
+        assert_eq!(pixel(&output, 11, 5), [0, 0, 0]);
+        assert_ne!(pixel(&output, 6, 3), [0, 0, 0]);
+    }
+}
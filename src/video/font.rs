@@ -0,0 +1,137 @@
+/// Width and height of a glyph, in pixels.
+pub const GLYPH_W: usize = 5;
+pub const GLYPH_H: usize = 7;
+
+/// Distance from the start of one character or line to the next, leaving
+/// room for the shadow.
+pub const ADVANCE: usize = GLYPH_W + 1;
+pub const LINE_HEIGHT: usize = GLYPH_H + 2;
+
+/// The rows of each glyph, top to bottom, with the leftmost pixel in bit 4.
+const GLYPHS: [(char, [u8; GLYPH_H]); 50] = [
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x00, 0x00, 0x04]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('\'', [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('A', [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+];
+
+/// Returns the rows of the glyph of the given character, drawn in upper
+/// case, or of a question mark if the font doesn't have it.
+fn glyph(c: char) -> &'static [u8; GLYPH_H] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|&&(glyph, _)| glyph == c)
+        .map_or_else(|| glyph('?'), |(_, rows)| rows)
+}
+
+/// Draws the given text in white, with a black shadow so it can be read
+/// over any picture, into an RGB frame of the given width. The text starts
+/// at the given position and each pixel of the font is drawn as a square of
+/// the given scale. Text outside the frame is clipped.
+pub fn draw_text(
+    frame: &mut [u8],
+    frame_w: usize,
+    (x, y): (usize, usize),
+    scale: usize,
+    text: &str,
+) {
+    for (shadow, colour) in [(scale, 0x00), (0, 0xFF)] {
+        for (i, c) in text.chars().enumerate() {
+            let glyph_x = x + i * ADVANCE * scale + shadow;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in (0..GLYPH_W).filter(|column| bits & (0x10 >> column) != 0) {
+                    let pixel_x = glyph_x + column * scale;
+                    let pixel_y = y + row * scale + shadow;
+                    fill(frame, frame_w, (pixel_x, pixel_y), scale, colour);
+                }
+            }
+        }
+    }
+}
+
+/// Fills a square of the given size in the frame with a grey level.
+fn fill(frame: &mut [u8], frame_w: usize, (x, y): (usize, usize), size: usize, level: u8) {
+    let frame_h = frame.len() / (frame_w * 3);
+    for row in y..(y + size).min(frame_h) {
+        let start = (row * frame_w + x.min(frame_w)) * 3;
+        let end = (row * frame_w + (x + size).min(frame_w)) * 3;
+        frame[start..end].fill(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let frame_w = 8;
+        let mut frame = vec![0x80; frame_w * 10 * 3];
+        draw_text(&mut frame, frame_w, (1, 1), 1, "1");
+        let level = |frame: &[u8], x: usize, y: usize| frame[(y * frame_w + x) * 3];
+
+        // The top of the 1, its shadow below, and the background around it.
+        assert_eq!(level(&frame, 3, 1), 0xFF);
+        assert_eq!(level(&frame, 3, 2), 0xFF);
+        assert_eq!(level(&frame, 4, 2), 0x00);
+        assert_eq!(level(&frame, 4, 8), 0x00);
+        assert_eq!(level(&frame, 1, 1), 0x80);
+
+        // Text running off the frame is clipped.
+        draw_text(&mut frame, frame_w, (6, 6), 2, "W");
+        assert_eq!(level(&frame, 7, 6), 0xFF);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+
+/// Number of frames of the console per frame of a GIF. Few GIF viewers
+/// show frames shorter than 2/100ths of a second, so every other frame is
+/// kept.
+const FRAME_STEP: usize = 2;
+
+/// Index of the colour table marking pixels unchanged from the last frame.
+const TRANSPARENT: u8 = 255;
+
+/// Largest number of codes LZW compression uses, of 12 bits.
+const MAX_CODES: u16 = 4096;
+
+/// Keeps the frames of the last few seconds, to save as an animated GIF.
+pub struct GifClip {
+    width: usize,
+    height: usize,
+    frame_rate: f64,
+    frames: VecDeque<Vec<u8>>,
+    max_frames: usize,
+    skipped: usize,
+}
+
+impl GifClip {
+    /// Returns a clip of the given number of seconds of RGB frames of the
+    /// given size, at the given frame rate in Hz.
+    pub fn new(seconds: f64, width: usize, height: usize, frame_rate: f64) -> Self {
+        let frame_rate = frame_rate / FRAME_STEP as f64;
+        let max_frames = (seconds * frame_rate).ceil() as usize;
+        GifClip {
+            width,
+            height,
+            frame_rate,
+            frames: VecDeque::with_capacity(max_frames),
+            max_frames,
+            skipped: 0,
+        }
+    }
+
+    /// Adds a frame to the clip, dropping the oldest once it's full.
+    pub fn push(&mut self, frame: &[u8]) {
+        self.skipped += 1;
+        if self.skipped < FRAME_STEP {
+            return;
+        }
+        self.skipped = 0;
+
+        let mut kept = match self.frames.len() < self.max_frames {
+            true => Vec::with_capacity(frame.len()),
+            false => self.frames.pop_front().unwrap(),
+        };
+        kept.clear();
+        kept.extend_from_slice(frame);
+        self.frames.push_back(kept);
+    }
+
+    /// Returns true if no frames have been kept.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Returns a copy of the clip, to be saved while the frames carry on.
+    pub fn snapshot(&self) -> GifClip {
+        GifClip {
+            width: self.width,
+            height: self.height,
+            frame_rate: self.frame_rate,
+            frames: self.frames.clone(),
+            max_frames: self.max_frames,
+            skipped: 0,
+        }
+    }
+
+    /// Writes the frames as a looping animated GIF.
+    ///
+    /// The frames share a colour table of the first 255 colours they use,
+    /// with any others drawn in the nearest of them. After the first, each
+    /// frame only covers the area changed since the last, with the pixels
+    /// that are the same left transparent, and frames that change nothing
+    /// lengthen the last instead.
+    ///
+    /// See: https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let palette = Palette::new(&self.frames);
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(self.width as u16).to_le_bytes())?;
+        out.write_all(&(self.height as u16).to_le_bytes())?;
+        // A global colour table of 256 colours, with 8 bits per channel.
+        out.write_all(&[0xF7, 0, 0])?;
+        out.write_all(&palette.table())?;
+
+        // Loop forever.
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        for (area, delay) in self.areas(&palette) {
+            // Leave each frame in place for the next to be drawn over.
+            out.write_all(&[0x21, 0xF9, 0x04, 0x05])?;
+            out.write_all(&delay.to_le_bytes())?;
+            out.write_all(&[TRANSPARENT, 0])?;
+
+            out.write_all(&[0x2C])?;
+            for value in [area.x, area.y, area.w, area.h] {
+                out.write_all(&(value as u16).to_le_bytes())?;
+            }
+            out.write_all(&[0])?;
+
+            out.write_all(&[8])?;
+            for block in lzw_encode(&area.pixels).chunks(255) {
+                out.write_all(&[block.len() as u8])?;
+                out.write_all(block)?;
+            }
+            out.write_all(&[0])?;
+        }
+
+        out.write_all(&[0x3B])?;
+        out.flush()
+    }
+
+    /// Returns the area of each frame changed since the last, as indices of
+    /// the palette, and how long it's shown for in 1/100ths of a second.
+    fn areas(&self, palette: &Palette) -> Vec<(Area, u16)> {
+        let mut areas: Vec<(Area, u16)> = vec![];
+        let mut shown = vec![None; self.width * self.height];
+        for (i, frame) in self.frames.iter().enumerate() {
+            // Delays are rounded so they add up to the length of the clip.
+            let centiseconds = |frame: usize| (frame as f64 * 100.0 / self.frame_rate).round();
+            let delay = (centiseconds(i + 1) - centiseconds(i)) as u16;
+
+            let indices: Vec<_> = frame
+                .chunks_exact(3)
+                .map(|rgb| Some(palette.index([rgb[0], rgb[1], rgb[2]])))
+                .collect();
+            match Area::changed(&shown, &indices, self.width) {
+                Some(area) => areas.push((area, delay)),
+                None => areas.last_mut().unwrap().1 += delay,
+            }
+            shown = indices;
+        }
+
+        areas
+    }
+}
+
+/// The colours of a GIF.
+struct Palette {
+    colours: Vec<[u8; 3]>,
+    indices: HashMap<[u8; 3], u8>,
+}
+
+impl Palette {
+    /// Returns the palette of the first 255 colours in the given frames.
+    fn new(frames: &VecDeque<Vec<u8>>) -> Self {
+        let mut palette = Palette {
+            colours: vec![],
+            indices: HashMap::new(),
+        };
+        let pixels = frames.iter().flat_map(|frame| frame.chunks_exact(3));
+        for rgb in pixels {
+            let colour = [rgb[0], rgb[1], rgb[2]];
+            if palette.colours.len() < TRANSPARENT as usize {
+                palette.indices.entry(colour).or_insert_with(|| {
+                    palette.colours.push(colour);
+                    (palette.colours.len() - 1) as u8
+                });
+            }
+        }
+
+        palette
+    }
+
+    /// Returns the colour table of 256 colours.
+    fn table(&self) -> Vec<u8> {
+        let mut table: Vec<_> = self.colours.iter().flatten().copied().collect();
+        table.resize(256 * 3, 0);
+        table
+    }
+
+    /// Returns the index of the given colour, or the nearest in the palette.
+    fn index(&self, colour: [u8; 3]) -> u8 {
+        if let Some(&index) = self.indices.get(&colour) {
+            return index;
+        }
+
+        let distance = |other: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|i| (colour[i] as i32 - other[i] as i32).pow(2))
+                .sum()
+        };
+        (0..self.colours.len())
+            .min_by_key(|&i| distance(&self.colours[i]))
+            .unwrap_or_default() as u8
+    }
+}
+
+/// A rectangle of a frame, as indices of the palette.
+struct Area {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    pixels: Vec<u8>,
+}
+
+impl Area {
+    /// Returns the area of the frame changed from the last frame shown,
+    /// with the pixels that are the same transparent, or none if nothing
+    /// changed.
+    fn changed(shown: &[Option<u8>], frame: &[Option<u8>], width: usize) -> Option<Area> {
+        let changed = |i: usize| shown[i] != frame[i];
+        let rows = frame.len() / width;
+        let top = (0..rows).find(|&y| (0..width).any(|x| changed(y * width + x)))?;
+        let bottom = (top..rows)
+            .rev()
+            .find(|&y| (0..width).any(|x| changed(y * width + x)))?;
+        let column_changed = |x: usize| (top..=bottom).any(|y| changed(y * width + x));
+        let left = (0..width).find(|&x| column_changed(x))?;
+        let right = (left..width).rev().find(|&x| column_changed(x))?;
+
+        let mut pixels = vec![];
+        for y in top..=bottom {
+            for x in left..=right {
+                let i = y * width + x;
+                pixels.push(match changed(i) {
+                    true => frame[i].unwrap(),
+                    false => TRANSPARENT,
+                });
+            }
+        }
+
+        Some(Area {
+            x: left,
+            y: top,
+            w: right - left + 1,
+            h: bottom - top + 1,
+            pixels,
+        })
+    }
+}
+
+/// Writes the codes of variable length into bytes, least significant bit
+/// first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses the given 8 bit indices with the variable length LZW of GIFs.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+
+    let mut out = BitWriter {
+        bytes: vec![],
+        bits: 0,
+        count: 0,
+    };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END + 1;
+    let mut size = 9;
+
+    out.write(CLEAR, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        out.write(END, size);
+        return out.finish();
+    };
+
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, size);
+        match next_code < MAX_CODES {
+            true => {
+                codes.insert((prefix, index), next_code);
+                next_code += 1;
+                if next_code > 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            false => {
+                out.write(CLEAR, size);
+                codes.clear();
+                next_code = END + 1;
+                size = 9;
+            }
+        }
+        prefix = index as u16;
+    }
+    out.write(prefix, size);
+    out.write(END, size);
+
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the indices compressed by lzw_encode.
+    fn lzw_decode(bytes: &[u8]) -> Vec<u8> {
+        let mut codes: Vec<Vec<u8>> = (0..=255).map(|i| vec![i]).collect();
+        codes.extend([vec![], vec![]]);
+        let mut out = vec![];
+        let (mut bits, mut count, mut size) = (0u32, 0, 9);
+        let mut last: Option<Vec<u8>> = None;
+        let mut bytes = bytes.iter();
+        loop {
+            while count < size {
+                bits |= (*bytes.next().unwrap() as u32) << count;
+                count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            count -= size;
+
+            match code {
+                256 => {
+                    codes.truncate(258);
+                    size = 9;
+                    last = None;
+                    continue;
+                }
+                257 => return out,
+                _ => {}
+            }
+
+            let entry = match (codes.get(code), &last) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(last)) => [last.clone(), vec![last[0]]].concat(),
+                (None, None) => panic!("unknown code {}", code),
+            };
+            if let Some(last) = last {
+                codes.push([last, vec![entry[0]]].concat());
+                if codes.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            out.extend(&entry);
+            last = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw() {
+        for indices in [
+            vec![],
+            vec![7],
+            vec![1, 2, 1, 2, 1, 2, 1, 2, 3],
+            (0..20000).map(|i| (i * i % 251) as u8).collect(),
+            vec![0; 70000],
+        ] {
+            assert_eq!(lzw_decode(&lzw_encode(&indices)), indices);
+        }
+    }
+
+    #[test]
+    fn test_changed_area() {
+        let shown = [Some(0); 12];
+        assert!(Area::changed(&shown, &shown, 4).is_none());
+
+        let mut frame = shown;
+        frame[5] = Some(1);
+        frame[10] = Some(2);
+        let area = Area::changed(&shown, &frame, 4).unwrap();
+        assert_eq!((area.x, area.y, area.w, area.h), (1, 1, 2, 2));
+        assert_eq!(area.pixels, [1, TRANSPARENT, TRANSPARENT, 2]);
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut clip = GifClip::new(1.0, 2, 1, 4.0);
+        for frame in 0..10u8 {
+            clip.push(&[frame, 0, 0, 255, 255, 255]);
+        }
+
+        // Every other frame is kept, for 1 second.
+        assert_eq!(clip.frames.len(), 2);
+        assert_eq!(clip.frames[0][0], 7);
+
+        let mut out = vec![];
+        clip.snapshot().write(&mut out).unwrap();
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(&out[13..19], [7, 0, 0, 255, 255, 255]);
+        assert_eq!(out.last(), Some(&0x3B));
+    }
+}
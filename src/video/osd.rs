@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use super::font::{draw_text, LINE_HEIGHT};
+use super::Message;
+use crate::thumbnail::{Thumbnail, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+
+/// How long each message is shown for.
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+/// Most messages shown at once. The oldest make way for new ones.
+const MAX_MESSAGES: usize = 4;
+
+/// Margin between the messages and the edges of the frame, in unscaled
+/// pixels.
+const MARGIN: usize = 4;
+
+/// An on-screen display of short messages, such as "Saved state to slot
+/// 3", drawn over the bottom of the frames for a few seconds each. The
+/// emulator and frontend send the messages down a channel, so they can be
+/// sent from any thread.
+pub struct Osd {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+
+    /// The messages shown, oldest first, with when each arrived.
+    messages: VecDeque<(Message, Instant)>,
+    overlaid: Vec<u8>,
+}
+
+impl Osd {
+    /// Returns a new display, showing no messages.
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Osd {
+            sender,
+            receiver,
+            messages: VecDeque::new(),
+            overlaid: vec![],
+        }
+    }
+
+    /// Returns a sender of messages to show.
+    pub fn sender(&self) -> Sender<Message> {
+        self.sender.clone()
+    }
+
+    /// Takes the messages sent since the last update, and drops those shown
+    /// for long enough.
+    pub fn update(&mut self) {
+        self.update_at(Instant::now());
+    }
+
+    fn update_at(&mut self, now: Instant) {
+        for message in self.receiver.try_iter() {
+            self.messages.push_back((message, now));
+        }
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages
+            .retain(|(_, arrived)| now.duration_since(*arrived) < MESSAGE_TIME);
+    }
+
+    /// Returns the given RGB frame, of the given width, with the messages
+    /// drawn over its bottom left corner at the given scale, the newest
+    /// lowest, and the newest thumbnail above them.
+    pub fn draw<'a>(&'a mut self, frame: &'a [u8], frame_w: usize, scale: usize) -> &'a [u8] {
+        if self.messages.is_empty() {
+            return frame;
+        }
+
+        self.overlaid.clear();
+        self.overlaid.extend_from_slice(frame);
+        let frame_h = frame.len() / (frame_w * 3);
+        let lines = self.messages.len();
+        for (i, (message, _)) in self.messages.iter().enumerate() {
+            let y = frame_h.saturating_sub((MARGIN + (lines - i) * LINE_HEIGHT) * scale);
+            draw_text(
+                &mut self.overlaid,
+                frame_w,
+                (MARGIN * scale, y),
+                scale,
+                &message.text,
+            );
+        }
+
+        let thumbnail = self
+            .messages
+            .iter()
+            .rev()
+            .find_map(|(message, _)| message.thumbnail.as_ref());
+        if let Some(thumbnail) = thumbnail {
+            let height = MARGIN + lines * LINE_HEIGHT + THUMBNAIL_HEIGHT;
+            let y = frame_h.saturating_sub(height * scale);
+            draw_thumbnail(
+                &mut self.overlaid,
+                frame_w,
+                (MARGIN * scale, y),
+                scale,
+                thumbnail,
+            );
+        }
+        &self.overlaid
+    }
+}
+
+/// Draws the thumbnail into an RGB frame of the given width, with its top
+/// left at the given position and each of its pixels a square of the given
+/// scale. Pixels outside the frame are clipped.
+fn draw_thumbnail(
+    frame: &mut [u8],
+    frame_w: usize,
+    (x, y): (usize, usize),
+    scale: usize,
+    thumbnail: &Thumbnail,
+) {
+    let frame_h = frame.len() / (frame_w * 3);
+    for row in 0..(THUMBNAIL_HEIGHT * scale).min(frame_h.saturating_sub(y)) {
+        let pixels = &thumbnail.pixels()[row / scale * THUMBNAIL_WIDTH * 3..];
+        for column in 0..(THUMBNAIL_WIDTH * scale).min(frame_w.saturating_sub(x)) {
+            let start = ((y + row) * frame_w + x + column) * 3;
+            let rgb = &pixels[column / scale * 3..column / scale * 3 + 3];
+            frame[start..start + 3].copy_from_slice(rgb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::Frame;
+
+    /// Returns the messages shown.
+    fn shown(osd: &Osd) -> Vec<&str> {
+        osd.messages
+            .iter()
+            .map(|(message, _)| message.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut osd = Osd::new();
+        let start = Instant::now();
+        let sender = osd.sender();
+        sender.send("Paused".to_string().into()).unwrap();
+        osd.update_at(start);
+        assert_eq!(shown(&osd), ["Paused"]);
+
+        sender.send("Resumed".to_string().into()).unwrap();
+        osd.update_at(start + Duration::from_secs(1));
+        assert_eq!(shown(&osd), ["Paused", "Resumed"]);
+
+        osd.update_at(start + MESSAGE_TIME);
+        assert_eq!(shown(&osd), ["Resumed"]);
+        osd.update_at(start + MESSAGE_TIME * 2);
+        assert!(shown(&osd).is_empty());
+    }
+
+    #[test]
+    fn test_max_messages() {
+        let mut osd = Osd::new();
+        for slot in 0..6 {
+            osd.sender().send(format!("Slot {}", slot).into()).unwrap();
+        }
+        osd.update();
+        assert_eq!(shown(&osd), ["Slot 2", "Slot 3", "Slot 4", "Slot 5"]);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut osd = Osd::new();
+        let frame = vec![0x40; 64 * 32 * 3];
+        assert_eq!(osd.draw(&frame, 64, 1), frame);
+
+        osd.sender().send("I".to_string().into()).unwrap();
+        osd.update();
+        let overlaid = osd.draw(&frame, 64, 1).to_vec();
+        let pixel = |x: usize, y: usize| overlaid[(y * 64 + x) * 3];
+
+        // The top of the I sits a line and the margin above the bottom.
+        let top = 32 - MARGIN - LINE_HEIGHT;
+        assert_eq!(pixel(MARGIN + 1, top), 0xFF);
+        assert_eq!(pixel(MARGIN + 1, top - 1), 0x40);
+        assert_eq!(pixel(MARGIN, top + 1), 0x40);
+    }
+
+    #[test]
+    fn test_draw_thumbnail() {
+        let mut osd = Osd::new();
+        let frame = vec![0x40; 128 * 128 * 3];
+        let thumbnail = Thumbnail::from_frame(&vec![0x10; Frame::WIDTH * Frame::HEIGHT * 3]);
+        let message = Message {
+            text: "Slot 1".to_string(),
+            thumbnail: Some(thumbnail),
+        };
+        osd.sender().send(message).unwrap();
+        osd.update();
+        let overlaid = osd.draw(&frame, 128, 1).to_vec();
+        let pixel = |x: usize, y: usize| overlaid[(y * 128 + x) * 3];
+
+        // The thumbnail sits above the line of the message.
+        let bottom = 128 - MARGIN - LINE_HEIGHT - 1;
+        assert_eq!(pixel(MARGIN, bottom), 0x10);
+        assert_eq!(
+            pixel(MARGIN + THUMBNAIL_WIDTH - 1, bottom + 1 - THUMBNAIL_HEIGHT),
+            0x10
+        );
+        assert_eq!(pixel(MARGIN + THUMBNAIL_WIDTH, bottom), 0x40);
+        assert_eq!(pixel(MARGIN, bottom - THUMBNAIL_HEIGHT), 0x40);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::OverscanConfig;
+use crate::region::Region;
+
+/// The lines and columns cropped from each edge of the frames, which TVs
+/// hide behind their bezels and games often leave garbage in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    /// Returns the overscan set in the config, with the edges left out
+    /// cropped by the default of the region: the 8 lines at the top and
+    /// bottom NTSC TVs hide, and nothing on PAL TVs, which show them.
+    pub fn new(config: &OverscanConfig, region: Region) -> Self {
+        let lines = match region {
+            Region::Ntsc => 8,
+            Region::Pal | Region::Dendy => 0,
+        };
+
+        Overscan {
+            top: config.top.unwrap_or(lines),
+            bottom: config.bottom.unwrap_or(lines),
+            left: config.left.unwrap_or(0),
+            right: config.right.unwrap_or(0),
+        }
+    }
+
+    /// Returns the size of frames of the given size once cropped.
+    pub fn size(&self, frame_w: u32, frame_h: u32) -> (u32, u32) {
+        (
+            frame_w - self.left - self.right,
+            frame_h - self.top - self.bottom,
+        )
+    }
+
+    /// Returns the position in the whole frame of the given position in the
+    /// cropped frame.
+    #[cfg(any(feature = "sdl", feature = "winit", test))]
+    pub fn uncrop(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        (x + self.left as usize, y + self.top as usize)
+    }
+
+    /// Copies the RGB pixels of the given frame, of the given width, left
+    /// once cropped to out.
+    pub fn crop(&self, frame: &[u8], frame_w: usize, out: &mut Vec<u8>) {
+        let frame_h = frame.len() / (frame_w * 3);
+        let (left, right) = (self.left as usize, self.right as usize);
+        let (top, bottom) = (self.top as usize, self.bottom as usize);
+
+        out.clear();
+        for row in frame
+            .chunks_exact(frame_w * 3)
+            .take(frame_h - bottom)
+            .skip(top)
+        {
+            out.extend_from_slice(&row[left * 3..(frame_w - right) * 3]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_defaults() {
+        let config = OverscanConfig::default();
+        let ntsc = Overscan::new(&config, Region::Ntsc);
+        assert_eq!(ntsc.size(256, 240), (256, 224));
+        let pal = Overscan::new(&config, Region::Pal);
+        assert_eq!(pal, Overscan::default());
+
+        let config = OverscanConfig {
+            top: Some(16),
+            left: Some(8),
+            ..OverscanConfig::default()
+        };
+        let custom = Overscan::new(&config, Region::Ntsc);
+        assert_eq!(custom.size(256, 240), (248, 216));
+        assert_eq!(custom.uncrop((0, 0)), (8, 16));
+    }
+
+    #[test]
+    fn test_crop() {
+        let overscan = Overscan {
+            top: 1,
+            bottom: 0,
+            left: 1,
+            right: 1,
+        };
+        let frame: Vec<_> = (0..3 * 2 * 3).map(|i| i as u8).collect();
+        let mut cropped = vec![];
+        overscan.crop(&frame, 3, &mut cropped);
+        assert_eq!(cropped, [12, 13, 14]);
+    }
+}
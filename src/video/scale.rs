@@ -0,0 +1,209 @@
+/// An RGB pixel.
+type Pixel = [u8; 3];
+
+/// Upscales frames by 2 or 3 with the Scale2x or Scale3x algorithm, which
+/// smooths the diagonal edges of pixel art without blurring it. Each pixel
+/// is split into 2x2 or 3x3, and the corners take the colour of matching
+/// neighbours.
+///
+/// See: https://www.scale2x.it/algorithm
+pub struct ScaleX {
+    factor: usize,
+    frame_w: usize,
+    frame_h: usize,
+    output: Vec<u8>,
+}
+
+impl ScaleX {
+    /// Returns the scaler of frames of the given size by 2 or 3.
+    pub fn new(factor: usize, frame_w: usize, frame_h: usize) -> Self {
+        assert!(factor == 2 || factor == 3, "scale factor must be 2 or 3");
+
+        ScaleX {
+            factor,
+            frame_w,
+            frame_h,
+            output: vec![0; frame_w * frame_h * factor * factor * 3],
+        }
+    }
+
+    /// Returns the number of output pixels across and down each pixel of
+    /// the frame.
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Returns the width of the output, in pixels.
+    pub fn width(&self) -> usize {
+        self.frame_w * self.factor
+    }
+
+    /// Returns the height of the output, in pixels.
+    pub fn height(&self) -> usize {
+        self.frame_h * self.factor
+    }
+
+    /// Upscales the given RGB frame, returning the RGB pixels of the output.
+    pub fn process(&mut self, frame: &[u8]) -> &[u8] {
+        let (w, h) = (self.frame_w, self.frame_h);
+
+        // Neighbours off the edges of the frame are taken from the edge.
+        let pixel = |x: usize, y: usize| -> Pixel {
+            let offset = (y * w + x) * 3;
+            [frame[offset], frame[offset + 1], frame[offset + 2]]
+        };
+
+        for y in 0..h {
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(h - 1));
+            for x in 0..w {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(w - 1));
+                let neighbours = [
+                    [pixel(left, up), pixel(x, up), pixel(right, up)],
+                    [pixel(left, y), pixel(x, y), pixel(right, y)],
+                    [pixel(left, down), pixel(x, down), pixel(right, down)],
+                ];
+
+                match self.factor {
+                    2 => self.write_block(x, y, &scale2x(&neighbours)),
+                    _ => self.write_block(x, y, &scale3x(&neighbours)),
+                }
+            }
+        }
+
+        &self.output
+    }
+
+    /// Writes the block of output pixels of the frame pixel at the given
+    /// position, given row by row.
+    fn write_block(&mut self, x: usize, y: usize, block: &[Pixel]) {
+        let output_w = self.width();
+        for (row, pixels) in block.chunks_exact(self.factor).enumerate() {
+            let offset = ((y * self.factor + row) * output_w + x * self.factor) * 3;
+            for (pixel, output) in pixels.iter().zip(self.output[offset..].chunks_exact_mut(3)) {
+                output.copy_from_slice(pixel);
+            }
+        }
+    }
+}
+
+/// Returns the 2x2 block of the middle pixel of a 3x3 neighbourhood.
+fn scale2x(n: &[[Pixel; 3]; 3]) -> [Pixel; 4] {
+    let (b, d, e, f, h) = (n[0][1], n[1][0], n[1][1], n[1][2], n[2][1]);
+    if b == h || d == f {
+        return [e; 4];
+    }
+
+    [
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+/// Returns the 3x3 block of the middle pixel of a 3x3 neighbourhood.
+fn scale3x(n: &[[Pixel; 3]; 3]) -> [Pixel; 9] {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *n;
+    if b == h || d == f {
+        return [e; 9];
+    }
+
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: Pixel = [255, 255, 255];
+    const O: Pixel = [0, 0, 0];
+
+    /// Returns the output of scaling the given frame, as pixels.
+    fn scale(factor: usize, frame: &[Pixel], frame_w: usize) -> Vec<Pixel> {
+        let mut scaler = ScaleX::new(factor, frame_w, frame.len() / frame_w);
+        let output = scaler.process(&frame.concat()).to_vec();
+        assert_eq!(output.len(), frame.len() * factor * factor * 3);
+
+        output
+            .chunks_exact(3)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect()
+    }
+
+    #[test]
+    fn test_scale2x() {
+        // The corner of the staircase is smoothed.
+        #[rustfmt::skip]
+        let frame = [
+            X, X, O,
+            X, O, O,
+            O, O, O,
+        ];
+        #[rustfmt::skip]
+        assert_eq!(
+            scale(2, &frame, 3),
+            [
+                X, X, X, X, O, O,
+                X, X, X, O, O, O,
+                X, X, X, O, O, O,
+                X, O, O, O, O, O,
+                O, O, O, O, O, O,
+                O, O, O, O, O, O,
+            ]
+        );
+
+        // Flat areas are left as they are.
+        assert_eq!(scale(2, &[X; 4], 2), [X; 16]);
+    }
+
+    #[test]
+    fn test_scale3x() {
+        #[rustfmt::skip]
+        let frame = [
+            X, X, O,
+            X, O, O,
+            O, O, O,
+        ];
+        #[rustfmt::skip]
+        assert_eq!(
+            scale(3, &frame, 3),
+            [
+                X, X, X, X, X, X, O, O, O,
+                X, X, X, X, X, O, O, O, O,
+                X, X, X, X, X, O, O, O, O,
+                X, X, X, X, O, O, O, O, O,
+                X, X, X, O, O, O, O, O, O,
+                X, O, O, O, O, O, O, O, O,
+                O, O, O, O, O, O, O, O, O,
+                O, O, O, O, O, O, O, O, O,
+                O, O, O, O, O, O, O, O, O,
+            ]
+        );
+    }
+}
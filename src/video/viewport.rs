@@ -0,0 +1,160 @@
+use serde::Deserialize;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use crate::config::VideoConfig;
+
+/// The shape of the pixels frames are drawn with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum PixelAspect {
+    /// Square pixels, as the console outputs them.
+    #[default]
+    #[serde(rename = "square")]
+    Square,
+
+    /// Pixels 8/7 as wide as they're tall, the shape an NTSC TV draws them.
+    #[serde(rename = "8:7")]
+    Ntsc,
+}
+
+impl PixelAspect {
+    /// Returns the width of a pixel over its height.
+    pub fn ratio(self) -> f32 {
+        match self {
+            PixelAspect::Square => 1.0,
+            PixelAspect::Ntsc => 8.0 / 7.0,
+        }
+    }
+}
+
+/// The area of a window frames are drawn in, in pixels.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl Viewport {
+    /// Returns the area to draw frames of the given size in a window of the
+    /// given size. Letterboxed, it's the largest that fits with the shape
+    /// of the pixels kept, centred between black bars, and if integer
+    /// scaling, a whole multiple of the frame height. Otherwise the frames
+    /// are stretched over the whole window.
+    pub fn new(config: &VideoConfig, window: (u32, u32), frame: (u32, u32)) -> Self {
+        if !config.letterbox {
+            return Viewport {
+                x: 0,
+                y: 0,
+                w: window.0,
+                h: window.1,
+            };
+        }
+
+        let frame_w = frame.0 as f32 * config.aspect.ratio();
+        let frame_h = frame.1 as f32;
+        let mut scale = (window.0 as f32 / frame_w).min(window.1 as f32 / frame_h);
+
+        // A window smaller than the frame can't fit a whole multiple.
+        if config.integer_scaling && scale >= 1.0 {
+            scale = scale.floor();
+        }
+
+        let w = ((frame_w * scale).round() as u32).min(window.0);
+        let h = ((frame_h * scale).round() as u32).min(window.1);
+        Viewport {
+            x: ((window.0 - w) / 2) as i32,
+            y: ((window.1 - h) / 2) as i32,
+            w,
+            h,
+        }
+    }
+
+    /// Returns the position in a frame of the given size under the given
+    /// window position, or none if it lies outside the viewport.
+    pub fn frame_position(&self, x: i32, y: i32, frame: (u32, u32)) -> Option<(usize, usize)> {
+        if self.w == 0 || self.h == 0 {
+            return None;
+        }
+
+        let x = ((x - self.x) as f32 * frame.0 as f32 / self.w as f32).floor() as i32;
+        let y = ((y - self.y) as f32 * frame.1 as f32 / self.h as f32).floor() as i32;
+        match (0..frame.0 as i32).contains(&x) && (0..frame.1 as i32).contains(&y) {
+            true => Some((x as usize, y as usize)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "sdl", feature = "winit")))]
+mod tests {
+    use super::*;
+
+    const FRAME: (u32, u32) = (256, 240);
+
+    /// Returns the viewport in the given window, as (x, y, w, h).
+    fn viewport(
+        aspect: PixelAspect,
+        integer_scaling: bool,
+        window: (u32, u32),
+    ) -> (i32, i32, u32, u32) {
+        let config = VideoConfig {
+            aspect,
+            integer_scaling,
+            ..VideoConfig::default()
+        };
+        let Viewport { x, y, w, h } = Viewport::new(&config, window, FRAME);
+        (x, y, w, h)
+    }
+
+    #[test]
+    fn test_letterbox() {
+        let square = viewport(PixelAspect::Square, false, (768, 720));
+        assert_eq!(square, (0, 0, 768, 720));
+
+        // Bars at the sides of a wide window.
+        let wide = viewport(PixelAspect::Square, false, (1280, 720));
+        assert_eq!(wide, (256, 0, 768, 720));
+
+        // Bars above and below a tall window.
+        let tall = viewport(PixelAspect::Square, false, (512, 720));
+        assert_eq!(tall, (0, 120, 512, 480));
+
+        let config = VideoConfig {
+            letterbox: false,
+            ..VideoConfig::default()
+        };
+        let stretched = Viewport::new(&config, (1280, 720), FRAME);
+        assert_eq!((stretched.w, stretched.h), (1280, 720));
+    }
+
+    #[test]
+    fn test_integer_scaling() {
+        let scaled = viewport(PixelAspect::Square, true, (1000, 1000));
+        assert_eq!(scaled, (116, 140, 768, 720));
+
+        let small = viewport(PixelAspect::Square, true, (128, 120));
+        assert_eq!(small, (0, 0, 128, 120));
+    }
+
+    #[test]
+    fn test_aspect() {
+        let ntsc = viewport(PixelAspect::Ntsc, false, (1280, 720));
+        assert_eq!(ntsc, (201, 0, 878, 720));
+
+        let ntsc = viewport(PixelAspect::Ntsc, true, (1000, 1000));
+        assert_eq!(ntsc, (61, 140, 878, 720));
+    }
+
+    #[test]
+    fn test_frame_position() {
+        let wide = Viewport::new(&VideoConfig::default(), (1280, 720), FRAME);
+        assert_eq!(wide.frame_position(256, 0, FRAME), Some((0, 0)));
+        assert_eq!(wide.frame_position(1023, 719, FRAME), Some((255, 239)));
+        assert_eq!(wide.frame_position(640, 360, FRAME), Some((128, 120)));
+        assert_eq!(wide.frame_position(255, 360, FRAME), None);
+        assert_eq!(wide.frame_position(1024, 360, FRAME), None);
+    }
+}
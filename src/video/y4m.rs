@@ -0,0 +1,75 @@
+use std::io::{self, Write};
+
+/// Writes RGB frames as uncompressed video in the YUV4MPEG2 format, with
+/// full resolution chroma (4:4:4), which ffmpeg and most encoders read.
+///
+/// See: https://wiki.multimedia.cx/index.php/YUV4MPEG2
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Creates a new writer of frames of the given size, at the given frame
+    /// rate in Hz, writing the header to out.
+    pub fn new(mut out: W, width: usize, height: usize, frame_rate: f64) -> io::Result<Self> {
+        // The frame rate is given as a fraction.
+        let denominator = 10000;
+        let numerator = (frame_rate * denominator as f64).round() as u64;
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+            width, height, numerator, denominator
+        )?;
+
+        Ok(Y4mWriter {
+            out,
+            width,
+            height,
+            planes: vec![0; width * height * 3],
+        })
+    }
+
+    /// Writes a frame of RGB pixels, converting them to BT.601 YCbCr.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let size = self.width * self.height;
+        let (y, chroma) = self.planes.split_at_mut(size);
+        let (cb, cr) = chroma.split_at_mut(size);
+        for (i, rgb) in frame.chunks_exact(3).take(size).enumerate() {
+            let (r, g, b) = (rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+            y[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+            cb[i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+            cr[i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    /// Flushes the output, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_y4m() {
+        let mut writer = Y4mWriter::new(vec![], 2, 1, 60.0988).unwrap();
+        writer.write_frame(&[0, 0, 0, 255, 255, 255]).unwrap();
+        let out = writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F600988:10000 Ip A1:1 C444\n";
+        assert_eq!(&out[..header.len()], header);
+
+        // Black and white, then neutral chroma for both.
+        let frame = &out[header.len()..];
+        assert_eq!(frame, b"FRAME\n\x10\xEB\x80\x80\x80\x80");
+    }
+}
@@ -0,0 +1,162 @@
+/// Bit reported while the photodiode doesn't sense light.
+const LIGHT_NOT_SENSED: u8 = 0b00001000;
+
+/// Bit reported while the trigger is pulled.
+const TRIGGER_PULLED: u8 = 0b00010000;
+
+const FRAME_WIDTH: i32 = 256;
+const FRAME_HEIGHT: i32 = 240;
+
+/// Number of scanlines the photodiode keeps sensing light after the beam
+/// has drawn a bright pixel in front of it, about 1.6 ms.
+const SENSE_SCANLINES: i32 = 25;
+
+/// Distance in pixels from the aimed position the photodiode sees.
+const SENSE_RADIUS: i32 = 2;
+
+/// Minimum luminance of a pixel sensed as light, which only bright colours
+/// such as white reach.
+const LIGHT_THRESHOLD: u32 = 0xC0;
+
+/// Represents a Zapper, the light gun used by games such as Duck Hunt and
+/// Wild Gunman, connected to a controller port in place of a joypad.
+///
+/// Reads of the port report the trigger and whether the photodiode senses
+/// light. Games flash targets in white on a black screen when the trigger is
+/// pulled, then check for light while the beam draws the frame. The
+/// photodiode only responds to the beam as it passes the aimed position,
+/// not to the image left on the screen, so light is only sensed for a short
+/// time after the bright pixels are drawn.
+///
+/// 7  bit  0
+/// ---- ----
+/// xxxT Lxxx
+///    | |
+///    | +---- Light sensed (0: detected; 1: not detected)
+///    +------ Trigger (0: released; 1: pulled)
+///
+/// See: https://www.nesdev.org/wiki/Zapper
+pub struct Zapper {
+    trigger: bool,
+
+    /// The aimed position in the frame, or none when aimed off screen.
+    aim: Option<(usize, usize)>,
+}
+
+impl Zapper {
+    /// Returns a Zapper aimed off screen with the trigger released.
+    pub fn new() -> Self {
+        Zapper {
+            trigger: false,
+            aim: None,
+        }
+    }
+
+    /// Sets whether the trigger is pulled.
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// Aims at the given position in the frame, or off screen.
+    pub fn aim(&mut self, position: Option<(usize, usize)>) {
+        self.aim = position;
+    }
+
+    /// Returns the state of the Zapper, given the RGB pixels of the frame
+    /// being drawn and the scanline and dot the PPU is drawing.
+    pub fn read(&self, frame: &[u8], scanline: i32, dot: usize) -> u8 {
+        let mut data = 0;
+        if !self.senses_light(frame, scanline, dot) {
+            data |= LIGHT_NOT_SENSED;
+        }
+        if self.trigger {
+            data |= TRIGGER_PULLED;
+        }
+
+        data
+    }
+
+    /// Returns true if a bright pixel around the aimed position has been
+    /// drawn in the last few scanlines.
+    fn senses_light(&self, frame: &[u8], scanline: i32, dot: usize) -> bool {
+        let Some((aim_x, aim_y)) = self.aim else {
+            return false;
+        };
+        let (aim_x, aim_y) = (aim_x as i32, aim_y as i32);
+        let rows = (aim_y - SENSE_RADIUS).max(0)..=(aim_y + SENSE_RADIUS).min(FRAME_HEIGHT - 1);
+        let columns = (aim_x - SENSE_RADIUS).max(0)..=(aim_x + SENSE_RADIUS).min(FRAME_WIDTH - 1);
+
+        for y in rows {
+            // Skip scanlines the beam has yet to draw, or passed too long ago.
+            let elapsed = scanline - y;
+            if !(0..SENSE_SCANLINES).contains(&elapsed) {
+                continue;
+            }
+
+            for x in columns.clone() {
+                // The pixel at x is drawn on dot x + 1.
+                if elapsed == 0 && x + 1 >= dot as i32 {
+                    break;
+                }
+
+                let base = ((y * FRAME_WIDTH + x) * 3) as usize;
+                if let [r, g, b] = frame[base..base + 3] {
+                    let luminance = (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000;
+                    if luminance >= LIGHT_THRESHOLD {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a black frame with a white pixel at the given position.
+    fn test_frame(x: usize, y: usize) -> Vec<u8> {
+        let mut frame = vec![0; (FRAME_WIDTH * FRAME_HEIGHT * 3) as usize];
+        let base = (y * FRAME_WIDTH as usize + x) * 3;
+        frame[base..base + 3].fill(0xFF);
+        frame
+    }
+
+    #[test]
+    fn test_trigger() {
+        let frame = test_frame(0, 0);
+        let mut zapper = Zapper::new();
+        assert_eq!(zapper.read(&frame, 100, 0), LIGHT_NOT_SENSED);
+
+        zapper.set_trigger(true);
+        assert_eq!(
+            zapper.read(&frame, 100, 0),
+            LIGHT_NOT_SENSED | TRIGGER_PULLED
+        );
+    }
+
+    #[test]
+    fn test_light() {
+        let frame = test_frame(100, 100);
+        let mut zapper = Zapper::new();
+        zapper.aim(Some((101, 99)));
+
+        // The beam has yet to draw the pixel.
+        assert_eq!(zapper.read(&frame, 99, 200), LIGHT_NOT_SENSED);
+        assert_eq!(zapper.read(&frame, 100, 101), LIGHT_NOT_SENSED);
+
+        // Light is sensed once the pixel is drawn, until it fades.
+        assert_eq!(zapper.read(&frame, 100, 102), 0);
+        assert_eq!(zapper.read(&frame, 124, 0), 0);
+        assert_eq!(zapper.read(&frame, 125, 0), LIGHT_NOT_SENSED);
+
+        // Nor is it sensed away from the pixel, or off screen.
+        zapper.aim(Some((110, 100)));
+        assert_eq!(zapper.read(&frame, 110, 0), LIGHT_NOT_SENSED);
+        zapper.aim(None);
+        assert_eq!(zapper.read(&frame, 110, 0), LIGHT_NOT_SENSED);
+    }
+}